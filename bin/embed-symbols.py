@@ -0,0 +1,155 @@
+#!/usr/bin/env python3
+#
+# Embeds the function symbols of a kernel ELF into the space the kernel
+# reserves for them (the `KERNEL_SYMBOLS` static), in both the ELF and the
+# raw binary objcopy'd from it, so that backtraces print `function+offset`.
+#
+#   embed-symbols.py build/kernel.elf build/kernel.bin
+#
+# Table layout (little-endian), as `aarch64::backtrace::Symbols` parses it:
+#   "SYMS" | u32 count | count * (u64 addr, u32 size, u32 name offset) | names
+# Names are NUL-terminated and their offsets are from the start of the table.
+
+import re
+import struct
+import sys
+
+SPACE = "KERNEL_SYMBOLS"
+
+SHT_SYMTAB = 2
+STT_FUNC = 2
+PT_LOAD = 1
+
+ESCAPES = {
+    "$SP$": "@", "$BP$": "*", "$RF$": "&", "$LT$": "<", "$GT$": ">",
+    "$LP$": "(", "$RP$": ")", "$C$": ",",
+}
+
+def demangle(name):
+    """Demangles a legacy Rust symbol, dropping its hash."""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+    parts = []
+    rest = name[3:-1]
+    while rest:
+        m = re.match(r"(\d+)", rest)
+        if m is None:
+            return name
+        n = int(m.group(1))
+        rest = rest[len(m.group(1)):]
+        parts.append(rest[:n])
+        rest = rest[n:]
+    if parts and re.fullmatch(r"h[0-9a-f]{16}", parts[-1]):
+        parts.pop()
+
+    def unescape(part):
+        if part.startswith("_$"):
+            part = part[1:]
+        for k, v in ESCAPES.items():
+            part = part.replace(k, v)
+        part = re.sub(r"\$u([0-9a-f]+)\$", lambda m: chr(int(m.group(1), 16)), part)
+        return part.replace("..", "::")
+
+    return "::".join(unescape(p) for p in parts)
+
+class Elf:
+    def __init__(self, data):
+        if data[:4] != b"\x7fELF" or data[4] != 2 or data[5] != 1:
+            raise ValueError("not a little-endian ELF64 file")
+        self.data = data
+        (self.phoff, self.shoff) = struct.unpack_from("<QQ", data, 0x20)
+        (self.phentsize, self.phnum, self.shentsize, self.shnum) = \
+            struct.unpack_from("<HHHH", data, 0x36)
+
+    def sections(self):
+        for i in range(self.shnum):
+            off = self.shoff + i * self.shentsize
+            (_, typ, _, addr, offset, size, link, _, _, entsize) = \
+                struct.unpack_from("<IIQQQQIIQQ", self.data, off)
+            yield dict(type=typ, addr=addr, offset=offset, size=size, link=link, entsize=entsize)
+
+    def segments(self):
+        for i in range(self.phnum):
+            off = self.phoff + i * self.phentsize
+            (typ, _, offset, vaddr, paddr, filesz, memsz, _) = \
+                struct.unpack_from("<IIQQQQQQ", self.data, off)
+            yield dict(type=typ, offset=offset, vaddr=vaddr, paddr=paddr, filesz=filesz)
+
+    def symbols(self):
+        sections = list(self.sections())
+        for s in sections:
+            if s["type"] != SHT_SYMTAB:
+                continue
+            strtab = sections[s["link"]]
+            for off in range(s["offset"], s["offset"] + s["size"], s["entsize"]):
+                (name, info, _, shndx, value, size) = struct.unpack_from("<IBBHQQ", self.data, off)
+                start = strtab["offset"] + name
+                end = self.data.index(b"\0", start)
+                yield (self.data[start:end].decode(), info & 0xf, shndx, value, size)
+
+    def file_offset(self, addr):
+        for s in self.segments():
+            if s["type"] == PT_LOAD and s["vaddr"] <= addr < s["vaddr"] + s["filesz"]:
+                return s["offset"] + addr - s["vaddr"]
+        raise ValueError("%#x isn't in the file" % addr)
+
+    def load_base(self):
+        return min(s["paddr"] for s in self.segments() if s["type"] == PT_LOAD and s["filesz"])
+
+def build_table(functions, space):
+    """Builds a table of `functions`, dropping those with the longest names
+    if it doesn't fit in `space` bytes."""
+    functions = list(functions)
+    size = 8 + sum(16 + len(name.encode()) + 1 for (_, _, name) in functions)
+    dropped = 0
+    by_length = sorted(functions, key=lambda f: len(f[2]))
+    while size > space and by_length:
+        (_, _, name) = by_length.pop()
+        size -= 16 + len(name.encode()) + 1
+        dropped += 1
+    if dropped:
+        print("[!] symbol table doesn't fit in %d bytes; dropped %d symbols with long names"
+              % (space, dropped), file=sys.stderr)
+
+    functions = sorted(by_length)
+    names = bytearray()
+    entries = bytearray()
+    names_at = 8 + 16 * len(functions)
+    for (addr, size, name) in functions:
+        entries += struct.pack("<QII", addr, size, names_at + len(names))
+        names += name.encode() + b"\0"
+    table = b"SYMS" + struct.pack("<I", len(functions)) + entries + names
+    return table + b"\0" * (space - len(table))
+
+def main():
+    if len(sys.argv) != 3:
+        print("usage: %s <kernel.elf> <kernel.bin>" % sys.argv[0], file=sys.stderr)
+        sys.exit(1)
+    (elf_path, bin_path) = sys.argv[1:]
+
+    elf = Elf(bytearray(open(elf_path, "rb").read()))
+    space = None
+    functions = {}
+    for (name, typ, shndx, value, size) in elf.symbols():
+        if name == SPACE:
+            space = (value, size)
+        elif typ == STT_FUNC and shndx != 0 and value != 0:
+            functions.setdefault(value, (value, size, demangle(name)))
+    if space is None:
+        print("[!] %s has no %s" % (elf_path, SPACE), file=sys.stderr)
+        sys.exit(1)
+
+    (addr, size) = space
+    table = build_table(functions.values(), size)
+
+    offset = elf.file_offset(addr)
+    elf.data[offset:offset + size] = table
+    open(elf_path, "wb").write(elf.data)
+
+    image = bytearray(open(bin_path, "rb").read())
+    offset = addr - elf.load_base()
+    image[offset:offset + size] = table
+    open(bin_path, "wb").write(image)
+
+if __name__ == "__main__":
+    main()
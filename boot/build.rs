@@ -1,3 +1,23 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Public key images must be signed with, as raw bytes. Without one, the
+/// bootloader still checks the digest of signed images but also boots
+/// unsigned ones.
+const PUBLIC_KEY: &str = "key.pub";
+
 pub fn main() {
     println!("cargo:rerun-if-changed=.cargo/layout.ld");
+    println!("cargo:rerun-if-changed={}", PUBLIC_KEY);
+
+    let key = match fs::read(PUBLIC_KEY) {
+        Ok(key) => {
+            assert_eq!(key.len(), 32, "{} should be a 32-byte Ed25519 public key", PUBLIC_KEY);
+            format!("Some({:?})", key)
+        },
+        Err(_) => String::from("None"),
+    };
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("key.rs");
+    fs::write(out, format!("pub const PUBLIC_KEY: Option<[u8; 32]> = {};\n", key)).unwrap();
 }
@@ -1,9 +1,14 @@
 #!/bin/sh
 
 TOP=$(git rev-parse --show-toplevel)
+
+# The UART's backend, e.g. SERIAL=tcp::4444,server,nowait for ttywrite's
+# tcp://localhost:4444 target.
+SERIAL=${SERIAL:-pty}
+
 $TOP/bin/qemu-system-aarch64 \
     -nographic \
     -M raspi3 \
-    -serial null -serial pty \
+    -serial null -serial $SERIAL \
     -kernel \
     "$@"
@@ -0,0 +1,40 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::cmp::max;
+
+use crate::{HEAP_END, HEAP_START};
+
+/// A "bump" allocator over the bootloader's heap: allocates memory by bumping
+/// a pointer and never frees. The file system is read once per boot, so
+/// nothing is reused anyway.
+pub struct Allocator {
+    current: UnsafeCell<usize>,
+}
+
+// The bootloader runs on one core without interrupts.
+unsafe impl Sync for Allocator {}
+
+impl Allocator {
+    pub const fn new() -> Allocator {
+        Allocator { current: UnsafeCell::new(HEAP_START) }
+    }
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = &mut *self.current.get();
+        let align = max(8, layout.align());
+        let start = (*current + align - 1) & !(align - 1);
+        match start.checked_add(layout.size()) {
+            Some(end) if end <= HEAP_END => {
+                *current = end;
+                start as *mut u8
+            },
+            _ => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        // LEAKED
+    }
+}
@@ -0,0 +1,61 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt::{self, Debug};
+use shim::io;
+use shim::ioerr;
+
+use fat32::traits::{File, FileSystem};
+use fat32::vfat::{VFat, VFatHandle};
+
+use sd::Sd;
+
+#[derive(Clone)]
+pub struct BootVFatHandle(Rc<RefCell<VFat<Self>>>);
+
+// Like the kernel's handle, these impls are unsound, but the bootloader only
+// ever runs on one core.
+unsafe impl Send for BootVFatHandle {}
+unsafe impl Sync for BootVFatHandle {}
+
+impl Debug for BootVFatHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "BootVFatHandle")
+    }
+}
+
+impl VFatHandle for BootVFatHandle {
+    fn new(val: VFat<BootVFatHandle>) -> Self {
+        BootVFatHandle(Rc::new(RefCell::new(val)))
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut VFat<BootVFatHandle>) -> R) -> R {
+        f(&mut self.0.borrow_mut())
+    }
+}
+
+/// Reads the file at `path` on the SD card's FAT32 partition into `buf` and
+/// returns its length.
+///
+/// # Safety
+///
+/// Initializes the SD card controller, which must happen only once.
+pub unsafe fn read_file(path: &str, buf: &mut [u8]) -> io::Result<usize> {
+    let vfat = match VFat::<BootVFatHandle>::from(Sd::new()?) {
+        Ok(vfat) => vfat,
+        Err(_) => return ioerr!(InvalidData, "no FAT32 partition"),
+    };
+    let mut file = (&vfat).open_file(path)?;
+    if file.size() > buf.len() as u64 {
+        return ioerr!(InvalidData, "file too large");
+    }
+
+    let len = file.size() as usize;
+    let mut n = 0;
+    while n < len {
+        match io::Read::read(&mut file, &mut buf[n..len])? {
+            0 => return ioerr!(UnexpectedEof, "file shorter than its size"),
+            read => n += read,
+        }
+    }
+    Ok(len)
+}
@@ -0,0 +1,6 @@
+use core::alloc::Layout;
+
+#[alloc_error_handler]
+pub fn oom(_layout: Layout) -> ! {
+    panic!("OOM");
+}
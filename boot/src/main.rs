@@ -1,17 +1,31 @@
+#![feature(alloc_error_handler)]
 #![feature(asm)]
 #![feature(global_asm)]
 
 #![cfg_attr(not(test), no_std)]
 #![cfg_attr(not(test), no_main)]
 
+extern crate alloc;
+
 #[cfg(not(test))]
 mod init;
 
+mod allocator;
+mod fs;
+mod menu;
+
 use shim::io;
+use shim::ioerr;
 use core::fmt::Write;
-use xmodem::{Xmodem};
+use xmodem::Ymodem;
 use core::time::Duration;
 use pi;
+use bootsig::image::{self, Header, ImageError};
+
+use allocator::Allocator;
+use menu::Choice;
+
+include!(concat!(env!("OUT_DIR"), "/key.rs"));
 
 /// Start address of the binary to load and of the bootloader.
 const BINARY_START_ADDR: usize = 0x80000;
@@ -23,6 +37,21 @@ const BINARY_START: *mut u8 = BINARY_START_ADDR as *mut u8;
 /// Free space between the bootloader and the loaded binary's start address.
 const MAX_BINARY_SIZE: usize = BOOTLOADER_START_ADDR - BINARY_START_ADDR;
 
+/// Heap for the file system, above the bootloader.
+const HEAP_START: usize = 0x8000000;
+const HEAP_END: usize = 0xC000000;
+
+/// Where images are read or received to before they are checked and loaded,
+/// so that ELF segments can go anywhere below the bootloader.
+const STAGING_START: usize = 0xC000000;
+const STAGING_SIZE: usize = 0x4000000;
+
+/// Image booted from the SD card's FAT32 partition.
+const SD_IMAGE: &str = "/kernel.bin";
+
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: Allocator = Allocator::new();
+
 /// Branches to the address `addr` unconditionally.
 unsafe fn jump_to(addr: *mut u8) -> ! {
     asm!("br $0" : : "r"(addr as usize));
@@ -31,23 +60,83 @@ unsafe fn jump_to(addr: *mut u8) -> ! {
     }
 }
 
+/// Receives a YMODEM batch from `uart` and writes its first file into `dst`.
+/// Returns the length of the file, without the padding of its last packet.
+/// A plain XMODEM transfer is taken too, padding and all.
+fn receive(uart: &mut pi::uart::MiniUart, dst: &mut [u8]) -> io::Result<usize> {
+    let mut ymodem = Ymodem::new(uart);
+    let info = match ymodem.recv_header_or_xmodem()? {
+        Some(info) => info,
+        None => return ioerr!(InvalidData, "empty batch"),
+    };
+    let n = ymodem.recv_data(&info, dst)?;
+    // the sender ends the batch after the image
+    if !info.is_xmodem() && ymodem.recv_header()?.is_some() {
+        return ioerr!(InvalidData, "send only the image");
+    }
+    Ok(n as usize)
+}
+
+/// Reasons an image isn't booted.
+#[derive(Debug)]
+enum Rejected {
+    /// `PUBLIC_KEY` is set, but the image isn't signed.
+    Unsigned,
+    /// The signed image failed its checks.
+    Image(ImageError),
+    /// The ELF image can't be loaded.
+    Elf(elf::Error),
+    /// The raw binary doesn't fit below the bootloader.
+    TooLarge,
+}
+
+/// Checks `data`, which is a signed image or, if there is no `PUBLIC_KEY`,
+/// possibly an unsigned one, and loads it: an ELF image's segments at their
+/// load addresses, a raw binary at `BINARY_START`. Returns the entry point.
+unsafe fn load(data: &[u8]) -> Result<usize, Rejected> {
+    let image = match Header::parse(data) {
+        Some(header) => image::check(&header, data, PUBLIC_KEY.as_ref()).map_err(Rejected::Image)?,
+        None if PUBLIC_KEY.is_some() => return Err(Rejected::Unsigned),
+        None => data,
+    };
+
+    if elf::is_elf(image) {
+        let elf = elf::Elf::parse(image).map_err(Rejected::Elf)?;
+        return elf.load(BINARY_START_ADDR..BOOTLOADER_START_ADDR).map_err(Rejected::Elf);
+    }
+    if image.len() > MAX_BINARY_SIZE {
+        return Err(Rejected::TooLarge);
+    }
+    core::ptr::copy_nonoverlapping(image.as_ptr(), BINARY_START, image.len());
+    Ok(BINARY_START_ADDR)
+}
+
 unsafe fn kmain() -> ! {
     let mut uart_dev = pi::uart::MiniUart::new();
-    uart_dev.set_read_timeout(Duration::from_millis(750));
+    let staging = core::slice::from_raw_parts_mut(STAGING_START as *mut u8, STAGING_SIZE);
 
-    'try_recv: loop {
-        let dst = core::slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE);
-        loop {
-            match Xmodem::receive(&mut uart_dev, dst) {
-                Ok(_) => { break 'try_recv }, // done
-                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue 'try_recv, // timeout, try again
-                Err(n) => { // error!
-                    write!(uart_dev, "Error! {}", n).unwrap();
-                    break
-                }
-            };
+    let choice = menu::show(&mut uart_dev, Choice::last().unwrap_or(Choice::Sd));
+    choice.remember();
+    if choice == Choice::Sd {
+        match fs::read_file(SD_IMAGE, staging) {
+            Ok(n) => match load(&staging[..n]) {
+                Ok(entry) => jump_to(entry as *mut u8),
+                Err(e) => write!(uart_dev, "Rejected {}: {:?}\r\n", SD_IMAGE, e).unwrap(),
+            },
+            Err(e) => write!(uart_dev, "Reading {} failed: {}\r\n", SD_IMAGE, e).unwrap(),
         }
+        write!(uart_dev, "Waiting for an upload instead\r\n").unwrap();
     }
 
-    jump_to(BINARY_START)
+    uart_dev.set_read_timeout(Duration::from_millis(750));
+    loop {
+        match receive(&mut uart_dev, staging) {
+            Ok(n) => match load(&staging[..n]) {
+                Ok(entry) => jump_to(entry as *mut u8),
+                Err(e) => write!(uart_dev, "Rejected image: {:?}\r\n", e).unwrap(),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue, // timeout, try again
+            Err(e) => write!(uart_dev, "Error! {}\r\n", e).unwrap(),
+        }
+    }
 }
@@ -0,0 +1,96 @@
+//! The boot menu and the choice it remembers across reboots.
+//!
+//! No line printed here may start with `C`, which a waiting YMODEM sender
+//! would take for the receiver's handshake.
+
+use core::fmt::Write;
+use core::ptr;
+use core::time::Duration;
+
+use pi::atags::{Atag, Atags};
+use pi::common::BOOT_RESERVED_SIZE;
+use pi::timer;
+use pi::uart::MiniUart;
+
+use crate::SD_IMAGE;
+
+/// How long the menu waits for a key before taking the default.
+const MENU_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Magic number of the remembered choice, "BOOTMENU".
+const CHOICE_MAGIC: u64 = 0x554e_454d_544f_4f42;
+
+/// Where the bootloader gets the image from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Choice {
+    /// `SD_IMAGE` on the SD card.
+    Sd = 0,
+    /// A YMODEM upload over the UART.
+    Upload = 1,
+}
+
+/// The choice record at the start of the `BOOT_RESERVED_SIZE` bytes at the
+/// end of RAM, which the kernel and the hypervisor leave alone. The
+/// bootloader runs with the caches off, and RAM keeps the record over a
+/// reset, but not over a power cycle.
+#[repr(C)]
+struct Record {
+    magic: u64,
+    choice: u64,
+    check: u64,
+}
+
+/// Returns the address of the choice record, if the firmware told us where
+/// RAM ends.
+fn record_addr() -> Option<*mut Record> {
+    let mem = Atags::get().find_map(Atag::mem)?;
+    Some(((mem.start + mem.size) as usize - BOOT_RESERVED_SIZE) as *mut Record)
+}
+
+impl Choice {
+    /// Returns the choice made on the previous boot, if it is remembered.
+    pub fn last() -> Option<Choice> {
+        let record = unsafe { ptr::read_volatile(record_addr()?) };
+        if record.magic != CHOICE_MAGIC || record.check != !record.choice {
+            return None;
+        }
+        match record.choice {
+            0 => Some(Choice::Sd),
+            1 => Some(Choice::Upload),
+            _ => None,
+        }
+    }
+
+    /// Remembers this choice for the next boot.
+    pub fn remember(self) {
+        let choice = self as u64;
+        let record = Record { magic: CHOICE_MAGIC, choice, check: !choice };
+        if let Some(addr) = record_addr() {
+            unsafe { ptr::write_volatile(addr, record) }
+        }
+    }
+}
+
+/// Shows the boot menu on `uart` and returns the choice, `default` if no key
+/// is pressed in time.
+pub fn show(uart: &mut MiniUart, default: Choice) -> Choice {
+    let mark = |choice| if choice == default { '*' } else { ' ' };
+    write!(uart, "\r\nBoot menu:\r\n").unwrap();
+    write!(uart, " {} 1. {} from the SD card\r\n", mark(Choice::Sd), SD_IMAGE).unwrap();
+    write!(uart, " {} 2. upload with YMODEM\r\n", mark(Choice::Upload)).unwrap();
+    write!(uart, "Press 1 or 2, or wait {}s for *\r\n", MENU_TIMEOUT.as_secs()).unwrap();
+
+    let deadline = timer::current_time() + MENU_TIMEOUT;
+    while timer::current_time() < deadline {
+        if !uart.has_byte() {
+            continue;
+        }
+        match uart.read_byte() {
+            b'1' => return Choice::Sd,
+            b'2' => return Choice::Upload,
+            b'\r' | b'\n' => return default,
+            _ => {},
+        }
+    }
+    default
+}
@@ -15,6 +15,7 @@ use core::fmt;
 use crate::console::kprintln;
 use crate::mutex::Mutex;
 use pi::atags::{Atag, Atags};
+use pi::common::BOOT_RESERVED_SIZE;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
@@ -80,7 +81,8 @@ pub fn memory_map() -> Option<(usize, usize)> {
 
     if let Some(mem_tag) = Atags::get().find_map(Atag::mem) {
         let heap_start = util::align_up(binary_end, page_size);
-        let mem_end = (mem_tag.start + mem_tag.size) as usize;
+        // the end of memory is the bootloader's
+        let mem_end = (mem_tag.start + mem_tag.size) as usize - BOOT_RESERVED_SIZE;
         Some((heap_start, mem_end))
     } else {
         None
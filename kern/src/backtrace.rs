@@ -0,0 +1,28 @@
+use aarch64::backtrace::{self, SymbolSpace};
+
+use crate::console::kprintln;
+use crate::param::{IO_BASE, PAGE_SIZE};
+
+/// The kernel's symbol table, filled in after linking by
+/// `bin/embed-symbols.py`.
+#[no_mangle]
+#[used]
+pub static mut KERNEL_SYMBOLS: SymbolSpace = SymbolSpace::new();
+
+/// Reads a doubleword of a frame record on the kernel's stack.
+fn read(addr: u64) -> Option<u64> {
+    if addr < PAGE_SIZE as u64 || addr > IO_BASE as u64 - 8 {
+        return None;
+    }
+    Some(unsafe { *(addr as *const u64) })
+}
+
+/// Prints a backtrace of the caller.
+#[inline(never)]
+pub fn print() {
+    let symbols = unsafe { &KERNEL_SYMBOLS }.symbols();
+    kprintln!("Backtrace:");
+    for frame in backtrace::trace(symbols, None, backtrace::frame_pointer(), read) {
+        kprintln!("{}", frame);
+    }
+}
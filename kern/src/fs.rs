@@ -1,5 +1,3 @@
-pub mod sd;
-
 use alloc::rc::Rc;
 use core::fmt::{self, Debug};
 use shim::io;
@@ -8,7 +6,7 @@ use shim::path::Path;
 pub use fat32::traits;
 use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
 
-use self::sd::Sd;
+use sd::Sd;
 use crate::mutex::Mutex;
 
 #[derive(Clone)]
@@ -15,6 +15,7 @@ fn panic(_info: &PanicInfo) -> ! {
     if let Some(s) = _info.location() {
         kprintln!("At file \"{}\", line {}", s.file(), s.line());
     }
+    crate::backtrace::print();
 
     kprintln!("\n       m(_ _)m       ");
     kprintln!("Strike RETURN to reboot");
@@ -0,0 +1,45 @@
+use core::fmt;
+
+use pi::timer;
+
+pub use klog::{Level, Logger, Record};
+
+use crate::console::kprintln;
+use crate::mutex::Mutex;
+
+/// Global log.
+pub static LOGGER: Mutex<Logger> = Mutex::new(Logger::new());
+
+/// Internal function called by the logging macros.
+#[doc(hidden)]
+pub fn _log(level: Level, module: &'static str, args: fmt::Arguments) {
+    let record = Record::new(timer::current_time(), level, module, args);
+    if LOGGER.lock().log(&record) {
+        kprintln!("{}", record);
+    }
+}
+
+/// Logs an error.
+pub macro error($($arg:tt)*) {
+    $crate::log::_log($crate::log::Level::Error, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a warning.
+pub macro warn($($arg:tt)*) {
+    $crate::log::_log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs an informational message.
+pub macro info($($arg:tt)*) {
+    $crate::log::_log($crate::log::Level::Info, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a debugging message.
+pub macro debug($($arg:tt)*) {
+    $crate::log::_log($crate::log::Level::Debug, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a detailed tracing message.
+pub macro trace($($arg:tt)*) {
+    $crate::log::_log($crate::log::Level::Trace, module_path!(), format_args!($($arg)*))
+}
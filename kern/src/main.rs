@@ -17,8 +17,10 @@ mod init;
 extern crate alloc;
 
 pub mod allocator;
+pub mod backtrace;
 pub mod console;
 pub mod fs;
+pub mod log;
 pub mod mutex;
 pub mod shell;
 pub mod param;
@@ -38,7 +40,7 @@ use vm::VMManager;
 use aarch64::current_el;
 
 use allocator::Allocator;
-use fs::sd::Sd;
+use sd::Sd;
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
@@ -52,7 +54,7 @@ fn kmain() -> ! {
     
     kprintln!("kern: we are in EL{}", unsafe { current_el() } );
     for atag in pi::atags::Atags::get() {
-        kprintln!("{:?}", atag);
+        log::info!("{:?}", atag);
     }
     kprintln!();
 
@@ -17,6 +17,7 @@ use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry, Metadata, Timestamp};
 
 use crate::fs;
+use crate::log::{Level, LOGGER};
 use crate::console::{kprint, kprintln, CONSOLE};
 use crate::ALLOCATOR;
 use crate::FILESYSTEM;
@@ -199,6 +200,46 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
         Ok(())
     }
 
+    /// `dmesg [clear]`: replays the log buffer, or clears it.
+    fn dmesg(&mut self, cmd: Command) -> io::Result<()> {
+        match cmd.args.get(1).map(|a| *a) {
+            None => {
+                let lines = LOGGER.lock().ring.lines();
+                kprint!("{}", String::from_utf8_lossy(&lines));
+            },
+            Some("clear") if cmd.args.len() == 2 => LOGGER.lock().ring.clear(),
+            _ => kprintln!("usage: dmesg [clear]"),
+        }
+        Ok(())
+    }
+
+    /// `log [<level> | <module> <level>|default]`: shows the log levels, or
+    /// sets the default level or the level of `module` and the modules
+    /// within it. `default` removes the module's filter.
+    fn log(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: log [<level> | <module> <level>|default], levels off|error|warn|info|debug|trace";
+        let mut logger = LOGGER.lock();
+        match (cmd.args.get(1).map(|a| *a), cmd.args.get(2).map(|a| *a)) {
+            (None, None) => {
+                kprintln!("default {}", logger.level);
+                for (module, level) in logger.filters.iter() {
+                    kprintln!("{} {}", module, level);
+                }
+            },
+            (Some(level), None) => match level.parse::<Level>() {
+                Ok(level) => logger.level = level,
+                Err(_) => kprintln!("{}", USAGE),
+            },
+            (Some(module), Some("default")) if cmd.args.len() == 3 => logger.set_filter(module, None),
+            (Some(module), Some(level)) if cmd.args.len() == 3 => match level.parse::<Level>() {
+                Ok(level) => logger.set_filter(module, Some(level)),
+                Err(_) => kprintln!("{}", USAGE),
+            },
+            _ => kprintln!("{}", USAGE),
+        }
+        Ok(())
+    }
+
     fn call_command(&mut self, cmd: Command) -> io::Result<()> {
         match cmd.path() {
             "echo" => {
@@ -234,6 +275,8 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
             },
             "ls" => self.ls(cmd),
             "cat" => self.cat(cmd),
+            "dmesg" => self.dmesg(cmd),
+            "log" => self.log(cmd),
             "hvc" => {
                 unsafe {
                     asm!("hvc 1");
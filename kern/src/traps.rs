@@ -41,14 +41,12 @@ pub struct Info {
 /// the trap frame for the exception.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
-    use crate::console::{kprintln};
+    use crate::log;
     use crate::shell::Shell;
-    kprintln!("we got EL1 exception!");
-    kprintln!("info: {:?}", info);
-    kprintln!("esr: {:x}", esr);
+    log::warn!("EL1 exception {:?}, ESR {:x}", info, esr);
     if info.kind == Kind::Synchronous {
         let syndrome = Syndrome::from(esr);
-        kprintln!("syndrome: {:?}", syndrome);
+        log::warn!("syndrome: {:?}", syndrome);
         if let Syndrome::Brk(x) = syndrome {
             Shell::new("! ").do_forever();
         }
@@ -0,0 +1,210 @@
+//! Frame-pointer stack unwinding and symbolization.
+//!
+//! Code built with `-C force-frame-pointers=yes` keeps a frame record of the
+//! caller's frame pointer and the return address at `[x29]`, chaining every
+//! frame on the stack. `bin/embed-symbols.py` writes a symbol table into the
+//! kernel image after linking so that return addresses can be printed as
+//! `function+offset`.
+
+use core::{fmt, slice, str};
+
+/// The most frames `Frames` walks, in case the chain loops.
+pub const MAX_FRAMES: usize = 64;
+
+/// Magic number of an embedded symbol table.
+pub const SYMBOLS_MAGIC: &[u8; 4] = b"SYMS";
+
+/// Bytes reserved in an image for the symbol table `bin/embed-symbols.py`
+/// fills in after linking.
+pub const SYMBOLS_SIZE: usize = 256 * 1024;
+
+/// Size of a symbol table header: the magic number and the symbol count.
+const HEADER_SIZE: usize = 8;
+
+/// Size of a symbol table entry: address, size and name offset.
+const ENTRY_SIZE: usize = 16;
+
+/// Returns the current frame pointer.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe {
+        asm!("mov $0, x29": "=r"(fp) ::: "volatile");
+    }
+    fp
+}
+
+/// An iterator over the return addresses of a frame record chain.
+pub struct Frames<F> {
+    fp: u64,
+    read: F,
+    depth: usize,
+}
+
+/// Walks the frame records starting at frame pointer `fp`, reading the
+/// doubleword at an address with `read`. The walk stops at a null or
+/// misaligned frame pointer, a record `read` fails on, or a chain that
+/// doesn't go up the stack.
+pub fn frames<F: FnMut(u64) -> Option<u64>>(fp: u64, read: F) -> Frames<F> {
+    Frames { fp, read, depth: 0 }
+}
+
+impl<F: FnMut(u64) -> Option<u64>> Iterator for Frames<F> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let fp = self.fp;
+        if fp == 0 || fp & 0xf != 0 || self.depth == MAX_FRAMES {
+            return None;
+        }
+        let prev = (self.read)(fp)?;
+        let lr = (self.read)(fp.checked_add(8)?)?;
+        if lr == 0 {
+            return None;
+        }
+        self.fp = if prev > fp { prev } else { 0 };
+        self.depth += 1;
+        Some(lr)
+    }
+}
+
+/// Space for the symbol table of an image, reserved by a `#[no_mangle]`
+/// static named `KERNEL_SYMBOLS` that `bin/embed-symbols.py` looks for.
+#[repr(C)]
+pub struct SymbolSpace {
+    magic: [u8; 4],
+    table: [u8; SYMBOLS_SIZE - 4],
+}
+
+impl SymbolSpace {
+    /// Returns a space with no table in it. Until one is embedded, frames
+    /// are printed as bare addresses. The placeholder magic number keeps the
+    /// space out of `.bss`, so it is part of the binary image.
+    pub const fn new() -> SymbolSpace {
+        SymbolSpace { magic: *b"NONE", table: [0; SYMBOLS_SIZE - 4] }
+    }
+
+    /// Returns the embedded symbol table, if any.
+    pub fn symbols(&'static self) -> Option<Symbols<'static>> {
+        // read through a pointer so the placeholder isn't constant-folded
+        let space = unsafe { slice::from_raw_parts(self as *const _ as *const u8, SYMBOLS_SIZE) };
+        Symbols::parse(space)
+    }
+}
+
+/// A symbol table, as laid out by `bin/embed-symbols.py`: the magic number
+/// and a little-endian `u32` count, the entries sorted by address, each a
+/// `u64` address, `u32` size and `u32` offset of the name, then the
+/// NUL-terminated names.
+#[derive(Debug, Copy, Clone)]
+pub struct Symbols<'a> {
+    count: usize,
+    data: &'a [u8],
+}
+
+/// A symbol an address falls in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub addr: u64,
+    pub offset: u64,
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[at..at + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], at: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[at..at + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+impl<'a> Symbols<'a> {
+    /// Parses the symbol table in `data`. Returns `None` if `data` doesn't
+    /// hold one, e.g. if no table was embedded in the image.
+    pub fn parse(data: &'a [u8]) -> Option<Symbols<'a>> {
+        if data.len() < HEADER_SIZE || &data[..4] != SYMBOLS_MAGIC {
+            return None;
+        }
+        let count = read_u32(data, 4) as usize;
+        if count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)? > data.len() {
+            return None;
+        }
+        Some(Symbols { count, data })
+    }
+
+    /// Returns the number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the address, size and name offset of entry `i`.
+    fn entry(&self, i: usize) -> (u64, u64, usize) {
+        let at = HEADER_SIZE + i * ENTRY_SIZE;
+        (read_u64(self.data, at), read_u32(self.data, at + 8) as u64, read_u32(self.data, at + 12) as usize)
+    }
+
+    /// Returns the NUL-terminated name at `offset` into the table.
+    fn name(&self, offset: usize) -> &'a str {
+        let rest = self.data.get(offset..).unwrap_or(&[]);
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        str::from_utf8(&rest[..len]).unwrap_or("?")
+    }
+
+    /// Returns the symbol `addr` falls in, if any.
+    pub fn lookup(&self, addr: u64) -> Option<Symbol<'a>> {
+        // the number of entries starting at or below `addr`
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.entry(mid).0 <= addr {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let (start, size, name) = self.entry(lo.checked_sub(1)?);
+        if addr - start >= size.max(1) {
+            return None;
+        }
+        Some(Symbol { name: self.name(name), addr: start, offset: addr - start })
+    }
+}
+
+/// A frame of a backtrace, printed as its number, address and, if known,
+/// `function+offset`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame<'a> {
+    pub n: usize,
+    pub pc: u64,
+    pub symbol: Option<Symbol<'a>>,
+}
+
+impl<'a> fmt::Display for Frame<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.symbol {
+            Some(symbol) => write!(f, "  #{:<2} {:#010x} {}+{:#x}", self.n, self.pc, symbol.name, symbol.offset),
+            None => write!(f, "  #{:<2} {:#010x}", self.n, self.pc),
+        }
+    }
+}
+
+/// Returns the frames of a backtrace: the one at `pc` if there is one, then
+/// the calls of the frame records `frames(fp, read)` walks, looked up in
+/// `symbols` if there are any.
+pub fn trace<'a, F: FnMut(u64) -> Option<u64>>(
+    symbols: Option<Symbols<'a>>,
+    pc: Option<u64>,
+    fp: u64,
+    read: F,
+) -> impl Iterator<Item = Frame<'a>> {
+    // the call is the instruction before the return address
+    let calls = frames(fp, read).map(|lr| lr.wrapping_sub(4));
+    pc.into_iter().chain(calls).enumerate().map(move |(n, pc)| {
+        Frame { n, pc, symbol: symbols.and_then(|s| s.lookup(pc)) }
+    })
+}
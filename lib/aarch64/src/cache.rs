@@ -85,3 +85,54 @@ pub fn nuke_local_tlb_guest() {
         );
     }
 }
+
+/// Runs `f` with `VTTBR_EL2` temporarily set to `vttbr`, so that TLB
+/// maintenance by VMID applies to the VM it names.
+fn with_vttbr<F: FnOnce()>(vttbr: u64, f: F) {
+    use crate::VTTBR_EL2;
+    unsafe {
+        let old = VTTBR_EL2.get();
+        if old != vttbr {
+            VTTBR_EL2.set(vttbr);
+            asm!("isb" :::: "volatile");
+        }
+        f();
+        if old != vttbr {
+            VTTBR_EL2.set(old);
+            asm!("isb" :::: "volatile");
+        }
+    }
+}
+
+// Flush TLB entries of the VM with VTTBR `vttbr` that translate IPAs in
+// [ipa, ipa + length), one `granule` at a time, on all cores. Stage 2 only
+// entries go by IPA; combined stage 1 & 2 entries can't be looked up by IPA,
+// so all of the VM's stage 1 entries go as well (ref: D5.10.2).
+pub fn invalidate_ipa_range(vttbr: u64, ipa: u64, length: u64, granule: u64) {
+    let start = align_down(ipa as usize, granule as usize) as u64;
+    let end = align_up((ipa + length) as usize, granule as usize) as u64;
+    with_vttbr(vttbr, || unsafe {
+        asm!("dsb ishst" :::: "volatile");
+        for addr in (start..end).step_by(granule as usize) {
+            asm!("tlbi ipas2e1is, $0" :: "r"(addr >> 12) : "memory" : "volatile");
+        }
+        asm!("dsb ish
+            tlbi vmalle1is
+            dsb ish
+            isb"
+            ::: "memory" : "volatile"
+        );
+    });
+}
+
+// Flush TLB, Stage 1 & Stage 2, for the VM with VTTBR `vttbr`, on all cores
+pub fn invalidate_vmid(vttbr: u64) {
+    with_vttbr(vttbr, || unsafe {
+        asm!("dsb ishst
+            tlbi vmalls12e1is
+            dsb ish
+            isb"
+            ::: "memory" : "volatile"
+        );
+    });
+}
@@ -0,0 +1,1212 @@
+//! A disassembler for the A64 instruction set.
+//!
+//! Decodes the base integer data processing, load/store, branch and system
+//! encodings, and the SIMD&FP loads and stores (ref. C4: A64 Instruction Set
+//! Encoding). Anything else is shown as `.inst 0x...`. Aliases are preferred
+//! the way the ARM ARM prefers them, e.g. `mov`, `cmp`, `lsl` and `ret`.
+
+use core::fmt;
+
+/// Returns bits `hi` to `lo`, inclusive, of `insn`.
+fn bits(insn: u32, hi: u32, lo: u32) -> u32 {
+    (insn >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Returns bit `n` of `insn`.
+fn bit(insn: u32, n: u32) -> bool {
+    insn & (1 << n) != 0
+}
+
+/// Sign-extends the `width`-bit `value`.
+fn sext(value: u32, width: u32) -> i64 {
+    let shift = 64 - width;
+    ((value as i64) << shift) >> shift
+}
+
+const CONDITIONS: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc",
+    "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+/// Writes the name of general-purpose register `n`, as a 64-bit register if
+/// `wide`. Register 31 is the stack pointer if `sp`, the zero register if not.
+fn write_reg(f: &mut fmt::Formatter, n: u8, wide: bool, sp: bool) -> fmt::Result {
+    match (n, wide, sp) {
+        (31, true, true) => f.write_str("sp"),
+        (31, false, true) => f.write_str("wsp"),
+        (31, true, false) => f.write_str("xzr"),
+        (31, false, false) => f.write_str("wzr"),
+        (n, true, _) => write!(f, "x{}", n),
+        (n, false, _) => write!(f, "w{}", n),
+    }
+}
+
+/// A general-purpose register operand.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reg {
+    n: u8,
+    wide: bool,
+    sp: bool,
+}
+
+impl Reg {
+    fn x(n: u32) -> Reg { Reg { n: n as u8, wide: true, sp: false } }
+    fn new(n: u32, wide: bool) -> Reg { Reg { n: n as u8, wide, sp: false } }
+    fn or_sp(n: u32, wide: bool) -> Reg { Reg { n: n as u8, wide, sp: true } }
+    fn is_zr(&self) -> bool { self.n == 31 && !self.sp }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_reg(f, self.n, self.wide, self.sp)
+    }
+}
+
+/// A SIMD&FP register operand, named by its size in bytes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct VReg {
+    n: u8,
+    size: u8,
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = match self.size {
+            1 => "b",
+            2 => "h",
+            4 => "s",
+            8 => "d",
+            _ => "q",
+        };
+        write!(f, "{}{}", prefix, self.n)
+    }
+}
+
+/// Decodes the bitmask immediate of a logical instruction (ref. J1:
+/// DecodeBitMasks). Returns `None` for reserved encodings.
+fn decode_bitmask(n: u32, imms: u32, immr: u32, wide: bool) -> Option<u64> {
+    let combined = (n << 6) | (!imms & 0x3f);
+    if combined == 0 || (!wide && n == 1) {
+        return None;
+    }
+    let len = 31 - combined.leading_zeros();
+    if len < 1 {
+        return None;
+    }
+    let size = 1u32 << len;
+    let levels = size - 1;
+    let (s, r) = (imms & levels, immr & levels);
+    if s == levels {
+        return None;
+    }
+    let mask = if size == 64 { !0 } else { (1u64 << size) - 1 };
+    let pattern = (1u64 << (s + 1)) - 1;
+    let mut value = if r == 0 { pattern } else { ((pattern >> r) | (pattern << (size - r))) & mask };
+    let mut width = size;
+    while width < 64 {
+        value |= value << width;
+        width *= 2;
+    }
+    Some(if wide { value } else { value & 0xffff_ffff })
+}
+
+/// Returns whether a `movz` or `movn` could load `imm` into a register of
+/// the given width, in which case an `orr` of it from the zero register isn't
+/// shown as `mov` (ref. MoveWidePreferred).
+fn move_wide_preferred(imm: u64, wide: bool) -> bool {
+    let mask = if wide { !0 } else { 0xffff_ffff };
+    let halfwords = if wide { 4 } else { 2 };
+    let fits = |value: u64| (0..halfwords).any(|hw| value & !(0xffff << (hw * 16)) == 0);
+    fits(imm) || fits(!imm & mask)
+}
+
+/// How the offset register of a load or store is extended.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Extend {
+    Uxtw,
+    Lsl,
+    Sxtw,
+    Sxtx,
+}
+
+/// The offset of a load or store from its base register.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Offset {
+    /// A byte offset.
+    Imm(i64),
+    /// Register `rm`, extended, then shifted left by `shift`.
+    Reg { rm: u8, extend: Extend, shift: u8 },
+}
+
+/// When the base register of a load or store is updated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Index {
+    /// Never; the access is at base plus offset.
+    Offset,
+    /// To base plus offset, before the access at the new base.
+    Pre,
+    /// To base plus offset, after the access at the old base.
+    Post,
+}
+
+/// The family of a load or store instruction.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Form {
+    /// `ldr`/`str` and their sized variants.
+    Single,
+    /// `ldur`/`stur`: an unscaled, signed immediate offset.
+    Unscaled,
+    /// `ldtr`/`sttr`: the access is checked as if made from EL0.
+    Unprivileged,
+    /// `ldr` of a PC-relative literal.
+    Literal,
+    /// `ldp`/`stp`.
+    Pair,
+    /// `ldnp`/`stnp`: a pair with a non-temporal hint.
+    NonTemporalPair,
+    /// `ldxr`/`stxr`, or `ldaxr`/`stlxr` if `ordered`. Stores write their
+    /// status to register `status`.
+    Exclusive { ordered: bool, status: u8 },
+    /// `ldar`/`stlr`.
+    Ordered,
+}
+
+/// A decoded load or store of one or two registers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoadStore {
+    pub form: Form,
+    pub load: bool,
+    /// Size of each register's access in bytes.
+    pub size: u8,
+    /// Whether loaded values are sign-extended.
+    pub signed: bool,
+    /// Whether the general-purpose register transferred is 64 bits wide.
+    pub wide: bool,
+    /// Whether the registers are SIMD&FP registers.
+    pub simd: bool,
+    pub rt: u8,
+    /// The second register of a pair.
+    pub rt2: Option<u8>,
+    /// The base register, 31 being the stack pointer, or `None` for a
+    /// PC-relative literal.
+    pub rn: Option<u8>,
+    pub offset: Offset,
+    pub index: Index,
+}
+
+impl LoadStore {
+    /// Returns the address accessed, given the value of the instruction's
+    /// base register, or its own address for a literal, and of its offset
+    /// register.
+    pub fn address(&self, base: u64, rm: u64) -> u64 {
+        let offset = match self.offset {
+            Offset::Imm(imm) => imm as u64,
+            Offset::Reg { extend, shift, .. } => {
+                let rm = match extend {
+                    Extend::Uxtw => rm as u32 as u64,
+                    Extend::Sxtw => rm as u32 as i32 as i64 as u64,
+                    Extend::Lsl | Extend::Sxtx => rm,
+                };
+                rm << shift
+            },
+        };
+        match self.index {
+            Index::Post => base,
+            Index::Offset | Index::Pre => base.wrapping_add(offset),
+        }
+    }
+
+    fn mnemonic(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suffix = match (self.simd, self.size, self.signed) {
+            (true, _, _) => "",
+            (false, 1, false) => "b",
+            (false, 2, false) => "h",
+            (false, 1, true) => "sb",
+            (false, 2, true) => "sh",
+            (false, 4, true) => "sw",
+            _ => "",
+        };
+        let op = if self.load { "ld" } else { "st" };
+        match self.form {
+            Form::Single | Form::Literal => write!(f, "{}r{}", op, suffix),
+            Form::Unscaled => write!(f, "{}ur{}", op, suffix),
+            Form::Unprivileged => write!(f, "{}tr{}", op, suffix),
+            Form::Pair => write!(f, "{}p{}", op, if self.signed { "sw" } else { "" }),
+            Form::NonTemporalPair => write!(f, "{}np", op),
+            Form::Exclusive { ordered, .. } => {
+                let order = match (ordered, self.load) {
+                    (false, _) => "",
+                    (true, true) => "a",
+                    (true, false) => "l",
+                };
+                let count = if self.rt2.is_some() { "p" } else { "r" };
+                write!(f, "{}{}x{}{}", op, order, count, suffix)
+            },
+            Form::Ordered => write!(f, "{}{}r{}", op, if self.load { "a" } else { "l" }, suffix),
+        }
+    }
+
+    fn write_rt(&self, f: &mut fmt::Formatter, n: u8) -> fmt::Result {
+        if self.simd {
+            write!(f, "{}", VReg { n, size: self.size })
+        } else {
+            write_reg(f, n, self.wide, false)
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, pc: u64) -> fmt::Result {
+        self.mnemonic(f)?;
+        f.write_str(" ")?;
+        if let Form::Exclusive { status, .. } = self.form {
+            if !self.load {
+                write!(f, "{}, ", Reg::new(status as u32, false))?;
+            }
+        }
+        self.write_rt(f, self.rt)?;
+        if let Some(rt2) = self.rt2 {
+            f.write_str(", ")?;
+            self.write_rt(f, rt2)?;
+        }
+        let rn = match self.rn {
+            Some(rn) => Reg::or_sp(rn as u32, true),
+            None => {
+                let imm = match self.offset { Offset::Imm(imm) => imm, _ => 0 };
+                return write!(f, ", {:#x}", pc.wrapping_add(imm as u64));
+            },
+        };
+        match (self.offset, self.index) {
+            (Offset::Imm(0), Index::Offset) => write!(f, ", [{}]", rn),
+            (Offset::Imm(imm), Index::Offset) => write!(f, ", [{}, #{}]", rn, imm),
+            (Offset::Imm(imm), Index::Pre) => write!(f, ", [{}, #{}]!", rn, imm),
+            (Offset::Imm(imm), Index::Post) => write!(f, ", [{}], #{}", rn, imm),
+            (Offset::Reg { rm, extend, shift }, _) => {
+                write!(f, ", ")?;
+                write_reg_offset(f, rn, rm, extend, shift)
+            },
+        }
+    }
+}
+
+/// Writes the address `[rn, rm{, extend #shift}]` of a register offset.
+fn write_reg_offset(
+    f: &mut fmt::Formatter,
+    rn: Reg,
+    rm: u8,
+    extend: Extend,
+    shift: u8,
+) -> fmt::Result {
+    let wide = extend == Extend::Lsl || extend == Extend::Sxtx;
+    write!(f, "[{}, {}", rn, Reg::new(rm as u32, wide))?;
+    let name = match extend {
+        Extend::Uxtw => "uxtw",
+        Extend::Lsl => "lsl",
+        Extend::Sxtw => "sxtw",
+        Extend::Sxtx => "sxtx",
+    };
+    match (extend, shift) {
+        (Extend::Lsl, 0) => f.write_str("]"),
+        (_, 0) => write!(f, ", {}]", name),
+        (_, shift) => write!(f, ", {} #{}]", name, shift),
+    }
+}
+
+/// Decodes the extend option of a register offset.
+fn decode_extend(option: u32) -> Option<Extend> {
+    match option {
+        0b010 => Some(Extend::Uxtw),
+        0b011 => Some(Extend::Lsl),
+        0b110 => Some(Extend::Sxtw),
+        0b111 => Some(Extend::Sxtx),
+        _ => None,
+    }
+}
+
+/// Decodes `insn` if it is a load or store of general-purpose or SIMD&FP
+/// registers, other than the SIMD structure loads and stores.
+pub fn decode_load_store(insn: u32) -> Option<LoadStore> {
+    let size = bits(insn, 31, 30);
+    let simd = bit(insn, 26);
+    let rt = bits(insn, 4, 0) as u8;
+    let rn = Some(bits(insn, 9, 5) as u8);
+
+    if bits(insn, 29, 24) == 0b001000 && !simd {
+        // load/store exclusive and ordered (ref. C4.1.4)
+        let (o2, load, o1, o0) = (bit(insn, 23), bit(insn, 22), bit(insn, 21), bit(insn, 15));
+        let form = match (o2, o1, o0) {
+            (false, _, ordered) => Form::Exclusive { ordered, status: bits(insn, 20, 16) as u8 },
+            (true, false, true) => Form::Ordered,
+            _ => return None,
+        };
+        if o1 && size < 2 {
+            return None;
+        }
+        return Some(LoadStore {
+            form,
+            load,
+            size: (1 << size) as u8,
+            signed: false,
+            wide: size == 3,
+            simd: false,
+            rt,
+            rt2: if o1 { Some(bits(insn, 14, 10) as u8) } else { None },
+            rn,
+            offset: Offset::Imm(0),
+            index: Index::Offset,
+        });
+    }
+
+    if bits(insn, 29, 27) == 0b011 && bits(insn, 25, 24) == 0 {
+        // load register (literal)
+        let (size, signed) = match (simd, size) {
+            (false, 0b00) => (4, false),
+            (false, 0b01) => (8, false),
+            (false, 0b10) => (4, true),
+            (true, 0b00) => (4, false),
+            (true, 0b01) => (8, false),
+            (true, 0b10) => (16, false),
+            _ => return None, // prfm, or unallocated
+        };
+        return Some(LoadStore {
+            form: Form::Literal,
+            load: true,
+            size,
+            signed,
+            wide: size == 8 || signed,
+            simd,
+            rt,
+            rt2: None,
+            rn: None,
+            offset: Offset::Imm(sext(bits(insn, 23, 5), 19) << 2),
+            index: Index::Offset,
+        });
+    }
+
+    if bits(insn, 29, 27) == 0b101 {
+        // load/store register pair
+        let load = bit(insn, 22);
+        let (form, index) = match bits(insn, 24, 23) {
+            0b00 => (Form::NonTemporalPair, Index::Offset),
+            0b01 => (Form::Pair, Index::Post),
+            0b10 => (Form::Pair, Index::Offset),
+            _ => (Form::Pair, Index::Pre),
+        };
+        let (bytes, signed) = match (simd, size) {
+            (false, 0b00) => (4, false),
+            (false, 0b01) if load && form == Form::Pair => (4, true),
+            (false, 0b10) => (8, false),
+            (true, 0b00) => (4, false),
+            (true, 0b01) => (8, false),
+            (true, 0b10) => (16, false),
+            _ => return None,
+        };
+        return Some(LoadStore {
+            form,
+            load,
+            size: bytes,
+            signed,
+            wide: bytes == 8 || signed,
+            simd,
+            rt,
+            rt2: Some(bits(insn, 14, 10) as u8),
+            rn,
+            offset: Offset::Imm(sext(bits(insn, 21, 15), 7) * bytes as i64),
+            index,
+        });
+    }
+
+    if bits(insn, 29, 27) != 0b111 {
+        return None;
+    }
+
+    // load/store register: the size, and whether and how it loads
+    let opc = bits(insn, 23, 22);
+    let (bytes, load, signed, wide) = if simd {
+        match (size, opc) {
+            (0b00, 0b10) | (0b00, 0b11) => (16, opc == 0b11, false, false),
+            (_, 0b00) | (_, 0b01) => (1 << size, opc == 0b01, false, false),
+            _ => return None,
+        }
+    } else {
+        match (size, opc) {
+            (_, 0b00) => (1 << size, false, false, size == 3),
+            (_, 0b01) => (1 << size, true, false, size == 3),
+            (0b11, 0b10) => return None, // prfm
+            (0b10, 0b10) => (4, true, true, true),
+            (0b11, 0b11) | (0b10, 0b11) => return None,
+            (_, 0b10) => (1 << size, true, true, true),
+            (_, _) => (1 << size, true, true, false),
+        }
+    };
+
+    let (form, offset, index) = if bit(insn, 24) {
+        (Form::Single, Offset::Imm(bits(insn, 21, 10) as i64 * bytes as i64), Index::Offset)
+    } else if !bit(insn, 21) {
+        let imm = Offset::Imm(sext(bits(insn, 20, 12), 9));
+        match bits(insn, 11, 10) {
+            0b00 => (Form::Unscaled, imm, Index::Offset),
+            0b01 => (Form::Single, imm, Index::Post),
+            0b10 if !simd => (Form::Unprivileged, imm, Index::Offset),
+            0b11 => (Form::Single, imm, Index::Pre),
+            _ => return None,
+        }
+    } else if bits(insn, 11, 10) == 0b10 {
+        let extend = decode_extend(bits(insn, 15, 13))?;
+        let shift = if bit(insn, 12) { (bytes as u8).trailing_zeros() as u8 } else { 0 };
+        (Form::Single, Offset::Reg { rm: bits(insn, 20, 16) as u8, extend, shift }, Index::Offset)
+    } else {
+        return None; // atomic memory operations
+    };
+
+    Some(LoadStore {
+        form,
+        load,
+        size: bytes as u8,
+        signed,
+        wide,
+        simd,
+        rt,
+        rt2: None,
+        rn,
+        offset,
+        index,
+    })
+}
+
+/// An instruction at a known address, displayed as its disassembly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Instruction {
+    pub insn: u32,
+    pub pc: u64,
+}
+
+/// Returns the instruction `insn` at address `pc`, which is displayed as its
+/// disassembly. `pc` resolves PC-relative operands to absolute addresses.
+pub fn disassemble(insn: u32, pc: u64) -> Instruction {
+    Instruction { insn, pc }
+}
+
+impl Instruction {
+    /// Data processing, immediate (ref. C4.1.2).
+    fn data_imm(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let wide = bit(i, 31);
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        Some(match bits(i, 25, 23) {
+            0b000 | 0b001 => {
+                let imm = sext((bits(i, 23, 5) << 2) | bits(i, 30, 29), 21);
+                if bit(i, 31) {
+                    let target = (self.pc & !0xfff).wrapping_add((imm << 12) as u64);
+                    write!(f, "adrp {}, {:#x}", Reg::x(rd), target)
+                } else {
+                    write!(f, "adr {}, {:#x}", Reg::x(rd), self.pc.wrapping_add(imm as u64))
+                }
+            },
+            0b010 => {
+                let (sub, flags) = (bit(i, 30), bit(i, 29));
+                let imm = bits(i, 21, 10);
+                let shift = if bit(i, 22) { ", lsl #12" } else { "" };
+                let rn = Reg::or_sp(rn, wide);
+                let rd = if flags { Reg::new(rd, wide) } else { Reg::or_sp(rd, wide) };
+                if !flags && !bit(i, 22) && imm == 0 && !sub && (rd.n == 31 || rn.n == 31) {
+                    write!(f, "mov {}, {}", rd, rn)
+                } else if flags && rd.is_zr() {
+                    write!(f, "{} {}, #{}{}", if sub { "cmp" } else { "cmn" }, rn, imm, shift)
+                } else {
+                    let op = match (sub, flags) {
+                        (false, false) => "add",
+                        (false, true) => "adds",
+                        (true, false) => "sub",
+                        (true, true) => "subs",
+                    };
+                    write!(f, "{} {}, {}, #{}{}", op, rd, rn, imm, shift)
+                }
+            },
+            0b100 => {
+                let imm = decode_bitmask(bits(i, 22, 22), bits(i, 15, 10), bits(i, 21, 16), wide)?;
+                let opc = bits(i, 30, 29);
+                let rd = if opc == 0b11 { Reg::new(rd, wide) } else { Reg::or_sp(rd, wide) };
+                let rn = Reg::new(rn, wide);
+                match opc {
+                    0b01 if rn.is_zr() && !move_wide_preferred(imm, wide) => {
+                        write!(f, "mov {}, #{:#x}", rd, imm)
+                    },
+                    0b11 if rd.is_zr() => write!(f, "tst {}, #{:#x}", rn, imm),
+                    _ => {
+                        let op = ["and", "orr", "eor", "ands"][opc as usize];
+                        write!(f, "{} {}, {}, #{:#x}", op, rd, rn, imm)
+                    },
+                }
+            },
+            0b101 => {
+                let hw = bits(i, 22, 21);
+                if !wide && hw > 1 {
+                    return None;
+                }
+                let imm = bits(i, 20, 5) as u64;
+                let rd = Reg::new(rd, wide);
+                match bits(i, 30, 29) {
+                    0b00 => {
+                        let value = !(imm << (hw * 16));
+                        let value = if wide { value } else { value & 0xffff_ffff };
+                        write!(f, "mov {}, #{:#x}", rd, value)
+                    },
+                    0b10 => write!(f, "mov {}, #{:#x}", rd, imm << (hw * 16)),
+                    0b11 if hw == 0 => write!(f, "movk {}, #{:#x}", rd, imm),
+                    0b11 => write!(f, "movk {}, #{:#x}, lsl #{}", rd, imm, hw * 16),
+                    _ => return None,
+                }
+            },
+            0b110 => {
+                let size = if wide { 64 } else { 32 };
+                let (immr, imms) = (bits(i, 21, 16), bits(i, 15, 10));
+                if bit(i, 22) != wide || immr >= size || imms >= size {
+                    return None;
+                }
+                let (rd, rn) = (Reg::new(rd, wide), Reg::new(rn, wide));
+                match bits(i, 30, 29) {
+                    0b00 => {
+                        if imms == size - 1 {
+                            write!(f, "asr {}, {}, #{}", rd, rn, immr)
+                        } else if immr == 0 && (imms == 7 || imms == 15 || imms == 31) {
+                            let op = match imms { 7 => "sxtb", 15 => "sxth", _ => "sxtw" };
+                            write!(f, "{} {}, {}", op, rd, Reg::new(rn.n as u32, false))
+                        } else if imms < immr {
+                            write!(f, "sbfiz {}, {}, #{}, #{}", rd, rn, size - immr, imms + 1)
+                        } else {
+                            write!(f, "sbfx {}, {}, #{}, #{}", rd, rn, immr, imms - immr + 1)
+                        }
+                    },
+                    0b01 => {
+                        if imms < immr {
+                            write!(f, "bfi {}, {}, #{}, #{}", rd, rn, size - immr, imms + 1)
+                        } else {
+                            write!(f, "bfxil {}, {}, #{}, #{}", rd, rn, immr, imms - immr + 1)
+                        }
+                    },
+                    0b10 => {
+                        if imms != size - 1 && imms + 1 == immr {
+                            write!(f, "lsl {}, {}, #{}", rd, rn, size - 1 - imms)
+                        } else if imms == size - 1 {
+                            write!(f, "lsr {}, {}, #{}", rd, rn, immr)
+                        } else if !wide && immr == 0 && (imms == 7 || imms == 15) {
+                            write!(f, "{} {}, {}", if imms == 7 { "uxtb" } else { "uxth" }, rd, rn)
+                        } else if imms < immr {
+                            write!(f, "ubfiz {}, {}, #{}, #{}", rd, rn, size - immr, imms + 1)
+                        } else {
+                            write!(f, "ubfx {}, {}, #{}, #{}", rd, rn, immr, imms - immr + 1)
+                        }
+                    },
+                    _ => return None,
+                }
+            },
+            0b111 => {
+                // extract
+                let lsb = bits(i, 15, 10);
+                if bits(i, 30, 29) != 0 || bit(i, 22) != wide || bit(i, 21) || (!wide && lsb > 31) {
+                    return None;
+                }
+                let (rd, rn, rm) = (Reg::new(rd, wide), Reg::new(rn, wide), Reg::new(bits(i, 20, 16), wide));
+                if rn == rm {
+                    write!(f, "ror {}, {}, #{}", rd, rn, lsb)
+                } else {
+                    write!(f, "extr {}, {}, {}, #{}", rd, rn, rm, lsb)
+                }
+            },
+            _ => return None,
+        })
+    }
+
+    /// Branches, exception generating and system instructions (ref. C4.1.3).
+    fn branch_system(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let rt = bits(i, 4, 0);
+        let target = |offset: i64| self.pc.wrapping_add((offset << 2) as u64);
+        if bits(i, 30, 26) == 0b00101 {
+            let op = if bit(i, 31) { "bl" } else { "b" };
+            return Some(write!(f, "{} {:#x}", op, target(sext(bits(i, 25, 0), 26))));
+        }
+        if bits(i, 31, 25) == 0b0101010 {
+            if bit(i, 24) || bit(i, 4) {
+                return None;
+            }
+            let cond = CONDITIONS[bits(i, 3, 0) as usize];
+            return Some(write!(f, "b.{} {:#x}", cond, target(sext(bits(i, 23, 5), 19))));
+        }
+        if bits(i, 30, 25) == 0b011010 {
+            let op = if bit(i, 24) { "cbnz" } else { "cbz" };
+            let rt = Reg::new(rt, bit(i, 31));
+            return Some(write!(f, "{} {}, {:#x}", op, rt, target(sext(bits(i, 23, 5), 19))));
+        }
+        if bits(i, 30, 25) == 0b011011 {
+            let op = if bit(i, 24) { "tbnz" } else { "tbz" };
+            let bit_no = (bits(i, 31, 31) << 5) | bits(i, 23, 19);
+            let rt = Reg::new(rt, bit(i, 31));
+            return Some(write!(f, "{} {}, #{}, {:#x}", op, rt, bit_no, target(sext(bits(i, 18, 5), 14))));
+        }
+        if bits(i, 31, 24) == 0b11010100 {
+            let imm = bits(i, 20, 5);
+            let op = match (bits(i, 23, 21), bits(i, 4, 0)) {
+                (0b000, 0b00001) => "svc",
+                (0b000, 0b00010) => "hvc",
+                (0b000, 0b00011) => "smc",
+                (0b001, 0b00000) => "brk",
+                (0b010, 0b00000) => "hlt",
+                _ => return None,
+            };
+            return Some(write!(f, "{} #{:#x}", op, imm));
+        }
+        if bits(i, 31, 25) == 0b1101011 {
+            if bits(i, 20, 10) != 0b11111_000000 || bits(i, 4, 0) != 0 {
+                return None;
+            }
+            let rn = Reg::x(bits(i, 9, 5));
+            return Some(match bits(i, 24, 21) {
+                0b0000 => write!(f, "br {}", rn),
+                0b0001 => write!(f, "blr {}", rn),
+                0b0010 if rn.n == 30 => f.write_str("ret"),
+                0b0010 => write!(f, "ret {}", rn),
+                0b0100 if rn.n == 31 => f.write_str("eret"),
+                0b0101 if rn.n == 31 => f.write_str("drps"),
+                _ => return None,
+            });
+        }
+        if bits(i, 31, 22) == 0b1101010100 {
+            return self.system(f);
+        }
+        None
+    }
+
+    /// System instructions: hints, barriers, `msr` to PSTATE fields, cache
+    /// and TLB maintenance, and system register moves (ref. C5.2, C5.3).
+    fn system(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let load = bit(i, 21);
+        let op0 = bits(i, 20, 19);
+        let op1 = bits(i, 18, 16);
+        let crn = bits(i, 15, 12);
+        let crm = bits(i, 11, 8);
+        let op2 = bits(i, 7, 5);
+        let rt = bits(i, 4, 0);
+        Some(match (load, op0) {
+            (false, 0b00) => {
+                if crn == 0b0010 && op1 == 0b011 && rt == 31 {
+                    match (crm << 3) | op2 {
+                        0 => f.write_str("nop"),
+                        1 => f.write_str("yield"),
+                        2 => f.write_str("wfe"),
+                        3 => f.write_str("wfi"),
+                        4 => f.write_str("sev"),
+                        5 => f.write_str("sevl"),
+                        hint => write!(f, "hint #{:#x}", hint),
+                    }
+                } else if crn == 0b0011 && op1 == 0b011 && rt == 31 {
+                    let option = match crm {
+                        0b1111 => "sy",
+                        0b1110 => "st",
+                        0b1101 => "ld",
+                        0b1011 => "ish",
+                        0b1010 => "ishst",
+                        0b1001 => "ishld",
+                        0b0111 => "nsh",
+                        0b0110 => "nshst",
+                        0b0101 => "nshld",
+                        0b0011 => "osh",
+                        0b0010 => "oshst",
+                        0b0001 => "oshld",
+                        _ => "",
+                    };
+                    match (op2, option) {
+                        (0b010, _) => f.write_str("clrex"),
+                        (0b100, "") => write!(f, "dsb #{}", crm),
+                        (0b100, option) => write!(f, "dsb {}", option),
+                        (0b101, "") => write!(f, "dmb #{}", crm),
+                        (0b101, option) => write!(f, "dmb {}", option),
+                        (0b110, "sy") => f.write_str("isb"),
+                        (0b110, _) => write!(f, "isb #{}", crm),
+                        _ => return None,
+                    }
+                } else if crn == 0b0100 && rt == 31 {
+                    let field = match (op1, op2) {
+                        (0b000, 0b101) => "spsel",
+                        (0b011, 0b110) => "daifset",
+                        (0b011, 0b111) => "daifclr",
+                        _ => return None,
+                    };
+                    write!(f, "msr {}, #{:#x}", field, crm)
+                } else {
+                    return None;
+                }
+            },
+            (true, 0b00) => return None,
+            (_, 0b01) => {
+                let name = match (op1, crn, crm, op2) {
+                    (0, 7, 1, 0) => "ic ialluis",
+                    (0, 7, 5, 0) => "ic iallu",
+                    (3, 7, 5, 1) => "ic ivau",
+                    (0, 7, 6, 1) => "dc ivac",
+                    (0, 7, 6, 2) => "dc isw",
+                    (0, 7, 10, 2) => "dc csw",
+                    (0, 7, 14, 2) => "dc cisw",
+                    (3, 7, 4, 1) => "dc zva",
+                    (3, 7, 10, 1) => "dc cvac",
+                    (3, 7, 11, 1) => "dc cvau",
+                    (3, 7, 14, 1) => "dc civac",
+                    (0, 7, 8, 0) => "at s1e1r",
+                    (0, 7, 8, 1) => "at s1e1w",
+                    (4, 7, 8, 4) => "at s12e1r",
+                    (4, 7, 8, 5) => "at s12e1w",
+                    (0, 8, 3, 0) => "tlbi vmalle1is",
+                    (0, 8, 3, 1) => "tlbi vae1is",
+                    (0, 8, 7, 0) => "tlbi vmalle1",
+                    (0, 8, 7, 1) => "tlbi vae1",
+                    (4, 8, 3, 0) => "tlbi alle2is",
+                    (4, 8, 3, 4) => "tlbi alle1is",
+                    (4, 8, 3, 6) => "tlbi vmalls12e1is",
+                    (4, 8, 7, 0) => "tlbi alle2",
+                    (4, 8, 7, 4) => "tlbi alle1",
+                    (4, 8, 7, 6) => "tlbi vmalls12e1",
+                    _ => "",
+                };
+                match (load, name) {
+                    (true, _) => write!(f, "sysl {}, #{}, c{}, c{}, #{}", Reg::x(rt), op1, crn, crm, op2),
+                    (false, "") => write!(f, "sys #{}, c{}, c{}, #{}, {}", op1, crn, crm, op2, Reg::x(rt)),
+                    (false, name) if rt == 31 && (name.starts_with("ic iall") || name.starts_with("tlbi v") && !name.contains("va")
+                                                  || name.starts_with("tlbi all")) => {
+                        f.write_str(name)
+                    },
+                    (false, name) => write!(f, "{}, {}", name, Reg::x(rt)),
+                }
+            },
+            (_, op0) => {
+                let reg = SysReg { op0, op1, crn, crm, op2 };
+                if load {
+                    write!(f, "mrs {}, {}", Reg::x(rt), reg)
+                } else {
+                    write!(f, "msr {}, {}", reg, Reg::x(rt))
+                }
+            },
+        })
+    }
+
+    /// Loads and stores (ref. C4.1.4).
+    fn load_store(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        if let Some(access) = decode_load_store(i) {
+            return Some(access.write(f, self.pc));
+        }
+        if bits(i, 31, 31) == 0 && bits(i, 29, 24) == 0b001100 && !bit(i, 21) {
+            return self.simd_structures(f);
+        }
+        if bits(i, 31, 31) == 0 && bits(i, 29, 24) == 0b001101 {
+            return self.simd_single(f);
+        }
+        // prefetches
+        let rn = Reg::or_sp(bits(i, 9, 5), true);
+        let op = bits(i, 4, 0);
+        if bits(i, 31, 22) == 0b11_111_0_01_10 {
+            return Some(match bits(i, 21, 10) * 8 {
+                0 => write!(f, "prfm #{}, [{}]", op, rn),
+                offset => write!(f, "prfm #{}, [{}, #{}]", op, rn, offset),
+            });
+        }
+        if bits(i, 31, 21) == 0b11_111_0_00_10_0 && bits(i, 11, 10) == 0b00 {
+            return Some(match sext(bits(i, 20, 12), 9) {
+                0 => write!(f, "prfum #{}, [{}]", op, rn),
+                offset => write!(f, "prfum #{}, [{}, #{}]", op, rn, offset),
+            });
+        }
+        if bits(i, 31, 21) == 0b11_111_0_00_10_1 && bits(i, 11, 10) == 0b10 {
+            let extend = decode_extend(bits(i, 15, 13))?;
+            let shift = if bit(i, 12) { 3 } else { 0 };
+            return Some(write!(f, "prfm #{}, ", op)
+                .and_then(|_| write_reg_offset(f, rn, bits(i, 20, 16) as u8, extend, shift)));
+        }
+        if bits(i, 31, 24) == 0b11_011_0_00 {
+            let target = self.pc.wrapping_add((sext(bits(i, 23, 5), 19) << 2) as u64);
+            return Some(write!(f, "prfm #{}, {:#x}", op, target));
+        }
+        None
+    }
+
+    /// SIMD load/store multiple structures (ref. C4.1.4).
+    fn simd_structures(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let q = bit(i, 30);
+        let load = bit(i, 22);
+        let post = bit(i, 23);
+        let (n, count) = match bits(i, 15, 12) {
+            0b0000 => (4, 4),
+            0b0010 => (1, 4),
+            0b0100 => (3, 3),
+            0b0110 => (1, 3),
+            0b0111 => (1, 1),
+            0b1000 => (2, 2),
+            0b1010 => (1, 2),
+            _ => return None,
+        };
+        let size = bits(i, 11, 10);
+        if size == 0b11 && !q && n != 1 {
+            return None;
+        }
+        if !post && bits(i, 20, 16) != 0 {
+            return None;
+        }
+        let arrangement = ["8b", "16b", "4h", "8h", "2s", "4s", "1d", "2d"][(size * 2 + q as u32) as usize];
+        let rt = bits(i, 4, 0);
+        let rn = Reg::or_sp(bits(i, 9, 5), true);
+        Some((|| {
+            write!(f, "{}{} {{", if load { "ld" } else { "st" }, n)?;
+            for r in 0..count {
+                if r > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "v{}.{}", (rt + r) % 32, arrangement)?;
+            }
+            write!(f, "}}, [{}]", rn)?;
+            match (post, bits(i, 20, 16)) {
+                (false, _) => Ok(()),
+                (true, 31) => write!(f, ", #{}", count * if q { 16 } else { 8 }),
+                (true, rm) => write!(f, ", {}", Reg::x(rm)),
+            }
+        })())
+    }
+
+    /// SIMD load/store single structure, to or from one lane, or loaded and
+    /// replicated to all lanes (ref. C4.1.4).
+    fn simd_single(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let q = bit(i, 30) as u32;
+        let load = bit(i, 22);
+        let post = bit(i, 23);
+        let opcode = bits(i, 15, 13);
+        let (s, size) = (bits(i, 12, 12), bits(i, 11, 10));
+        let n = ((opcode & 1) << 1 | bit(i, 21) as u32) + 1;
+        if !post && bits(i, 20, 16) != 0 {
+            return None;
+        }
+        // the element, or arrangement when replicating, its size in bytes and
+        // the lane index
+        let (element, bytes, lane) = match opcode >> 1 {
+            0b00 => ("b", 1, Some(q << 3 | s << 2 | size)),
+            0b01 if size & 1 == 0 => ("h", 2, Some(q << 2 | s << 1 | size >> 1)),
+            0b10 if size == 0b00 => ("s", 4, Some(q << 1 | s)),
+            0b10 if size == 0b01 && s == 0 => ("d", 8, Some(q)),
+            0b11 if load && s == 0 => {
+                let arrangement = ["8b", "16b", "4h", "8h", "2s", "4s", "1d", "2d"][(size * 2 + q) as usize];
+                (arrangement, 1 << size, None)
+            },
+            _ => return None,
+        };
+        let rt = bits(i, 4, 0);
+        let rn = Reg::or_sp(bits(i, 9, 5), true);
+        Some((|| {
+            let op = if load { "ld" } else { "st" };
+            write!(f, "{}{}{} {{", op, n, if lane.is_none() { "r" } else { "" })?;
+            for r in 0..n {
+                if r > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "v{}.{}", (rt + r) % 32, element)?;
+            }
+            f.write_str("}")?;
+            if let Some(lane) = lane {
+                write!(f, "[{}]", lane)?;
+            }
+            write!(f, ", [{}]", rn)?;
+            match (post, bits(i, 20, 16)) {
+                (false, _) => Ok(()),
+                (true, 31) => write!(f, ", #{}", n * bytes),
+                (true, rm) => write!(f, ", {}", Reg::x(rm)),
+            }
+        })())
+    }
+
+    /// Data processing, register (ref. C4.1.5).
+    fn data_reg(&self, f: &mut fmt::Formatter) -> Option<fmt::Result> {
+        let i = self.insn;
+        let wide = bit(i, 31);
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        let rm = bits(i, 20, 16);
+        let (d, n, m) = (Reg::new(rd, wide), Reg::new(rn, wide), Reg::new(rm, wide));
+        let shift = bits(i, 23, 22);
+        let amount = bits(i, 15, 10);
+        if !wide && bit(i, 15) && !bit(i, 28) && !(bit(i, 24) && bit(i, 21)) {
+            return None; // 32-bit shift amount out of range
+        }
+        let shifted = |f: &mut fmt::Formatter| -> fmt::Result {
+            if amount != 0 {
+                write!(f, ", {} #{}", ["lsl", "lsr", "asr", "ror"][shift as usize], amount)?;
+            }
+            Ok(())
+        };
+
+        if !bit(i, 28) && !bit(i, 24) {
+            // logical, shifted register
+            let op = bits(i, 30, 29) * 2 + bit(i, 21) as u32;
+            let name = ["and", "bic", "orr", "orn", "eor", "eon", "ands", "bics"][op as usize];
+            return Some((|| {
+                match op {
+                    2 if n.is_zr() && amount == 0 => return write!(f, "mov {}, {}", d, m),
+                    3 if n.is_zr() => write!(f, "mvn {}, {}", d, m)?,
+                    6 if d.is_zr() => write!(f, "tst {}, {}", n, m)?,
+                    _ => write!(f, "{} {}, {}, {}", name, d, n, m)?,
+                }
+                shifted(f)
+            })());
+        }
+
+        if !bit(i, 28) {
+            let (sub, flags) = (bit(i, 30), bit(i, 29));
+            let name = match (sub, flags) {
+                (false, false) => "add",
+                (false, true) => "adds",
+                (true, false) => "sub",
+                (true, true) => "subs",
+            };
+            if !bit(i, 21) {
+                // add/subtract, shifted register
+                if shift == 0b11 {
+                    return None;
+                }
+                return Some((|| {
+                    if flags && d.is_zr() {
+                        write!(f, "{} {}, {}", if sub { "cmp" } else { "cmn" }, n, m)?;
+                    } else if sub && n.is_zr() {
+                        write!(f, "{} {}, {}", if flags { "negs" } else { "neg" }, d, m)?;
+                    } else {
+                        write!(f, "{} {}, {}, {}", name, d, n, m)?;
+                    }
+                    shifted(f)
+                })());
+            }
+            // add/subtract, extended register
+            let option = bits(i, 15, 13);
+            let imm = bits(i, 12, 10);
+            if shift != 0 || imm > 4 {
+                return None;
+            }
+            let d = if flags { d } else { Reg::or_sp(rd, wide) };
+            let n = Reg::or_sp(rn, wide);
+            let m = Reg::new(rm, wide && option & 0b011 == 0b011);
+            let lsl = ((d.n == 31 && d.sp) || n.n == 31) && option == if wide { 0b011 } else { 0b010 };
+            let extend = ["uxtb", "uxth", "uxtw", "uxtx", "sxtb", "sxth", "sxtw", "sxtx"][option as usize];
+            return Some((|| {
+                if flags && d.is_zr() {
+                    write!(f, "{} {}, {}", if sub { "cmp" } else { "cmn" }, n, m)?;
+                } else {
+                    write!(f, "{} {}, {}, {}", name, d, n, m)?;
+                }
+                match (lsl, imm) {
+                    (true, 0) => Ok(()),
+                    (true, imm) => write!(f, ", lsl #{}", imm),
+                    (false, 0) => write!(f, ", {}", extend),
+                    (false, imm) => write!(f, ", {} #{}", extend, imm),
+                }
+            })());
+        }
+
+        if bit(i, 24) {
+            // data processing, 3 source
+            if bits(i, 30, 29) != 0 {
+                return None;
+            }
+            let ra = Reg::new(bits(i, 14, 10), true);
+            let sub = bit(i, 15);
+            let (name, alias, long) = match (bits(i, 23, 21), sub) {
+                (0b000, false) => ("madd", "mul", false),
+                (0b000, true) => ("msub", "mneg", false),
+                (0b001, false) if wide => ("smaddl", "smull", true),
+                (0b001, true) if wide => ("smsubl", "smnegl", true),
+                (0b010, false) if wide => ("smulh", "smulh", true),
+                (0b101, false) if wide => ("umaddl", "umull", true),
+                (0b101, true) if wide => ("umsubl", "umnegl", true),
+                (0b110, false) if wide => ("umulh", "umulh", true),
+                _ => return None,
+            };
+            let high = name.ends_with('h');
+            let (n, m) = if long && !high {
+                (Reg::new(rn, false), Reg::new(rm, false))
+            } else {
+                (n, m)
+            };
+            return Some(if high {
+                write!(f, "{} {}, {}, {}", name, d, n, m)
+            } else if ra.is_zr() {
+                write!(f, "{} {}, {}, {}", alias, d, n, m)
+            } else {
+                write!(f, "{} {}, {}, {}, {}", name, d, n, m, Reg::new(ra.n as u32, wide))
+            });
+        }
+
+        let cond = bits(i, 15, 12);
+        Some(match bits(i, 24, 21) {
+            0b0000 => {
+                if amount != 0 {
+                    return None;
+                }
+                let name = ["adc", "adcs", "sbc", "sbcs"][bits(i, 30, 29) as usize];
+                write!(f, "{} {}, {}, {}", name, d, n, m)
+            },
+            0b0010 => {
+                if !bit(i, 29) || bit(i, 10) || bit(i, 4) {
+                    return None;
+                }
+                let name = if bit(i, 30) { "ccmp" } else { "ccmn" };
+                let nzcv = bits(i, 3, 0);
+                if bit(i, 11) {
+                    write!(f, "{} {}, #{}, #{}, {}", name, n, rm, nzcv, CONDITIONS[cond as usize])
+                } else {
+                    write!(f, "{} {}, {}, #{}, {}", name, n, m, nzcv, CONDITIONS[cond as usize])
+                }
+            },
+            0b0100 => {
+                if bit(i, 29) || bit(i, 11) {
+                    return None;
+                }
+                let op = bits(i, 30, 30) * 2 + bits(i, 10, 10);
+                let inverted = CONDITIONS[(cond ^ 1) as usize];
+                match op {
+                    1 | 2 if rn == rm && cond < 0b1110 && n.is_zr() => {
+                        write!(f, "{} {}, {}", if op == 1 { "cset" } else { "csetm" }, d, inverted)
+                    },
+                    1 | 2 | 3 if rn == rm && cond < 0b1110 && !n.is_zr() => {
+                        let alias = ["", "cinc", "cinv", "cneg"][op as usize];
+                        write!(f, "{} {}, {}, {}", alias, d, n, inverted)
+                    },
+                    _ => {
+                        let name = ["csel", "csinc", "csinv", "csneg"][op as usize];
+                        write!(f, "{} {}, {}, {}, {}", name, d, n, m, CONDITIONS[cond as usize])
+                    },
+                }
+            },
+            0b0110 if bit(i, 29) => return None,
+            0b0110 if !bit(i, 30) => {
+                // data processing, 2 source
+                let name = match bits(i, 15, 10) {
+                    0b000010 => "udiv",
+                    0b000011 => "sdiv",
+                    0b001000 => "lsl",
+                    0b001001 => "lsr",
+                    0b001010 => "asr",
+                    0b001011 => "ror",
+                    _ => return None,
+                };
+                write!(f, "{} {}, {}, {}", name, d, n, m)
+            },
+            0b0110 => {
+                // data processing, 1 source
+                if rm != 0 {
+                    return None;
+                }
+                let name = match (bits(i, 15, 10), wide) {
+                    (0b000000, _) => "rbit",
+                    (0b000001, _) => "rev16",
+                    (0b000010, false) => "rev",
+                    (0b000010, true) => "rev32",
+                    (0b000011, true) => "rev",
+                    (0b000100, _) => "clz",
+                    (0b000101, _) => "cls",
+                    _ => return None,
+                };
+                write!(f, "{} {}, {}", name, d, n)
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decoded = match bits(self.insn, 28, 25) {
+            0b1000 | 0b1001 => self.data_imm(f),
+            0b1010 | 0b1011 => self.branch_system(f),
+            0b0100 | 0b0110 | 0b1100 | 0b1110 => self.load_store(f),
+            0b0101 | 0b1101 => self.data_reg(f),
+            _ => None,
+        };
+        match decoded {
+            Some(result) => result,
+            None => write!(f, ".inst {:#010x}", self.insn),
+        }
+    }
+}
+
+/// A system register, by its encoding.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SysReg {
+    op0: u32,
+    op1: u32,
+    crn: u32,
+    crm: u32,
+    op2: u32,
+}
+
+/// Names of the system registers the hypervisor and its guests commonly use,
+/// by `(op0, op1, CRn, CRm, op2)`.
+const SYSREGS: &[((u32, u32, u32, u32, u32), &str)] = &[
+    ((2, 0, 0, 2, 2), "mdscr_el1"),
+    ((2, 0, 1, 0, 4), "oslar_el1"),
+    ((3, 0, 0, 0, 0), "midr_el1"),
+    ((3, 0, 0, 0, 5), "mpidr_el1"),
+    ((3, 0, 1, 0, 0), "sctlr_el1"),
+    ((3, 0, 1, 0, 1), "actlr_el1"),
+    ((3, 0, 1, 0, 2), "cpacr_el1"),
+    ((3, 0, 2, 0, 0), "ttbr0_el1"),
+    ((3, 0, 2, 0, 1), "ttbr1_el1"),
+    ((3, 0, 2, 0, 2), "tcr_el1"),
+    ((3, 0, 4, 0, 0), "spsr_el1"),
+    ((3, 0, 4, 0, 1), "elr_el1"),
+    ((3, 0, 4, 1, 0), "sp_el0"),
+    ((3, 0, 4, 2, 0), "spsel"),
+    ((3, 0, 4, 2, 2), "currentel"),
+    ((3, 0, 5, 2, 0), "esr_el1"),
+    ((3, 0, 6, 0, 0), "far_el1"),
+    ((3, 0, 7, 4, 0), "par_el1"),
+    ((3, 0, 10, 2, 0), "mair_el1"),
+    ((3, 0, 12, 0, 0), "vbar_el1"),
+    ((3, 0, 13, 0, 1), "contextidr_el1"),
+    ((3, 0, 13, 0, 4), "tpidr_el1"),
+    ((3, 0, 14, 1, 0), "cntkctl_el1"),
+    ((3, 3, 4, 2, 0), "nzcv"),
+    ((3, 3, 4, 2, 1), "daif"),
+    ((3, 3, 4, 4, 0), "fpcr"),
+    ((3, 3, 4, 4, 1), "fpsr"),
+    ((3, 3, 13, 0, 2), "tpidr_el0"),
+    ((3, 3, 13, 0, 3), "tpidrro_el0"),
+    ((3, 3, 14, 0, 0), "cntfrq_el0"),
+    ((3, 3, 14, 0, 1), "cntpct_el0"),
+    ((3, 3, 14, 0, 2), "cntvct_el0"),
+    ((3, 3, 14, 2, 0), "cntp_tval_el0"),
+    ((3, 3, 14, 2, 1), "cntp_ctl_el0"),
+    ((3, 3, 14, 2, 2), "cntp_cval_el0"),
+    ((3, 3, 14, 3, 0), "cntv_tval_el0"),
+    ((3, 3, 14, 3, 1), "cntv_ctl_el0"),
+    ((3, 3, 14, 3, 2), "cntv_cval_el0"),
+    ((3, 4, 1, 0, 0), "sctlr_el2"),
+    ((3, 4, 1, 1, 0), "hcr_el2"),
+    ((3, 4, 1, 1, 1), "mdcr_el2"),
+    ((3, 4, 1, 1, 2), "cptr_el2"),
+    ((3, 4, 2, 1, 0), "vttbr_el2"),
+    ((3, 4, 2, 1, 2), "vtcr_el2"),
+    ((3, 4, 4, 0, 0), "spsr_el2"),
+    ((3, 4, 4, 0, 1), "elr_el2"),
+    ((3, 4, 4, 1, 0), "sp_el1"),
+    ((3, 4, 5, 2, 0), "esr_el2"),
+    ((3, 4, 6, 0, 0), "far_el2"),
+    ((3, 4, 6, 0, 4), "hpfar_el2"),
+    ((3, 4, 12, 0, 0), "vbar_el2"),
+    ((3, 4, 14, 0, 3), "cntvoff_el2"),
+    ((3, 4, 14, 1, 0), "cnthctl_el2"),
+];
+
+impl fmt::Display for SysReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = (self.op0, self.op1, self.crn, self.crm, self.op2);
+        match SYSREGS.iter().find(|&&(k, _)| k == key) {
+            Some(&(_, name)) => f.write_str(name),
+            None => write!(f, "s{}_{}_c{}_c{}_{}", self.op0, self.op1, self.crn, self.crm, self.op2),
+        }
+    }
+}
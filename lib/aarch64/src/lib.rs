@@ -3,31 +3,52 @@
 
 #![cfg_attr(not(test), no_std)]
 
+//! The modules that read or write the CPU's state are only built for AArch64;
+//! the rest builds anywhere, so the tests run on the host with
+//! `cargo test --target=$(../../bin/get-host-target.sh)`.
+
 #[macro_use]
 pub mod macros;
 
+#[cfg(target_arch = "aarch64")]
 pub mod sp;
+#[cfg(target_arch = "aarch64")]
 pub mod asm;
+#[cfg(target_arch = "aarch64")]
 pub mod regs;
+#[cfg(target_arch = "aarch64")]
 pub mod vmsa;
+#[cfg(target_arch = "aarch64")]
 pub mod cache;
+pub mod disasm;
+pub mod backtrace;
+
+#[cfg(test)]
+mod tests;
 
+#[cfg(target_arch = "aarch64")]
 pub use sp::SP;
+#[cfg(target_arch = "aarch64")]
 pub use regs::*;
+#[cfg(target_arch = "aarch64")]
 pub use vmsa::*;
+#[cfg(target_arch = "aarch64")]
 pub use asm::*;
+#[cfg(target_arch = "aarch64")]
 pub use cache::*;
 
 /// Returns the current exception level.
 ///
 /// # Safety
 /// This function should only be called when EL is >= 1.
+#[cfg(target_arch = "aarch64")]
 #[inline(always)]
 pub unsafe fn current_el() -> u8 {
     ((CurrentEL.get() & 0b1100) >> 2) as u8
 }
 
 /// Returns the SPSel value.
+#[cfg(target_arch = "aarch64")]
 #[inline(always)]
 pub fn sp_sel() -> u8 {
     unsafe {
@@ -40,6 +61,7 @@ pub fn sp_sel() -> u8 {
 /// # Safety
 ///
 /// This function should only be called when EL is >= 1.
+#[cfg(target_arch = "aarch64")]
 pub fn affinity() -> usize {
     unsafe {
         MPIDR_EL1.get_value(MPIDR_EL1::Aff0) as usize
@@ -145,7 +145,14 @@ defreg!(ELR_EL1);
 defreg!(ELR_EL2);
 defreg!(ELR_EL3);
 
-defreg!(CPTR_EL2);
+// (ref. D13.2.31: Architectural Feature Trap Register (EL2))
+defreg!(CPTR_EL2, [
+    TCPAC [31-31], // Traps accesses to CPACR_EL1
+    TTA   [20-20], // Traps trace register accesses
+    TFP   [10-10], // Traps Advanced SIMD and floating-point accesses
+
+    RES1  [13-12|09-00],
+]);
 defreg!(CPACR_EL1);
 
 // (ref. D13.2 Exception Syndrome Register)
@@ -240,6 +247,131 @@ defreg!(VBAR_EL2, [
 defreg!(CNTHCTL_EL2, [
     EL0VCTEN [1-1],
     EL0PCTEN [0-0],
+
+    // (ref. D13.8.1: meaning of the same bits when HCR_EL2.E2H is 0)
+    EL1PCEN  [1-1], // EL1/EL0 access to the physical timer
+    EL1PCTEN [0-0], // EL1/EL0 access to the physical counter
 ]);
 
 defreg!(CNTVOFF_EL2);
+
+// (ref. D13.8.17: Virtual Timer Control register)
+defreg!(CNTV_CTL_EL0, [
+    ISTATUS [2-2], // Timer condition met
+    IMASK   [1-1], // Interrupt masked
+    ENABLE  [0-0], // Timer enabled
+]);
+
+defreg!(CNTV_CVAL_EL0);
+defreg!(CNTPCT_EL0);
+defreg!(CNTVCT_EL0);
+
+// (ref. D13.4.7 Performance Monitors Control Register)
+defreg!(PMCR_EL0, [
+    N    [15-11], // Number of event counters implemented
+    LC   [06-06], // Long cycle counter enable
+    DP   [05-05], // Disable cycle counter when event counting is prohibited
+    X    [04-04], // Enable export of events
+    D    [03-03], // Clock divider (count every 64th cycle)
+    C    [02-02], // Cycle counter reset
+    P    [01-01], // Event counter reset
+    E    [00-00], // Enable
+]);
+
+// (ref. D13.4.4 Performance Monitors Count Enable Set register)
+defreg!(PMCNTENSET_EL0, [
+    C    [31-31], // PMCCNTR_EL0 enable
+]);
+
+// (ref. D13.4.3 Performance Monitors Cycle Count Filter Register)
+defreg!(PMCCFILTR_EL0, [
+    P    [31-31], // Don't count at EL1
+    U    [30-30], // Don't count at EL0
+    NSK  [29-29], // Non-secure EL1 filtering
+    NSU  [28-28], // Non-secure EL0 filtering
+    NSH  [27-27], // Count at EL2
+]);
+
+// (ref. D13.4.2 Performance Monitors Cycle Count Register)
+defreg!(PMCCNTR_EL0);
+
+// (ref. D13.3.4 Monitor Debug Configuration Register (EL2))
+defreg!(MDCR_EL2, [
+    TDRA  [11-11], // Trap debug ROM address register accesses
+    TDOSA [10-10], // Trap debug OS-related register accesses
+    TDA   [09-09], // Trap debug register accesses
+    TDE   [08-08], // Route debug exceptions from EL1/EL0 to EL2
+    HPME  [07-07], // Enable the counters reserved for EL2
+    TPM   [06-06], // Trap Performance Monitors accesses
+    TPMCR [05-05], // Trap PMCR_EL0 accesses
+    HPMN  [04-00], // Number of counters accessible from EL1/EL0
+]);
+
+// (ref. D13.3.15 Monitor Debug System Control Register)
+defreg!(MDSCR_EL1, [
+    MDE  [15-15], // Enable breakpoint, watchpoint and vector catch exceptions
+    KDE  [13-13], // Enable debug exceptions within the debug target EL
+    SS   [00-00], // Enable software step
+]);
+
+// (ref. D13.3.18 OS Lock Access Register)
+defreg!(OSLAR_EL1, [
+    OSLK [00-00], // Lock the debug registers against external access
+]);
+
+// (ref. D13.3.19 OS Lock Status Register)
+defreg!(OSLSR_EL1, [
+    OSLM1 [03-03], // OS lock model, 0b10 = implemented
+    OSLK  [01-01], // Status of the OS lock
+    OSLM0 [00-00],
+]);
+
+// (ref. D13.3.2 Debug Breakpoint Value Registers, A53: 11.4.2)
+// The A53 implements six breakpoints, whose control registers all share the
+// fields of `DBGBCR0_EL1`.
+defreg!(DBGBVR0_EL1);
+defreg!(DBGBVR1_EL1);
+defreg!(DBGBVR2_EL1);
+defreg!(DBGBVR3_EL1);
+defreg!(DBGBVR4_EL1);
+defreg!(DBGBVR5_EL1);
+
+// (ref. D13.3.1 Debug Breakpoint Control Registers)
+defreg!(DBGBCR0_EL1, [
+    BT   [23-20], // Breakpoint type, 0b0000 = unlinked address match
+    LBN  [19-16], // Linked breakpoint number
+    SSC  [15-14], // Security state control
+    HMC  [13-13], // Higher mode control
+    BAS  [08-05], // Byte address select
+    PMC  [02-01], // Privilege mode control, 0b11 = EL1 and EL0
+    E    [00-00], // Enable breakpoint
+]);
+defreg!(DBGBCR1_EL1);
+defreg!(DBGBCR2_EL1);
+defreg!(DBGBCR3_EL1);
+defreg!(DBGBCR4_EL1);
+defreg!(DBGBCR5_EL1);
+
+// (ref. D13.3.12 Debug Watchpoint Value Registers)
+// The A53 implements four watchpoints, whose control registers all share the
+// fields of `DBGWCR0_EL1`.
+defreg!(DBGWVR0_EL1);
+defreg!(DBGWVR1_EL1);
+defreg!(DBGWVR2_EL1);
+defreg!(DBGWVR3_EL1);
+
+// (ref. D13.3.11 Debug Watchpoint Control Registers)
+defreg!(DBGWCR0_EL1, [
+    MASK [28-24], // Address mask, watches 2^MASK bytes
+    WT   [20-20], // Watchpoint type, 0 = unlinked data address match
+    LBN  [19-16], // Linked breakpoint number
+    SSC  [15-14], // Security state control
+    HMC  [13-13], // Higher mode control
+    BAS  [12-05], // Byte address select within the doubleword
+    LSC  [04-03], // Load/store control, 0b01 = load, 0b10 = store
+    PAC  [02-01], // Privilege of access control, 0b11 = EL1 and EL0
+    E    [00-00], // Enable watchpoint
+]);
+defreg!(DBGWCR1_EL1);
+defreg!(DBGWCR2_EL1);
+defreg!(DBGWCR3_EL1);
@@ -0,0 +1,354 @@
+use crate::backtrace::{self, Symbol, Symbols};
+use crate::disasm::*;
+
+/// Checks that every `(insn, text)` in `cases` disassembles to `text`.
+fn check(cases: &[(u32, &str)]) {
+    for &(insn, text) in cases {
+        assert_eq!(format!("{}", disassemble(insn, 0x80000)), text, "insn {:#010x}", insn);
+    }
+}
+
+#[test]
+fn data_processing_immediate() {
+    check(&[
+        (0x91004020, "add x0, x1, #16"),
+        (0x91400420, "add x0, x1, #1, lsl #12"),
+        (0x9100003f, "mov sp, x1"),
+        (0x910003fd, "mov x29, sp"),
+        (0xf100145f, "cmp x2, #5"),
+        (0x3100047f, "cmn w3, #1"),
+        (0x71001020, "subs w0, w1, #4"),
+        (0x92401c20, "and x0, x1, #0xff"),
+        (0x321c03e2, "orr w2, wzr, #0x10"),
+        (0xb200f3e0, "mov x0, #0x5555555555555555"),
+        (0xb2703fe0, "orr x0, xzr, #0xffff0000"),
+        (0x32003fe0, "orr w0, wzr, #0xffff"),
+        (0x32005fe0, "orr w0, wzr, #0xffffff"),
+        (0xf241007f, "tst x3, #0x8000000000000000"),
+        (0xd2824680, "mov x0, #0x1234"),
+        (0xd2a24680, "mov x0, #0x12340000"),
+        (0x12800000, "mov w0, #0xffffffff"),
+        (0x929fffe0, "mov x0, #0xffffffffffff0000"),
+        (0xf2d7dde1, "movk x1, #0xbeef, lsl #32"),
+        (0x7297dde1, "movk w1, #0xbeef"),
+        (0xd37df020, "lsl x0, x1, #3"),
+        (0x53057c20, "lsr w0, w1, #5"),
+        (0x937ffc62, "asr x2, x3, #63"),
+        (0xd3442c20, "ubfx x0, x1, #4, #8"),
+        (0x13021020, "sbfx w0, w1, #2, #3"),
+        (0x53001c20, "uxtb w0, w1"),
+        (0x93407c20, "sxtw x0, w1"),
+        (0x93403c83, "sxth x3, w4"),
+        (0xb3780c20, "bfi x0, x1, #8, #4"),
+        (0x33031c20, "bfxil w0, w1, #3, #5"),
+        (0xd37e1420, "ubfiz x0, x1, #2, #6"),
+        (0x93c11c20, "ror x0, x1, #7"),
+        (0x93c23020, "extr x0, x1, x2, #12"),
+    ]);
+}
+
+#[test]
+fn branches_and_system() {
+    check(&[
+        (0xd65f03c0, "ret"),
+        (0xd65f0020, "ret x1"),
+        (0xd61f0200, "br x16"),
+        (0xd63f0100, "blr x8"),
+        (0xd69f03e0, "eret"),
+        (0xd4000001, "svc #0x0"),
+        (0xd4000022, "hvc #0x1"),
+        (0xd4207d00, "brk #0x3e8"),
+        (0xd503201f, "nop"),
+        (0xd503207f, "wfi"),
+        (0xd503205f, "wfe"),
+        (0xd5033fdf, "isb"),
+        (0xd5033f9f, "dsb sy"),
+        (0xd5033bbf, "dmb ish"),
+        (0xd5033a9f, "dsb ishst"),
+        (0xd5034fdf, "msr daifset, #0xf"),
+        (0xd50342ff, "msr daifclr, #0x2"),
+        (0xd50041bf, "msr spsel, #0x1"),
+        (0xd5384240, "mrs x0, currentel"),
+        (0xd518c001, "msr vbar_el1, x1"),
+        (0xd53c5202, "mrs x2, esr_el2"),
+        (0xd51c1103, "msr hcr_el2, x3"),
+        (0xd53be044, "mrs x4, cntvct_el0"),
+        (0xd539f205, "mrs x5, s3_1_c15_c2_0"),
+        (0xd50b7e20, "dc civac, x0"),
+        (0xd508751f, "ic iallu"),
+        (0xd50b7523, "ic ivau, x3"),
+        (0xd508831f, "tlbi vmalle1is"),
+        (0xd5088722, "tlbi vae1, x2"),
+        (0xd50c871f, "tlbi alle2"),
+    ]);
+}
+
+#[test]
+fn loads_and_stores() {
+    check(&[
+        (0xf9400020, "ldr x0, [x1]"),
+        (0xb9400820, "ldr w0, [x1, #8]"),
+        (0x39400462, "ldrb w2, [x3, #1]"),
+        (0x39800062, "ldrsb x2, [x3]"),
+        (0x79c00462, "ldrsh w2, [x3, #2]"),
+        (0xb98007e5, "ldrsw x5, [sp, #4]"),
+        (0x79000c41, "strh w1, [x2, #6]"),
+        (0xf81f0fe0, "str x0, [sp, #-16]!"),
+        (0xf84107e0, "ldr x0, [sp], #16"),
+        (0xf85f8020, "ldur x0, [x1, #-8]"),
+        (0x38003020, "sturb w0, [x1, #3]"),
+        (0xf8400820, "ldtr x0, [x1]"),
+        (0xf8626820, "ldr x0, [x1, x2]"),
+        (0xf8627820, "ldr x0, [x1, x2, lsl #3]"),
+        (0xb8624820, "ldr w0, [x1, w2, uxtw]"),
+        (0x3862c820, "ldrb w0, [x1, w2, sxtw]"),
+        (0xb8227820, "str w0, [x1, x2, lsl #2]"),
+        (0xa9be7bfd, "stp x29, x30, [sp, #-32]!"),
+        (0xa8c27bfd, "ldp x29, x30, [sp], #32"),
+        (0x29410440, "ldp w0, w1, [x2, #8]"),
+        (0x69400440, "ldpsw x0, x1, [x2]"),
+        (0xa8010440, "stnp x0, x1, [x2, #16]"),
+        (0xad410400, "ldp q0, q1, [x0, #32]"),
+        (0x3d8007e0, "str q0, [sp, #16]"),
+        (0xfd400001, "ldr d1, [x0]"),
+        (0xbd000422, "str s2, [x1, #4]"),
+        (0x3d400023, "ldr b3, [x1]"),
+        (0x7d400424, "ldr h4, [x1, #2]"),
+        (0xc85f7c20, "ldxr x0, [x1]"),
+        (0xc8027c20, "stxr w2, x0, [x1]"),
+        (0x885ffc20, "ldaxr w0, [x1]"),
+        (0x8803fc20, "stlxr w3, w0, [x1]"),
+        (0xc87f0440, "ldxp x0, x1, [x2]"),
+        (0xc8240440, "stxp w4, x0, x1, [x2]"),
+        (0xc8dffc20, "ldar x0, [x1]"),
+        (0x889ffc20, "stlr w0, [x1]"),
+        (0x085f7c20, "ldxrb w0, [x1]"),
+        (0x489ffc20, "stlrh w0, [x1]"),
+        (0xf9800000, "prfm #0, [x0]"),
+        (0xf8a17800, "prfm #0, [x0, x1, lsl #3]"),
+        (0xf8a1c800, "prfm #0, [x0, w1, sxtw]"),
+        (0xf89ff000, "prfum #0, [x0, #-1]"),
+        (0xf8800033, "prfum #19, [x1]"),
+    ]);
+}
+
+#[test]
+fn simd_loads_and_stores() {
+    check(&[
+        (0x4c407000, "ld1 {v0.16b}, [x0]"),
+        (0x4cdfa820, "ld1 {v0.4s, v1.4s}, [x1], #32"),
+        (0x4c002c40, "st1 {v0.2d, v1.2d, v2.2d, v3.2d}, [x2]"),
+        (0x0cc40060, "ld4 {v0.8b, v1.8b, v2.8b, v3.8b}, [x3], x4"),
+        (0x4c0084a4, "st2 {v4.8h, v5.8h}, [x5]"),
+        (0x0d405800, "ld1 {v0.h}[3], [x0]"),
+        (0x0dff9061, "ld2 {v1.s, v2.s}[1], [x3], #8"),
+        (0x4dc2a7e4, "ld3 {v4.d, v5.d, v6.d}[1], [sp], x2"),
+        (0x4d203c20, "st4 {v0.b, v1.b, v2.b, v3.b}[15], [x1]"),
+        (0x0d9f8407, "st1 {v7.d}[0], [x0], #8"),
+        (0x4dffe800, "ld4r {v0.4s, v1.4s, v2.4s, v3.4s}, [x0], #16"),
+        (0x0d40c020, "ld1r {v0.8b}, [x1]"),
+    ]);
+}
+
+#[test]
+fn data_processing_register() {
+    check(&[
+        (0x8b020020, "add x0, x1, x2"),
+        (0x8b020c20, "add x0, x1, x2, lsl #3"),
+        (0x4b820820, "sub w0, w1, w2, asr #2"),
+        (0xeb02003f, "cmp x1, x2"),
+        (0x2b02003f, "cmn w1, w2"),
+        (0xcb0103e0, "neg x0, x1"),
+        (0x6b0107e0, "negs w0, w1, lsl #1"),
+        (0x8a020020, "and x0, x1, x2"),
+        (0x0a220020, "bic w0, w1, w2"),
+        (0xaac21020, "orr x0, x1, x2, ror #4"),
+        (0xaa0103e0, "mov x0, x1"),
+        (0x2a0103e0, "mov w0, w1"),
+        (0xaa2103e0, "mvn x0, x1"),
+        (0x6a01001f, "tst w0, w1"),
+        (0xca220020, "eon x0, x1, x2"),
+        (0xea020020, "ands x0, x1, x2"),
+        (0x8b2163e0, "add x0, sp, x1"),
+        (0x8b2163ff, "add sp, sp, x1"),
+        (0x8b224820, "add x0, x1, w2, uxtw #2"),
+        (0xcb22c020, "sub x0, x1, w2, sxtw"),
+        (0x0b220020, "add w0, w1, w2, uxtb"),
+        (0xeb21401f, "cmp x0, w1, uxtw"),
+        (0x9a020020, "adc x0, x1, x2"),
+        (0x7a020020, "sbcs w0, w1, w2"),
+        (0xfa431804, "ccmp x0, #3, #4, ne"),
+        (0x3a410000, "ccmn w0, w1, #0, eq"),
+        (0x9a820020, "csel x0, x1, x2, eq"),
+        (0x1a82b420, "csinc w0, w1, w2, lt"),
+        (0x9a9f17e0, "cset x0, eq"),
+        (0x5a9f03e0, "csetm w0, ne"),
+        (0x9a81d420, "cinc x0, x1, gt"),
+        (0x5a815420, "cneg w0, w1, mi"),
+        (0x9b020c20, "madd x0, x1, x2, x3"),
+        (0x9b027c20, "mul x0, x1, x2"),
+        (0x1b028c20, "msub w0, w1, w2, w3"),
+        (0x9b02fc20, "mneg x0, x1, x2"),
+        (0x9b227c20, "smull x0, w1, w2"),
+        (0x9ba20c20, "umaddl x0, w1, w2, x3"),
+        (0x9bc27c20, "umulh x0, x1, x2"),
+        (0x9b427c20, "smulh x0, x1, x2"),
+        (0x9ac20820, "udiv x0, x1, x2"),
+        (0x1ac20c20, "sdiv w0, w1, w2"),
+        (0x9ac22020, "lsl x0, x1, x2"),
+        (0x1ac22820, "asr w0, w1, w2"),
+        (0x9ac22c20, "ror x0, x1, x2"),
+        (0xdac00020, "rbit x0, x1"),
+        (0x5ac00820, "rev w0, w1"),
+        (0xdac00c20, "rev x0, x1"),
+        (0xdac00820, "rev32 x0, x1"),
+        (0x5ac00420, "rev16 w0, w1"),
+        (0xdac01020, "clz x0, x1"),
+        (0x5ac01420, "cls w0, w1"),
+    ]);
+}
+
+#[test]
+fn pc_relative() {
+    check(&[
+        (0x14000004, "b 0x80010"),
+        (0x97fffffc, "bl 0x7fff0"),
+        (0x54000101, "b.ne 0x80020"),
+        (0xb4000040, "cbz x0, 0x80008"),
+        (0x372fff03, "tbnz w3, #5, 0x7ffe0"),
+        (0x10000800, "adr x0, 0x80100"),
+        (0x90000081, "adrp x1, 0x90000"),
+        (0x58000200, "ldr x0, 0x80040"),
+    ]);
+}
+
+#[test]
+fn unallocated() {
+    check(&[
+        (0x00000000, ".inst 0x00000000"),
+        (0x1e222820, ".inst 0x1e222820"), // fadd s0, s1, s2
+        (0xd4000000, ".inst 0xd4000000"), // svc with LL = 0
+        (0x3b1c4558, ".inst 0x3b1c4558"), // 3 source with op54 != 00
+        (0x3acc2e32, ".inst 0x3acc2e32"), // 2 source with S = 1
+        (0x7ac01020, ".inst 0x7ac01020"), // 1 source with S = 1
+        (0x91cbb613, ".inst 0x91cbb613"), // immediate op 011
+        (0x11955c67, ".inst 0x11955c67"), // immediate op 011
+        (0x0d00c020, ".inst 0x0d00c020"), // st1r: replicate with L = 0
+        (0x0d404c00, ".inst 0x0d404c00"), // ld1 {v0.h}[1] with size<0> = 1
+        (0xf8a00800, ".inst 0xf8a00800"), // prfm with extend option 000
+        (0xf8800800, ".inst 0xf8800800"), // prfm, unprivileged
+    ]);
+}
+
+#[test]
+fn decode_loads_and_stores() {
+    // ldr w0, [x1, #8]
+    let access = decode_load_store(0xb9400820).unwrap();
+    assert_eq!(access.form, Form::Single);
+    assert!(access.load && !access.wide && !access.signed && !access.simd);
+    assert_eq!((access.size, access.rt, access.rn), (4, 0, Some(1)));
+    assert_eq!(access.offset, Offset::Imm(8));
+    assert_eq!(access.address(0x1000, 0), 0x1008);
+
+    // str x0, [sp, #-16]!
+    let access = decode_load_store(0xf81f0fe0).unwrap();
+    assert!(!access.load && access.wide);
+    assert_eq!((access.size, access.rn, access.index), (8, Some(31), Index::Pre));
+    assert_eq!(access.address(0x1000, 0), 0xff0);
+
+    // ldr x0, [sp], #16
+    let access = decode_load_store(0xf84107e0).unwrap();
+    assert_eq!((access.index, access.offset), (Index::Post, Offset::Imm(16)));
+    assert_eq!(access.address(0x1000, 0), 0x1000);
+
+    // ldrb w0, [x1, w2, sxtw]
+    let access = decode_load_store(0x38a2c820).unwrap();
+    assert_eq!(access.offset, Offset::Reg { rm: 2, extend: Extend::Sxtw, shift: 0 });
+    assert_eq!(access.address(0x1000, 0xffff_ffff), 0xfff);
+
+    // ldrsh w2, [x3, #2]
+    let access = decode_load_store(0x79c00462).unwrap();
+    assert!(access.signed && !access.wide);
+    assert_eq!(access.size, 2);
+
+    // stp x29, x30, [sp, #-32]!
+    let access = decode_load_store(0xa9be7bfd).unwrap();
+    assert_eq!((access.form, access.rt, access.rt2), (Form::Pair, 29, Some(30)));
+    assert_eq!(access.offset, Offset::Imm(-32));
+
+    // stxr w2, x0, [x1]
+    let access = decode_load_store(0xc8027c20).unwrap();
+    assert_eq!(access.form, Form::Exclusive { ordered: false, status: 2 });
+
+    // not loads or stores: prfm pldl1keep, [x0] and ld1 {v0.16b}, [x0]
+    assert_eq!(decode_load_store(0xf9800000), None);
+    assert_eq!(decode_load_store(0x4c407000), None);
+}
+
+/// Builds a symbol table of `(addr, size, name)` the way `embed-symbols.py`
+/// lays it out.
+fn symbol_table(symbols: &[(u64, u32, &str)]) -> Vec<u8> {
+    let mut table = b"SYMS".to_vec();
+    table.extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+    let mut names = Vec::new();
+    let names_at = 8 + 16 * symbols.len();
+    for &(addr, size, name) in symbols {
+        table.extend_from_slice(&addr.to_le_bytes());
+        table.extend_from_slice(&size.to_le_bytes());
+        table.extend_from_slice(&((names_at + names.len()) as u32).to_le_bytes());
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    table.extend_from_slice(&names);
+    table
+}
+
+#[test]
+fn symbol_lookup() {
+    let table = symbol_table(&[(0x80000, 0x40, "_start"), (0x80040, 0x100, "kernel::kmain"), (0x80200, 0, "end")]);
+    let symbols = Symbols::parse(&table).expect("valid table");
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.lookup(0x7fffc), None);
+    assert_eq!(symbols.lookup(0x80000), Some(Symbol { name: "_start", addr: 0x80000, offset: 0 }));
+    assert_eq!(symbols.lookup(0x80084), Some(Symbol { name: "kernel::kmain", addr: 0x80040, offset: 0x44 }));
+    // past the end of `kmain`, before `end`
+    assert_eq!(symbols.lookup(0x80140), None);
+    assert_eq!(symbols.lookup(0x80200), Some(Symbol { name: "end", addr: 0x80200, offset: 0 }));
+    assert_eq!(symbols.lookup(0x80204), None);
+
+    assert!(Symbols::parse(&[0; 64]).is_none());
+    assert!(Symbols::parse(&table[..20]).is_none());
+}
+
+#[test]
+fn frame_chain() {
+    // frame records at 0x1000, 0x1020 and 0x1040, the last one ending the chain
+    let stack = [(0x1000, 0x1020), (0x1008, 0x80104), (0x1020, 0x1040), (0x1028, 0x80208), (0x1040, 0), (0x1048, 0x8030c)];
+    let read = |addr| stack.iter().find(|&&(at, _)| at == addr).map(|&(_, value)| value);
+    let pcs: Vec<u64> = backtrace::frames(0x1000, read).collect();
+    assert_eq!(pcs, [0x80104, 0x80208, 0x8030c]);
+
+    // a chain going down the stack stops rather than looping
+    let looping = [(0x1000, 0x1000), (0x1008, 0x80104)];
+    let read = |addr| looping.iter().find(|&&(at, _)| at == addr).map(|&(_, value)| value);
+    assert_eq!(backtrace::frames(0x1000, read).count(), 1);
+
+    assert_eq!(backtrace::frames(0x1004, read).count(), 0);
+    assert_eq!(backtrace::frames(0x2000, read).count(), 0);
+}
+
+#[test]
+fn trace_frames() {
+    let table = symbol_table(&[(0x80000, 0x40, "_start"), (0x80100, 0x100, "kernel::kmain")]);
+    let symbols = Symbols::parse(&table);
+    let stack = [(0x1000, 0x1020), (0x1008, 0x80108), (0x1020, 0), (0x1028, 0x2)];
+    let read = |addr| stack.iter().find(|&&(at, _)| at == addr).map(|&(_, value)| value);
+    let frames: Vec<String> = backtrace::trace(symbols, Some(0x80010), 0x1000, read)
+        .map(|frame| frame.to_string())
+        .collect();
+    assert_eq!(frames, [
+        "  #0  0x00080010 _start+0x10",
+        "  #1  0x00080104 kernel::kmain+0x4",
+        "  #2  0xfffffffffffffffe",
+    ]);
+}
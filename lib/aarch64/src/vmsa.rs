@@ -67,6 +67,7 @@ defbit!(RawEntry, [
 ]);
 
 defbit!(RawStage2Entry, [
+    SHARED [55-55], // reserved for software: page is shared copy-on-write
     ADDR  [47-16],
 
     AF    [10-10],
@@ -0,0 +1,102 @@
+//! Host tool to make keys and sign images for the bootloader.
+//!
+//!     bootsig keygen <secret-key> <public-key>
+//!     bootsig sign <secret-key> <image> <signed-image>
+//!     bootsig verify <public-key> <signed-image>
+//!
+//! Keys are raw 32-byte files. Copy the public key to `boot/key.pub` so the
+//! bootloader only boots images signed with the secret key.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+
+use bootsig::ed25519;
+use bootsig::image::{self, Header};
+
+const USAGE: &str = "usage:
+    bootsig keygen <secret-key> <public-key>
+    bootsig sign <secret-key> <image> <signed-image>
+    bootsig verify <public-key> <signed-image>";
+
+fn read_key(path: &str) -> io::Result<[u8; 32]> {
+    let bytes = fs::read(path)?;
+    if bytes.len() != 32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't a 32-byte key", path)));
+    }
+    let mut key = [0; 32];
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+fn keygen(secret_path: &str, public_path: &str) -> io::Result<()> {
+    let mut secret = [0; 32];
+    File::open("/dev/urandom")?.read_exact(&mut secret)?;
+    // only the owner may read the secret key
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(secret_path)?
+        .write_all(&secret)?;
+    fs::write(public_path, &ed25519::public_key(&secret))?;
+    println!("wrote secret key {} and public key {}", secret_path, public_path);
+    Ok(())
+}
+
+fn sign(secret_path: &str, image_path: &str, signed_path: &str) -> io::Result<()> {
+    let secret = read_key(secret_path)?;
+    let image = fs::read(image_path)?;
+    let mut signed = image::sign(&secret, &image).to_bytes().to_vec();
+    signed.extend_from_slice(&image);
+    fs::write(signed_path, &signed)?;
+    println!("signed {} ({} bytes) into {}", image_path, image.len(), signed_path);
+    Ok(())
+}
+
+fn verify(public_path: &str, signed_path: &str) -> io::Result<bool> {
+    let public = read_key(public_path)?;
+    let data = fs::read(signed_path)?;
+    let header = match Header::parse(&data) {
+        Some(header) => header,
+        None => {
+            println!("{}: not a signed image", signed_path);
+            return Ok(false);
+        }
+    };
+    match image::check(&header, &data, Some(&public)) {
+        Ok(image) => {
+            println!("{}: good signature, {} bytes", signed_path, image.len());
+            Ok(true)
+        },
+        Err(e) => {
+            println!("{}: rejected: {:?}", signed_path, e);
+            Ok(false)
+        },
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let result = match args[..] {
+        ["keygen", secret, public] => keygen(secret, public).map(|_| true),
+        ["sign", secret, image, signed] => sign(secret, image, signed).map(|_| true),
+        ["verify", public, signed] => verify(public, signed),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("bootsig: {}", e);
+            process::exit(1);
+        }
+    }
+}
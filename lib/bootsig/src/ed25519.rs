@@ -0,0 +1,176 @@
+//! Ed25519 signatures (RFC 8032).
+//!
+//! Verification only handles public data. Signing isn't constant-time and is
+//! meant for the host tool, not for secrets on a shared machine.
+
+mod field;
+mod scalar;
+
+use crate::sha2::Sha512;
+use self::field::{Fe, D, D2, ONE, SQRT_M1, ZERO};
+
+/// Length of a public key.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of a secret key, the seed keys are derived from.
+pub const SECRET_KEY_LEN: usize = 32;
+
+/// Length of a signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Encoding of the base point, y = 4/5 with x even.
+const BASE: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/// A point of the curve in extended coordinates: x = X/Z, y = Y/Z, xy = T/Z.
+#[derive(Debug, Copy, Clone)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+const IDENTITY: Point = Point { x: ZERO, y: ONE, z: ONE, t: ZERO };
+
+impl Point {
+    /// Decodes a point. Returns `None` if `bytes` doesn't encode one.
+    fn decompress(bytes: &[u8; 32]) -> Option<Point> {
+        let y = Fe::from_bytes(bytes);
+        let sign = bytes[31] >> 7 == 1;
+        // y must be encoded below p (RFC 8032, section 5.1.3)
+        let mut canonical = y.to_bytes();
+        canonical[31] |= bytes[31] & 0x80;
+        if &canonical != bytes {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1) = u / v
+        let y2 = y.square();
+        let u = y2 - ONE;
+        let v = D * y2 + ONE;
+        let v3 = v.square() * v;
+        let v7 = v3.square() * v;
+        let mut x = u * v3 * (u * v7).pow_p58();
+        let vx2 = v * x.square();
+        if vx2 != u {
+            if vx2 != -u {
+                return None;
+            }
+            x = x * SQRT_M1;
+        }
+        if x.is_zero() && sign {
+            return None;
+        }
+        if x.is_negative() != sign {
+            x = -x;
+        }
+        Some(Point { x, y, z: ONE, t: x * y })
+    }
+
+    fn compress(&self) -> [u8; 32] {
+        let zi = self.z.invert();
+        let x = self.x * zi;
+        let y = self.y * zi;
+        let mut bytes = y.to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    fn add(&self, other: &Point) -> Point {
+        let a = (self.y - self.x) * (other.y - other.x);
+        let b = (self.y + self.x) * (other.y + other.x);
+        let c = self.t * D2 * other.t;
+        let d = self.z * (other.z + other.z);
+        let (e, f, g, h) = (b - a, d - c, d + c, b + a);
+        Point { x: e * f, y: g * h, z: f * g, t: e * h }
+    }
+
+    fn neg(&self) -> Point {
+        Point { x: -self.x, y: self.y, z: self.z, t: -self.t }
+    }
+
+    /// Multiplies the point by the little-endian scalar `s`.
+    fn mul(&self, s: &[u8; 32]) -> Point {
+        let mut result = IDENTITY;
+        for i in (0..256).rev() {
+            result = result.add(&result);
+            if (s[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result.add(self);
+            }
+        }
+        result
+    }
+}
+
+fn base() -> Point {
+    Point::decompress(&BASE).expect("base point")
+}
+
+/// Expands a secret key into the clamped secret scalar and the nonce prefix.
+fn expand(secret: &[u8; SECRET_KEY_LEN]) -> ([u8; 32], [u8; 32]) {
+    let h = Sha512::digest(secret);
+    let mut a = [0; 32];
+    let mut prefix = [0; 32];
+    a.copy_from_slice(&h[..32]);
+    prefix.copy_from_slice(&h[32..]);
+    a[0] &= 248;
+    a[31] &= 127;
+    a[31] |= 64;
+    (a, prefix)
+}
+
+/// Returns H(R || A || M) modulo L.
+fn challenge(r: &[u8], public: &[u8; PUBLIC_KEY_LEN], message: &[u8]) -> [u8; 32] {
+    let mut hash = Sha512::new();
+    hash.update(r);
+    hash.update(public);
+    hash.update(message);
+    scalar::reduce(&hash.finish())
+}
+
+/// Returns the public key of `secret`.
+pub fn public_key(secret: &[u8; SECRET_KEY_LEN]) -> [u8; PUBLIC_KEY_LEN] {
+    let (a, _) = expand(secret);
+    base().mul(&a).compress()
+}
+
+/// Signs `message` with `secret`.
+pub fn sign(secret: &[u8; SECRET_KEY_LEN], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let (a, prefix) = expand(secret);
+    let public = base().mul(&a).compress();
+
+    let mut hash = Sha512::new();
+    hash.update(&prefix);
+    hash.update(message);
+    let r = scalar::reduce(&hash.finish());
+    let big_r = base().mul(&r).compress();
+
+    let k = challenge(&big_r, &public, message);
+    let s = scalar::mul_add(&k, &a, &r);
+
+    let mut signature = [0; SIGNATURE_LEN];
+    signature[..32].copy_from_slice(&big_r);
+    signature[32..].copy_from_slice(&s);
+    signature
+}
+
+/// Returns `true` if `signature` is a valid signature of `message` by the
+/// holder of `public`.
+pub fn verify(public: &[u8; PUBLIC_KEY_LEN], message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+    let mut s = [0; 32];
+    s.copy_from_slice(&signature[32..]);
+    if !scalar::is_canonical(&s) {
+        return false;
+    }
+    let a = match Point::decompress(public) {
+        Some(a) => a,
+        None => return false,
+    };
+    let k = challenge(&signature[..32], public, message);
+    // R = [S]B - [k]A
+    let r = base().mul(&s).add(&a.neg().mul(&k));
+    r.compress()[..] == signature[..32]
+}
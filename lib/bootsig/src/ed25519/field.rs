@@ -0,0 +1,220 @@
+//! Arithmetic modulo p = 2^255 - 19 on five 51-bit limbs.
+
+use core::ops::{Add, Mul, Neg, Sub};
+
+const MASK: u64 = (1 << 51) - 1;
+
+/// An element of GF(2^255 - 19). Limbs are kept below 2^52 between
+/// operations, so products of two limbs times 19 fit a `u128` sum.
+#[derive(Debug, Copy, Clone)]
+pub struct Fe(pub [u64; 5]);
+
+pub const ZERO: Fe = Fe([0, 0, 0, 0, 0]);
+pub const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+/// The curve constant d = -121665/121666.
+pub const D: Fe = Fe([0x34dca135978a3, 0x1a8283b156ebd, 0x5e7a26001c029, 0x739c663a03cbb, 0x52036cee2b6ff]);
+
+/// 2d.
+pub const D2: Fe = Fe([0x69b9426b2f159, 0x35050762add7a, 0x3cf44c0038052, 0x6738cc7407977, 0x2406d9dc56dff]);
+
+/// A square root of -1.
+pub const SQRT_M1: Fe = Fe([0x61b274a0ea0b0, 0xd5a5fc8f189d, 0x7ef5e9cbd0c60, 0x78595a6804c9e, 0x2b8324804fc1d]);
+
+/// p - 2, little-endian, the exponent of inversion.
+const P_MINUS_2: [u8; 32] = [
+    0xeb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+];
+
+/// (p - 5) / 8, little-endian, the exponent of the square root candidate.
+const P_MINUS_5_OVER_8: [u8; 32] = [
+    0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x0f,
+];
+
+fn load8(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+impl Fe {
+    /// Carries the limbs back below 2^51, plus a small excess in the first.
+    fn reduce(mut l: [u64; 5]) -> Fe {
+        let c0 = l[0] >> 51;
+        let c1 = l[1] >> 51;
+        let c2 = l[2] >> 51;
+        let c3 = l[3] >> 51;
+        let c4 = l[4] >> 51;
+        l[0] &= MASK;
+        l[1] &= MASK;
+        l[2] &= MASK;
+        l[3] &= MASK;
+        l[4] &= MASK;
+        l[0] += c4 * 19;
+        l[1] += c0;
+        l[2] += c1;
+        l[3] += c2;
+        l[4] += c3;
+        Fe(l)
+    }
+
+    /// Decodes a little-endian element, ignoring the top bit.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        Fe([
+            load8(&bytes[0..]) & MASK,
+            (load8(&bytes[6..]) >> 3) & MASK,
+            (load8(&bytes[12..]) >> 6) & MASK,
+            (load8(&bytes[19..]) >> 1) & MASK,
+            (load8(&bytes[24..]) >> 12) & MASK,
+        ])
+    }
+
+    /// Encodes the element fully reduced, little-endian.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut l = Fe::reduce(self.0).0;
+        // q is 1 if the value is at least p, i.e. if adding 19 carries past 2^255
+        let mut q = (l[0] + 19) >> 51;
+        q = (l[1] + q) >> 51;
+        q = (l[2] + q) >> 51;
+        q = (l[3] + q) >> 51;
+        q = (l[4] + q) >> 51;
+        l[0] += 19 * q;
+        l[1] += l[0] >> 51;
+        l[0] &= MASK;
+        l[2] += l[1] >> 51;
+        l[1] &= MASK;
+        l[3] += l[2] >> 51;
+        l[2] &= MASK;
+        l[4] += l[3] >> 51;
+        l[3] &= MASK;
+        l[4] &= MASK;
+
+        let mut bytes = [0u8; 32];
+        let mut acc: u128 = 0;
+        let mut bits = 0;
+        let mut at = 0;
+        for &limb in l.iter() {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                bytes[at] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                at += 1;
+            }
+        }
+        bytes[at] = acc as u8;
+        bytes
+    }
+
+    pub fn square(&self) -> Fe {
+        *self * *self
+    }
+
+    /// Raises the element to the little-endian power `exp`.
+    fn pow(&self, exp: &[u8; 32]) -> Fe {
+        let mut result = ONE;
+        for i in (0..256).rev() {
+            result = result.square();
+            if (exp[i / 8] >> (i % 8)) & 1 == 1 {
+                result = result * *self;
+            }
+        }
+        result
+    }
+
+    pub fn invert(&self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    /// Raises the element to the power (p - 5) / 8.
+    pub fn pow_p58(&self) -> Fe {
+        self.pow(&P_MINUS_5_OVER_8)
+    }
+
+    /// Returns `true` if the reduced element is odd, the "negative" ones.
+    pub fn is_negative(&self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.to_bytes() == [0; 32]
+    }
+}
+
+impl PartialEq for Fe {
+    fn eq(&self, other: &Fe) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Add for Fe {
+    type Output = Fe;
+
+    fn add(self, other: Fe) -> Fe {
+        let (a, b) = (self.0, other.0);
+        Fe::reduce([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3], a[4] + b[4]])
+    }
+}
+
+impl Sub for Fe {
+    type Output = Fe;
+
+    fn sub(self, other: Fe) -> Fe {
+        // add 16p so no limb underflows
+        let (a, b) = (self.0, other.0);
+        Fe::reduce([
+            (a[0] + 0x7ffffffffffed0) - b[0],
+            (a[1] + 0x7ffffffffffff0) - b[1],
+            (a[2] + 0x7ffffffffffff0) - b[2],
+            (a[3] + 0x7ffffffffffff0) - b[3],
+            (a[4] + 0x7ffffffffffff0) - b[4],
+        ])
+    }
+}
+
+impl Neg for Fe {
+    type Output = Fe;
+
+    fn neg(self) -> Fe {
+        ZERO - self
+    }
+}
+
+impl Mul for Fe {
+    type Output = Fe;
+
+    fn mul(self, other: Fe) -> Fe {
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+        let (a, b) = (self.0, other.0);
+        let b1 = b[1] * 19;
+        let b2 = b[2] * 19;
+        let b3 = b[3] * 19;
+        let b4 = b[4] * 19;
+
+        let c0 = m(a[0], b[0]) + m(a[4], b1) + m(a[3], b2) + m(a[2], b3) + m(a[1], b4);
+        let mut c1 = m(a[1], b[0]) + m(a[0], b[1]) + m(a[4], b2) + m(a[3], b3) + m(a[2], b4);
+        let mut c2 = m(a[2], b[0]) + m(a[1], b[1]) + m(a[0], b[2]) + m(a[4], b3) + m(a[3], b4);
+        let mut c3 = m(a[3], b[0]) + m(a[2], b[1]) + m(a[1], b[2]) + m(a[0], b[3]) + m(a[4], b4);
+        let mut c4 = m(a[4], b[0]) + m(a[3], b[1]) + m(a[2], b[2]) + m(a[1], b[3]) + m(a[0], b[4]);
+
+        c1 += c0 >> 51;
+        c2 += c1 >> 51;
+        c3 += c2 >> 51;
+        c4 += c3 >> 51;
+        let carry = (c4 >> 51) as u64;
+        let mut l = [
+            (c0 as u64) & MASK,
+            (c1 as u64) & MASK,
+            (c2 as u64) & MASK,
+            (c3 as u64) & MASK,
+            (c4 as u64) & MASK,
+        ];
+        l[0] += carry * 19;
+        l[1] += l[0] >> 51;
+        l[0] &= MASK;
+        Fe(l)
+    }
+}
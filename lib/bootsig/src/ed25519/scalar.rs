@@ -0,0 +1,104 @@
+//! Arithmetic modulo the group order L = 2^252 + 27742317777372353535851937790883648493.
+
+/// L as little-endian 64-bit words.
+const L: [u64; 4] = [0x5812631a5cf5d3ed, 0x14def9dea2f79cd6, 0, 0x1000000000000000];
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    bytes.chunks(8).map(|chunk| {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        u64::from_le_bytes(word)
+    })
+}
+
+/// Returns `a >= b`.
+fn ge(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] > b[i];
+        }
+    }
+    true
+}
+
+/// Subtracts `b` from `a`, which is at least `b`.
+fn sub(a: &mut [u64; 4], b: &[u64; 4]) {
+    let mut borrow = 0;
+    for i in 0..4 {
+        let (d, b1) = a[i].overflowing_sub(b[i]);
+        let (d, b2) = d.overflowing_sub(borrow);
+        a[i] = d;
+        borrow = (b1 || b2) as u64;
+    }
+}
+
+/// Reduces a number of little-endian 64-bit words modulo L, a bit at a time.
+fn reduce_words(number: &[u64]) -> [u8; 32] {
+    let mut rem = [0u64; 4];
+    for i in (0..number.len() * 64).rev() {
+        // rem < L < 2^253, so doubling it doesn't overflow
+        rem[3] = (rem[3] << 1) | (rem[2] >> 63);
+        rem[2] = (rem[2] << 1) | (rem[1] >> 63);
+        rem[1] = (rem[1] << 1) | (rem[0] >> 63);
+        rem[0] = (rem[0] << 1) | ((number[i / 64] >> (i % 64)) & 1);
+        if ge(&rem, &L) {
+            sub(&mut rem, &L);
+        }
+    }
+    let mut bytes = [0; 32];
+    for (i, w) in rem.iter().enumerate() {
+        bytes[i * 8..i * 8 + 8].copy_from_slice(&w.to_le_bytes());
+    }
+    bytes
+}
+
+/// Reduces a 512-bit little-endian number, e.g. a SHA-512 digest, modulo L.
+pub fn reduce(wide: &[u8; 64]) -> [u8; 32] {
+    let mut number = [0u64; 8];
+    for (n, w) in number.iter_mut().zip(words(wide)) {
+        *n = w;
+    }
+    reduce_words(&number)
+}
+
+/// Returns `a * b + c` modulo L, for 256-bit little-endian `a`, `b`, `c`.
+pub fn mul_add(a: &[u8; 32], b: &[u8; 32], c: &[u8; 32]) -> [u8; 32] {
+    let mut x = [0u64; 4];
+    let mut y = [0u64; 4];
+    for (x, w) in x.iter_mut().zip(words(a)) {
+        *x = w;
+    }
+    for (y, w) in y.iter_mut().zip(words(b)) {
+        *y = w;
+    }
+    let mut product = [0u64; 9];
+    for (i, w) in words(c).enumerate() {
+        product[i] = w;
+    }
+    for i in 0..4 {
+        let mut carry: u128 = 0;
+        for j in 0..4 {
+            let t = (x[i] as u128) * (y[j] as u128) + product[i + j] as u128 + carry;
+            product[i + j] = t as u64;
+            carry = t >> 64;
+        }
+        let mut k = i + 4;
+        while carry != 0 {
+            let t = product[k] as u128 + carry;
+            product[k] = t as u64;
+            carry = t >> 64;
+            k += 1;
+        }
+    }
+    reduce_words(&product)
+}
+
+/// Returns `true` if the little-endian `s` is below L, as a signature's S
+/// must be.
+pub fn is_canonical(s: &[u8; 32]) -> bool {
+    let mut number = [0u64; 4];
+    for (n, w) in number.iter_mut().zip(words(s)) {
+        *n = w;
+    }
+    !ge(&number, &L)
+}
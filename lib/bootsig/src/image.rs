@@ -0,0 +1,109 @@
+//! The signed image format.
+//!
+//! A signed image is a `HEADER_LEN`-byte header followed by the image:
+//!
+//! | offset | size | field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 8    | `MAGIC`                                      |
+//! | 8      | 8    | image length, little-endian                  |
+//! | 16     | 32   | SHA-256 digest of the image                  |
+//! | 48     | 64   | Ed25519 signature of bytes 0 to 48           |
+//! | 112    | 16   | zero                                         |
+//!
+//! XMODEM pads transfers to whole packets, so the length tells where the
+//! image ends.
+
+use crate::ed25519::{self, PUBLIC_KEY_LEN, SECRET_KEY_LEN, SIGNATURE_LEN};
+use crate::sha2::Sha256;
+
+/// Magic number of a signed image.
+pub const MAGIC: [u8; 8] = *b"RPISIGN1";
+
+/// Length of the header before the image.
+pub const HEADER_LEN: usize = 128;
+
+/// Length of the signed part of the header.
+const SIGNED_LEN: usize = 48;
+
+/// Reasons a signed image is rejected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageError {
+    /// The data is shorter than the length in the header.
+    Truncated,
+    /// The image doesn't match the digest in the header.
+    BadDigest,
+    /// The header isn't signed by the key.
+    BadSignature,
+}
+
+/// The header of a signed image.
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub len: u64,
+    pub digest: [u8; 32],
+    pub signature: [u8; SIGNATURE_LEN],
+}
+
+impl Header {
+    /// Parses the header at the start of `data`. Returns `None` if `data`
+    /// isn't a signed image.
+    pub fn parse(data: &[u8]) -> Option<Header> {
+        if data.len() < HEADER_LEN || data[..8] != MAGIC {
+            return None;
+        }
+        let mut len = [0; 8];
+        let mut digest = [0; 32];
+        let mut signature = [0; SIGNATURE_LEN];
+        len.copy_from_slice(&data[8..16]);
+        digest.copy_from_slice(&data[16..48]);
+        signature.copy_from_slice(&data[48..112]);
+        Some(Header { len: u64::from_le_bytes(len), digest, signature })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..8].copy_from_slice(&MAGIC);
+        bytes[8..16].copy_from_slice(&self.len.to_le_bytes());
+        bytes[16..48].copy_from_slice(&self.digest);
+        bytes[48..112].copy_from_slice(&self.signature);
+        bytes
+    }
+
+    /// Returns the header bytes covered by the signature.
+    fn signed(&self) -> [u8; SIGNED_LEN] {
+        let mut signed = [0; SIGNED_LEN];
+        signed.copy_from_slice(&self.to_bytes()[..SIGNED_LEN]);
+        signed
+    }
+}
+
+/// Returns the header of `image` signed with `secret`.
+pub fn sign(secret: &[u8; SECRET_KEY_LEN], image: &[u8]) -> Header {
+    let mut header = Header {
+        len: image.len() as u64,
+        digest: Sha256::digest(image),
+        signature: [0; SIGNATURE_LEN],
+    };
+    header.signature = ed25519::sign(secret, &header.signed());
+    header
+}
+
+/// Checks the signed image `data`, header first. The digest is always
+/// checked; the signature only if there is a `key` to check it against.
+/// Returns the image.
+pub fn check<'a>(header: &Header, data: &'a [u8], key: Option<&[u8; PUBLIC_KEY_LEN]>) -> Result<&'a [u8], ImageError> {
+    let len = header.len as usize;
+    if data.len() < HEADER_LEN || data.len() - HEADER_LEN < len {
+        return Err(ImageError::Truncated);
+    }
+    let image = &data[HEADER_LEN..HEADER_LEN + len];
+    if Sha256::digest(image) != header.digest {
+        return Err(ImageError::BadDigest);
+    }
+    if let Some(key) = key {
+        if !ed25519::verify(key, &header.signed(), &header.signature) {
+            return Err(ImageError::BadSignature);
+        }
+    }
+    Ok(image)
+}
@@ -0,0 +1,11 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Signed boot images: SHA-256 digests and Ed25519 signatures of images the
+//! bootloader receives.
+
+pub mod sha2;
+pub mod ed25519;
+pub mod image;
+
+#[cfg(test)]
+mod tests;
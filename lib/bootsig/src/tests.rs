@@ -0,0 +1,165 @@
+use crate::ed25519;
+use crate::image::{self, Header, ImageError, HEADER_LEN};
+use crate::sha2::{Sha256, Sha512};
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+}
+
+fn array32(s: &str) -> [u8; 32] {
+    let mut a = [0; 32];
+    a.copy_from_slice(&hex(s));
+    a
+}
+
+fn array64(s: &str) -> [u8; 64] {
+    let mut a = [0; 64];
+    a.copy_from_slice(&hex(s));
+    a
+}
+
+#[test]
+fn sha256() {
+    let long: Vec<u8> = (0..=255u8).cycle().take(1280).collect();
+    let cases: &[(&[u8], &str)] = &[
+        (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+        // padding spills into a second block
+        (&[b'a'; 56], "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a"),
+        (&long, "d414b085826eb06778483ba35564dc849e643359f69ed9747878ba6e54985bed"),
+    ];
+    for &(data, digest) in cases {
+        assert_eq!(&Sha256::digest(data)[..], &hex(digest)[..], "{} bytes", data.len());
+    }
+
+    // fed in pieces that straddle blocks
+    let mut hash = Sha256::new();
+    for chunk in long.chunks(100) {
+        hash.update(chunk);
+    }
+    assert_eq!(&hash.finish()[..], &hex(cases[3].1)[..]);
+}
+
+#[test]
+fn sha512() {
+    let long: Vec<u8> = (0..=255u8).cycle().take(1280).collect();
+    let cases: &[(&[u8], &str)] = &[
+        (b"abc", "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                  2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"),
+        (&[b'a'; 112], "c01d080efd492776a1c43bd23dd99d0a2e626d481e16782e75d54c2503b5dc32\
+                        bd05f0f1ba33e568b88fd2d970929b719ecbb152f58f130a407c8830604b70ca"),
+        (&long, "c93f55ccf2fa8c82699ff9b58afe3591242b135d908a6d865e17e38adb41c21d\
+                 1d5359e51273036373d54d20b5659cc87e6e7b381ff027d33f971416cc590f90"),
+    ];
+    for &(data, digest) in cases {
+        assert_eq!(&Sha512::digest(data)[..], &hex(digest)[..], "{} bytes", data.len());
+    }
+}
+
+/// RFC 8032 section 7.1 tests 1 to 3: secret key, public key, message,
+/// signature.
+const RFC8032: &[(&str, &str, &str, &str)] = &[
+    ("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+     "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+     "",
+     "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"),
+    ("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+     "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+     "72",
+     "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"),
+    ("c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+     "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+     "af82",
+     "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a"),
+];
+
+#[test]
+fn ed25519_vectors() {
+    for &(secret, public, message, signature) in RFC8032 {
+        let (secret, public) = (array32(secret), array32(public));
+        let (message, signature) = (hex(message), array64(signature));
+        assert_eq!(ed25519::public_key(&secret), public);
+        assert_eq!(&ed25519::sign(&secret, &message)[..], &signature[..]);
+        assert!(ed25519::verify(&public, &message, &signature));
+    }
+}
+
+#[test]
+fn ed25519_rejects() {
+    let (secret, public, _, _) = RFC8032[2];
+    let (secret, public) = (array32(secret), array32(public));
+    let signature = ed25519::sign(&secret, b"boot me");
+    assert!(ed25519::verify(&public, b"boot me", &signature));
+    assert!(!ed25519::verify(&public, b"boot me!", &signature));
+
+    for &byte in &[0, 31, 32, 63] {
+        let mut bad = signature;
+        bad[byte] ^= 0x10;
+        assert!(!ed25519::verify(&public, b"boot me", &bad), "flipped byte {}", byte);
+    }
+
+    // S + L is the same scalar, but not a canonical encoding
+    let mut malleable = signature;
+    let l = hex("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+    let mut carry = 0u16;
+    for i in 0..32 {
+        let sum = malleable[32 + i] as u16 + l[i] as u16 + carry;
+        malleable[32 + i] = sum as u8;
+        carry = sum >> 8;
+    }
+    assert!(!ed25519::verify(&public, b"boot me", &malleable));
+
+    let (_, other, _, _) = RFC8032[0];
+    assert!(!ed25519::verify(&array32(other), b"boot me", &signature));
+}
+
+#[test]
+fn ed25519_rejects_non_canonical_keys() {
+    // with the identity as the key, R = [S]B; here S = 1 and R = B
+    let mut signature = [0; 64];
+    signature[..32].copy_from_slice(&hex("5866666666666666666666666666666666666666666666666666666666666666"));
+    signature[32] = 1;
+    let identity = array32("0100000000000000000000000000000000000000000000000000000000000000");
+    assert!(ed25519::verify(&identity, b"boot me", &signature));
+
+    // y = p + 1 is the same point, but not a canonical encoding
+    let identity = array32("eeffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f");
+    assert!(!ed25519::verify(&identity, b"boot me", &signature));
+}
+
+#[test]
+fn signed_image() {
+    let (secret, public, _, _) = RFC8032[1];
+    let (secret, public) = (array32(secret), array32(public));
+    let payload: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+
+    let header = image::sign(&secret, &payload);
+    let mut data = header.to_bytes().to_vec();
+    data.extend_from_slice(&payload);
+    // XMODEM padding after the image
+    data.extend_from_slice(&[0x1a; 24]);
+
+    let parsed = Header::parse(&data).expect("signed image");
+    assert_eq!(parsed.len, payload.len() as u64);
+    assert_eq!(image::check(&parsed, &data, Some(&public)), Ok(&payload[..]));
+    assert_eq!(image::check(&parsed, &data, None), Ok(&payload[..]));
+
+    let mut corrupt = data.clone();
+    corrupt[HEADER_LEN + 500] ^= 1;
+    assert_eq!(image::check(&parsed, &corrupt, Some(&public)), Err(ImageError::BadDigest));
+    assert_eq!(image::check(&parsed, &data[..HEADER_LEN + 999], None), Err(ImageError::Truncated));
+
+    let (_, other, _, _) = RFC8032[0];
+    assert_eq!(image::check(&parsed, &data, Some(&array32(other))), Err(ImageError::BadSignature));
+
+    // a header rewritten for a different image isn't signed
+    let mut forged = parsed;
+    let other_payload = vec![0u8; 1000];
+    forged.digest = Sha256::digest(&other_payload);
+    let mut data = forged.to_bytes().to_vec();
+    data.extend_from_slice(&other_payload);
+    assert_eq!(image::check(&forged, &data, None), Ok(&other_payload[..]));
+    assert_eq!(image::check(&forged, &data, Some(&public)), Err(ImageError::BadSignature));
+
+    assert!(Header::parse(&payload).is_none());
+}
@@ -0,0 +1,168 @@
+#![cfg_attr(not(test), no_std)]
+
+//! Just enough of ELF64 to load a little-endian AArch64 executable.
+
+#[cfg(test)]
+mod tests;
+
+use core::ops::Range;
+
+/// The first bytes of every ELF file.
+pub const MAGIC: [u8; 4] = *b"\x7fELF";
+
+const CLASS_64: u8 = 2;
+const DATA_LE: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+
+const HEADER_LEN: usize = 64;
+const PHDR_LEN: usize = 56;
+
+/// Reasons an ELF image can't be loaded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The file header is truncated or malformed.
+    BadHeader,
+    /// The image isn't a 64-bit little-endian AArch64 executable.
+    WrongMachine,
+    /// A program header or segment lies outside the file.
+    BadSegment,
+    /// A segment would be loaded outside the allowed memory.
+    OutOfRange,
+    /// The entry point isn't in a loaded segment.
+    BadEntry,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(&data[offset..offset + 2]);
+    u16::from_le_bytes(bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// A loadable segment.
+#[derive(Debug)]
+pub struct Segment<'a> {
+    /// Physical address the segment is loaded at.
+    pub addr: usize,
+    /// Virtual address the segment is linked at.
+    pub vaddr: usize,
+    /// Contents from the file; the rest of the segment is zeroed.
+    pub data: &'a [u8],
+    /// Size of the segment in memory.
+    pub mem_size: usize,
+}
+
+/// A parsed ELF executable.
+#[derive(Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry: u64,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+/// Returns `true` if `data` starts like an ELF file.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.len() >= MAGIC.len() && data[..MAGIC.len()] == MAGIC
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the file header of `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, Error> {
+        if data.len() < HEADER_LEN || !is_elf(data) {
+            return Err(Error::BadHeader);
+        }
+        if data[4] != CLASS_64 || data[5] != DATA_LE
+            || read_u16(data, 16) != TYPE_EXEC || read_u16(data, 18) != MACHINE_AARCH64 {
+            return Err(Error::WrongMachine);
+        }
+
+        let elf = Elf {
+            data,
+            entry: read_u64(data, 24),
+            phoff: read_u64(data, 32) as usize,
+            phentsize: read_u16(data, 54) as usize,
+            phnum: read_u16(data, 56) as usize,
+        };
+        let table = elf.phentsize.checked_mul(elf.phnum).and_then(|len| len.checked_add(elf.phoff));
+        match table {
+            Some(end) if elf.phentsize >= PHDR_LEN && end <= data.len() => Ok(elf),
+            _ => Err(Error::BadSegment),
+        }
+    }
+
+    /// Returns the `PT_LOAD` segments in file order.
+    pub fn segments(&self) -> impl Iterator<Item = Result<Segment<'a>, Error>> + '_ {
+        (0..self.phnum)
+            .map(move |i| &self.data[self.phoff + i * self.phentsize..])
+            .filter(|phdr| read_u32(phdr, 0) == PT_LOAD)
+            .map(move |phdr| {
+                let offset = read_u64(phdr, 8) as usize;
+                let file_size = read_u64(phdr, 32) as usize;
+                let mem_size = read_u64(phdr, 40) as usize;
+                let end = offset.checked_add(file_size).ok_or(Error::BadSegment)?;
+                if end > self.data.len() || file_size > mem_size {
+                    return Err(Error::BadSegment);
+                }
+                Ok(Segment {
+                    addr: read_u64(phdr, 24) as usize,
+                    vaddr: read_u64(phdr, 16) as usize,
+                    data: &self.data[offset..end],
+                    mem_size,
+                })
+            })
+    }
+
+    /// Returns the physical address of the entry point.
+    pub fn entry(&self) -> Result<usize, Error> {
+        let entry = self.entry as usize;
+        for segment in self.segments() {
+            let segment = segment?;
+            if entry >= segment.vaddr && entry - segment.vaddr < segment.mem_size {
+                return Ok(entry - segment.vaddr + segment.addr);
+            }
+        }
+        Err(Error::BadEntry)
+    }
+
+    /// Copies every segment to its physical address, zeroing the part that
+    /// isn't in the file, and returns the physical entry point. Nothing is
+    /// written unless every segment fits in `allowed`.
+    ///
+    /// # Safety
+    ///
+    /// `allowed` must be memory the caller may overwrite and must not
+    /// overlap the image.
+    pub unsafe fn load(&self, allowed: Range<usize>) -> Result<usize, Error> {
+        let entry = self.entry()?;
+        for segment in self.segments() {
+            let segment = segment?;
+            let end = segment.addr.checked_add(segment.mem_size).ok_or(Error::OutOfRange)?;
+            if segment.addr < allowed.start || end > allowed.end {
+                return Err(Error::OutOfRange);
+            }
+        }
+
+        for segment in self.segments() {
+            let segment = segment?;
+            let dst = segment.addr as *mut u8;
+            core::ptr::copy_nonoverlapping(segment.data.as_ptr(), dst, segment.data.len());
+            core::ptr::write_bytes(dst.add(segment.data.len()), 0, segment.mem_size - segment.data.len());
+        }
+        Ok(entry)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::*;
+
+/// A program header: type, file offset, virtual and physical address, size
+/// in the file and in memory.
+#[derive(Copy, Clone)]
+struct Phdr(u32, u64, u64, u64, u64, u64);
+
+/// A loadable segment at `offset` in the file, linked at `vaddr` and loaded
+/// at `paddr`.
+fn load(offset: u64, vaddr: u64, paddr: u64, file_size: u64, mem_size: u64) -> Phdr {
+    Phdr(PT_LOAD, offset, vaddr, paddr, file_size, mem_size)
+}
+
+/// Builds an AArch64 executable entering at `entry` with the program headers
+/// `phdrs` right after the file header, padded to `len` bytes with `0xaa`.
+fn image(entry: u64, phdrs: &[Phdr], len: usize) -> Vec<u8> {
+    let mut data = vec![0xaa; len];
+    data[..HEADER_LEN].iter_mut().for_each(|b| *b = 0);
+    data[..4].copy_from_slice(&MAGIC);
+    data[4] = CLASS_64;
+    data[5] = DATA_LE;
+    data[16..18].copy_from_slice(&TYPE_EXEC.to_le_bytes());
+    data[18..20].copy_from_slice(&MACHINE_AARCH64.to_le_bytes());
+    data[24..32].copy_from_slice(&entry.to_le_bytes());
+    data[32..40].copy_from_slice(&(HEADER_LEN as u64).to_le_bytes());
+    data[54..56].copy_from_slice(&(PHDR_LEN as u16).to_le_bytes());
+    data[56..58].copy_from_slice(&(phdrs.len() as u16).to_le_bytes());
+    for (i, phdr) in phdrs.iter().enumerate() {
+        let at = HEADER_LEN + i * PHDR_LEN;
+        data[at..at + 4].copy_from_slice(&phdr.0.to_le_bytes());
+        for (j, field) in [phdr.1, phdr.2, phdr.3, phdr.4, phdr.5].iter().enumerate() {
+            data[at + 8 + 8 * j..at + 16 + 8 * j].copy_from_slice(&field.to_le_bytes());
+        }
+    }
+    data
+}
+
+#[test]
+fn parse_segments() {
+    let phdrs = [load(0x100, 0xffff_0000_0008_0000, 0x80000, 0x20, 0x40), Phdr(4, 0, 0, 0, 0, 0)];
+    let data = image(0xffff_0000_0008_0010, &phdrs, 0x120);
+    assert!(is_elf(&data));
+    let elf = Elf::parse(&data).expect("valid elf");
+
+    let segments: Vec<Segment> = elf.segments().map(|s| s.expect("valid segment")).collect();
+    assert_eq!(segments.len(), 1);
+    assert_eq!((segments[0].addr, segments[0].vaddr), (0x80000, 0xffff_0000_0008_0000));
+    assert_eq!(segments[0].data, &data[0x100..0x120]);
+    assert_eq!(segments[0].mem_size, 0x40);
+    assert_eq!(elf.entry(), Ok(0x80010));
+}
+
+#[test]
+fn truncated_headers() {
+    let data = image(0x80000, &[load(0x100, 0x80000, 0x80000, 0x10, 0x10)], 0x110);
+    assert!(!is_elf(&data[..3]));
+    assert_eq!(Elf::parse(&data[..HEADER_LEN - 1]).unwrap_err(), Error::BadHeader);
+    // the program header table runs past the end of the file
+    assert_eq!(Elf::parse(&data[..HEADER_LEN + PHDR_LEN - 1]).unwrap_err(), Error::BadSegment);
+
+    let mut wrong = data.clone();
+    wrong[18] = 62; // x86-64
+    assert_eq!(Elf::parse(&wrong).unwrap_err(), Error::WrongMachine);
+
+    let mut short = data.clone();
+    short[54..56].copy_from_slice(&(PHDR_LEN as u16 - 1).to_le_bytes());
+    assert_eq!(Elf::parse(&short).unwrap_err(), Error::BadSegment);
+}
+
+#[test]
+fn phoff_overflow() {
+    let mut data = image(0x80000, &[load(0x100, 0x80000, 0x80000, 0x10, 0x10)], 0x110);
+    data[32..40].copy_from_slice(&(core::u64::MAX - 8).to_le_bytes());
+    assert_eq!(Elf::parse(&data).unwrap_err(), Error::BadSegment);
+
+    let mut data = image(0x80000, &[], 0x110);
+    data[54..56].copy_from_slice(&0xffffu16.to_le_bytes());
+    data[56..58].copy_from_slice(&0xffffu16.to_le_bytes());
+    assert_eq!(Elf::parse(&data).unwrap_err(), Error::BadSegment);
+}
+
+#[test]
+fn bad_segments() {
+    // more in the file than in memory
+    let data = image(0x80000, &[load(0x100, 0x80000, 0x80000, 0x10, 0x8)], 0x110);
+    let elf = Elf::parse(&data).expect("valid header");
+    assert_eq!(elf.segments().next().unwrap().unwrap_err(), Error::BadSegment);
+    assert_eq!(elf.entry(), Err(Error::BadSegment));
+
+    // contents past the end of the file, or wrapping around
+    let data = image(0x80000, &[load(0x100, 0x80000, 0x80000, 0x20, 0x20)], 0x110);
+    assert_eq!(Elf::parse(&data).unwrap().entry(), Err(Error::BadSegment));
+    let data = image(0x80000, &[load(core::u64::MAX, 0x80000, 0x80000, 2, 2)], 0x110);
+    assert_eq!(Elf::parse(&data).unwrap().entry(), Err(Error::BadSegment));
+}
+
+#[test]
+fn entry_outside_segments() {
+    let phdrs = [load(0x100, 0x80000, 0x80000, 0x10, 0x20)];
+    let data = image(0x80020, &phdrs, 0x110);
+    assert_eq!(Elf::parse(&data).unwrap().entry(), Err(Error::BadEntry));
+    let data = image(0x7fffc, &phdrs, 0x110);
+    assert_eq!(Elf::parse(&data).unwrap().entry(), Err(Error::BadEntry));
+    let data = image(0x8001c, &phdrs, 0x110);
+    assert_eq!(Elf::parse(&data).unwrap().entry(), Ok(0x8001c));
+}
+
+#[test]
+fn load_segments() {
+    let mut memory = vec![0x55u8; 0x100];
+    let base = memory.as_mut_ptr() as usize;
+    let phdrs = [load(0x100, 0x1000, base as u64 + 0x10, 0x8, 0x20)];
+    let data = image(0x1004, &phdrs, 0x108);
+    let elf = Elf::parse(&data).unwrap();
+
+    // out of range: starting below, ending past, or wrapping around
+    assert_eq!(unsafe { elf.load(base + 0x11..base + 0x100) }, Err(Error::OutOfRange));
+    assert_eq!(unsafe { elf.load(base..base + 0x2f) }, Err(Error::OutOfRange));
+    let wrapping = [load(0x100, 0x1000, core::u64::MAX - 4, 0x8, 0x20)];
+    let data_wrapping = image(0x1004, &wrapping, 0x108);
+    let elf_wrapping = Elf::parse(&data_wrapping).unwrap();
+    assert_eq!(unsafe { elf_wrapping.load(0..core::usize::MAX) }, Err(Error::OutOfRange));
+    assert!(memory.iter().all(|&b| b == 0x55));
+
+    assert_eq!(unsafe { elf.load(base..base + 0x100) }, Ok(base + 0x14));
+    assert!(memory[..0x10].iter().all(|&b| b == 0x55));
+    assert!(memory[0x10..0x18].iter().all(|&b| b == 0xaa));
+    assert!(memory[0x18..0x30].iter().all(|&b| b == 0));
+    assert!(memory[0x30..].iter().all(|&b| b == 0x55));
+}
@@ -0,0 +1,205 @@
+#![feature(const_fn)]
+#![cfg_attr(not(test), no_std)]
+
+//! The kernel log shared by the kernel and the hypervisor: levels, module
+//! filters and a ring buffer of the most recent lines for `dmesg`. Each of
+//! them keeps its own `LOGGER` behind its own lock and prints to its own
+//! console.
+
+extern crate alloc;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::str::FromStr;
+use core::time::Duration;
+
+/// Bytes of recent log lines kept for `dmesg`.
+pub const LOG_SIZE: usize = 16 * 1024;
+
+/// Severity of a log message, from most to least severe. A filter at a level
+/// lets through messages at that level and the ones above it.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Lets no messages through; not a message level.
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(&self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Level, ()> {
+        match s {
+            "off" => Ok(Level::Off),
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A byte ring buffer of the most recent log lines.
+pub struct Ring {
+    buf: [u8; LOG_SIZE],
+    start: usize,
+    len: usize,
+    wrapped: bool,
+}
+
+impl Ring {
+    pub const fn new() -> Ring {
+        Ring { buf: [0; LOG_SIZE], start: 0, len: 0, wrapped: false }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == LOG_SIZE {
+            self.start = (self.start + 1) % LOG_SIZE;
+            self.len -= 1;
+            self.wrapped = true;
+        }
+        self.buf[(self.start + self.len) % LOG_SIZE] = byte;
+        self.len += 1;
+    }
+
+    /// Returns the last `n` buffered lines, oldest first. Once older lines
+    /// have been overwritten, the partial line left at the start is skipped.
+    pub fn tail<'a>(&'a self, n: usize) -> impl Iterator<Item = u8> + 'a {
+        let byte = move |i: usize| self.buf[(self.start + i) % LOG_SIZE];
+        let mut from = self.len;
+        let mut lines = 0;
+        while from > 0 && lines < n {
+            // back over the newline ending the line, then to its start
+            from -= 1;
+            while from > 0 && byte(from - 1) != b'\n' {
+                from -= 1;
+            }
+            lines += 1;
+        }
+        if from == 0 && self.wrapped {
+            from = (0..self.len).find(|&i| byte(i) == b'\n').map_or(self.len, |i| i + 1);
+        }
+        (from..self.len).map(byte)
+    }
+
+    /// Returns the buffered lines, oldest first.
+    pub fn lines(&self) -> Vec<u8> {
+        self.tail(core::usize::MAX).collect()
+    }
+
+    /// Forgets every buffered line.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+        self.wrapped = false;
+    }
+}
+
+impl fmt::Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            self.push(b);
+        }
+        Ok(())
+    }
+}
+
+/// The log: its filters and its ring buffer.
+pub struct Logger {
+    /// Level of the modules no filter matches.
+    pub level: Level,
+    /// Levels of modules and the modules within them, by module path without
+    /// the crate name, e.g. `traps` or `vm::share`.
+    pub filters: Vec<(String, Level)>,
+    pub ring: Ring,
+}
+
+impl Logger {
+    pub const fn new() -> Logger {
+        Logger { level: Level::Info, filters: Vec::new(), ring: Ring::new() }
+    }
+
+    /// Returns the level messages from `module` are let through at: the
+    /// level of the most specific filter matching it, or the default level.
+    pub fn level_for(&self, module: &str) -> Level {
+        self.filters.iter()
+            .filter(|(prefix, _)| {
+                module == prefix || (module.starts_with(prefix.as_str()) && module[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |&(_, level)| level)
+    }
+
+    /// Sets the level of `module` and the modules within it, or removes its
+    /// filter if `level` is `None`.
+    pub fn set_filter(&mut self, module: &str, level: Option<Level>) {
+        self.filters.retain(|(prefix, _)| prefix != module);
+        if let Some(level) = level {
+            self.filters.push((String::from(module), level));
+        }
+    }
+
+    /// Buffers `record` if its module's level lets it through. Returns
+    /// whether it did, in which case the record should also be printed.
+    pub fn log(&mut self, record: &Record) -> bool {
+        if record.level > self.level_for(record.module) {
+            return false;
+        }
+        let _ = write!(self.ring, "{}\n", record);
+        true
+    }
+}
+
+/// A log message as it is printed and buffered.
+pub struct Record<'a> {
+    time: Duration,
+    level: Level,
+    module: &'a str,
+    args: fmt::Arguments<'a>,
+}
+
+impl<'a> Record<'a> {
+    /// Returns the message `args` at `level`, logged at `time` from the
+    /// module with path `module_path`.
+    pub fn new(time: Duration, level: Level, module_path: &'a str, args: fmt::Arguments<'a>) -> Record<'a> {
+        // drop the crate name, which is the same for every module
+        let module = module_path.splitn(2, "::").nth(1).unwrap_or(module_path);
+        Record { time, level, module, args }
+    }
+}
+
+impl<'a> fmt::Display for Record<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:5}.{:06}] {:<5} {}: {}", self.time.as_secs(), self.time.subsec_micros(),
+               self.level, self.module, self.args)
+    }
+}
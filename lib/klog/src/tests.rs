@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use crate::*;
+
+fn log(logger: &mut Logger, level: Level, module_path: &str, message: &str) -> bool {
+    logger.log(&Record::new(Duration::from_micros(1_500_000), level, module_path, format_args!("{}", message)))
+}
+
+fn lines(ring: &Ring) -> String {
+    String::from_utf8(ring.lines()).unwrap()
+}
+
+#[test]
+fn levels() {
+    assert!(Level::Error < Level::Warn && Level::Debug < Level::Trace);
+    assert_eq!("debug".parse(), Ok(Level::Debug));
+    assert_eq!("loud".parse::<Level>(), Err(()));
+    assert_eq!(format!("[{:<5}]", Level::Info), "[info ]");
+}
+
+#[test]
+fn filters() {
+    let mut logger = Logger::new();
+    logger.set_filter("vm", Some(Level::Trace));
+    logger.set_filter("vm::share", Some(Level::Error));
+    assert_eq!(logger.level_for("traps"), Level::Info);
+    assert_eq!(logger.level_for("vm"), Level::Trace);
+    assert_eq!(logger.level_for("vm::guest"), Level::Trace);
+    assert_eq!(logger.level_for("vm::share"), Level::Error);
+    assert_eq!(logger.level_for("vmm"), Level::Info);
+
+    logger.set_filter("vm::share", None);
+    assert_eq!(logger.level_for("vm::share"), Level::Trace);
+}
+
+#[test]
+fn records() {
+    let mut logger = Logger::new();
+    assert!(log(&mut logger, Level::Info, "kernel::traps", "irq"));
+    assert!(!log(&mut logger, Level::Debug, "kernel::traps", "hidden"));
+    assert!(log(&mut logger, Level::Warn, "kernel", "no module"));
+    assert_eq!(lines(&logger.ring), "[    1.500000] info  traps: irq\n[    1.500000] warn  kernel: no module\n");
+
+    logger.ring.clear();
+    assert_eq!(lines(&logger.ring), "");
+}
+
+#[test]
+fn ring_tail() {
+    let mut ring = Ring::new();
+    for i in 0..4 {
+        write!(ring, "line {}\n", i).unwrap();
+    }
+    assert_eq!(String::from_utf8(ring.tail(2).collect()).unwrap(), "line 2\nline 3\n");
+
+    // once it wraps, the partial oldest line is dropped
+    let line = [b'x'; 99];
+    for _ in 0..LOG_SIZE / 100 + 1 {
+        write!(ring, "{}\n", std::str::from_utf8(&line).unwrap()).unwrap();
+    }
+    let text = lines(&ring);
+    assert!(text.len() < LOG_SIZE && text.len() % 100 == 0);
+    assert!(text.lines().all(|l| l.len() == 99));
+}
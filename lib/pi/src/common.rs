@@ -5,6 +5,10 @@ pub const IO_BASE_END: usize = 0x40000000;
 /// The base address of the `GPIO` registers
 pub const GPIO_BASE: usize = IO_BASE + 0x200000;
 
+/// Bytes at the end of RAM that the kernel and the hypervisor leave alone for
+/// the bootloader, which keeps its boot menu choice there across resets.
+pub const BOOT_RESERVED_SIZE: usize = 0x1000;
+
 /// The number of cores in Rpi3
 pub const NCORES: usize = 4;
 
@@ -5,7 +5,7 @@ use volatile::{Volatile, ReadVolatile};
 
 const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
@@ -116,9 +116,9 @@ impl Controller {
     pub fn disable(&mut self, int: Interrupt) {
         let irq_idx = int as u32;
         if irq_idx < 32 {
-            self.registers.Enable_IRQ_1.or_mask(1 << irq_idx);
+            self.registers.Disable_IRQ_1.write(1 << irq_idx);
         } else {
-            self.registers.Enable_IRQ_2.or_mask(1 << (irq_idx - 32));
+            self.registers.Disable_IRQ_2.write(1 << (irq_idx - 32));
         }
     }
 
@@ -126,9 +126,9 @@ impl Controller {
     pub fn is_pending(&self, int: Interrupt) -> bool {
         let irq_idx = int as u32;
         if irq_idx < 32 {
-            self.registers.Enable_IRQ_1.read() & (1 << irq_idx) != 0
+            self.registers.IRQ_pending_1.read() & (1 << irq_idx) != 0
         } else {
-            self.registers.Enable_IRQ_2.read() & (1 << (irq_idx - 32)) != 0
+            self.registers.IRQ_pending_2.read() & (1 << (irq_idx - 32)) != 0
         }
     }
 }
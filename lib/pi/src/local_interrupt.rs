@@ -0,0 +1,83 @@
+use volatile::prelude::*;
+use volatile::{Volatile, ReadVolatile, Reserved};
+
+/// The base address of the ARM local peripherals: per-core timer, mailbox and
+/// interrupt routing registers (ref: BCM2836 ARM-local peripherals, QA7).
+pub const LOCAL_BASE: usize = 0x4000_0000;
+pub const LOCAL_BASE_END: usize = LOCAL_BASE + 0x100;
+
+/// Interrupt sources of a core, as bit positions in its interrupt source
+/// registers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LocalInterrupt {
+    CntPs = 0,
+    CntPns = 1,
+    CntHp = 2,
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// Any interrupt of the GPU (`interrupt::Controller`), when routed here.
+    Gpu = 8,
+    Pmu = 9,
+    LocalTimer = 11,
+}
+
+impl LocalInterrupt {
+    /// Returns `true` if this is one of the four generic timer interrupts,
+    /// which are enabled through the core timer interrupt control register.
+    pub fn is_timer(self) -> bool {
+        (self as u32) < 4
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    __r0: Reserved<u32>,
+    CORE_TIMER_PRESCALER: Volatile<u32>,
+    GPU_INT_ROUTING: Volatile<u32>,
+    __r1: [Reserved<u32>; 12],
+    CORE_TIMER_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_MAILBOX_INT_CONTROL: [Volatile<u32>; 4],
+    CORE_IRQ_SOURCE: [ReadVolatile<u32>; 4],
+    CORE_FIQ_SOURCE: [ReadVolatile<u32>; 4],
+}
+
+/// The ARM local interrupt controller of one core. Generic timer interrupts
+/// can only reach a core through it, and it reports whether a pending IRQ
+/// came from the GPU interrupt controller.
+pub struct LocalController {
+    core: usize,
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller of `core`.
+    pub fn new(core: usize) -> LocalController {
+        assert!(core < crate::common::NCORES);
+        LocalController {
+            core,
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Routes the generic timer interrupt `int` to this core's IRQ.
+    pub fn enable_timer(&mut self, int: LocalInterrupt) {
+        assert!(int.is_timer());
+        self.registers.CORE_TIMER_INT_CONTROL[self.core].or_mask(1 << int as u32);
+    }
+
+    /// Stops routing the generic timer interrupt `int` to this core's IRQ.
+    pub fn disable_timer(&mut self, int: LocalInterrupt) {
+        assert!(int.is_timer());
+        self.registers.CORE_TIMER_INT_CONTROL[self.core].and_mask(!(1 << int as u32));
+    }
+
+    /// Returns `true` if `int` is pending as an IRQ on this core.
+    pub fn is_pending(&self, int: LocalInterrupt) -> bool {
+        self.registers.CORE_IRQ_SOURCE[self.core].read() & (1 << int as u32) != 0
+    }
+}
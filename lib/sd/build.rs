@@ -0,0 +1,27 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Directory with `libsd.a` when `LIBSD_DIR` isn't set, relative to this
+/// manifest: the kernel's `.cargo`, where the archive has always lived.
+const DEFAULT_DIR: &str = "../../kern/.cargo";
+
+/// Links `libsd.a` into whatever uses the driver: the kernel, the hypervisor
+/// and the bootloader. The archive isn't tracked; it is taken from the
+/// directory in `LIBSD_DIR` or else from `kern/.cargo`.
+pub fn main() {
+    println!("cargo:rerun-if-env-changed=LIBSD_DIR");
+
+    let dir = match env::var("LIBSD_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join(DEFAULT_DIR),
+    };
+    let lib = dir.join("libsd.a");
+    if !lib.is_file() {
+        panic!("{} is missing: copy the SD card driver's libsd.a there, \
+                or point LIBSD_DIR at the directory holding it", lib.display());
+    }
+
+    println!("cargo:rerun-if-changed={}", lib.display());
+    println!("cargo:rustc-link-search=native={}", dir.display());
+    println!("cargo:rustc-link-lib=static=sd");
+}
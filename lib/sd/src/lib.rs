@@ -1,3 +1,10 @@
+#![no_std]
+
+//! The SD card driver shared by the kernel, the hypervisor and the
+//! bootloader, over the controller code in `libsd.a`. The archive isn't
+//! tracked; `build.rs` links it from `kern/.cargo/`, or from the directory in
+//! `LIBSD_DIR` if that is set.
+
 use core::time::Duration;
 use shim::io;
 use shim::ioerr;
@@ -30,10 +37,10 @@ extern "C" {
     fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
 }
 
-// FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
-// The `wait_micros` C signature is: `void wait_micros(unsigned int);`
+/// Sleeps for `100 * time` microseconds on behalf of `libsd`, which calls it
+/// as `void wait_micros(unsigned int)`.
 #[no_mangle]
-fn wait_micros(time: u32) {
+pub extern "C" fn wait_micros(time: u32) {
     timer::spin_sleep(Duration::from_micros(100 * time as u64));
 }
 
@@ -43,17 +50,22 @@ pub struct Sd;
 
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
-    /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization. We can enforce the requirement in safe Rust code
-    /// with atomic memory access, but we can't use it yet since we haven't
-    /// written the memory management unit (MMU).
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `TimedOut` is returned if the controller times out,
+    /// and an error of kind `Other` for every other failure.
+    ///
+    /// # Safety
+    ///
+    /// The controller must be initialized only once, and only one handle to
+    /// it may be used at a time.
     pub unsafe fn new() -> Result<Sd, io::Error> {
-        let result = sd_init();
-        match result {
-            0 => Ok(Sd{}),
+        match sd_init() {
+            0 => Ok(Sd),
             -1 => ioerr!(TimedOut, "sd card init timeout"),
             -2 => ioerr!(Other, "unknown sd card init error"),
-            _ => panic!("unexpected sd card return code")
+            _ => ioerr!(Other, "unexpected sd card init return code"),
         }
     }
 }
@@ -64,31 +76,36 @@ impl BlockDevice for Sd {
     ///
     /// # Errors
     ///
-    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512`,
+    /// `buf` isn't 4-byte aligned or `n > 2^31 - 1` (the maximum value for an
+    /// `i32`).
     ///
     /// An error of kind `TimedOut` is returned if a timeout occurs while
     /// reading from the SD card.
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() < 512 || n > ((1<<31)-1) {
+        if buf.len() < 512 || n > ((1 << 31) - 1) {
             return ioerr!(InvalidInput, "invalid n");
         }
-        unsafe {
-            assert!((buf.as_ptr() as usize) & 0b11 == 0, "sd buffer alignment"); // assert alignment
-            let result = sd_readsector(n as i32, buf.as_mut_ptr());
-            match result {
-                0 => ioerr!(Other, "sd card i/o error"),
-                -1 => ioerr!(TimedOut, "sd card timeout"),
-                -2 => ioerr!(Other, "sd card controller timeout"),
-                n if n > 0 => Ok(n as usize),
-                _ => panic!("unexpected sd card return code")
-            }
+        if (buf.as_ptr() as usize) & 0b11 != 0 {
+            return ioerr!(InvalidInput, "sd buffer not 4-byte aligned");
+        }
+        match unsafe { sd_readsector(n as i32, buf.as_mut_ptr()) } {
+            0 => ioerr!(Other, "sd card i/o error"),
+            -1 => ioerr!(TimedOut, "sd card timeout"),
+            -2 => ioerr!(Other, "sd card controller timeout"),
+            n if n > 0 => Ok(n as usize),
+            _ => ioerr!(Other, "unexpected sd card return code"),
         }
     }
 
+    /// The SD card is read only.
+    ///
+    /// # Errors
+    ///
+    /// Always returns an error of kind `PermissionDenied`.
     fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+        ioerr!(PermissionDenied, "sd card is read only")
     }
 }
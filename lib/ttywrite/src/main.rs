@@ -1,17 +1,23 @@
 mod parsers;
+mod port;
 
 use serial;
 use structopt;
 use structopt_derive::StructOpt;
-use xmodem::Xmodem;
+use xmodem::{Xmodem, Ymodem};
 
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
 use std::time::Duration;
 
 use structopt::StructOpt;
 use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, SerialPortSettings};
 
 use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+use port::Port;
 
 #[derive(StructOpt, Debug)]
 #[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
@@ -31,7 +37,8 @@ struct Opt {
                 help = "Set data character width in bits", default_value = "8")]
     char_width: CharSize,
 
-    #[structopt(help = "Path to TTY device", parse(from_os_str))]
+    #[structopt(help = "Path to TTY device, or tcp://host:port or unix:/path for a socket",
+                parse(from_os_str))]
     tty_path: PathBuf,
 
     #[structopt(short = "f", long = "flow-control", parse(try_from_str = "parse_flow_control"),
@@ -44,35 +51,187 @@ struct Opt {
 
     #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
     raw: bool,
+
+    #[structopt(short = "y", long = "ymodem", help = "Send the input as a named file with YMODEM")]
+    ymodem: bool,
+
+    #[structopt(long = "receive", help = "Receive with XMODEM instead of sending",
+                conflicts_with = "input", conflicts_with = "raw",
+                conflicts_with = "ymodem", conflicts_with = "reboot")]
+    receive: bool,
+
+    #[structopt(short = "o", long = "output", requires = "receive",
+                help = "Output file for --receive (defaults to stdout if not set)", parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[structopt(long = "reboot", conflicts_with = "raw", help = "Reboot into the bootloader with ^R first, then send with YMODEM")]
+    reboot: bool,
+
+    #[structopt(long = "terminal", requires = "input",
+                help = "Stay attached to the TTY after sending; ^] detaches")]
+    terminal: bool,
 }
 
+/// Makes the shell reboot into the bootloader.
+const REBOOT_KEY: u8 = 0x12; // ^R
+
+/// Printed by the bootloader before its menu.
+const BOOT_MENU: &[u8] = b"Boot menu:";
+
+/// The bootloader's menu entry that waits for an upload.
+const UPLOAD_ENTRY: u8 = b'2';
+
+/// Detaches the terminal.
+const DETACH_KEY: u8 = 0x1d; // ^]
+
 fn on_progress(progress: xmodem::Progress) {
-    println!("Progress: {:?}", progress);
+    eprintln!("Progress: {:?}", progress);
+}
+
+/// Reboots the device with the shell's reboot key and picks the upload entry
+/// of the bootloader's menu, echoing everything received until then.
+fn reboot<T: io::Read + io::Write>(port: &mut T) -> io::Result<()> {
+    port.write_all(&[REBOOT_KEY])?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut seen = Vec::with_capacity(BOOT_MENU.len() + 1);
+    let mut byte = [0u8];
+    while seen != BOOT_MENU {
+        port.read_exact(&mut byte)?;
+        stdout.write_all(&byte)?;
+        seen.push(byte[0]);
+        if seen.len() > BOOT_MENU.len() {
+            seen.remove(0);
+        }
+    }
+    stdout.flush()?;
+    port.write_all(&[UPLOAD_ENTRY])
+}
+
+/// Puts the controlling terminal in raw mode and returns its old settings, or
+/// `None` if stdin isn't a terminal.
+fn raw_mode() -> Option<String> {
+    let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).output().ok()?;
+    if !saved.status.success() {
+        return None;
+    }
+    Command::new("stty").args(&["raw", "-echo"]).status().ok()?;
+    Some(String::from_utf8_lossy(&saved.stdout).trim().to_string())
+}
+
+/// Sends keys from stdin to `port` and prints what it receives until stdin is
+/// closed, `DETACH_KEY` is pressed or the other end of a socket hangs up.
+fn attach<T: io::Read + io::Write>(port: &mut T) -> io::Result<()> {
+    let (keys, key_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = io::stdin().read(&mut buf) {
+            if n == 0 || keys.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut buf = [0u8; 256];
+    loop {
+        match port.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                stdout.write_all(&buf[..n])?;
+                stdout.flush()?;
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {},
+            Err(e) => return Err(e),
+        }
+
+        loop {
+            match key_rx.try_recv() {
+                Ok(keys) => match keys.iter().position(|&k| k == DETACH_KEY) {
+                    Some(i) => return port.write_all(&keys[..i]),
+                    None => port.write_all(&keys)?,
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Attaches the terminal to `port` until it is detached.
+fn terminal(port: &mut Port) -> io::Result<()> {
+    // poll the port often enough that typing doesn't lag
+    port.set_timeout(Duration::from_millis(10))?;
+    eprintln!("Attached; press ^] to detach.");
+
+    let saved = raw_mode();
+    let result = attach(port);
+    if let Some(saved) = saved {
+        Command::new("stty").arg(saved).status()?;
+    }
+    result
 }
 
 fn main() {
     use std::fs::File;
-    use std::io::{self, BufReader};
+    use std::io::BufReader;
 
     let opt = Opt::from_args();
-    let mut port = serial::open(&opt.tty_path).expect("path points to invalid TTY");
+    let mut port = Port::open(&opt.tty_path).expect("path points to invalid TTY or socket");
     
     port.set_timeout(Duration::from_secs(opt.timeout)).expect("failed to set timeout");
-    let mut settings = port.read_settings().expect("failed to get settings");
-    settings.set_baud_rate(opt.baud_rate).expect("failed to set baud rate");
-    settings.set_char_size(opt.char_width);
-    settings.set_flow_control(opt.flow_control);
-    settings.set_stop_bits(opt.stop_bits);
-    port.write_settings(&settings).expect("failed to write settings");
-
-    let mut source: Box<dyn io::Read> = match opt.input {
-        Some(p) => Box::new(BufReader::new(File::open(p).expect("nonexistent input file"))),
-        None => Box::new(std::io::stdin())
-    };
-    
-    if opt.raw {
-        io::copy(source.as_mut(), &mut port).expect("write failed");
+    if let Port::Serial(ref mut tty) = port {
+        let mut settings = tty.read_settings().expect("failed to get settings");
+        settings.set_baud_rate(opt.baud_rate).expect("failed to set baud rate");
+        settings.set_char_size(opt.char_width);
+        settings.set_flow_control(opt.flow_control);
+        settings.set_stop_bits(opt.stop_bits);
+        tty.write_settings(&settings).expect("failed to write settings");
+    }
+
+    if opt.receive {
+        let mut sink: Box<dyn io::Write> = match opt.output {
+            Some(p) => Box::new(File::create(p).expect("failed to create output file")),
+            None => Box::new(io::stdout())
+        };
+        Xmodem::receive_with_progress(&mut port, sink.as_mut(), on_progress).expect("read failed");
+        return;
+    }
+
+    if opt.reboot {
+        reboot(&mut port).expect("bootloader didn't show its menu");
+    }
+
+    if opt.ymodem || opt.reboot {
+        let (name, data) = match opt.input {
+            Some(p) => {
+                let name = p.file_name().expect("input is not a file").to_string_lossy().into_owned();
+                (name, std::fs::read(&p).expect("nonexistent input file"))
+            },
+            None => {
+                let mut data = vec![];
+                io::stdin().read_to_end(&mut data).expect("failed to read stdin");
+                (String::from("stdin"), data)
+            }
+        };
+        Ymodem::transmit_with_progress(&name, data.len() as u64, &data[..], &mut port, on_progress)
+            .expect("write failed");
     } else {
-        Xmodem::transmit_with_progress(source.as_mut(), &mut port, on_progress).expect("write failed");
+        let mut source: Box<dyn io::Read> = match opt.input {
+            Some(p) => Box::new(BufReader::new(File::open(p).expect("nonexistent input file"))),
+            None => Box::new(std::io::stdin())
+        };
+
+        if opt.raw {
+            io::copy(source.as_mut(), &mut port).expect("write failed");
+        } else {
+            Xmodem::transmit_with_progress(source.as_mut(), &mut port, on_progress).expect("write failed");
+        }
+    }
+
+    if opt.terminal {
+        terminal(&mut port).expect("terminal failed");
     }
 }
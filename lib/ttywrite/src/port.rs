@@ -0,0 +1,101 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use serial::SystemPort;
+use serial::core::SerialDevice;
+
+/// Prefix of TCP targets, followed by `host:port`.
+const TCP_PREFIX: &str = "tcp://";
+
+/// Prefix of Unix socket targets, followed by the socket's path.
+#[cfg(unix)]
+const UNIX_PREFIX: &str = "unix:";
+
+/// The serial endpoint being written to: a TTY or a socket in its place, such
+/// as an emulated board's UART.
+pub enum Port {
+    Serial(SystemPort),
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Port {
+    /// Opens `target`, which is either `tcp://host:port`, `unix:/path` or the
+    /// path to a TTY device.
+    pub fn open(target: &Path) -> io::Result<Port> {
+        let name = target.to_str().unwrap_or("");
+        if name.starts_with(TCP_PREFIX) {
+            return Ok(Port::Tcp(TcpStream::connect(&name[TCP_PREFIX.len()..])?));
+        }
+        #[cfg(unix)]
+        {
+            if name.starts_with(UNIX_PREFIX) {
+                return Ok(Port::Unix(UnixStream::connect(&name[UNIX_PREFIX.len()..])?));
+            }
+        }
+        Ok(Port::Serial(serial::open(target)?))
+    }
+
+    /// Sets how long reads and writes wait before failing with `TimedOut`.
+    pub fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        match *self {
+            Port::Serial(ref mut port) => Ok(port.set_timeout(timeout)?),
+            Port::Tcp(ref stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            },
+            #[cfg(unix)]
+            Port::Unix(ref stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            },
+        }
+    }
+}
+
+/// Sockets fail with `WouldBlock` when they time out, but XMODEM expects
+/// `TimedOut`, like the TTY gives.
+fn timed_out<T>(result: io::Result<T>) -> io::Result<T> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "operation timed out"))
+        },
+        result => result,
+    }
+}
+
+impl Read for Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Port::Serial(ref mut port) => port.read(buf),
+            Port::Tcp(ref mut stream) => timed_out(stream.read(buf)),
+            #[cfg(unix)]
+            Port::Unix(ref mut stream) => timed_out(stream.read(buf)),
+        }
+    }
+}
+
+impl Write for Port {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Port::Serial(ref mut port) => port.write(buf),
+            Port::Tcp(ref mut stream) => timed_out(stream.write(buf)),
+            #[cfg(unix)]
+            Port::Unix(ref mut stream) => timed_out(stream.write(buf)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Port::Serial(ref mut port) => port.flush(),
+            Port::Tcp(ref mut stream) => stream.flush(),
+            #[cfg(unix)]
+            Port::Unix(ref mut stream) => stream.flush(),
+        }
+    }
+}
@@ -1,15 +1,92 @@
 #! /bin/bash
 
+# compare bytes, not characters
+export LC_ALL=C
+
+PIDS=()
+
 function cleanup_and_exit() {
-  kill $!
+  exec 3>&-
+  kill ${PIDS[@]} 2> /dev/null
+  rm -f sent received socket
   exit $1
 }
 
+# prints an error with message $1 and exits
+function fail() {
+  echo -e "${KRED}ERROR: $1${KNRM}" >&2
+  cleanup_and_exit 1
+}
+
+# fails unless the expected output $1 and the output $2 are the same
+function check() {
+  if [[ "$2" != "$1" ]]; then
+    echo "$1 != $2" >&2
+    fail "input and output differ"
+  fi
+}
+
 # generates a random base64 encoded string between 1 and 512 bytes
 function rand_string() {
   base64 < /dev/urandom | head -c $((1 + RANDOM % 512))
 }
 
+# sets the options "${@:2}" of the TTY at $1
+function set_tty() {
+  if [[ "$(uname)" = "Darwin" ]]; then
+    stty -f "$1" "${@:2}"
+  else
+    stty -F "$1" "${@:2}"
+  fi
+}
+
+# reads the next byte ttywrite sends to the fake device into $byte
+function read_byte() {
+  read -r -N1 -t 5 byte <&3 || fail "timed out waiting for ttywrite"
+}
+
+# prints the XMODEM CRC-16 of the string $1
+function crc16() {
+  local crc=0 i j
+  for ((i = 0; i < ${#1}; i++)); do
+    crc=$((crc ^ ($(printf '%d' "'${1:i:1}") << 8)))
+    for ((j = 0; j < 8; j++)); do
+      if ((crc & 0x8000)); then
+        crc=$((((crc << 1) ^ 0x1021) & 0xffff))
+      else
+        crc=$(((crc << 1) & 0xffff))
+      fi
+    done
+  done
+  echo ${crc}
+}
+
+# checks a send to, and a terminal on, the $1 socket socat listens on at $2
+# and ttywrite connects to at $3
+function test_socket() {
+  echo -e "${KBLU}Running $1 socket test.${KNRM}"
+  local input output listener
+  input=$(rand_string)
+  socat -u "$2" CREATE:received &
+  listener=$!
+  sleep 1
+  ./target/debug/ttywrite -i <(echo "${input}") -r "$3"
+  wait ${listener}
+  check "${input}" "$(cat received)"
+
+  # the terminal returns once the other end hangs up
+  echo "${input}" > sent
+  socat -u OPEN:sent "$2" &
+  listener=$!
+  sleep 1
+  output=$(timeout 5 ./target/debug/ttywrite -i /dev/null -r --terminal "$3" 2> /dev/null < <(sleep 10))
+  if [[ $? -ne 0 ]]; then
+    fail "the terminal didn't return when the $1 socket closed"
+  fi
+  wait ${listener}
+  check "${input}" "${output}"
+}
+
 # Use color when outputting to the terminal.
 if [ -t 1 ]; then
   KNRM="\x1B[0m"; KRED="\x1B[31m"; KGRN="\x1B[32m"; KBLU="\x1B[34m"
@@ -32,15 +109,16 @@ echo -e "${KBLU}Opening PTYs...${KNRM}"
 # PARAMS="pty,echo=0,raw,ispeed=19200,ospeed=19200,parenb=0,cs8,cstopb=0"
 PARAMS="pty,echo=0,raw,parenb=0,cs8,cstopb=0"
 socat -u ${PARAMS},link=input ${PARAMS},link=output &
+PIDS+=($!)
+# a device ttywrite talks to both ways, played by this script
+socat ${PARAMS},link=device ${PARAMS},link=host &
+PIDS+=($!)
 sleep 1
 
-if [[ "$(uname)" = "Darwin" ]]; then
-  stty -f input min 0 time 1
-  stty -f output min 0 time 1
-else
-  stty -F input min 0 time 1
-  stty -F output min 0 time 1
-fi
+set_tty input min 0 time 1
+set_tty output min 0 time 1
+set_tty device min 1 time 0
+exec 3<> device
 
 for i in {1..10}; do
   echo -e "${KBLU}Running test ${i}/10.${KNRM}"
@@ -55,5 +133,43 @@ for i in {1..10}; do
   fi
 done
 
+echo -e "${KBLU}Running receive test.${KNRM}"
+input=$(base64 < /dev/urandom | tr -d '\n' | head -c 128)
+./target/debug/ttywrite --receive -o received -t 1 host 2> /dev/null &
+receiver=$!
+read_byte
+[[ "${byte}" == C ]] || fail "the receiver didn't ask for CRC mode"
+crc=$(crc16 "${input}")
+printf '\x01\x01\xfe%s' "${input}" >&3
+printf "\\x$(printf %02x $((crc >> 8)))\\x$(printf %02x $((crc & 0xff)))" >&3
+read_byte
+[[ "${byte}" == $'\x06' ]] || fail "the receiver didn't ACK the packet"
+printf '\x04' >&3
+read_byte
+[[ "${byte}" == $'\x15' ]] || fail "the receiver didn't NAK the first EOT"
+printf '\x04' >&3
+read_byte
+[[ "${byte}" == $'\x06' ]] || fail "the receiver didn't ACK the second EOT"
+wait ${receiver} || fail "the receiver failed"
+check "${input}" "$(cat received)"
+
+echo -e "${KBLU}Running reboot test.${KNRM}"
+./target/debug/ttywrite --reboot -t 1 -i <(echo "${input}") host > /dev/null 2>&1 &
+sender=$!
+read_byte
+[[ "${byte}" == $'\x12' ]] || fail "ttywrite didn't send the reboot key"
+printf 'oyasumi.\r\nBoot menu:\r\n' >&3
+read_byte
+[[ "${byte}" == 2 ]] || fail "ttywrite didn't pick the upload entry"
+printf 'C' >&3
+read_byte
+[[ "${byte}" == $'\x01' ]] || fail "ttywrite didn't start the YMODEM header"
+kill ${sender}
+wait ${sender} 2> /dev/null
+
+PORT=$((20000 + RANDOM % 20000))
+test_socket TCP "TCP-LISTEN:${PORT},reuseaddr,bind=127.0.0.1" "tcp://127.0.0.1:${PORT}"
+test_socket Unix "UNIX-LISTEN:socket,unlink-early" "unix:socket"
+
 echo -e "${KGRN}SUCCESS${KNRM}"
 cleanup_and_exit 0
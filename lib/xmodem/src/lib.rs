@@ -8,21 +8,46 @@ use shim::ioerr;
 #[cfg(test)] mod tests;
 mod read_ext;
 mod progress;
+mod ymodem;
 
 pub use progress::{Progress, ProgressFn};
+pub use ymodem::{FileInfo, Ymodem};
 
 use read_ext::ReadExt;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+
+/// Number of `CRC` handshakes a receiver sends before falling back to `NAK`.
+const CRC_TRIES: u8 = 3;
+
+/// Most bytes a sender skips while waiting for the receiver's handshake, such
+/// as a banner or menu the receiver prints first.
+const MAX_SKIP: usize = 4096;
+
+/// Number of times in a row a receiver rejects a 1024-byte block before the
+/// sender falls back to 128-byte packets.
+const ONE_K_NAKS: u8 = 3;
 
 /// Implementation of the XMODEM protocol.
+///
+/// Transfers use XMODEM-CRC when the receiver asks for it with `C` and plain
+/// checksums when it sends `NAK`. In CRC mode the sender uses 1024-byte
+/// XMODEM-1K blocks and drops back to 128-byte packets if the receiver
+/// keeps rejecting one.
 pub struct Xmodem<R> {
     packet: u8,
     started: bool,
+    crc: bool,
+    one_k: bool,
+    tries: u8,
+    naks: u8,
+    header_optional: bool,
     inner: R,
     progress: ProgressFn
 }
@@ -42,40 +67,20 @@ impl Xmodem<()> {
 
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
     /// length of the total data yielded by `data` is not a multiple of 128
-    /// bytes, the data is padded with zeroes and sent to the receiver.
+    /// bytes, the data is padded with zeroes and sent to the receiver. Data
+    /// is sent in 1024-byte blocks if the receiver negotiates CRC mode.
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
+    pub fn transmit_with_progress<R, W>(data: R, to: W, f: ProgressFn) -> io::Result<usize>
         where W: io::Read + io::Write, R: io::Read
     {
         let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
-
-            return ioerr!(BrokenPipe, "bad transmit");
-        }
+        let written = transmitter.send_all(data, None)?;
+        transmitter.write_packet(&[])?;
+        Ok(written)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
@@ -96,26 +101,17 @@ impl Xmodem<()> {
        where R: io::Read + io::Write, W: io::Write
     {
         let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
+        let mut packet = [0u8; 1024];
         let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => break 'next_packet,
-                    Ok(n) => {
-                        received += n;
-                        into.write_all(&packet)?;
-                        continue 'next_packet;
-                    }
+        loop {
+            match receiver.retry(|x| x.read_packet(&mut packet), "bad receive")? {
+                0 => return Ok(received),
+                n => {
+                    received += n;
+                    into.write_all(&packet[..n])?;
                 }
             }
-
-            return ioerr!(BrokenPipe, "bad receive");
         }
-
-        Ok(received)
     }
 }
 
@@ -123,12 +119,22 @@ fn get_checksum(buf: &[u8]) -> u8 {
     return buf.iter().fold(0, |a, b| a.wrapping_add(*b));
 }
 
+/// Returns the CRC-16 of `buf` used by XMODEM-CRC: polynomial 0x1021, zero
+/// initial value, sent high byte first.
+fn get_crc(buf: &[u8]) -> u16 {
+    buf.iter().fold(0, |crc, b| {
+        (0..8).fold(crc ^ ((*b as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
 impl<T: io::Read + io::Write> Xmodem<T> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading).
     pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: progress::noop}
+        Xmodem::new_with_progress(inner, progress::noop)
     }
 
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
@@ -137,7 +143,68 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
     pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: f }
+        Xmodem { packet: 1, started: false, crc: false, one_k: false, tries: 0, naks: 0, header_optional: false, inner, progress: f }
+    }
+
+    /// Starts over at packet `packet` with a new handshake, as for each file
+    /// of a YMODEM batch.
+    fn restart(&mut self, packet: u8) {
+        self.packet = packet;
+        self.started = false;
+        self.crc = false;
+        self.tries = 0;
+        self.naks = 0;
+    }
+
+    /// Calls `op` until it succeeds, trying up to 10 times while it fails with
+    /// `Interrupted` because the other side asked for the packet again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error `op` returns that isn't `Interrupted`, or an
+    /// error of kind `BrokenPipe` with the message `failure` after 10 tries.
+    fn retry<F>(&mut self, mut op: F, failure: &'static str) -> io::Result<usize>
+        where F: FnMut(&mut Self) -> io::Result<usize>
+    {
+        for _ in 0..10 {
+            match op(self) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+        ioerr!(BrokenPipe, failure)
+    }
+
+    /// Sends everything `data` yields, padded with zeroes to a multiple of 128
+    /// bytes, but not the `EOT` that ends the transfer. If the `size` of the
+    /// data is known, the progress callback is called with `Progress::Bytes`
+    /// after every packet.
+    ///
+    /// Returns the number of bytes read from `data`.
+    fn send_all<R: io::Read>(&mut self, mut data: R, size: Option<u64>) -> io::Result<usize> {
+        let mut block = [0u8; 1024];
+        let mut written = 0;
+        loop {
+            let n = data.read_max(&mut block)?;
+            if n == 0 {
+                return Ok(written);
+            }
+
+            let len = (n + 127) / 128 * 128;
+            block[n..len].iter_mut().for_each(|b| *b = 0);
+
+            let mut sent = 0;
+            while sent < len {
+                let m = self.retry(|x| x.write_packet(&block[sent..len]), "bad transmit")?;
+                sent += m;
+                if let Some(size) = size {
+                    let done = core::cmp::min((written + sent) as u64, size);
+                    (self.progress)(Progress::Bytes(done, size));
+                }
+            }
+
+            written += n;
+        }
     }
 
     /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
@@ -209,8 +276,38 @@ impl<T: io::Read + io::Write> Xmodem<T> {
         }
     }
 
+    /// Sends the receiver's handshake, `CRC` for the first `CRC_TRIES`
+    /// attempts and `NAK` after that, and returns the sender's first byte.
+    /// A sender that doesn't answer a `CRC` in time is asked again and
+    /// eventually offered plain checksums.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading or writing to the inner stream fails, other
+    /// than a `TimedOut` read after a `CRC` handshake.
+    fn handshake(&mut self) -> io::Result<u8> {
+        loop {
+            self.crc = self.tries < CRC_TRIES;
+            self.write_byte(if self.crc { CRC } else { NAK })?;
+            match self.read_byte(true) {
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut && self.crc => {
+                    self.tries += 1;
+                }
+                result => {
+                    self.started = true;
+                    (self.progress)(Progress::Started);
+                    return result;
+                }
+            }
+        }
+    }
+
     /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// protocol. On success, returns the number of bytes read: 128, or 1024 for
+    /// an XMODEM-1K block.
+    ///
+    /// The first call negotiates the transfer mode: CRC mode is requested with
+    /// `C` and, if the sender doesn't answer, plain checksums with `NAK`.
     ///
     /// The progress callback is called with `Progress::Started` when reception
     /// for the first packet has started and subsequently with
@@ -222,26 +319,27 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
+    ///   * The sender's first byte for a packet isn't `EOT`, `SOH` or `STX`.
     ///   * The sender doesn't send a second `EOT` after the first.
     ///   * The received packet numbers don't match the expected values.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// An error of kind `Interrupted` is returned if a packet checksum or CRC
+    /// fails.
     ///
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
+    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`, or if
+    /// `buf.len() < 1024` and the sender starts a 1024-byte block.
     pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if buf.len() < 128 {
             return ioerr!(UnexpectedEof, "buffer must be of length 128");
         }
-        if !self.started {
-            self.write_byte(NAK)?;
-            self.started = true;
-            (self.progress)(Progress::Started);
-        }
-        let read_result = self.read_byte(true)?;
+        let read_result = if self.started {
+            self.read_byte(true)?
+        } else {
+            self.handshake()?
+        };
         if read_result == EOT {
             self.started = false;
             self.write_byte(NAK)?;
@@ -249,23 +347,44 @@ impl<T: io::Read + io::Write> Xmodem<T> {
             self.write_byte(ACK)?;
             return Ok(0); // apparently, return 0 means EOT.
         }
-        if read_result != SOH {
-            return ioerr!(InvalidData, "expected EOT or SOH at start of packet");
+        let size = match read_result {
+            SOH => 128,
+            STX => 1024,
+            _ => return ioerr!(InvalidData, "expected EOT, SOH or STX at start of packet"),
+        };
+        if buf.len() < size {
+            self.write_byte(CAN)?;
+            return ioerr!(UnexpectedEof, "buffer must be of length 1024 for STX");
+        }
+        match self.read_byte(self.packet != CAN)? {
+            packet if packet == self.packet => {},
+            // a plain XMODEM sender starts with packet 1 in place of a header
+            1 if self.header_optional => self.packet = 1,
+            _ => {
+                self.write_byte(CAN)?;
+                return ioerr!(InvalidData, "expected packet number");
+            }
         }
-        self.expect_byte_or_cancel(self.packet, "expected packet number")?;
         self.expect_byte_or_cancel(!self.packet, "expected complemented packet number")?;
-        for i in 0..128 {
+        for i in 0..size {
             buf[i] = self.read_byte(false)?;
         }
-        if self.read_byte(false)? != get_checksum(&buf[..128]) {
+        let valid = if self.crc {
+            let crc = (self.read_byte(false)? as u16) << 8 | self.read_byte(false)? as u16;
+            crc == get_crc(&buf[..size])
+        } else {
+            self.read_byte(false)? == get_checksum(&buf[..size])
+        };
+        if !valid {
             (self.progress)(Progress::NAK);
             self.write_byte(NAK)?;
             return ioerr!(Interrupted, "checksum failed");
         }
         self.write_byte(ACK)?;
+        self.header_optional = false;
         (self.progress)(Progress::Packet(self.packet));
         self.packet = self.packet.wrapping_add(1);
-        Ok(128)
+        Ok(size)
     }
 
     /// Sends (uploads) a single packet to the inner stream using the XMODEM
@@ -274,10 +393,21 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// transmission is complete. On success, returns the number of bytes
     /// written.
     ///
+    /// The first call waits for the receiver's handshake: `C` at the start of
+    /// a line selects CRC mode and `NAK` plain checksums. Up to `MAX_SKIP`
+    /// other bytes, such as text the receiver printed first, are skipped; a
+    /// `C` within a line of that text doesn't start the transfer.
+    ///
+    /// In CRC mode, the first 1024 bytes of `buf` are sent as one XMODEM-1K
+    /// block if `buf` is long enough. Otherwise, and
+    /// for the rest of the transfer once the receiver has rejected the same
+    /// 1024-byte block `ONE_K_NAKS` times in a row, the first 128 bytes are
+    /// sent.
+    ///
     /// The progress callback is called with `Progress::Waiting` before waiting
-    /// for the receiver's `NAK`, `Progress::Started` when transmission of the
-    /// first packet has started and subsequently with `Progress::Packet` when a
-    /// packet is sent successfully.
+    /// for the receiver's handshake, `Progress::Started` when transmission of
+    /// the first packet has started and subsequently with `Progress::Packet`
+    /// when a packet is sent successfully.
     ///
     /// # Errors
     ///
@@ -285,7 +415,7 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The receiver's first byte isn't a `NAK`.
+    ///   * The receiver doesn't handshake within `MAX_SKIP` bytes.
     ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
     ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
     ///   * The receiver responds to a complete packet with something besides
@@ -301,7 +431,25 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
         if !self.started {
             (self.progress)(Progress::Waiting);
-            self.expect_byte(NAK, "expected NAK at start of packet")?;
+            // skip whatever the receiver printed before its handshake
+            let mut line_start = true;
+            let mut skipped = 0;
+            loop {
+                match self.read_byte(true)? {
+                    NAK => self.crc = false,
+                    CRC if line_start => self.crc = true,
+                    byte => {
+                        line_start = byte == b'\n';
+                        skipped += 1;
+                        if skipped > MAX_SKIP {
+                            return ioerr!(InvalidData, "expected a handshake");
+                        }
+                        continue;
+                    }
+                }
+                break;
+            }
+            self.one_k = self.crc;
             self.started = true;
             (self.progress)(Progress::Started);
         }
@@ -315,24 +463,40 @@ impl<T: io::Read + io::Write> Xmodem<T> {
         if buf.len() < 128 {
             return ioerr!(UnexpectedEof, "buffer length is not 128 or 0");
         }
-        self.write_byte(SOH)?;
+        let size = if self.one_k && buf.len() >= 1024 { 1024 } else { 128 };
+        self.write_byte(if size == 1024 { STX } else { SOH })?;
         self.write_byte(self.packet)?;
         self.write_byte(!self.packet)?;
-        for i in 0..128 {
+        for i in 0..size {
             self.write_byte(buf[i])?;
         }
-        self.write_byte(get_checksum(&buf[..128]))?;
-        let read_result = self.read_byte(true)?;
+        if self.crc {
+            let crc = get_crc(&buf[..size]);
+            self.write_byte((crc >> 8) as u8)?;
+            self.write_byte(crc as u8)?;
+        } else {
+            self.write_byte(get_checksum(&buf[..size]))?;
+        }
+        // skip handshakes the receiver repeated while we were starting up
+        let mut read_result = self.read_byte(true)?;
+        while read_result == CRC {
+            read_result = self.read_byte(true)?;
+        }
         if read_result == NAK {
+            self.naks += 1;
+            if size == 1024 && self.naks >= ONE_K_NAKS {
+                self.one_k = false;
+            }
             (self.progress)(Progress::NAK);
             return ioerr!(Interrupted, "retransmission requested");
         }
         if read_result != ACK {
             return ioerr!(InvalidData, "expected NAK or ACK at end of packet");
         }
+        self.naks = 0;
         (self.progress)(Progress::Packet(self.packet));
         self.packet = self.packet.wrapping_add(1);
-        Ok(128)
+        Ok(size)
     }
 
     /// Flush this output stream, ensuring that all intermediately buffered
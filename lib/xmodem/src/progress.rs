@@ -6,12 +6,14 @@
 /// is intended to be used by progress indicators or for debugging purposes.
 #[derive(Debug, Copy, Clone)]
 pub enum Progress {
-    /// Waiting for receiver to send NAK.
+    /// Waiting for receiver to send NAK or C.
     Waiting,
     /// Download/upload has started.
     Started,
     /// Packet `.0` was transmitted/received.
     Packet(u8),
+    /// `.0` of the `.1` bytes of a YMODEM file were transmitted/received.
+    Bytes(u64, u64),
     NAK,
     Unknown,
 }
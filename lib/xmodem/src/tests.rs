@@ -174,20 +174,200 @@ fn test_raw_transmission() {
     let tx_buf = rx_thread.join().expect("rx join okay");
 
     // check packet 1
+    let crc = get_crc(&input[..128]);
     assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
     assert_eq!(&rx_buf[3..(3 + 128)], &input[..128]);
-    assert_eq!(rx_buf[131], input[..128].iter().fold(0, |a: u8, b| a.wrapping_add(*b)));
+    assert_eq!(&rx_buf[131..133], &[(crc >> 8) as u8, crc as u8]);
 
     // check packet 2
-    assert_eq!(&rx_buf[132..135], &[SOH, 2, 255 - 2]);
-    assert_eq!(&rx_buf[135..(135 + 128)], &input[128..]);
-    assert_eq!(rx_buf[263], input[128..].iter().fold(0, |a: u8, b| a.wrapping_add(*b)));
+    let crc = get_crc(&input[128..]);
+    assert_eq!(&rx_buf[133..136], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[136..(136 + 128)], &input[128..]);
+    assert_eq!(&rx_buf[264..266], &[(crc >> 8) as u8, crc as u8]);
 
     // check EOT
-    assert_eq!(&rx_buf[264..], &[EOT, EOT]);
+    assert_eq!(&rx_buf[266..], &[EOT, EOT]);
 
     // check receiver responses
-    assert_eq!(&tx_buf, &[NAK, ACK, ACK, NAK, ACK]);
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, NAK, ACK]);
+}
+
+/// A peer that answers reads from a script, with `None` standing for a read
+/// that times out, and records what is written to it.
+struct Script(std::collections::VecDeque<Option<u8>>, Vec<u8>);
+
+fn script(reads: &[Option<u8>]) -> Script {
+    Script(reads.iter().cloned().collect(), vec![])
+}
+
+impl io::Read for Script {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.pop_front() {
+            Some(Some(byte)) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            Some(None) => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+            None => Ok(0),
+        }
+    }
+}
+
+impl io::Write for Script {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc() {
+    assert_eq!(get_crc(b"123456789"), 0x31C3);
+    assert_eq!(get_crc(&[]), 0);
+}
+
+#[test]
+fn test_1k_loop() {
+    let input: Vec<u8> = (0..3000u32).map(|i| (i * 7 + i / 256) as u8).collect();
+    let expected = input.clone();
+
+    let (tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let n = Xmodem::transmit(&input[..], &mut rx).expect("transmit okay");
+        (n, rx.2)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = vec![];
+        Xmodem::receive(tx, &mut output).map(|n| (n, output))
+    });
+
+    let (sent, rx_buf) = tx_thread.join().expect("tx join okay");
+    let (received, output) = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(sent, 3000);
+    assert_eq!(received, 3072);
+    assert_eq!(&output[..3000], &expected[..]);
+    assert!(output[3000..].iter().all(|b| *b == 0));
+
+    // two full blocks, then the 952-byte tail padded to a third
+    let block = 3 + 1024 + 2;
+    assert_eq!(&rx_buf[0..3], &[STX, 1, 255 - 1]);
+    assert_eq!(&rx_buf[block..block + 3], &[STX, 2, 255 - 2]);
+    assert_eq!(&rx_buf[2 * block..2 * block + 3], &[STX, 3, 255 - 3]);
+    assert_eq!(&rx_buf[3 * block..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_receive_checksum_fallback() {
+    let data = [0x5au8; 128];
+    let mut reads = vec![None, None, None, Some(SOH), Some(1), Some(255 - 1)];
+    reads.extend(data.iter().map(|b| Some(*b)));
+    reads.extend(&[Some(get_checksum(&data)), Some(EOT), Some(EOT)]);
+
+    let mut peer = script(&reads);
+    let mut output = vec![];
+    let n = Xmodem::receive(&mut peer, &mut output).expect("receive okay");
+    assert_eq!(n, 128);
+    assert_eq!(&output[..], &data[..]);
+    assert_eq!(&peer.1, &[CRC, CRC, CRC, NAK, ACK, NAK, ACK]);
+
+    // no answer to the checksum handshake either
+    let e = Xmodem::receive(script(&[None, None, None, None]), vec![])
+        .expect_err("times out");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn test_transmit_checksum_mode() {
+    let input = [0xa5u8; 1024];
+    let mut reads = vec![Some(NAK)];
+    reads.extend(std::iter::repeat(Some(ACK)).take(8));
+    reads.extend(&[Some(NAK), Some(ACK)]);
+    let mut peer = script(&reads);
+
+    let n = Xmodem::transmit(&input[..], &mut peer).expect("transmit okay");
+    assert_eq!(n, 1024);
+
+    // eight classic packets, no 1K blocks
+    let packet = 3 + 128 + 1;
+    for i in 0..8 {
+        let num = i as u8 + 1;
+        assert_eq!(&peer.1[i * packet..i * packet + 3], &[SOH, num, 255 - num]);
+        assert_eq!(peer.1[i * packet + 131], get_checksum(&input[..128]));
+    }
+    assert_eq!(&peer.1[8 * packet..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_transmit_skips_banner() {
+    let input = [1u8; 128];
+    let banner = b"Boot menu\r\n 2. Continue\r\n";
+    let mut reads: Vec<Option<u8>> = banner.iter().map(|b| Some(*b)).collect();
+    reads.extend(&[Some(CRC), Some(ACK), Some(NAK), Some(ACK)]);
+    let mut peer = script(&reads);
+
+    assert_eq!(Xmodem::transmit(&input[..], &mut peer).expect("transmit okay"), 128);
+    assert_eq!(&peer.1[..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(peer.1.len(), 3 + 128 + 2 + 2);
+}
+
+#[test]
+fn test_transmit_skip_limit() {
+    let input = [1u8; 128];
+    let mut reads = vec![Some(b'x'); MAX_SKIP + 1];
+    reads.push(Some(CRC));
+    let mut peer = script(&reads);
+
+    let e = Xmodem::transmit(&input[..], &mut peer).expect_err("no handshake");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(peer.1.is_empty());
+}
+
+#[test]
+fn test_1k_rejected() {
+    let input = [0x3cu8; 1024];
+    let mut reads = vec![Some(CRC), Some(CRC), Some(NAK), Some(NAK), Some(NAK)];
+    reads.extend(std::iter::repeat(Some(ACK)).take(8));
+    reads.extend(&[Some(NAK), Some(ACK)]);
+    let mut peer = script(&reads);
+
+    let n = Xmodem::transmit(&input[..], &mut peer).expect("transmit okay");
+    assert_eq!(n, 1024);
+
+    // three rejected 1K blocks, then 128-byte packets with CRCs
+    let block = 3 + 1024 + 2;
+    let packet = 3 + 128 + 2;
+    let crc = get_crc(&input[..128]);
+    for i in 0..3 {
+        assert_eq!(&peer.1[i * block..i * block + 3], &[STX, 1, 255 - 1]);
+    }
+    for i in 0..8 {
+        let (start, num) = (3 * block + i * packet, i as u8 + 1);
+        assert_eq!(&peer.1[start..start + 3], &[SOH, num, 255 - num]);
+        assert_eq!(&peer.1[start + 131..start + 133], &[(crc >> 8) as u8, crc as u8]);
+    }
+    assert_eq!(&peer.1[3 * block + 8 * packet..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_1k_resent() {
+    let input = [0x3cu8; 2048];
+    let reads = [Some(CRC), Some(NAK), Some(NAK), Some(ACK), Some(NAK), Some(ACK), Some(NAK), Some(ACK)];
+    let mut peer = script(&reads);
+
+    let n = Xmodem::transmit(&input[..], &mut peer).expect("transmit okay");
+    assert_eq!(n, 2048);
+
+    // occasional NAKs don't give up on 1K blocks
+    let block = 3 + 1024 + 2;
+    let nums = [1, 1, 1, 2, 2];
+    for (i, &num) in nums.iter().enumerate() {
+        assert_eq!(&peer.1[i * block..i * block + 3], &[STX, num, 255 - num]);
+    }
+    assert_eq!(&peer.1[5 * block..], &[EOT, EOT]);
 }
 
 #[test]
@@ -227,3 +407,148 @@ fn test_eot() {
 
     assert_eq!(&buffer[..], &[NAK, EOT, NAK, EOT, ACK]);
 }
+
+#[test]
+fn test_ymodem_header() {
+    let text = b"kernel.bin\x0012345 13 644";
+    let mut block = [0u8; 128];
+    block[..text.len()].copy_from_slice(text);
+    let info = ymodem::FileInfo::parse(&block).expect("a file");
+    assert_eq!(info.name(), "kernel.bin");
+    assert_eq!(info.size, Some(12345));
+
+    assert!(ymodem::FileInfo::parse(&[0; 128]).is_none());
+    let info = ymodem::FileInfo::parse(b"no-size\0\0").expect("a file");
+    assert_eq!((info.name(), info.size), ("no-size", None));
+
+    let header = ymodem::header("a.bin", 1024).expect("fits");
+    assert_eq!(&header[..11], b"a.bin\x001024\0");
+    assert!(header[11..].iter().all(|b| *b == 0));
+
+    let long = "x".repeat(123);
+    let e = ymodem::header(&long, 1024).expect_err("too long");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    assert!(ymodem::header("", 0).is_err());
+}
+
+#[test]
+fn test_ymodem_batch() {
+    let first: Vec<u8> = (0..3000u32).map(|i| (i * 13) as u8).collect();
+    let second = b"hello".to_vec();
+    let files = vec![("first.bin", first), ("second.txt", second)];
+    let expected = files.clone();
+
+    let (mut tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(rx);
+        for (name, data) in files.iter() {
+            let n = ymodem.send(name, data.len() as u64, &data[..]).expect("send okay");
+            assert_eq!(n, data.len());
+        }
+        ymodem.finish().expect("finish okay");
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut received = vec![];
+        let mut ymodem = Ymodem::new(&mut tx);
+        while let Some(info) = ymodem.recv_header().expect("header okay") {
+            let mut data = vec![];
+            let n = ymodem.recv_data(&info, &mut data).expect("data okay");
+            assert_eq!(Some(n), info.size);
+            received.push((info.name().to_string(), data));
+        }
+        (received, tx.2)
+    });
+
+    tx_thread.join().expect("tx join okay");
+    let (received, responses) = rx_thread.join().expect("rx join okay");
+    assert_eq!(received.len(), 2);
+    for ((name, data), (expected_name, expected_data)) in received.iter().zip(expected.iter()) {
+        assert_eq!(name, expected_name);
+        assert_eq!(data, expected_data);
+    }
+
+    // header, handshake for data, 3 blocks, EOT; header, handshake, 1
+    // packet, EOT; empty header
+    assert_eq!(&responses[..], &[
+        CRC, ACK, CRC, ACK, ACK, ACK, NAK, ACK,
+        CRC, ACK, CRC, ACK, NAK, ACK,
+        CRC, ACK,
+    ]);
+}
+
+#[test]
+fn test_ymodem_one_file() {
+    let input = [7u8; 200];
+    let (mut tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Ymodem::transmit_with_progress("seven", 200, &input[..], rx, progress::noop)
+    });
+
+    let mut ymodem = Ymodem::new(&mut tx);
+    let info = ymodem.recv_header().expect("header okay").expect("a file");
+    assert_eq!((info.name(), info.size), ("seven", Some(200)));
+    let mut output = [0u8; 256];
+    let n = ymodem.recv_data(&info, &mut output[..]).expect("data okay");
+    assert_eq!(n, 200);
+    assert!(ymodem.recv_header().expect("end okay").is_none());
+    assert_eq!(&output[..200], &input[..]);
+    assert!(output[200..].iter().all(|b| *b == 0));
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 200);
+}
+
+/// Returns the reads of a CRC-mode packet `num` carrying `data`.
+fn crc_packet(num: u8, data: &[u8; 128]) -> Vec<Option<u8>> {
+    let crc = get_crc(data);
+    let mut reads = vec![Some(SOH), Some(num), Some(255 - num)];
+    reads.extend(data.iter().map(|b| Some(*b)));
+    reads.extend(&[Some((crc >> 8) as u8), Some(crc as u8)]);
+    reads
+}
+
+#[test]
+fn test_ymodem_handshake_tries_reset() {
+    let mut header = [0u8; 128];
+    header[..8].copy_from_slice(b"a.bin\x003\0");
+    let data = [9u8; 128];
+
+    // the sender is slow to answer the first handshake, but not the second
+    let mut reads = vec![None, None];
+    reads.extend(crc_packet(0, &header));
+    reads.push(None);
+    reads.extend(crc_packet(1, &data));
+    reads.extend(&[Some(EOT), Some(EOT)]);
+    let mut peer = script(&reads);
+
+    let mut ymodem = Ymodem::new(&mut peer);
+    let info = ymodem.recv_header().expect("header okay").expect("a file");
+    let mut output = vec![];
+    assert_eq!(ymodem.recv_data(&info, &mut output).expect("data okay"), 3);
+    assert_eq!(&output[..], &[9, 9, 9]);
+    assert_eq!(&peer.1[..], &[CRC, CRC, CRC, ACK, CRC, CRC, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_ymodem_xmodem_fallback() {
+    let (first, second) = ([1u8; 128], [2u8; 128]);
+    let mut reads = crc_packet(1, &first);
+    reads.extend(crc_packet(2, &second));
+    reads.extend(&[Some(EOT), Some(EOT)]);
+
+    // a plain receiver wants a header
+    let mut ymodem = Ymodem::new(script(&reads));
+    let e = ymodem.recv_header().expect_err("no header");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    let mut peer = script(&reads);
+    let mut ymodem = Ymodem::new(&mut peer);
+    let info = ymodem.recv_header_or_xmodem().expect("first packet okay").expect("a file");
+    assert!(info.is_xmodem());
+    assert_eq!((info.name(), info.size), ("", None));
+    let mut output = vec![];
+    assert_eq!(ymodem.recv_data(&info, &mut output).expect("data okay"), 256);
+    assert_eq!(&output[..128], &first[..]);
+    assert_eq!(&output[128..], &second[..]);
+    assert_eq!(&peer.1[..], &[CRC, ACK, ACK, NAK, ACK]);
+}
@@ -0,0 +1,262 @@
+use core::fmt;
+
+use shim::io;
+use shim::ioerr;
+
+use crate::progress::{self, Progress, ProgressFn};
+use crate::Xmodem;
+
+/// Longest file name kept from a YMODEM header. Longer names are cut short.
+pub const NAME_MAX: usize = 128;
+
+/// The name and size of a file, as carried by a YMODEM header block.
+#[derive(Copy, Clone)]
+pub struct FileInfo {
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    /// Length of the file in bytes, if the sender gave one.
+    pub size: Option<u64>,
+    xmodem: bool,
+}
+
+impl FileInfo {
+    /// Returns the info of the file a plain XMODEM sender sends, which has no
+    /// name or size.
+    fn xmodem() -> FileInfo {
+        FileInfo { name: [0; NAME_MAX], name_len: 0, size: None, xmodem: true }
+    }
+
+    /// Returns whether the file came from a plain XMODEM sender rather than
+    /// with a header, in which case it's the only one and there is no batch
+    /// to end.
+    pub fn is_xmodem(&self) -> bool {
+        self.xmodem
+    }
+
+    /// Returns the file name, up to the first byte that isn't valid UTF-8.
+    pub fn name(&self) -> &str {
+        let name = &self.name[..self.name_len];
+        match core::str::from_utf8(name) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&name[..e.valid_up_to()]).unwrap(),
+        }
+    }
+
+    /// Parses the header `block`: the NUL-terminated file name followed by
+    /// the decimal file size and optional fields separated by spaces. Returns
+    /// `None` for the empty header that ends a batch.
+    pub(crate) fn parse(block: &[u8]) -> Option<FileInfo> {
+        let name_len = block.iter().position(|&b| b == 0).unwrap_or(block.len());
+        if name_len == 0 {
+            return None;
+        }
+
+        let mut info = FileInfo { name: [0; NAME_MAX], name_len: name_len.min(NAME_MAX), size: None, xmodem: false };
+        info.name[..info.name_len].copy_from_slice(&block[..info.name_len]);
+        for &b in block.iter().skip(name_len + 1).take_while(|b| b.is_ascii_digit()) {
+            let digit = (b - b'0') as u64;
+            info.size = info.size.unwrap_or(0).checked_mul(10).and_then(|s| s.checked_add(digit));
+            if info.size.is_none() {
+                break;
+            }
+        }
+        Some(info)
+    }
+}
+
+impl fmt::Debug for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileInfo")
+            .field("name", &self.name())
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+/// Returns the header block announcing the file `name` of `size` bytes.
+pub(crate) fn header(name: &str, size: u64) -> io::Result<[u8; 128]> {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    let mut rest = size;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    let digits = &digits[start..];
+
+    if name.is_empty() || name.as_bytes().contains(&0) {
+        return ioerr!(InvalidInput, "bad file name");
+    }
+    // the name, its NUL and the size, leaving a NUL after the size
+    if name.len() + 1 + digits.len() >= 128 {
+        return ioerr!(InvalidInput, "file name too long");
+    }
+
+    let mut block = [0u8; 128];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    block[name.len() + 1..name.len() + 1 + digits.len()].copy_from_slice(digits);
+    Ok(block)
+}
+
+/// Implementation of the YMODEM batch protocol on top of XMODEM-CRC.
+///
+/// Each file is preceded by a header, packet 0, with its name and size, so
+/// the receiver knows where the padding of the last packet starts. An empty
+/// header ends the batch.
+pub struct Ymodem<T> {
+    xmodem: Xmodem<T>,
+    /// The first data packet of a plain XMODEM transfer, received in place
+    /// of a header and not yet handed out.
+    block: [u8; 1024],
+    pending: usize,
+}
+
+impl Ymodem<()> {
+    /// Transmits `data`, the `size`-byte file `name`, to the receiver `to` as
+    /// a batch of one file using the YMODEM protocol.
+    ///
+    /// The function `f` is used as a callback to indicate progress throughout
+    /// the transmission. See the [`Progress`] enum for more information.
+    ///
+    /// Returns the number of bytes read from `data`.
+    pub fn transmit_with_progress<R, W>(name: &str, size: u64, data: R, to: W, f: ProgressFn) -> io::Result<usize>
+        where W: io::Read + io::Write, R: io::Read
+    {
+        let mut transmitter = Ymodem::new_with_progress(to, f);
+        let written = transmitter.send(name, size, data)?;
+        transmitter.finish()?;
+        Ok(written)
+    }
+}
+
+impl<T: io::Read + io::Write> Ymodem<T> {
+    /// Returns a new `Ymodem` instance with the internal reader/writer set to
+    /// `inner`.
+    pub fn new(inner: T) -> Self {
+        Ymodem::new_with_progress(inner, progress::noop)
+    }
+
+    /// Returns a new `Ymodem` instance with the internal reader/writer set to
+    /// `inner`. The function `f` is used as a callback to indicate progress
+    /// throughout the transfer. See the [`Progress`] enum for more
+    /// information.
+    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
+        Ymodem { xmodem: Xmodem::new_with_progress(inner, f), block: [0; 1024], pending: 0 }
+    }
+
+    /// Sends the header `block` as packet 0.
+    fn send_header(&mut self, block: &[u8; 128]) -> io::Result<()> {
+        let xmodem = &mut self.xmodem;
+        xmodem.restart(0);
+        xmodem.retry(|x| x.write_packet(block), "bad transmit")?;
+        // the receiver asks for the data with another handshake
+        xmodem.restart(1);
+        Ok(())
+    }
+
+    /// Sends the `size`-byte file `name` with the contents yielded by `data`,
+    /// as the next file of the batch. The progress callback is called with
+    /// `Progress::Bytes` after every packet.
+    ///
+    /// Returns the number of bytes read from `data`.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `InvalidInput` is returned if `name` is empty or too
+    /// long to fit in a header. Otherwise, errors are as for
+    /// [`Xmodem::write_packet()`].
+    pub fn send<R: io::Read>(&mut self, name: &str, size: u64, data: R) -> io::Result<usize> {
+        self.send_header(&header(name, size)?)?;
+        let written = self.xmodem.send_all(data, Some(size))?;
+        self.xmodem.write_packet(&[])?;
+        Ok(written)
+    }
+
+    /// Ends the batch with an empty header.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.send_header(&[0; 128])
+    }
+
+    /// Waits for the header of the next file of the batch. Returns `None` if
+    /// the sender ended the batch instead. The file's data must be read with
+    /// `recv_data()` before the next header.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind `InvalidData` is returned if the sender doesn't start
+    /// with a header. Otherwise, errors are as for
+    /// [`Xmodem::read_packet()`].
+    pub fn recv_header(&mut self) -> io::Result<Option<FileInfo>> {
+        self.recv_first(false)
+    }
+
+    /// Like `recv_header()`, but also takes a plain XMODEM transfer, which
+    /// starts with packet 1 rather than a header. Its file is the only one
+    /// and `is_xmodem()`, with no name or size; its data is read with
+    /// `recv_data()` as usual.
+    pub fn recv_header_or_xmodem(&mut self) -> io::Result<Option<FileInfo>> {
+        self.recv_first(true)
+    }
+
+    /// Waits for the header of the next file, or with `xmodem`, for the first
+    /// packet of a plain XMODEM transfer.
+    fn recv_first(&mut self, xmodem: bool) -> io::Result<Option<FileInfo>> {
+        let block = &mut self.block;
+        let x = &mut self.xmodem;
+        x.restart(0);
+        x.header_optional = xmodem;
+        let n = x.retry(|x| x.read_packet(block), "bad receive");
+        x.header_optional = false;
+        let n = n?;
+        if n == 0 {
+            return ioerr!(InvalidData, "expected a file header");
+        }
+        if x.packet == 2 {
+            // packet 1 is data, and the transfer goes on without a handshake
+            self.pending = n;
+            return Ok(Some(FileInfo::xmodem()));
+        }
+
+        let info = FileInfo::parse(&block[..n]);
+        if info.is_some() {
+            // ask for the data with another handshake
+            x.restart(1);
+        }
+        Ok(info)
+    }
+
+    /// Receives the data of the file `info` and writes it into `into`,
+    /// dropping the padding after `info.size` bytes. The progress callback is
+    /// called with `Progress::Bytes` after every packet if the size is known.
+    ///
+    /// Returns the number of bytes written to `into`.
+    pub fn recv_data<W: io::Write>(&mut self, info: &FileInfo, mut into: W) -> io::Result<u64> {
+        let mut packet = [0u8; 1024];
+        let mut received = 0;
+        if self.pending > 0 {
+            into.write_all(&self.block[..self.pending])?;
+            received += self.pending as u64;
+            self.pending = 0;
+        }
+        loop {
+            let n = self.xmodem.retry(|x| x.read_packet(&mut packet), "bad receive")?;
+            if n == 0 {
+                return Ok(received);
+            }
+
+            let keep = match info.size {
+                Some(size) => core::cmp::min(n as u64, size - received) as usize,
+                None => n,
+            };
+            into.write_all(&packet[..keep])?;
+            received += keep as u64;
+            if let Some(size) = info.size {
+                (self.xmodem.progress)(Progress::Bytes(received, size));
+            }
+        }
+    }
+}
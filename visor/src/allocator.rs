@@ -13,8 +13,10 @@ use core::fmt;
 
 use crate::console::kprintln;
 use crate::mutex::Mutex;
+use crate::param::CRASH_LOG_SIZE;
 use crate::util;
 use pi::atags::{Atag, Atags};
+use pi::common::BOOT_RESERVED_SIZE;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
 /// but it takes `&mut self` in `alloc()` and `dealloc()`.
@@ -80,7 +82,9 @@ pub fn memory_map() -> Option<(usize, usize)> {
 
     if let Some(mem_tag) = Atags::get().find_map(Atag::mem) {
         let heap_start = util::align_up(binary_end, page_size);
+        // the end of memory holds the crash log, then the bootloader's page
         let mem_end = (mem_tag.start + mem_tag.size) as usize;
+        let mem_end = mem_end - BOOT_RESERVED_SIZE - CRASH_LOG_SIZE;
         Some((heap_start, mem_end))
     } else {
         None
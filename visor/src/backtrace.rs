@@ -0,0 +1,63 @@
+use aarch64::backtrace::{self, SymbolSpace};
+
+use crate::console::kprintln;
+use crate::param::{IO_BASE, PAGE_SIZE};
+use crate::traps::TrapFrame;
+use crate::vm::{guest, GuestPageTable};
+
+/// The hypervisor's symbol table, filled in after linking by
+/// `bin/embed-symbols.py`.
+#[no_mangle]
+#[used]
+pub static mut KERNEL_SYMBOLS: SymbolSpace = SymbolSpace::new();
+
+/// Reads a doubleword of a frame record on the hypervisor's stack.
+fn read(addr: u64) -> Option<u64> {
+    if addr < PAGE_SIZE as u64 || addr > IO_BASE as u64 - 8 {
+        return None;
+    }
+    Some(unsafe { *(addr as *const u64) })
+}
+
+/// Prints the frames of the hypervisor's stack starting from frame pointer
+/// `fp`, after the one at `pc` if there is one.
+fn print_frames(pc: Option<u64>, fp: u64) {
+    let symbols = unsafe { &KERNEL_SYMBOLS }.symbols();
+    kprintln!("Backtrace:");
+    for frame in backtrace::trace(symbols, pc, fp, read) {
+        kprintln!("{}", frame);
+    }
+}
+
+/// Prints a backtrace of the caller.
+#[inline(never)]
+pub fn print() {
+    print_frames(None, backtrace::frame_pointer());
+}
+
+/// Prints a backtrace of the hypervisor from context `tf` of an exception it
+/// took itself.
+pub fn print_trap(tf: &TrapFrame) {
+    print_frames(Some(tf.ELR), tf.xn[29]);
+}
+
+/// Prints a backtrace of the guest with context `tf` and stage 2 table
+/// `vmap`, walking the frame records on its stack.
+///
+/// The frames are bare guest VAs: the guest's symbol table is somewhere in
+/// its own image, which the hypervisor doesn't parse. Resolve them against
+/// the guest kernel's ELF, e.g. with `addr2line -fe kernel.elf`.
+pub fn print_guest(tf: &TrapFrame, vmap: &GuestPageTable) {
+    let read = |va| {
+        let mut buf = [0u8; 8];
+        if guest::read_va(tf, vmap, va, &mut buf) == 8 {
+            Some(u64::from_le_bytes(buf))
+        } else {
+            None
+        }
+    };
+    kprintln!("Guest backtrace (unsymbolized guest VAs):");
+    for frame in backtrace::trace(None, Some(tf.ELR), tf.xn[29], read) {
+        kprintln!("{}", frame);
+    }
+}
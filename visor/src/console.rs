@@ -1,6 +1,9 @@
 use core::fmt;
+use core::time::Duration;
+use pi::timer;
 use pi::uart::MiniUart;
 use shim::io;
+use shim::ioerr;
 
 use crate::mutex::Mutex;
 
@@ -35,10 +38,58 @@ impl Console {
         self.inner().read_byte()
     }
 
+    /// Returns `true` if a byte is waiting to be read.
+    pub fn has_byte(&mut self) -> bool {
+        self.inner().has_byte()
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write_byte(byte)
     }
+
+    /// Returns a reader/writer over the UART device that passes every byte
+    /// through untouched, for binary transfers such as XMODEM.
+    pub fn raw(&mut self) -> RawConsole {
+        RawConsole(self.inner(), None)
+    }
+
+    /// Like `raw()`, but reads fail with `TimedOut` after waiting `timeout`
+    /// for a byte, so receivers can repeat their handshake.
+    pub fn raw_with_timeout(&mut self, timeout: Duration) -> RawConsole {
+        RawConsole(self.inner(), Some(timeout))
+    }
+}
+
+/// Binary-safe view of the console. Unlike `Console`, writes don't insert a
+/// `\r` after every `\n`.
+pub struct RawConsole<'a>(&'a mut MiniUart, Option<Duration>);
+
+impl<'a> io::Read for RawConsole<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(timeout) = self.1 {
+            let start = timer::current_time();
+            while !self.0.has_byte() {
+                if timer::current_time() > start + timeout {
+                    return ioerr!(TimedOut, "read timed out");
+                }
+            }
+        }
+        io::Read::read(self.0, buf)
+    }
+}
+
+impl<'a> io::Write for RawConsole<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0.write_byte(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::Write::flush(self.0)
+    }
 }
 
 impl io::Read for Console {
@@ -72,6 +123,7 @@ pub fn _print(args: fmt::Arguments) {
     #[cfg(not(test))]
     {
         use core::fmt::Write;
+        crate::crashlog::capture(args);
         let mut console = CONSOLE.lock();
         console.write_fmt(args).unwrap();
     }
@@ -0,0 +1,187 @@
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use klog::Ring;
+use pi::atags::{Atag, Atags};
+use pi::common::BOOT_RESERVED_SIZE;
+use pi::timer;
+
+use crate::console::{kprint, kprintln, CONSOLE};
+use crate::log::LOGGER;
+use crate::param::{CRASH_LOG_LINES, CRASH_LOG_SIZE};
+
+/// Marks a crash record, "VISCRASH" in little-endian ASCII.
+const MAGIC: u64 = 0x4853_4152_4353_4956;
+
+/// FNV-1a offset basis, the checksum of an empty record.
+const FNV_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+/// Header of the crash record at the start of the crash log region, followed
+/// by `len` bytes of text.
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u64,
+    /// FNV-1a hash of the text.
+    checksum: u64,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+/// Whether console output is being captured into the crash record.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// Returns the crash log region: the `CRASH_LOG_SIZE` bytes of RAM below the
+/// bootloader's reserved end, which the allocator leaves alone and nothing
+/// clears on boot.
+fn region() -> Option<&'static mut [u8]> {
+    let mem = Atags::get().find_map(Atag::mem)?;
+    let start = (mem.start + mem.size) as usize - BOOT_RESERVED_SIZE - CRASH_LOG_SIZE;
+    Some(unsafe { slice::from_raw_parts_mut(start as *mut u8, CRASH_LOG_SIZE) })
+}
+
+/// Returns the header and the text space of `region`.
+fn split(region: &mut [u8]) -> (&mut Header, &mut [u8]) {
+    let (header, text) = region.split_at_mut(HEADER_SIZE);
+    (unsafe { &mut *(header.as_mut_ptr() as *mut Header) }, text)
+}
+
+fn checksum(init: u64, data: &[u8]) -> u64 {
+    data.iter().fold(init, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Writes the region's cache lines out to RAM, so a reset doesn't lose them.
+fn flush(region: &[u8]) {
+    aarch64::clean_invalidate_dcache(region.as_ptr() as u64, region.len() as u64);
+}
+
+/// Appends to the crash record.
+struct Record;
+
+impl fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let region = match region() {
+            Some(region) => region,
+            None => return Ok(()),
+        };
+        let (header, text) = split(region);
+        let at = header.len as usize;
+        let n = s.len().min(text.len() - at);
+        text[at..at + n].copy_from_slice(&s.as_bytes()[..n]);
+        header.checksum = checksum(header.checksum, &text[at..at + n]);
+        header.len += n as u64;
+        flush(&region[..HEADER_SIZE + at + n]);
+        Ok(())
+    }
+}
+
+/// Starts a crash record with the time, `reason` and the last
+/// `CRASH_LOG_LINES` log lines, then captures console output into it, e.g.
+/// the trap frame and the backtrace, until `end`.
+pub fn begin(reason: fmt::Arguments) {
+    if CAPTURING.load(Ordering::SeqCst) {
+        // a crash while recording one, e.g. a panic in the fatal-trap path
+        let _ = write!(Record, "--- crashed again: {}\n", reason);
+        return;
+    }
+    let region = match region() {
+        Some(region) => region,
+        None => return,
+    };
+    let (header, _) = split(region);
+    header.magic = MAGIC;
+    header.len = 0;
+    header.checksum = FNV_BASIS;
+
+    let now = timer::current_time();
+    let _ = write!(Record, "crash at {}.{:06}s: {}\n", now.as_secs(), now.subsec_micros(), reason);
+    // the crash may have happened with the log locked, in which case its
+    // tail is left out rather than waiting on the lock forever
+    match LOGGER.try_lock() {
+        Some(logger) => {
+            let _ = write!(Record, "--- last log lines\n");
+            write_tail(&logger.ring);
+        },
+        None => {
+            let _ = write!(Record, "--- log locked, last lines left out\n");
+        },
+    }
+    let _ = write!(Record, "--- console\n");
+    CAPTURING.store(true, Ordering::SeqCst);
+}
+
+/// Appends the last `CRASH_LOG_LINES` lines of `ring` to the crash record.
+fn write_tail(ring: &Ring) {
+    let mut line = [0u8; 256];
+    let mut len = 0;
+    for b in ring.tail(CRASH_LOG_LINES) {
+        if len < line.len() {
+            line[len] = b;
+            len += 1;
+        }
+        if b == b'\n' {
+            let _ = Record.write_str(core::str::from_utf8(&line[..len]).unwrap_or("?\n"));
+            len = 0;
+        }
+    }
+}
+
+/// Stops capturing console output into the crash record.
+pub fn end() {
+    CAPTURING.store(false, Ordering::SeqCst);
+}
+
+/// Appends console output to the crash record while one is being written.
+pub fn capture(args: fmt::Arguments) {
+    if CAPTURING.load(Ordering::Relaxed) {
+        let _ = Record.write_fmt(args);
+    }
+}
+
+/// Returns the text of the crash record left by a previous boot, if there is
+/// a valid one.
+pub fn last() -> Option<&'static [u8]> {
+    let region = region()?;
+    let (header, text) = split(region);
+    let len = header.len as usize;
+    if header.magic != MAGIC || len > text.len() || checksum(FNV_BASIS, &text[..len]) != header.checksum {
+        return None;
+    }
+    Some(&text[..len])
+}
+
+/// Discards the crash record.
+pub fn clear() {
+    if let Some(region) = region() {
+        split(region).0.magic = 0;
+        flush(&region[..HEADER_SIZE]);
+    }
+}
+
+/// Prints the crash record left by a previous boot.
+pub fn print(text: &[u8]) {
+    kprint!("{}", String::from_utf8_lossy(text));
+}
+
+/// Offers to show the crash record left by a previous boot, if there is one.
+pub fn check() {
+    let text = match last() {
+        Some(text) => text,
+        None => return,
+    };
+    kprintln!("The last boot ended in a crash. Show its crash log? [y/N]");
+    let deadline = timer::current_time() + Duration::from_secs(3);
+    while timer::current_time() < deadline {
+        if CONSOLE.lock().has_byte() {
+            if CONSOLE.lock().read_byte() == b'y' {
+                print(text);
+            }
+            break;
+        }
+    }
+    kprintln!("`crashlog` shows it again, `crashlog clear` discards it");
+}
@@ -0,0 +1,450 @@
+pub mod gdb;
+
+use alloc::vec::Vec;
+
+use aarch64::*;
+
+use crate::console::kprintln;
+use crate::shell::Shell;
+use crate::traps::TrapFrame;
+use crate::SCHEDULER;
+
+/// Number of hardware breakpoints of the A53.
+pub const BREAKPOINTS: usize = 6;
+
+/// Number of hardware watchpoints of the A53.
+pub const WATCHPOINTS: usize = 4;
+
+/// The accesses a watchpoint stops on.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+/// A hardware watchpoint on `len` bytes of guest memory at guest VA `addr`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u64,
+    pub len: u64,
+    pub kind: WatchKind,
+}
+
+/// Reasons a hardware breakpoint or watchpoint couldn't be set.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugError {
+    /// Every register pair is in use.
+    NoSlot,
+    /// The watched range isn't 1 to 8 bytes within a doubleword, nor a
+    /// naturally aligned power of two bytes.
+    BadRange,
+}
+
+impl Watchpoint {
+    /// Returns the values of the watchpoint's value and control registers.
+    fn registers(&self) -> Option<(u64, u64)> {
+        let lsc = match self.kind {
+            WatchKind::Read => 0b01,
+            WatchKind::Write => 0b10,
+            WatchKind::Access => 0b11,
+        };
+        let control = DBGWCR0_EL1::PAC | DBGWCR0_EL1::E | (lsc << 3);
+        let offset = self.addr & 0b111;
+        if self.len >= 1 && offset + self.len <= 8 {
+            let bas = ((1 << self.len) - 1) << offset;
+            Some((self.addr - offset, control | (bas << 5)))
+        } else if self.len.is_power_of_two() && self.len <= 1 << 31 && self.addr % self.len == 0 {
+            let mask = self.len.trailing_zeros() as u64;
+            Some((self.addr, control | DBGWCR0_EL1::BAS | (mask << 24)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the watchpoint covers guest VA `va`.
+    pub fn contains(&self, va: u64) -> bool {
+        va >= self.addr && va - self.addr < self.len
+    }
+}
+
+/// A debug exception taken from a VM.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    Brk,
+    Step,
+    Breakpoint,
+    /// A watchpoint, hit by an access to the guest VA.
+    Watchpoint(u64),
+}
+
+/// Writes breakpoint register pair `n`.
+unsafe fn set_breakpoint(n: usize, value: u64, control: u64) {
+    match n {
+        0 => { DBGBVR0_EL1.set(value); DBGBCR0_EL1.set(control) },
+        1 => { DBGBVR1_EL1.set(value); DBGBCR1_EL1.set(control) },
+        2 => { DBGBVR2_EL1.set(value); DBGBCR2_EL1.set(control) },
+        3 => { DBGBVR3_EL1.set(value); DBGBCR3_EL1.set(control) },
+        4 => { DBGBVR4_EL1.set(value); DBGBCR4_EL1.set(control) },
+        5 => { DBGBVR5_EL1.set(value); DBGBCR5_EL1.set(control) },
+        _ => panic!("no breakpoint {}", n),
+    }
+}
+
+/// Reads breakpoint register pair `n`.
+unsafe fn get_breakpoint(n: usize) -> (u64, u64) {
+    match n {
+        0 => (DBGBVR0_EL1.get(), DBGBCR0_EL1.get()),
+        1 => (DBGBVR1_EL1.get(), DBGBCR1_EL1.get()),
+        2 => (DBGBVR2_EL1.get(), DBGBCR2_EL1.get()),
+        3 => (DBGBVR3_EL1.get(), DBGBCR3_EL1.get()),
+        4 => (DBGBVR4_EL1.get(), DBGBCR4_EL1.get()),
+        5 => (DBGBVR5_EL1.get(), DBGBCR5_EL1.get()),
+        _ => panic!("no breakpoint {}", n),
+    }
+}
+
+/// Reads watchpoint register pair `n`.
+unsafe fn get_watchpoint(n: usize) -> (u64, u64) {
+    match n {
+        0 => (DBGWVR0_EL1.get(), DBGWCR0_EL1.get()),
+        1 => (DBGWVR1_EL1.get(), DBGWCR1_EL1.get()),
+        2 => (DBGWVR2_EL1.get(), DBGWCR2_EL1.get()),
+        3 => (DBGWVR3_EL1.get(), DBGWCR3_EL1.get()),
+        _ => panic!("no watchpoint {}", n),
+    }
+}
+
+/// Writes watchpoint register pair `n`.
+unsafe fn set_watchpoint(n: usize, value: u64, control: u64) {
+    match n {
+        0 => { DBGWVR0_EL1.set(value); DBGWCR0_EL1.set(control) },
+        1 => { DBGWVR1_EL1.set(value); DBGWCR1_EL1.set(control) },
+        2 => { DBGWVR2_EL1.set(value); DBGWCR2_EL1.set(control) },
+        3 => { DBGWVR3_EL1.set(value); DBGWCR3_EL1.set(control) },
+        _ => panic!("no watchpoint {}", n),
+    }
+}
+
+/// The guest's own debug registers, kept aside while the hypervisor uses
+/// the hardware ones.
+#[derive(Debug, Default, Copy, Clone)]
+struct GuestRegisters {
+    mdscr: u64,
+    os_lock: bool,
+    /// Breakpoint value and control register pairs.
+    breakpoints: [(u64, u64); BREAKPOINTS],
+    /// Watchpoint value and control register pairs.
+    watchpoints: [(u64, u64); WATCHPOINTS],
+}
+
+impl GuestRegisters {
+    /// Reads the guest's registers, `MDSCR_EL1` from its context `tf` and the
+    /// others from the hardware.
+    unsafe fn save(tf: &TrapFrame) -> GuestRegisters {
+        let mut regs = GuestRegisters::default();
+        regs.mdscr = tf.MDSCR_EL1;
+        regs.os_lock = OSLSR_EL1.get() & OSLSR_EL1::OSLK != 0;
+        for n in 0..BREAKPOINTS {
+            regs.breakpoints[n] = get_breakpoint(n);
+        }
+        for n in 0..WATCHPOINTS {
+            regs.watchpoints[n] = get_watchpoint(n);
+        }
+        regs
+    }
+
+    /// Gives the registers back to the guest with context `tf`.
+    unsafe fn restore(&self, tf: &mut TrapFrame) {
+        tf.MDSCR_EL1 = self.mdscr;
+        OSLAR_EL1.set(self.os_lock as u64);
+        for (n, &(value, control)) in self.breakpoints.iter().enumerate() {
+            set_breakpoint(n, value, control);
+        }
+        for (n, &(value, control)) in self.watchpoints.iter().enumerate() {
+            set_watchpoint(n, value, control);
+        }
+    }
+
+    /// Returns the register of debug system register encoding `reg`, if it
+    /// is one that is kept.
+    fn register(&mut self, reg: SysReg) -> Option<&mut u64> {
+        let n = reg.crm as usize;
+        match (reg.op1, reg.crn, reg.op2) {
+            (0, 0, 2) if reg.crm == 2 => Some(&mut self.mdscr),
+            (0, 0, 4) if n < BREAKPOINTS => Some(&mut self.breakpoints[n].0),
+            (0, 0, 5) if n < BREAKPOINTS => Some(&mut self.breakpoints[n].1),
+            (0, 0, 6) if n < WATCHPOINTS => Some(&mut self.watchpoints[n].0),
+            (0, 0, 7) if n < WATCHPOINTS => Some(&mut self.watchpoints[n].1),
+            _ => None,
+        }
+    }
+}
+
+/// A trapped system register access, decoded from its ISS (ref: D12.2.37).
+#[derive(Debug, Copy, Clone)]
+struct SysReg {
+    op0: u32,
+    op1: u32,
+    crn: u32,
+    crm: u32,
+    op2: u32,
+    rt: usize,
+    read: bool,
+}
+
+impl SysReg {
+    fn from_iss(iss: u32) -> SysReg {
+        SysReg {
+            op0: (iss >> 20) & 0b11,
+            op2: (iss >> 17) & 0b111,
+            op1: (iss >> 14) & 0b111,
+            crn: (iss >> 10) & 0b1111,
+            rt: ((iss >> 5) & 0b11111) as usize,
+            crm: (iss >> 1) & 0b1111,
+            read: iss & 1 == 1,
+        }
+    }
+}
+
+/// The debugging state of a VM.
+///
+/// While a debugger is attached or hardware breakpoints or watchpoints are
+/// set, `MDCR_EL2.TDE` routes the VM's debug exceptions (`BRK`, software
+/// step, breakpoints, watchpoints) to the hypervisor, and `MDSCR_EL1` and the
+/// breakpoint and watchpoint registers belong to the hypervisor rather than
+/// to the guest. `TDE` also traps the guest's accesses to those registers,
+/// which are emulated on a copy of the guest's own values, given back to it
+/// once debugging stops.
+#[derive(Debug, Default)]
+pub struct DebugState {
+    /// Whether a debugger is attached.
+    pub attached: bool,
+    /// Whether the VM stops after executing one more instruction.
+    pub step: bool,
+    /// Whether the debugger resumed the VM and is waiting for it to stop.
+    pub waiting: bool,
+    /// Whether the VM is stepping over the instruction a hardware breakpoint
+    /// or watchpoint stopped it at, with those disabled.
+    pub stepping_over: bool,
+    /// Software breakpoints, as the guest VA and the instruction the `BRK`
+    /// replaced.
+    pub breakpoints: Vec<(u64, u32)>,
+    /// Hardware breakpoints, by register pair, as guest VAs.
+    pub hw_breakpoints: [Option<u64>; BREAKPOINTS],
+    /// Hardware watchpoints, by register pair.
+    pub watchpoints: [Option<Watchpoint>; WATCHPOINTS],
+    /// The guest's debug registers while the hypervisor uses the hardware.
+    guest: Option<GuestRegisters>,
+}
+
+impl DebugState {
+    /// Attaches a debugger to the VM with context `tf`, stopping it after its
+    /// next instruction.
+    pub fn attach(&mut self, tf: &mut TrapFrame) {
+        self.attached = true;
+        self.waiting = false;
+        self.set_step(tf, true);
+    }
+
+    /// Has the VM with context `tf` stop after its next instruction, or not.
+    pub fn set_step(&mut self, tf: &mut TrapFrame, step: bool) {
+        self.step = step;
+        if step {
+            tf.SPSR |= SPSR_EL2::SS;
+        } else {
+            tf.SPSR &= !SPSR_EL2::SS;
+        }
+    }
+
+    /// Sets a hardware breakpoint at guest VA `addr`. Returns the register
+    /// pair used.
+    pub fn add_breakpoint(&mut self, addr: u64) -> Result<usize, DebugError> {
+        let addr = addr & !0b11;
+        if let Some(n) = self.hw_breakpoints.iter().position(|&b| b == Some(addr)) {
+            return Ok(n);
+        }
+        let n = self.hw_breakpoints.iter().position(Option::is_none).ok_or(DebugError::NoSlot)?;
+        self.hw_breakpoints[n] = Some(addr);
+        Ok(n)
+    }
+
+    /// Removes the hardware breakpoint at guest VA `addr`. Returns `false` if
+    /// there was none.
+    pub fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        let addr = addr & !0b11;
+        match self.hw_breakpoints.iter().position(|&b| b == Some(addr)) {
+            Some(n) => { self.hw_breakpoints[n] = None; true },
+            None => false,
+        }
+    }
+
+    /// Sets hardware watchpoint `watch`. Returns the register pair used.
+    pub fn add_watchpoint(&mut self, watch: Watchpoint) -> Result<usize, DebugError> {
+        if watch.registers().is_none() {
+            return Err(DebugError::BadRange);
+        }
+        if let Some(n) = self.watchpoints.iter().position(|&w| w == Some(watch)) {
+            return Ok(n);
+        }
+        let n = self.watchpoints.iter().position(Option::is_none).ok_or(DebugError::NoSlot)?;
+        self.watchpoints[n] = Some(watch);
+        Ok(n)
+    }
+
+    /// Removes the hardware watchpoints at guest VA `addr`. Returns `false`
+    /// if there were none.
+    pub fn remove_watchpoint(&mut self, addr: u64) -> bool {
+        let mut removed = false;
+        for slot in self.watchpoints.iter_mut() {
+            if slot.map_or(false, |w| w.addr == addr) {
+                *slot = None;
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    /// Returns the watchpoint covering guest VA `va`, if any.
+    pub fn watchpoint_at(&self, va: u64) -> Option<Watchpoint> {
+        self.watchpoints.iter().filter_map(|&w| w).find(|w| w.contains(va))
+    }
+
+    /// Returns `true` if any hardware breakpoint or watchpoint is set.
+    pub fn has_hw(&self) -> bool {
+        self.hw_breakpoints.iter().any(Option::is_some) || self.watchpoints.iter().any(Option::is_some)
+    }
+
+    /// Programs the debug configuration of the VM with context `tf` into the
+    /// hardware as it is switched in.
+    pub fn switch_in(&mut self, tf: &mut TrapFrame) {
+        unsafe {
+            if self.attached || self.has_hw() {
+                if self.guest.is_none() {
+                    self.guest = Some(GuestRegisters::save(tf));
+                    OSLAR_EL1.set(0);
+                }
+                MDCR_EL2.set(MDCR_EL2.get() | MDCR_EL2::TDE);
+                let mut mdscr = 0;
+                if self.step {
+                    mdscr |= MDSCR_EL1::SS;
+                }
+                if self.has_hw() && !self.stepping_over {
+                    mdscr |= MDSCR_EL1::MDE;
+                }
+                tf.MDSCR_EL1 = mdscr;
+                for (n, b) in self.hw_breakpoints.iter().enumerate() {
+                    match b {
+                        Some(addr) => set_breakpoint(n, *addr, DBGBCR0_EL1::BAS | DBGBCR0_EL1::PMC | DBGBCR0_EL1::E),
+                        None => set_breakpoint(n, 0, 0),
+                    }
+                }
+                for (n, w) in self.watchpoints.iter().enumerate() {
+                    match w.and_then(|w| w.registers()) {
+                        Some((value, control)) => set_watchpoint(n, value, control),
+                        None => set_watchpoint(n, 0, 0),
+                    }
+                }
+            } else if let Some(guest) = self.guest.take() {
+                // debugging just stopped
+                MDCR_EL2.set(MDCR_EL2.get() & !MDCR_EL2::TDE);
+                guest.restore(tf);
+            } else if MDCR_EL2.get() & MDCR_EL2::TDE != 0 {
+                // the VM before was being debugged
+                MDCR_EL2.set(MDCR_EL2.get() & !MDCR_EL2::TDE);
+                for n in 0..BREAKPOINTS {
+                    set_breakpoint(n, 0, 0);
+                }
+                for n in 0..WATCHPOINTS {
+                    set_watchpoint(n, 0, 0);
+                }
+            }
+            asm!("isb" :::: "volatile");
+        }
+    }
+
+    /// Emulates the trapped access with ISS `iss` of the running VM with
+    /// context `tf` to a debug system register. Returns `false` if it isn't
+    /// one, or the VM isn't being debugged.
+    ///
+    /// The kept registers read back what the guest wrote, the OS lock is
+    /// tracked, and the other debug registers read as zero and ignore
+    /// writes.
+    pub fn emulate_sysreg(&mut self, tf: &mut TrapFrame, iss: u32) -> bool {
+        let reg = SysReg::from_iss(iss);
+        let guest = match self.guest.as_mut() {
+            Some(guest) if reg.op0 == 0b10 => guest,
+            _ => return false,
+        };
+        let value = if reg.rt == 31 { 0 } else { tf.xn[reg.rt] };
+        let read = match (reg.op1, reg.crn, reg.crm, reg.op2) {
+            // OSLAR_EL1
+            (0, 1, 0, 4) => {
+                guest.os_lock = value & OSLAR_EL1::OSLK != 0;
+                0
+            },
+            // OSLSR_EL1
+            (0, 1, 1, 4) => OSLSR_EL1::OSLM1 | if guest.os_lock { OSLSR_EL1::OSLK } else { 0 },
+            _ => match guest.register(reg) {
+                Some(kept) if reg.read => *kept,
+                Some(kept) => { *kept = value; 0 },
+                None => 0,
+            },
+        };
+        if reg.read && reg.rt != 31 {
+            tf.xn[reg.rt] = read;
+        }
+        tf.ELR += 4;
+        true
+    }
+}
+
+/// Handles debug exception `event` of the running VM with context `tf`: hands
+/// the VM to the debugger if one is attached, or to the monitor shell if a
+/// hardware breakpoint or watchpoint stopped it. Returns `false` if the
+/// exception is the guest's own, e.g. from a debugger within the guest.
+pub fn handle(tf: &mut TrapFrame, event: Event) -> bool {
+    let vmid = tf.vmid();
+    let (attached, watch) = {
+        let mut process = SCHEDULER.get_by_vmid(vmid);
+        let debug = &mut process.debug;
+        if debug.stepping_over && event == Event::Step {
+            debug.stepping_over = false;
+            debug.set_step(tf, false);
+            debug.switch_in(tf);
+            return true;
+        }
+        let watch = match event {
+            Event::Watchpoint(va) => debug.watchpoint_at(va),
+            _ => None,
+        };
+        (debug.attached, watch)
+    };
+    if attached {
+        gdb::stop(tf, gdb::SIGTRAP, watch);
+        return true;
+    }
+
+    match event {
+        Event::Breakpoint => kprintln!("\nVM {}: breakpoint at {:#x}", vmid, tf.ELR),
+        Event::Watchpoint(va) => {
+            kprintln!("\nVM {}: watchpoint hit by access to {:#x} at {:#x}", vmid, va, tf.ELR)
+        },
+        Event::Brk | Event::Step => return false,
+    }
+    kprintln!("hypervisor monitor, `exit` to resume VM {}", vmid);
+    Shell::with_guest("> ", tf).do_until_exit();
+
+    // the instruction that stopped the VM hasn't executed yet, so it steps
+    // over it before breakpoints and watchpoints are enabled again
+    if tf.vmid() == vmid {
+        let mut process = SCHEDULER.get_by_vmid(vmid);
+        let debug = &mut process.debug;
+        if !debug.attached {
+            debug.stepping_over = true;
+            debug.set_step(tf, true);
+            debug.switch_in(tf);
+        }
+    }
+    true
+}
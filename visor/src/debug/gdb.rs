@@ -0,0 +1,431 @@
+//! A GDB remote serial protocol stub for debugging guests over the console.
+//!
+//! `gdb <vmid>` in the shell attaches to a VM, which then stops after its next
+//! instruction and waits for `target remote` from `aarch64-*-gdb` on the other
+//! end of the console UART. While the debugger has the VM stopped, every VM
+//! is stopped. Console output from guests is not kept apart from the packets,
+//! so it is best to debug a VM that doesn't print much.
+
+use alloc::vec::Vec;
+
+use crate::console::CONSOLE;
+use crate::debug::{WatchKind, Watchpoint};
+use crate::mutex::Mutex;
+use crate::process::{Process, StopReason};
+use crate::traps::TrapFrame;
+use crate::vm::guest;
+use crate::SCHEDULER;
+
+/// Signals reported to the debugger as the reason the VM stopped.
+pub const SIGINT: u8 = 2;
+pub const SIGTRAP: u8 = 5;
+
+/// Byte the debugger sends to interrupt the running VM.
+const INTERRUPT: u8 = 0x03;
+
+/// `BRK #0`, written over the instruction at a software breakpoint.
+const BRK: u32 = 0xd420_0000;
+
+/// Size of the largest packet we accept, as advertised in `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Number of registers in the `g` packet: `x0` to `x30`, `sp`, `pc`, `cpsr`.
+const REGS: usize = 34;
+
+/// What happens to the VM once the debugger lets go of it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Resume {
+    Continue,
+    Detach,
+    Kill,
+}
+
+/// Console bytes `poll_interrupt` read that weren't an interrupt, kept for
+/// the next packet.
+static PENDING: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+fn read_byte() -> u8 {
+    let pending = {
+        let mut pending = PENDING.lock();
+        if pending.is_empty() { None } else { Some(pending.remove(0)) }
+    };
+    match pending {
+        Some(b) => b,
+        None => CONSOLE.lock().read_byte(),
+    }
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses a big-endian hexadecimal number, as addresses and lengths are sent.
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0u64, |n, &b| hex_digit(b).map(|d| (n << 4) | d as u64))
+}
+
+/// Decodes hexadecimal bytes, as memory and register contents are sent.
+fn parse_bytes(s: &[u8]) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks(2).map(|pair| Some((hex_digit(pair[0])? << 4) | hex_digit(pair[1])?)).collect()
+}
+
+fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for &b in bytes {
+        out.push(DIGITS[(b >> 4) as usize]);
+        out.push(DIGITS[(b & 0xf) as usize]);
+    }
+}
+
+/// Receives the next intact packet into `buf`, acknowledging it.
+fn recv(buf: &mut Vec<u8>) {
+    loop {
+        buf.clear();
+        while read_byte() != b'$' {}
+        let mut sum = 0u8;
+        loop {
+            let b = read_byte();
+            if b == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(b);
+            if buf.len() < PACKET_SIZE {
+                buf.push(b);
+            }
+        }
+        let checksum = [read_byte(), read_byte()];
+        let intact = parse_hex(&checksum) == Some(sum as u64);
+        CONSOLE.lock().write_byte(if intact { b'+' } else { b'-' });
+        if intact {
+            return;
+        }
+    }
+}
+
+/// Sends a packet with payload `data` until the debugger acknowledges it.
+fn send(data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    let mut checksum = Vec::new();
+    push_hex(&mut checksum, &[sum]);
+    loop {
+        {
+            let mut console = CONSOLE.lock();
+            console.write_byte(b'$');
+            for &b in data.iter().chain(b"#").chain(checksum.iter()) {
+                console.write_byte(b);
+            }
+        }
+        loop {
+            match read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Returns register `n` in `tf`, as numbered by GDB's AArch64 target.
+fn reg(tf: &mut TrapFrame, n: usize) -> &mut u64 {
+    match n {
+        0..=30 => &mut tf.xn[n],
+        31 => tf.sp_mut(),
+        32 => &mut tf.ELR,
+        _ => &mut tf.SPSR,
+    }
+}
+
+/// Returns the size of register `n` in bytes.
+fn reg_size(n: usize) -> usize {
+    if n == 33 { 4 } else { 8 } // cpsr is 32 bits wide
+}
+
+/// A debugger session with a stopped VM.
+struct Session<'a> {
+    tf: &'a mut TrapFrame,
+    process: &'a mut Process,
+    /// Signal the VM stopped with.
+    signal: u8,
+    /// Watchpoint the VM stopped at, if it did.
+    watch: Option<Watchpoint>,
+}
+
+impl<'a> Session<'a> {
+    /// Serves packets until the debugger resumes the VM. If it is waiting
+    /// for the VM to stop, it is told why the VM stopped first.
+    fn run(&mut self) -> Resume {
+        let mut packet = Vec::new();
+        let mut reply = Vec::new();
+        if self.process.debug.waiting {
+            self.stop_reply(&mut reply);
+            send(&reply);
+        }
+        loop {
+            recv(&mut packet);
+            reply.clear();
+            if let Some(resume) = self.handle(&packet, &mut reply) {
+                self.process.debug.waiting = resume == Resume::Continue;
+                // the debugger hangs up after `k` without acknowledging
+                if resume == Resume::Detach {
+                    send(&reply);
+                }
+                return resume;
+            }
+            send(&reply);
+        }
+    }
+
+    fn stop_reply(&self, reply: &mut Vec<u8>) {
+        match self.watch {
+            Some(watch) => {
+                reply.push(b'T');
+                push_hex(reply, &[self.signal]);
+                reply.extend_from_slice(match watch.kind {
+                    WatchKind::Write => &b"watch:"[..],
+                    WatchKind::Read => &b"rwatch:"[..],
+                    WatchKind::Access => &b"awatch:"[..],
+                });
+                reply.extend_from_slice(alloc::format!("{:x};", watch.addr).as_bytes());
+            },
+            None => {
+                reply.push(b'S');
+                push_hex(reply, &[self.signal]);
+            },
+        }
+    }
+
+    /// Handles one packet, leaving the reply in `reply`. Returns how to
+    /// resume the VM if the packet resumes it.
+    fn handle(&mut self, packet: &[u8], reply: &mut Vec<u8>) -> Option<Resume> {
+        let (&command, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return None,
+        };
+        match command {
+            b'?' => self.stop_reply(reply),
+            b'g' => {
+                for n in 0..REGS {
+                    let value = *reg(self.tf, n);
+                    push_hex(reply, &value.to_le_bytes()[..reg_size(n)]);
+                }
+            },
+            b'G' => match parse_bytes(args) {
+                Some(ref bytes) if bytes.len() >= REGS * 8 - 4 => {
+                    let mut at = 0;
+                    for n in 0..REGS {
+                        let mut value = [0u8; 8];
+                        value[..reg_size(n)].copy_from_slice(&bytes[at..at + reg_size(n)]);
+                        *reg(self.tf, n) = u64::from_le_bytes(value);
+                        at += reg_size(n);
+                    }
+                    reply.extend_from_slice(b"OK");
+                },
+                _ => reply.extend_from_slice(b"E01"),
+            },
+            b'p' => match parse_hex(args) {
+                Some(n) if (n as usize) < REGS => {
+                    let n = n as usize;
+                    let value = *reg(self.tf, n);
+                    push_hex(reply, &value.to_le_bytes()[..reg_size(n)]);
+                },
+                _ => reply.extend_from_slice(b"E01"),
+            },
+            b'P' => {
+                let mut parts = args.splitn(2, |&b| b == b'=');
+                match (parts.next().and_then(parse_hex), parts.next().and_then(parse_bytes)) {
+                    (Some(n), Some(ref bytes)) if (n as usize) < REGS && bytes.len() == reg_size(n as usize) => {
+                        let mut value = [0u8; 8];
+                        value[..bytes.len()].copy_from_slice(bytes);
+                        *reg(self.tf, n as usize) = u64::from_le_bytes(value);
+                        reply.extend_from_slice(b"OK");
+                    },
+                    _ => reply.extend_from_slice(b"E01"),
+                }
+            },
+            b'm' => {
+                let mut parts = args.splitn(2, |&b| b == b',');
+                match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+                    (Some(addr), Some(len)) => {
+                        let mut data = Vec::new();
+                        data.resize(core::cmp::min(len as usize, PACKET_SIZE / 2), 0);
+                        let n = guest::read_va(self.tf, &self.process.vmap, addr, &mut data);
+                        if n == 0 && len != 0 {
+                            reply.extend_from_slice(b"E14");
+                        } else {
+                            push_hex(reply, &data[..n]);
+                        }
+                    },
+                    _ => reply.extend_from_slice(b"E01"),
+                }
+            },
+            b'M' => {
+                let mut parts = args.splitn(2, |&b| b == b':');
+                let (addr, data) = match (parts.next(), parts.next().and_then(parse_bytes)) {
+                    (Some(range), Some(data)) => {
+                        let addr = range.splitn(2, |&b| b == b',').next().and_then(parse_hex);
+                        (addr, data)
+                    },
+                    _ => (None, Vec::new()),
+                };
+                match addr {
+                    Some(addr) if guest::write_va(self.tf, &mut self.process.vmap, addr, &data) == data.len() => {
+                        reply.extend_from_slice(b"OK");
+                    },
+                    Some(_) => reply.extend_from_slice(b"E14"),
+                    None => reply.extend_from_slice(b"E01"),
+                }
+            },
+            b'Z' | b'z' => {
+                let mut parts = args.split(|&b| b == b',');
+                let kind = parts.next().and_then(parse_hex);
+                let addr = parts.next().and_then(parse_hex);
+                let len = parts.next().and_then(parse_hex);
+                let insert = command == b'Z';
+                let debug = &mut self.process.debug;
+                let done = match (kind, addr, len) {
+                    (Some(0), Some(addr), _) => {
+                        if insert { self.insert(addr) } else { self.remove(addr) }
+                    },
+                    (Some(1), Some(addr), _) if insert => debug.add_breakpoint(addr).is_ok(),
+                    (Some(1), Some(addr), _) => debug.remove_breakpoint(addr),
+                    (Some(kind @ 2..=4), Some(addr), Some(len)) if insert => {
+                        let kind = match kind {
+                            2 => WatchKind::Write,
+                            3 => WatchKind::Read,
+                            _ => WatchKind::Access,
+                        };
+                        debug.add_watchpoint(Watchpoint { addr, len, kind }).is_ok()
+                    },
+                    (Some(2..=4), Some(addr), Some(_)) => debug.remove_watchpoint(addr),
+                    // other kinds of breakpoints are unsupported
+                    _ => return None,
+                };
+                reply.extend_from_slice(if done { &b"OK"[..] } else { &b"E14"[..] });
+            },
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    self.tf.ELR = addr;
+                }
+                self.process.debug.set_step(self.tf, command == b's');
+                return Some(Resume::Continue);
+            },
+            b'D' => {
+                self.detach();
+                reply.extend_from_slice(b"OK");
+                return Some(Resume::Detach);
+            },
+            b'k' => {
+                self.detach();
+                return Some(Resume::Kill);
+            },
+            b'H' | b'T' => reply.extend_from_slice(b"OK"),
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    reply.extend_from_slice(b"PacketSize=1000");
+                } else if args == b"Attached" {
+                    reply.extend_from_slice(b"1");
+                } else if args == b"C" {
+                    reply.extend_from_slice(b"QC1");
+                } else if args == b"fThreadInfo" {
+                    reply.extend_from_slice(b"m1");
+                } else if args == b"sThreadInfo" {
+                    reply.extend_from_slice(b"l");
+                }
+            },
+            // an empty reply tells the debugger the packet is unsupported
+            _ => {},
+        }
+        None
+    }
+
+    /// Inserts a software breakpoint at guest VA `addr`. Returns `false` if
+    /// the instruction there can't be accessed.
+    fn insert(&mut self, addr: u64) -> bool {
+        if self.process.debug.breakpoints.iter().any(|&(va, _)| va == addr) {
+            return true;
+        }
+        let mut insn = [0u8; 4];
+        if guest::read_va(self.tf, &self.process.vmap, addr, &mut insn) != 4 {
+            return false;
+        }
+        if guest::write_va(self.tf, &mut self.process.vmap, addr, &BRK.to_le_bytes()) != 4 {
+            return false;
+        }
+        self.process.debug.breakpoints.push((addr, u32::from_le_bytes(insn)));
+        true
+    }
+
+    /// Removes the software breakpoint at guest VA `addr`, putting the
+    /// original instruction back.
+    fn remove(&mut self, addr: u64) -> bool {
+        let breakpoints = &mut self.process.debug.breakpoints;
+        let (va, insn) = match breakpoints.iter().position(|&(va, _)| va == addr) {
+            Some(index) => breakpoints.remove(index),
+            None => return true,
+        };
+        guest::write_va(self.tf, &mut self.process.vmap, va, &insn.to_le_bytes()) == 4
+    }
+
+    /// Removes every breakpoint and lets the VM run freely again.
+    fn detach(&mut self) {
+        while let Some(&(va, _)) = self.process.debug.breakpoints.last() {
+            self.remove(va);
+        }
+        self.process.debug.set_step(self.tf, false);
+        self.process.debug.attached = false;
+        PENDING.lock().clear();
+    }
+}
+
+/// Hands the VM with context `tf` to the debugger attached to it until the
+/// debugger resumes it. The VM stopped with `signal`, at watchpoint `watch`
+/// if one stopped it.
+pub fn stop(tf: &mut TrapFrame, signal: u8, watch: Option<Watchpoint>) {
+    let resume = {
+        let mut process = SCHEDULER.get_by_vmid(tf.vmid());
+        let process = &mut *process;
+        process.debug.set_step(tf, false);
+        let resume = Session { tf: &mut *tf, process: &mut *process, signal, watch }.run();
+        process.debug.switch_in(tf);
+        resume
+    };
+    if resume == Resume::Kill {
+        SCHEDULER.stop(tf, StopReason::Stopped);
+    }
+}
+
+/// Stops the running VM with context `tf` for the debugger if one is
+/// attached to it and asked to interrupt it. Called periodically.
+pub fn poll_interrupt(tf: &mut TrapFrame) {
+    let vmid = tf.vmid();
+    let attached = SCHEDULER.critical(|scheduler| {
+        scheduler.get_by_vmid(vmid).map_or(false, |p| p.debug.attached)
+    });
+    if !attached {
+        return;
+    }
+    loop {
+        let byte = {
+            let mut console = CONSOLE.lock();
+            if console.has_byte() { console.read_byte() } else { return }
+        };
+        if byte == INTERRUPT {
+            return stop(tf, SIGINT, None);
+        }
+        let mut pending = PENDING.lock();
+        if pending.len() < PACKET_SIZE {
+            pending.push(byte);
+        }
+    }
+}
@@ -71,5 +71,10 @@ unsafe fn kinit() -> ! {
     }
     VBAR_EL2.set(&vectors as *const u64 as u64);
 
+    // run the cycle counter at every EL, including EL2, for exit accounting
+    PMCCFILTR_EL0.set(PMCCFILTR_EL0::NSH);
+    PMCNTENSET_EL0.set(PMCNTENSET_EL0::C);
+    PMCR_EL0.set(PMCR_EL0.get() | PMCR_EL0::LC | PMCR_EL0::C | PMCR_EL0::E);
+
     kmain();
 }
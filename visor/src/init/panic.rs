@@ -5,6 +5,7 @@ use crate::mutex::ReentrantLock;
 
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
+    crate::crashlog::begin(format_args!("panic"));
     kprint!("\n\nBruh moment: ");
     if let Some(s) = _info.message() {
         kprintln!("{:?}", s);
@@ -16,6 +17,8 @@ fn panic(_info: &PanicInfo) -> ! {
     if let Some(s) = _info.location() {
         kprintln!("At file \"{}\", line {}", s.file(), s.line());
     }
+    crate::backtrace::print();
+    crate::crashlog::end();
 
     kprintln!("\n       m(_ _)m       ");
     kprintln!("Strike RETURN to reboot");
@@ -17,25 +17,20 @@ context_save:
     stp x2, x3, [SP, #-16]!
     stp x0, x1, [SP, #-16]!
 
-    stp q30, q31, [SP, #-32]!
-    stp q28, q29, [SP, #-32]!
-    stp q26, q27, [SP, #-32]!
-    stp q24, q25, [SP, #-32]!
-    stp q22, q23, [SP, #-32]!
-    stp q20, q21, [SP, #-32]!
-    stp q18, q19, [SP, #-32]!
-    stp q16, q17, [SP, #-32]!
-    stp q14, q15, [SP, #-32]!
-    stp q12, q13, [SP, #-32]!
-    stp q10, q11, [SP, #-32]!
-    stp q8, q9, [SP, #-32]!
-    stp q6, q7, [SP, #-32]!
-    stp q4, q5, [SP, #-32]!
-    stp q2, q3, [SP, #-32]!
-    stp q0, q1, [SP, #-32]!
+    mrs x0, MDSCR_EL1
+    stp x0, xzr, [SP, #-16]!
+
+    mrs x0, FAR_EL1
+    mrs x1, SPSR_EL2
+    stp x0, x1, [SP, #-16]!
+
+    mrs x0, ELR_EL1
+    mrs x1, ESR_EL1
+    stp x0, x1, [SP, #-16]!
 
     mrs x0, SPSR_EL1
-    stp x0, xzr, [SP, #-16]!
+    mrs x1, TCR_EL1
+    stp x0, x1, [SP, #-16]!
 
     mrs x0, TPIDR_EL0
     mrs x1, TPIDR_EL1
@@ -63,6 +58,7 @@ context_save:
     mrs x2, FAR_EL2
     mrs x3, HPFAR_EL2
     mov x4, SP
+    mov x5, x28
     stp lr, xzr, [SP, #-16]!
     bl handle_exception
     ldp lr, xzr, [SP], #16
@@ -92,34 +88,26 @@ context_restore:
     msr TPIDR_EL0, x0
     msr TPIDR_EL1, x1
 
-    ldp x0, xzr, [SP], #16
+    ldp x0, x1, [SP], #16
     msr SPSR_EL1, x0
+    msr TCR_EL1, x1
+
+    ldp x0, x1, [SP], #16
+    msr ELR_EL1, x0
+    msr ESR_EL1, x1
+
+    ldp x0, x1, [SP], #16
+    msr FAR_EL1, x0
+    msr SPSR_EL2, x1
+
+    ldp x0, x1, [SP], #16
+    msr MDSCR_EL1, x0
 
-    // Fence after changing ttbr
+    // TLB entries are tagged with the VMID in VTTBR_EL2, so switching
+    // VMs needs no flush. Translation table writes, such as stage 2
+    // entries mapped lazily, must still complete before the guest's
+    // walks see them; the eret only synchronizes the context.
     dsb ishst
-    tlbi vmalle1
-    tlbi vmalls12e1
-    tlbi alle2
-    tlbi alle1
-    dsb ish
-    isb
-
-    ldp q0, q1, [SP], #32
-    ldp q2, q3, [SP], #32
-    ldp q4, q5, [SP], #32
-    ldp q6, q7, [SP], #32
-    ldp q8, q9, [SP], #32
-    ldp q10, q11, [SP], #32
-    ldp q12, q13, [SP], #32
-    ldp q14, q15, [SP], #32
-    ldp q16, q17, [SP], #32
-    ldp q18, q19, [SP], #32
-    ldp q20, q21, [SP], #32
-    ldp q22, q23, [SP], #32
-    ldp q24, q25, [SP], #32
-    ldp q26, q27, [SP], #32
-    ldp q28, q29, [SP], #32
-    ldp q30, q31, [SP], #32
 
     ldp x0, x1, [SP], #16
     ldp x2, x3, [SP], #16
@@ -138,11 +126,64 @@ context_restore:
     // x28, x29, lr, xzr restored by HANDLER
     ret
 
+// The hypervisor is built without FP/SIMD, which only these two use.
+.arch_extension fp
+.arch_extension simd
+
+.global fp_save
+fp_save:
+    // x0: pointer to an `FpState`
+    stp q0, q1, [x0, #0]
+    stp q2, q3, [x0, #32]
+    stp q4, q5, [x0, #64]
+    stp q6, q7, [x0, #96]
+    stp q8, q9, [x0, #128]
+    stp q10, q11, [x0, #160]
+    stp q12, q13, [x0, #192]
+    stp q14, q15, [x0, #224]
+    stp q16, q17, [x0, #256]
+    stp q18, q19, [x0, #288]
+    stp q20, q21, [x0, #320]
+    stp q22, q23, [x0, #352]
+    stp q24, q25, [x0, #384]
+    stp q26, q27, [x0, #416]
+    stp q28, q29, [x0, #448]
+    stp q30, q31, [x0, #480]
+    mrs x1, FPCR
+    mrs x2, FPSR
+    stp x1, x2, [x0, #512]
+    ret
+
+.global fp_restore
+fp_restore:
+    // x0: pointer to an `FpState`
+    ldp q0, q1, [x0, #0]
+    ldp q2, q3, [x0, #32]
+    ldp q4, q5, [x0, #64]
+    ldp q6, q7, [x0, #96]
+    ldp q8, q9, [x0, #128]
+    ldp q10, q11, [x0, #160]
+    ldp q12, q13, [x0, #192]
+    ldp q14, q15, [x0, #224]
+    ldp q16, q17, [x0, #256]
+    ldp q18, q19, [x0, #288]
+    ldp q20, q21, [x0, #320]
+    ldp q22, q23, [x0, #352]
+    ldp q24, q25, [x0, #384]
+    ldp q26, q27, [x0, #416]
+    ldp q28, q29, [x0, #448]
+    ldp q30, q31, [x0, #480]
+    ldp x1, x2, [x0, #512]
+    msr FPCR, x1
+    msr FPSR, x2
+    ret
+
 .macro HANDLER source, kind
     .align 7
     stp     lr, xzr, [SP, #-16]!
     stp     x28, x29, [SP, #-16]!
     
+    mrs     x28, PMCCNTR_EL0
     mov     x29, \source
     movk    x29, \kind, LSL #16
     bl      context_save
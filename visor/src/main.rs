@@ -17,13 +17,18 @@ mod init;
 extern crate alloc;
 
 pub mod allocator;
+pub mod backtrace;
 pub mod console;
+pub mod crashlog;
+pub mod debug;
 pub mod fs;
+pub mod log;
 pub mod mutex;
 pub mod shell;
 pub mod param;
 pub mod process;
 pub mod traps;
+pub mod vdev;
 pub mod vm;
 pub mod util;
 
@@ -38,7 +43,7 @@ use traps::irq::Irq;
 use vm::VMManager;
 
 use allocator::Allocator;
-use fs::sd::Sd;
+use sd::Sd;
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
@@ -79,6 +84,8 @@ fn kmain() -> ! {
         SCHEDULER.initialize();
     }
 
+    crashlog::check();
+
     kprintln!("Welcome to cs3210!!");
     
     SCHEDULER.start()
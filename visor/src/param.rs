@@ -13,10 +13,43 @@ pub const PAGE_MASK: usize = !(PAGE_SIZE - 1);
 pub const GUEST_MASK_BITS: usize = 34;
 pub const VISOR_MASK_BITS: usize = 32;
 
+/// Size of the intermediate physical address space seen by a guest.
+pub const GUEST_IPA_SIZE: usize = 1 << (64 - GUEST_MASK_BITS);
+
 pub const KERN_START_ADDR: u64 = 0x80000u64;
 pub const GUEST_MAX_VM_SIZE: usize = 0x1000_0000; // 256MiB
 pub const KERN_STACK_BASE: usize = 0x80_000;
 
-/// The `tick` time.
+/// `VBAR_EL1` of a guest that hasn't installed exception vectors yet. It is
+/// not translatable, so a guest that faults before setting up its handlers
+/// doesn't loop on its own vectors.
+pub const GUEST_VBAR_SENTINEL: u64 = 0x1DEAD0000;
+
+/// Number of recent guest exits kept in the exit trace.
+pub const EXIT_TRACE_LEN: usize = 256;
+
+/// Bytes at the end of RAM kept for the crash record of the last boot.
+pub const CRASH_LOG_SIZE: usize = 64 * 1024;
+
+/// Log lines copied into a crash record.
+pub const CRASH_LOG_LINES: usize = 32;
+
+/// Pages by which the balloon targets of the other VMs are raised when a VM
+/// can't get a page for itself.
+pub const BALLOON_RECLAIM_PAGES: usize = 16;
+
+/// Guest pages the page merger looks at on every scheduler tick.
+pub const MERGE_PAGES_PER_TICK: usize = 16;
+
+/// The `tick` time, used as the timeslice of VMs that don't set their own.
 // FIXME: When you're ready, change this to something more reasonable.
 pub const TICK: Duration = Duration::from_millis(1000);
+
+/// Window over which CPU caps are enforced. A VM capped at `n`% runs for at
+/// most `n`% of each window.
+pub const CAP_PERIOD: Duration = Duration::from_millis(100);
+
+/// VMs to start at boot, one per line. If it is missing, only
+/// `DEFAULT_IMAGE` is started.
+pub const VMS_CONFIG: &str = "/vms.cfg";
+pub const DEFAULT_IMAGE: &str = "/kernel.bin";
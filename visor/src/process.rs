@@ -1,10 +1,22 @@
+mod balloon;
+mod config;
+pub mod fpu;
 mod process;
+mod restart;
 mod scheduler;
+mod snapshot;
 mod stack;
 mod state;
+mod stride;
 
+pub use self::balloon::Balloon;
+pub use self::config::{FaultPolicy, RestartPolicy, VmConfig};
+pub use self::fpu::FpState;
 pub use self::process::{Id, Process};
+pub use self::restart::{CrashReason, StopReason};
 pub use self::scheduler::GlobalScheduler;
+pub use self::snapshot::{Snapshot, SnapshotReader};
 pub use self::stack::Stack;
 pub use self::state::State;
+pub use self::stride::Stride;
 pub use crate::param::TICK;
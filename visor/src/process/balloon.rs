@@ -0,0 +1,41 @@
+use alloc::collections::BTreeSet;
+
+/// The pages a guest has handed back to the hypervisor, and how many the
+/// hypervisor would like it to have handed back.
+///
+/// A ballooned page is unmapped and its memory freed. If the guest touches
+/// it again, it is lazily mapped to a fresh page and leaves the balloon.
+#[derive(Debug, Default, Clone)]
+pub struct Balloon {
+    /// Page-aligned IPAs of the pages in the balloon.
+    pages: BTreeSet<usize>,
+    /// Number of pages the guest is asked to keep in the balloon.
+    pub target: usize,
+    /// Whether the guest runs a balloon driver, which it shows by asking for
+    /// its target.
+    pub active: bool,
+}
+
+impl Balloon {
+    /// Returns the number of pages in the balloon.
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Records that the page at `ipa` was handed back.
+    pub fn inflate(&mut self, ipa: usize) {
+        self.pages.insert(ipa);
+    }
+
+    /// Takes the page at `ipa` back out of the balloon. Returns `false` if it
+    /// wasn't in it.
+    pub fn deflate(&mut self, ipa: usize) -> bool {
+        self.pages.remove(&ipa)
+    }
+
+    /// Returns how many more pages the guest should hand back to reach its
+    /// target. Negative if it may take that many back.
+    pub fn wanted(&self) -> i64 {
+        self.target as i64 - self.len() as i64
+    }
+}
@@ -0,0 +1,163 @@
+use core::str::FromStr;
+use core::time::Duration;
+
+use crate::param::TICK;
+
+/// What to do when a VM takes an exception the hypervisor can't handle.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FaultPolicy {
+    /// Reflect the exception back to the guest's own EL1 vectors.
+    Inject,
+    /// Kill the VM and keep running the others.
+    Kill,
+    /// Stop everything and drop into the shell with the guest's context.
+    Debug,
+}
+
+impl FromStr for FaultPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<FaultPolicy, ()> {
+        match s {
+            "inject" => Ok(FaultPolicy::Inject),
+            "kill" => Ok(FaultPolicy::Kill),
+            "debug" => Ok(FaultPolicy::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+/// When to bring a VM back up after it stops.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RestartPolicy {
+    /// Leave the VM stopped.
+    Never,
+    /// Restart the VM only if it crashed.
+    OnCrash,
+    /// Restart the VM whenever it stops, including clean shutdowns.
+    Always,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<RestartPolicy, ()> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on-crash" => Ok(RestartPolicy::OnCrash),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Per-VM policies.
+#[derive(Debug, Clone)]
+pub struct VmConfig {
+    /// Handling of exceptions the hypervisor can't handle itself.
+    pub on_fault: FaultPolicy,
+    /// Whether the VM is restarted after it stops.
+    pub restart: RestartPolicy,
+    /// Delay before the first restart after a crash. Doubles with every
+    /// further crash, up to `MAX_BACKOFF_SHIFT` doublings.
+    pub backoff: Duration,
+    /// Number of crashes after which the VM is no longer restarted. `None`
+    /// restarts it forever.
+    pub max_retries: Option<u32>,
+    /// Share of the CPU relative to other VMs. A VM with twice the weight of
+    /// another gets twice as much CPU time when both are busy.
+    pub weight: u32,
+    /// Hard limit on the VM's CPU usage, in percent, enforced even if the
+    /// CPU would otherwise be idle. `None` leaves it uncapped.
+    pub cap: Option<u32>,
+    /// How long the VM runs before it is preempted.
+    pub timeslice: Duration,
+}
+
+impl VmConfig {
+    /// Upper bound on how many times the backoff delay is doubled.
+    pub const MAX_BACKOFF_SHIFT: u32 = 6;
+
+    /// Weight of VMs that don't set one.
+    pub const DEFAULT_WEIGHT: u32 = 256;
+
+    /// Returns how long to wait before restarting after crash number
+    /// `crashes` (counting from 1).
+    pub fn backoff_for(&self, crashes: u32) -> Duration {
+        let shift = core::cmp::min(crashes.saturating_sub(1), VmConfig::MAX_BACKOFF_SHIFT);
+        self.backoff * (1 << shift)
+    }
+
+    /// Sets the option `key` to `value`, as written in the VM config file:
+    ///
+    ///   * `onfault=inject|kill|debug`
+    ///   * `restart=never|on-crash|always`
+    ///   * `backoff=<ms>`, `retries=<n>|-`
+    ///   * `weight=<n>`, `cap=<percent>|-`, `slice=<ms>`
+    ///
+    /// Returns `Err` if the key is unknown or the value is invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ()> {
+        let num = |value: &str| value.parse::<u32>().map_err(|_| ());
+        match key {
+            "onfault" => self.on_fault = value.parse()?,
+            "restart" => self.restart = value.parse()?,
+            "backoff" => self.backoff = Duration::from_millis(num(value)? as u64),
+            "retries" => self.max_retries = match value {
+                "-" => None,
+                _ => Some(num(value)?),
+            },
+            "weight" => self.weight = match num(value)? {
+                0 => return Err(()),
+                weight => weight,
+            },
+            "cap" => self.cap = match value {
+                "-" => None,
+                _ => match num(value)? {
+                    cap @ 1..=100 => Some(cap),
+                    _ => return Err(()),
+                },
+            },
+            "slice" => self.timeslice = match num(value)? {
+                0 => return Err(()),
+                ms => Duration::from_millis(ms as u64),
+            },
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    /// Parses a line of the VM config file: an image path followed by
+    /// `key=value` options, with `#` starting a comment. Returns `Ok(None)`
+    /// for blank lines, or `Err` with the first option that is invalid.
+    pub fn parse_line(line: &str) -> Result<Option<(&str, VmConfig)>, &str> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let image = match words.next() {
+            Some(image) => image,
+            None => return Ok(None),
+        };
+        let mut config = VmConfig::default();
+        for word in words {
+            let mut kv = word.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => config.set(key, value).map_err(|_| word)?,
+                _ => return Err(word),
+            }
+        }
+        Ok(Some((image, config)))
+    }
+}
+
+impl Default for VmConfig {
+    fn default() -> VmConfig {
+        VmConfig {
+            on_fault: FaultPolicy::Inject,
+            restart: RestartPolicy::Never,
+            backoff: Duration::from_secs(1),
+            max_retries: Some(5),
+            weight: VmConfig::DEFAULT_WEIGHT,
+            cap: None,
+            timeslice: TICK,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use alloc::vec::Vec;
+
+use aarch64::CPTR_EL2;
+
+extern "C" {
+    fn fp_save(state: *mut FpState);
+    fn fp_restore(state: *const FpState);
+}
+
+/// The FP/SIMD registers of a VM.
+///
+/// They are not part of the trap frame. While a VM doesn't own the hardware
+/// registers, `CPTR_EL2.TFP` traps its first FP/SIMD access, and its registers
+/// are loaded then. They are saved again only when the VM is switched out.
+///
+/// This relies on the hypervisor never touching the FP/SIMD registers
+/// itself, so it is built without FP/SIMD code generation (`KERN_FLAGS` in
+/// the Makefile) and must not use floating point.
+#[repr(C, align(16))]
+#[derive(Debug, Default, Clone)]
+pub struct FpState {
+    q: [u128; 32],
+    fpcr: u64,
+    fpsr: u64,
+}
+
+impl FpState {
+    /// Saves the hardware registers into `self`.
+    pub fn save(&mut self) {
+        unsafe { fp_save(self) }
+    }
+
+    /// Loads `self` into the hardware registers.
+    pub fn restore(&self) {
+        unsafe { fp_restore(self) }
+    }
+
+    /// Appends the registers to `out`: `q0` to `q31` as `u128`s, then FPCR
+    /// and FPSR as `u64`s.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for q in self.q.iter() {
+            out.extend_from_slice(&q.to_le_bytes());
+        }
+        out.extend_from_slice(&self.fpcr.to_le_bytes());
+        out.extend_from_slice(&self.fpsr.to_le_bytes());
+    }
+}
+
+/// Turns trapping of FP/SIMD accesses to EL2 on or off (ref: D13.2.31).
+pub fn set_trap(trap: bool) {
+    let tfp = if trap { CPTR_EL2::TFP } else { 0 };
+    unsafe {
+        CPTR_EL2.set(CPTR_EL2::RES1 | tfp);
+        asm!("isb" :::: "volatile");
+    }
+}
+
+/// Returns `true` if FP/SIMD accesses are currently trapped.
+pub fn trapping() -> bool {
+    unsafe { CPTR_EL2.get() & CPTR_EL2::TFP != 0 }
+}
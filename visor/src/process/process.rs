@@ -1,12 +1,15 @@
 use alloc::boxed::Box;
 use shim::io;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 
 use aarch64;
 
+use crate::debug::DebugState;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{Balloon, FpState, Stack, State, Stride, VmConfig};
 use crate::traps::TrapFrame;
+use crate::traps::stats::ExitStats;
+use crate::vdev::Devices;
 use crate::vm::*;
 use kernel_api::{OsError, OsResult};
 
@@ -18,10 +21,30 @@ pub type Id = u8;
 pub struct Process {
     /// The saved trap frame of a process.
     pub context: Box<TrapFrame>,
+    /// The saved FP/SIMD registers, loaded lazily.
+    pub fp: Box<FpState>,
     /// The page table describing the Virtual Memory of the process
     pub vmap: Box<GuestPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// Why and how often this VM has exited to the hypervisor.
+    pub exits: ExitStats,
+    /// Per-VM policies.
+    pub config: VmConfig,
+    /// The image this VM was loaded from, used to rebuild it on restart.
+    pub image: Option<PathBuf>,
+    /// Number of times this VM has crashed and been restarted.
+    pub crashes: u32,
+    /// Whether the guest has been seen with its own exception vectors.
+    pub vectors_installed: bool,
+    /// Devices emulated for this VM alone.
+    pub devices: Devices,
+    /// CPU time accounting for the scheduler.
+    pub stride: Stride,
+    /// Pages the guest gave back to the hypervisor.
+    pub balloon: Balloon,
+    /// The state of the debugger attached to this VM, if any.
+    pub debug: DebugState,
 }
 
 impl Process {
@@ -34,21 +57,46 @@ impl Process {
         let vmap = Box::new(GuestPageTable::new());
         let mut tf = TrapFrame::default();
         tf.VTTBR = vmap.get_baddr().as_u64();
-        tf.VBAR_EL1 = 0x1DEAD0000; // avoid exception looping. just set this to an nontranslatable address if the kernel crashes before setting up its handler
+        tf.VBAR_EL1 = GUEST_VBAR_SENTINEL; // avoid exception looping. just set this to an nontranslatable address if the kernel crashes before setting up its handler
         tf.SCTLR_EL1 = aarch64::SCTLR_EL1::RES1;
+        // target execution level EL1 (ref: C5.2.19)
+        tf.SPSR = aarch64::SPSR_EL2::M & 0b0101; // EL1h
         Ok(Process{
             context: Box::new(tf),
+            fp: Box::new(FpState::default()),
             vmap: vmap,
             state: State::Ready,
+            exits: ExitStats::default(),
+            config: VmConfig::default(),
+            image: None,
+            crashes: 0,
+            vectors_installed: false,
+            devices: Devices::default(),
+            stride: Stride::default(),
+            balloon: Balloon::default(),
+            debug: DebugState::default(),
         })
     }
 
     pub fn set_vmid(&mut self, vmid: Id) {
-        self.context.VTTBR = (self.context.VTTBR & 0x0000FFFFFFFFFFFF) | ((vmid as u64) << 48);
+        self.vmap.set_vmid(vmid);
+        self.context.VTTBR = self.vmap.vttbr();
     }
 
     pub fn get_vmid(&self) -> Id {
-        aarch64::VTTBR_EL2::get_masked(self.context.VTTBR, aarch64::VTTBR_EL2::VMID) as Id
+        self.context.vmid()
+    }
+
+    /// Checks the guest's `VBAR_EL1` in `tf`, remembering once the guest has
+    /// installed its own vectors. Returns `true` if the guest has since put
+    /// the sentinel back, which it does to report that it crashed.
+    pub fn vectors_removed(&mut self, tf: &TrapFrame) -> bool {
+        if tf.VBAR_EL1 != GUEST_VBAR_SENTINEL {
+            self.vectors_installed = true;
+            false
+        } else {
+            self.vectors_installed
+        }
     }
 
     /// Load a program stored in the given path by calling `do_load()` method.
@@ -56,28 +104,26 @@ impl Process {
     ///
     /// Returns Os Error if do_load fails.
     pub fn load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        use crate::VMM;
-
-        let mut p = Process::do_load(pn)?;
-
-        // flush dcache of guest pagetable so we are sure that future translations will see our new pagetable.
-        // aarch64::clean_invalidate_dcache(p.vmap.get_baddr().as_u64(), core::mem::size_of::<PageTable>() as u64);
-
-        p.context.ELR = Process::get_image_base().as_u64();
-        // guest expects interrupts to be masked
-        p.context.SPSR_EL1 = aarch64::SPSR_EL1::F | aarch64::SPSR_EL1::A | aarch64::SPSR_EL1::I | aarch64::SPSR_EL1::D;
+        use crate::FILESYSTEM;
+        use fat32::traits::FileSystem;
 
+        let image = pn.as_ref().to_path_buf();
+        let mut p = Process::do_load(FILESYSTEM.open_file(pn)?)?;
+        p.image = Some(image);
         Ok(p)
     }
 
-    /// Creates a process and open a file with given path.
+    /// Loads a program from the bytes of `image`, such as a kernel received
+    /// over the console. The process has no image path, so it isn't
+    /// restarted when it stops.
+    pub fn load_image(image: &[u8]) -> OsResult<Process> {
+        Process::do_load(image)
+    }
+
+    /// Creates a process and loads the program read from `file`.
     /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
     /// permission to load file's contents.
-    fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
-        use crate::FILESYSTEM;
-        use fat32::traits::FileSystem;
-        use io::Read;
-
+    fn do_load<R: io::Read>(mut file: R) -> OsResult<Process> {
         let mut p = Process::new()?;
 
         let mut va = VirtualAddr::from(0);
@@ -121,7 +167,6 @@ impl Process {
         }
     
         // load image
-        let mut file = FILESYSTEM.open_file(pn)?;
         'outer: loop {
             let page = p.vmap.alloc(va, PagePerm::RWX);
             va += VirtualAddr::from(PAGE_SIZE);
@@ -134,7 +179,14 @@ impl Process {
                 n += nread;
             }
         }
-        
+
+        // flush dcache of guest pagetable so we are sure that future translations will see our new pagetable.
+        // aarch64::clean_invalidate_dcache(p.vmap.get_baddr().as_u64(), core::mem::size_of::<PageTable>() as u64);
+
+        p.context.ELR = Process::get_image_base().as_u64();
+        // guest expects interrupts to be masked
+        p.context.SPSR_EL1 = aarch64::SPSR_EL1::F | aarch64::SPSR_EL1::A | aarch64::SPSR_EL1::I | aarch64::SPSR_EL1::D;
+
         Ok(p)
     }
 
@@ -0,0 +1,99 @@
+use core::time::Duration;
+
+use shim::path::PathBuf;
+
+use crate::process::{Id, Process, RestartPolicy, VmConfig};
+use crate::traps::stats::ExitStats;
+
+/// Ways a guest is considered to have crashed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CrashReason {
+    /// The guest faulted while its `VBAR_EL1` was the unmapped sentinel.
+    NoVectors,
+    /// The guest set its `VBAR_EL1` back to the sentinel.
+    VectorsRemoved,
+    /// The guest faulted again while handling an injected exception.
+    NestedFault,
+    /// The guest faulted and its fault policy is to kill it.
+    Killed,
+    /// The guest stopped petting its watchdog.
+    Watchdog,
+    /// The guest needed a page, and no other VM could give any back.
+    OutOfMemory,
+}
+
+/// Why a VM stopped running.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    /// The guest asked to be powered off.
+    Shutdown,
+    /// The guest asked to be reset.
+    Reset,
+    /// The guest crashed.
+    Crash(CrashReason),
+    /// Someone at the shell stopped it.
+    Stopped,
+}
+
+/// A VM waiting to be rebuilt from its image.
+#[derive(Debug)]
+pub struct Restart {
+    pub vmid: Id,
+    pub image: PathBuf,
+    pub config: VmConfig,
+    /// Crashes so far, including the one that led to this restart.
+    pub crashes: u32,
+    /// Exit statistics carried over from the previous incarnation.
+    pub exits: ExitStats,
+    /// Time at which the VM should be started again.
+    pub at: Duration,
+}
+
+impl Restart {
+    /// Decides whether `process`, which stopped for `reason` at time `now`,
+    /// should be restarted. Returns the pending restart if so.
+    pub fn plan(process: Process, reason: StopReason, now: Duration) -> Option<Restart> {
+        let image = process.image?;
+        let config = process.config;
+        let mut crashes = process.crashes;
+        let delay = match reason {
+            StopReason::Stopped => return None,
+            StopReason::Reset => Duration::from_secs(0),
+            StopReason::Shutdown => match config.restart {
+                RestartPolicy::Always => Duration::from_secs(0),
+                _ => return None,
+            },
+            StopReason::Crash(_) => {
+                if config.restart == RestartPolicy::Never {
+                    return None;
+                }
+                crashes += 1;
+                if config.max_retries.map_or(false, |max| crashes > max) {
+                    return None;
+                }
+                config.backoff_for(crashes)
+            },
+        };
+        Some(Restart {
+            vmid: process.context.vmid(),
+            image,
+            config,
+            crashes,
+            exits: process.exits,
+            at: now + delay,
+        })
+    }
+
+    /// Rebuilds the VM from its image.
+    pub fn load(self) -> Result<Process, (Restart, kernel_api::OsError)> {
+        match Process::load(&self.image) {
+            Ok(mut process) => {
+                process.config = self.config;
+                process.crashes = self.crashes;
+                process.exits = self.exits;
+                Ok(process)
+            },
+            Err(e) => Err((self, e)),
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use core::ops::DerefMut;
+use core::time::Duration;
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt;
 
 use pi::timer;
@@ -9,11 +12,21 @@ use aarch64::*;
 
 use crate::param;
 use crate::mutex::{Mutex, MutexFunctor};
-use crate::param::{PAGE_MASK, PAGE_SIZE, TICK};
-use crate::process::{Id, Process, State};
+use crate::param::{PAGE_MASK, PAGE_SIZE, DEFAULT_IMAGE, VMS_CONFIG};
+use crate::console::kprintln;
+use crate::debug::gdb;
+use crate::log;
+use crate::process::{CrashReason, Id, Process, Snapshot, State, StopReason, VmConfig};
+use crate::process::fpu;
+use crate::process::restart::Restart;
+use crate::shell::Shell;
 use crate::traps::TrapFrame;
+use crate::vm::GuestPageTable;
+use crate::vm::share;
 use crate::VMM;
+use kernel_api::{OsError, OsResult};
 use crate::IRQ;
+use crate::SCHEDULER;
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
@@ -55,17 +68,106 @@ impl GlobalScheduler {
     /// restoring the next process's trap frame into `tf`. For more details, see
     /// the documentation on `Scheduler::schedule_out()` and `Scheduler::switch_to()`.
     pub fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Id {
-        self.critical(|scheduler| scheduler.schedule_out(new_state, tf));
+        let now = timer::current_time();
+        self.critical(|scheduler| scheduler.schedule_out(new_state, tf, now));
         self.switch_to(tf)
     }
 
+    /// Restores the next process that can run into `tf` and arms the timer
+    /// for the end of its timeslice. While there is none, waits for pending
+    /// restarts and throttled VMs; drops into the shell if there is nothing
+    /// left to wait for.
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
         loop {
-            let rtn = self.critical(|scheduler| scheduler.switch_to(tf));
-            if let Some(id) = rtn {
+            let now = timer::current_time();
+            let rtn = self.critical(|scheduler| scheduler.switch_to(tf, now));
+            if let Some((id, slice)) = rtn {
+                timer::tick_in(slice);
                 return id;
             }
-            aarch64::wfe();
+            let idle = self.critical(|scheduler| {
+                scheduler.poll_restarts(timer::current_time());
+                scheduler.processes.is_empty() && scheduler.restarts.is_empty()
+            });
+            if idle {
+                kprintln!("no VMs left to run");
+                Shell::new("! ").do_forever();
+            }
+            timer::spin_sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Stops the VM running with context `tf` for `reason`, arranging for it
+    /// to be restarted as its restart policy says, then switches `tf` to the
+    /// next VM that can run.
+    pub fn stop(&self, tf: &mut TrapFrame, reason: StopReason) {
+        let now = timer::current_time();
+        self.critical(|scheduler| {
+            if scheduler.schedule_out(State::Dead, tf, now) {
+                let process = scheduler.processes.pop_back().unwrap();
+                scheduler.retire(process, reason, now);
+            }
+        });
+        self.switch_to(tf);
+    }
+
+    /// Stops VM `vmid`, which must not be the one currently running, for
+    /// `reason`. Returns `false` if there is no such VM.
+    pub fn stop_vmid(&self, vmid: Id, reason: StopReason) -> bool {
+        let now = timer::current_time();
+        self.critical(|scheduler| {
+            match scheduler.processes.iter().position(|p| p.get_vmid() == vmid) {
+                Some(index) => {
+                    let process = scheduler.processes.remove(index).unwrap();
+                    scheduler.retire(process, reason, now);
+                    true
+                },
+                None => false
+            }
+        })
+    }
+
+    /// Periodic housekeeping, run from the timer interrupt with the context
+    /// `tf` of the running VM: starts VMs whose restart is due, and stops VMs
+    /// that have crashed or whose emulated PM watchdog fired. Returns `true`
+    /// if the running VM was stopped, in which case `tf` already holds the
+    /// next VM.
+    pub fn tick(&self, tf: &mut TrapFrame) -> bool {
+        let now = timer::current_time();
+        let running = tf.vmid();
+        let (removed, expired) = self.critical(|scheduler| {
+            scheduler.poll_restarts(now);
+            let removed = scheduler.get_by_vmid(running)
+                .map_or(false, |process| process.vectors_removed(tf));
+            let expired: Vec<(Id, StopReason)> = scheduler.processes.iter()
+                .filter_map(|p| {
+                    p.devices.pm.expired(now).map(|reason| (p.get_vmid(), reason))
+                })
+                .collect();
+            let mut tables: Vec<&mut GuestPageTable> = scheduler.processes.iter_mut()
+                .map(|p| &mut p.vmap)
+                .collect();
+            share::scan(&mut tables, param::MERGE_PAGES_PER_TICK);
+            (removed, expired)
+        });
+
+        let mut running_reason = None;
+        for &(vmid, reason) in expired.iter() {
+            if vmid == running {
+                running_reason = Some(reason);
+            } else {
+                self.stop_vmid(vmid, reason);
+            }
+        }
+        if removed {
+            running_reason = running_reason.or(Some(StopReason::Crash(CrashReason::VectorsRemoved)));
+        }
+        match running_reason {
+            Some(reason) => {
+                self.stop(tf, reason);
+                true
+            },
+            None => false,
         }
     }
 
@@ -79,43 +181,46 @@ impl GlobalScheduler {
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal conditions.
     pub fn start(&self) -> ! {
-        // schedule a timer interrupt 1 timeslice from now
+        // the timer fires at the end of every timeslice
         IRQ.register(Interrupt::Timer1, Box::new(|tf| {
-            crate::console::kprintln!("Tick!");
-            timer::tick_in(TICK);
-            // SCHEDULER.switch(State::Ready, tf);
+            gdb::poll_interrupt(tf);
+            if !SCHEDULER.tick(tf) {
+                SCHEDULER.switch(State::Ready, tf);
+            }
         }));
-        timer::tick_in(TICK);
         let mut controller = Controller::new();
         controller.enable(Interrupt::Timer1);
 
         unsafe {
-            // enable CNTP for EL1/EL0 (ref: D7.5.2, D7.5.13)
-            // NOTE: This doesn't actually enable the counter stream.
-            // CNTHCTL_EL2.set(CNTHCTL_EL2.get() | CNTHCTL_EL2::EL0VCTEN | CNTHCTL_EL2::EL0PCTEN);
-            CNTVOFF_EL2.set(0);
+            // let EL1/EL0 read the physical counter, but trap the physical
+            // timer, which all VMs would share; each gets a virtual timer
+            // instead, with its own CNTVOFF_EL2 (ref: D7.5.2, D7.5.13)
+            CNTHCTL_EL2.set((CNTHCTL_EL2.get() | CNTHCTL_EL2::EL1PCTEN) & !CNTHCTL_EL2::EL1PCEN);
 
             // enable AArch64 in EL1 (A53: 4.3.36)
             HCR_EL2.set(HCR_EL2.get() | HCR_EL2::RW | HCR_EL2::IMO | HCR_EL2::RES1);
 
-            // enable floating point and SVE (SIMD) (A53: 4.3.38, 4.3.34)
-            CPTR_EL2.set(0);
+            // enable floating point and SVE (SIMD) (A53: 4.3.38, 4.3.34), but
+            // trap it to EL2 until a VM uses it, so its registers are loaded
+            // lazily
+            fpu::set_trap(true);
             CPACR_EL1.set(CPACR_EL1.get() | (0b11 << 20));
 
+            // unlock the OS lock, which masks software step (ref: D2.5)
+            OSLAR_EL1.set(0);
+
             // mask interrupts
             // DAIF.set(DAIF.get() | DAIF::D | DAIF::A | DAIF::I | DAIF::F);
 
-            // target execution level EL1 (ref: C5.2.19)
-            SPSR_EL2.set(
-                SPSR_EL2::M & 0b0101 // EL1h
-                // | SPSR_EL2::F
-                // | SPSR_EL2::I
-                // | SPSR_EL2::D
-                // | SPSR_EL2::A,
-            );
+            // the target execution level (SPSR_EL2) comes from the trap frame
         }
         
-        let process = self.get_by_vmid(0);
+        let mut process = self.get_by_vmid(0);
+        process.state = State::Running;
+        let process = &mut *process;
+        timer::tick_in(process.stride.start(&process.config, timer::current_time()));
+        process.devices.switch_in();
+        process.debug.switch_in(&mut process.context);
 
         // flush pagetables from dcache
         aarch64::clean_invalidate_dcache(process.vmap.get_baddr().as_u64(), core::mem::size_of::<crate::vm::PageTable>() as u64);
@@ -136,11 +241,21 @@ impl GlobalScheduler {
         unreachable!("bruh moment");
     }
 
-    /// Initializes the scheduler and add userspace processes to the Scheduler
+    /// Initializes the scheduler and adds the VMs listed in `VMS_CONFIG`, or
+    /// just `DEFAULT_IMAGE` if there is no such file.
     pub unsafe fn initialize(&self) {
         let mut scheduler = Scheduler::new();
-        let kernel = Process::load("/kernel.bin").expect("load failed");
-        scheduler.add(kernel);
+        for (image, config) in read_vms_config() {
+            match Process::load(&image) {
+                Ok(mut process) => {
+                    process.config = config;
+                    let vmid = scheduler.add(process);
+                    log::info!("VM {}: loaded {}", vmid, image);
+                },
+                Err(e) => log::error!("{}: load failed: {:?}", image, e),
+            }
+        }
+        assert!(!scheduler.processes.is_empty(), "no VMs to run");
         self.0.lock().replace(scheduler);
     }
 
@@ -162,10 +277,44 @@ impl GlobalScheduler {
     // }
 }
 
+/// Reads the image and configuration of every VM to start from
+/// `VMS_CONFIG`. Lines that don't parse are reported and skipped.
+fn read_vms_config() -> Vec<(String, VmConfig)> {
+    use crate::FILESYSTEM;
+    use fat32::traits::FileSystem;
+    use shim::io::Read;
+
+    let mut vms = Vec::new();
+    let mut text = String::new();
+    let read = FILESYSTEM.open_file(VMS_CONFIG)
+        .and_then(|mut file| file.read_to_string(&mut text));
+    if read.is_err() {
+        vms.push((String::from(DEFAULT_IMAGE), VmConfig::default()));
+        return vms;
+    }
+
+    for (n, line) in text.lines().enumerate() {
+        match VmConfig::parse_line(line) {
+            Ok(Some((image, config))) => vms.push((String::from(image), config)),
+            Ok(None) => {},
+            Err(word) => log::warn!("{}:{}: bad option `{}`", VMS_CONFIG, n + 1, word),
+        }
+    }
+    vms
+}
+
 #[derive(Debug)]
 pub struct Scheduler {
     processes: VecDeque<Process>,
-    last_id: Id
+    restarts: Vec<Restart>,
+    last_id: Id,
+    /// Pass of the VM that was picked last. VMs that join the queue start
+    /// from here, so they neither starve the others nor get starved.
+    pass: u64,
+    /// The VM whose FP/SIMD registers are in the hardware.
+    fp_owner: Option<Id>,
+    /// Snapshots taken from the shell.
+    snapshots: Vec<Snapshot>,
 }
 
 impl Scheduler {
@@ -173,12 +322,22 @@ impl Scheduler {
     fn new() -> Scheduler {
         Scheduler {
             processes: VecDeque::new(),
-            last_id: 0
+            restarts: Vec::new(),
+            last_id: 0,
+            pass: 0,
+            fp_owner: None,
+            snapshots: Vec::new(),
         }
     }
 
-    fn get_by_vmid(&mut self, vmid: u8) -> Option<&mut Process> {
-        return Some(&mut self.processes[0]) // TODO: actually implement this lol
+    /// Returns an iterator over every process, in queue order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.processes.iter_mut()
+    }
+
+    /// Returns the process running in VM `vmid`, if any.
+    pub fn get_by_vmid(&mut self, vmid: u8) -> Option<&mut Process> {
+        self.processes.iter_mut().find(|p| p.get_vmid() == vmid)
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID if
@@ -191,37 +350,260 @@ impl Scheduler {
     fn add(&mut self, mut process: Process) -> Id {
         let vmid = self.last_id;
         process.set_vmid(vmid);
+        process.stride.pass = self.pass;
         self.processes.push_back(process);
         self.last_id = self.last_id.checked_add(1).expect("too many vmids");
         vmid
     }
 
+    /// Hands the FP/SIMD registers to VM `vmid`, which trapped on accessing
+    /// them, loading its saved registers unless they are still in place.
+    pub fn load_fp(&mut self, vmid: Id) {
+        if self.fp_owner != Some(vmid) {
+            if let Some(process) = self.get_by_vmid(vmid) {
+                process.fp.restore();
+            }
+            self.fp_owner = Some(vmid);
+        }
+        fpu::set_trap(false);
+    }
+
+    /// Raises the balloon targets of the VMs other than `vmid` that run a
+    /// balloon driver by up to `pages` each, no further than the pages they
+    /// have mapped. Returns the total number of pages they still owe,
+    /// including those asked for now; none if no VM can give any back.
+    pub fn reclaim(&mut self, vmid: Id, pages: usize) -> usize {
+        let mut owed = 0;
+        for process in self.processes.iter_mut().filter(|p| p.get_vmid() != vmid) {
+            let balloon = &mut process.balloon;
+            if !balloon.active {
+                continue;
+            }
+            let limit = process.vmap.mappings().count() + balloon.len();
+            let target = core::cmp::min(balloon.target + pages, limit);
+            balloon.target = core::cmp::max(balloon.target, target);
+            owed += core::cmp::max(balloon.wanted(), 0) as usize;
+        }
+        owed
+    }
+
+    /// Saves VM `vmid`'s FP/SIMD registers into its `FpState` if they are
+    /// still in the hardware.
+    fn sync_fp(&mut self, vmid: Id) {
+        if self.fp_owner != Some(vmid) {
+            return;
+        }
+        let trapping = fpu::trapping();
+        fpu::set_trap(false);
+        if let Some(process) = self.get_by_vmid(vmid) {
+            process.fp.save();
+        }
+        fpu::set_trap(trapping);
+    }
+
+    /// Takes a snapshot of VM `vmid` at time `now`, with `tf` as its context
+    /// if it is the one running. Returns the index of the snapshot, or `None`
+    /// if there is no such VM.
+    pub fn snapshot(&mut self, vmid: Id, tf: Option<&TrapFrame>, now: Duration) -> Option<usize> {
+        self.sync_fp(vmid);
+        let process = self.get_by_vmid(vmid)?;
+        let tf = match process.state {
+            State::Running => tf.filter(|tf| tf.vmid() == vmid),
+            _ => None,
+        };
+        let snapshot = Snapshot::take(process, tf, now);
+        self.snapshots.push(snapshot);
+        Some(self.snapshots.len() - 1)
+    }
+
+    /// Returns the snapshots taken so far.
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+
+    /// Discards snapshot `index`. Later snapshots move down by one. Returns
+    /// `false` if there is no such snapshot.
+    pub fn drop_snapshot(&mut self, index: usize) -> bool {
+        if index < self.snapshots.len() {
+            self.snapshots.remove(index);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Puts the VM snapshot `index` was taken of back into that state at time
+    /// `now`, bringing it back if it has stopped since. If the VM is the one
+    /// running, `tf` must be its context, which is replaced as well. Returns
+    /// the VMID.
+    pub fn restore(&mut self, index: usize, tf: Option<&mut TrapFrame>, now: Duration) -> OsResult<Id> {
+        let snapshot = self.snapshots.get(index).ok_or(OsError::InvalidArgument)?;
+        let vmid = snapshot.vmid;
+        let position = self.processes.iter().position(|p| p.get_vmid() == vmid);
+        let running = position.map_or(false, |i| match self.processes[i].state {
+            State::Running => true,
+            _ => false,
+        });
+        let tf = match tf {
+            Some(tf) if tf.vmid() == vmid => Some(tf),
+            _ if running => return Err(OsError::InvalidArgument),
+            _ => None,
+        };
+
+        let mut process = snapshot.instantiate(now)?;
+        process.set_vmid(vmid);
+        if self.fp_owner == Some(vmid) {
+            // the registers in the hardware are the ones being replaced
+            self.fp_owner = None;
+            fpu::set_trap(true);
+        }
+        match position {
+            Some(i) => {
+                let old = &mut self.processes[i];
+                process.state = core::mem::replace(&mut old.state, State::Dead);
+                process.stride = old.stride.clone();
+                process.crashes = old.crashes;
+                process.exits = core::mem::replace(&mut old.exits, Default::default());
+                if running {
+                    process.devices.switch_in();
+                    process.debug.switch_in(&mut process.context);
+                    if let Some(tf) = tf {
+                        *tf = *process.context;
+                    }
+                }
+                // dropping the old process frees whatever the VM didn't share
+                self.processes[i] = process;
+            },
+            None => {
+                self.restarts.retain(|r| r.vmid != vmid);
+                process.stride.pass = self.pass;
+                self.processes.push_back(process);
+            }
+        }
+        Ok(vmid)
+    }
+
+    /// Starts a new VM from snapshot `index` at time `now`. Returns its VMID.
+    pub fn clone_snapshot(&mut self, index: usize, now: Duration) -> OsResult<Id> {
+        let snapshot = self.snapshots.get(index).ok_or(OsError::InvalidArgument)?;
+        let process = snapshot.instantiate(now)?;
+        Ok(self.add(process))
+    }
+
+    /// Returns the restarts that are waiting for their backoff to pass.
+    pub fn pending_restarts(&self) -> &[Restart] {
+        &self.restarts
+    }
+
+    /// Disposes of `process`, which stopped for `reason` at time `now`,
+    /// queueing it for a restart if its policy asks for one.
+    fn retire(&mut self, process: Process, reason: StopReason, now: Duration) {
+        let vmid = process.get_vmid();
+        if self.fp_owner == Some(vmid) {
+            self.fp_owner = None;
+        }
+        match Restart::plan(process, reason, now) {
+            Some(restart) => {
+                log::warn!("VM {}: stopped ({:?}), restarting in {}ms",
+                           vmid, reason, (restart.at - now).as_millis());
+                self.restarts.push(restart);
+            },
+            None => log::info!("VM {}: stopped ({:?})", vmid, reason),
+        }
+    }
+
+    /// Rebuilds every VM whose restart is due at time `now`, keeping its VMID.
+    fn poll_restarts(&mut self, now: Duration) {
+        let mut i = 0;
+        while i < self.restarts.len() {
+            if self.restarts[i].at > now {
+                i += 1;
+                continue;
+            }
+            let restart = self.restarts.swap_remove(i);
+            let vmid = restart.vmid;
+            match restart.load() {
+                Ok(mut process) => {
+                    process.set_vmid(vmid);
+                    process.stride.pass = self.pass;
+                    self.processes.push_back(process);
+                    log::info!("VM {}: restarted", vmid);
+                },
+                Err((restart, e)) => {
+                    log::error!("VM {}: restart from {} failed: {:?}", vmid, restart.image.display(), e);
+                }
+            }
+        }
+    }
+
     /// Finds the currently running process, sets the current process's state
-    /// to `new_state`, prepares the context switch on `tf` by saving `tf`
-    /// into the current process, and push the current process back to the
-    /// end of `processes` queue.
+    /// to `new_state`, charges it for the CPU time it used up to `now`,
+    /// prepares the context switch on `tf` by saving `tf` into the current
+    /// process, and push the current process back to the end of `processes`
+    /// queue.
     ///
     /// If the `processes` queue is empty or there is no current process,
     /// returns `false`. Otherwise, returns `true`.
-    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
-        unimplemented!("Scheduler::schedule_out()")
+    fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame, now: Duration) -> bool {
+        let vmid = tf.vmid();
+        let index = self.processes.iter()
+            .position(|p| p.get_vmid() == vmid && match p.state { State::Running => true, _ => false });
+        match index {
+            Some(index) => {
+                let mut process = self.processes.remove(index).unwrap();
+                process.state = new_state;
+                process.stride.stop(&process.config, now);
+                process.devices.switch_out();
+                if self.fp_owner == Some(vmid) && !fpu::trapping() {
+                    process.fp.save();
+                }
+                *process.context = *tf;
+                self.processes.push_back(process);
+                true
+            },
+            None => false
+        }
     }
 
-    /// Finds the next process to switch to, brings the next process to the
-    /// front of the `processes` queue, changes the next process's state to
-    /// `Running`, and performs context switch by restoring the next process`s
+    /// Finds the next process to switch to at time `now`: the ready process
+    /// with the lowest pass that isn't throttled by its cap, earlier in the
+    /// queue on ties. Brings it to the front of the `processes` queue, changes
+    /// its state to `Running`, and performs context switch by restoring its
     /// trap frame into `tf`.
     ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
-    /// `Some` of the next process`s process ID.
-    fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        unimplemented!("Scheduler::switch_to()")
+    /// `Some` of the next process`s process ID and its timeslice.
+    fn switch_to(&mut self, tf: &mut TrapFrame, now: Duration) -> Option<(Id, Duration)> {
+        let mut next: Option<(usize, u64)> = None;
+        for (index, p) in self.processes.iter_mut().enumerate() {
+            if !p.is_ready() || p.stride.throttled(&p.config, now) {
+                continue;
+            }
+            if next.map_or(true, |(_, pass)| p.stride.pass < pass) {
+                next = Some((index, p.stride.pass));
+            }
+        }
+        let (index, pass) = next?;
+        let mut process = self.processes.remove(index).unwrap();
+        process.state = State::Running;
+        let slice = process.stride.start(&process.config, now);
+        process.devices.switch_in();
+        process.debug.switch_in(&mut process.context);
+        self.pass = pass;
+        *tf = *process.context;
+        let vmid = process.get_vmid();
+        fpu::set_trap(self.fp_owner != Some(vmid));
+        self.processes.push_front(process);
+        Some((vmid, slice))
     }
 
     /// Kills currently running process by scheduling out the current process
     /// as `Dead` state. Removes the dead process from the queue, drop the
     /// dead process's instance, and returns the dead process's process ID.
     fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        unimplemented!("Scheduler::kill()")
+        if !self.schedule_out(State::Dead, tf, timer::current_time()) {
+            return None;
+        }
+        self.processes.pop_back().map(|process| process.get_vmid())
     }
 }
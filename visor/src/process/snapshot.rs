@@ -0,0 +1,202 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use shim::io;
+use shim::path::PathBuf;
+
+use aarch64::CNTPCT_EL0;
+use kernel_api::OsResult;
+
+use crate::param::PAGE_SIZE;
+use crate::process::{Balloon, FpState, Id, Process, VmConfig};
+use crate::traps::TrapFrame;
+use crate::vdev::Devices;
+use crate::vm::share;
+use crate::vm::{PhysicalAddr, VirtualAddr};
+
+/// Identifies a serialized snapshot.
+const MAGIC: &[u8; 4] = b"VSNP";
+
+/// Version of the serialized format, bumped on every incompatible change.
+pub const VERSION: u32 = 2;
+
+/// The complete state of a VM at one point in time: its vCPU context, the
+/// pages mapped in its stage 2 table, and its emulated devices.
+///
+/// The pages aren't copied. Taking a snapshot maps them read-only and shared
+/// in the VM, so the VM and the snapshot only part ways on the pages the VM
+/// writes to afterwards (see `vm::share`). VMs restored or cloned from the
+/// snapshot share them the same way.
+#[derive(Debug)]
+pub struct Snapshot {
+    /// VM the snapshot was taken of.
+    pub vmid: Id,
+    /// Time at which it was taken.
+    pub taken: Duration,
+    /// Physical count at which it was taken.
+    count: u64,
+    context: TrapFrame,
+    fp: FpState,
+    config: VmConfig,
+    image: Option<PathBuf>,
+    vectors_installed: bool,
+    devices: Devices,
+    balloon: Balloon,
+    /// Every page the VM had mapped, as `(ipa, pa)`, in ascending IPA order.
+    /// The snapshot holds a reference to each.
+    pages: Vec<(VirtualAddr, PhysicalAddr)>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of `process` at time `now`, with `tf` as its context
+    /// if it is running. Its FP/SIMD registers must have been saved into
+    /// `process.fp` beforehand.
+    pub fn take(process: &mut Process, tf: Option<&TrapFrame>, now: Duration) -> Snapshot {
+        let running = tf.is_some();
+        let ipas: Vec<VirtualAddr> = process.vmap.mappings().map(|(ipa, _)| ipa).collect();
+        let mut pages = Vec::with_capacity(ipas.len());
+        for ipa in ipas {
+            if let Some(pa) = share::share(&mut process.vmap, ipa) {
+                pages.push((ipa, pa));
+            }
+        }
+        Snapshot {
+            vmid: process.get_vmid(),
+            taken: now,
+            count: unsafe { CNTPCT_EL0.get() },
+            context: *tf.unwrap_or(&*process.context),
+            fp: (*process.fp).clone(),
+            config: process.config.clone(),
+            image: process.image.clone(),
+            vectors_installed: process.vectors_installed,
+            devices: process.devices.snapshot(running),
+            balloon: process.balloon.clone(),
+            pages,
+        }
+    }
+
+    /// Returns the number of pages in the snapshot.
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Builds a new process, without a VMID yet, in the state the snapshot
+    /// was taken in. The guest resumes at time `now` as if it had been paused
+    /// in between: its virtual count picks up where it left off, and an armed
+    /// watchdog gets its full timeout again.
+    pub fn instantiate(&self, now: Duration) -> OsResult<Process> {
+        let mut process = Process::new()?;
+        let vttbr = process.context.VTTBR;
+        *process.context = self.context;
+        process.context.VTTBR = vttbr;
+        *process.fp = self.fp.clone();
+        process.config = self.config.clone();
+        process.image = self.image.clone();
+        process.vectors_installed = self.vectors_installed;
+        process.devices = self.devices.clone();
+        process.devices.pm.rearm(now);
+        process.devices.vtimer.offset += unsafe { CNTPCT_EL0.get() } - self.count;
+        process.balloon = self.balloon.clone();
+        for &(ipa, pa) in self.pages.iter() {
+            share::map(&mut process.vmap, ipa, pa);
+        }
+        Ok(process)
+    }
+
+    /// Returns the fixed-size part of the serialized snapshot, all integers
+    /// little-endian:
+    ///
+    ///   * magic `VSNP`, then the format version, VMID and page count, and
+    ///     the page size, as `u32`s
+    ///   * the time the snapshot was taken in microseconds, as a `u64`
+    ///   * the trap frame, as the `u64`s of its fields in order
+    ///   * the FP/SIMD registers, see `FpState::encode`
+    ///   * the devices, see `Devices::encode`
+    ///
+    /// The pages follow, each as its IPA (`u64`) and `PAGE_SIZE` bytes.
+    fn header(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        for &word in [VERSION, self.vmid as u32, self.pages.len() as u32, PAGE_SIZE as u32].iter() {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.taken.as_micros() as u64).to_le_bytes());
+        let context = unsafe {
+            core::slice::from_raw_parts(&self.context as *const TrapFrame as *const u64,
+                                        core::mem::size_of::<TrapFrame>() / 8)
+        };
+        for word in context.iter() {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        self.fp.encode(&mut out);
+        self.devices.encode(&mut out);
+        out
+    }
+
+    /// Returns a reader of the serialized snapshot, e.g. to send with
+    /// `Xmodem::transmit`.
+    pub fn reader(&self) -> SnapshotReader {
+        SnapshotReader { snapshot: self, header: self.header(), offset: 0 }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        for &(_, pa) in self.pages.iter() {
+            share::release(pa);
+        }
+    }
+}
+
+/// Reads a snapshot in its serialized format, see `Snapshot::header`.
+pub struct SnapshotReader<'a> {
+    snapshot: &'a Snapshot,
+    header: Vec<u8>,
+    /// Bytes read so far.
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Size of the record of every page: its IPA and contents.
+    const RECORD: usize = 8 + PAGE_SIZE;
+
+    /// Returns the total size of the serialized snapshot.
+    pub fn len(&self) -> usize {
+        self.header.len() + self.snapshot.pages.len() * SnapshotReader::RECORD
+    }
+}
+
+impl<'a> io::Read for SnapshotReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset < self.header.len() {
+            let n = core::cmp::min(buf.len(), self.header.len() - self.offset);
+            buf[..n].copy_from_slice(&self.header[self.offset..self.offset + n]);
+            self.offset += n;
+            return Ok(n);
+        }
+        let at = self.offset - self.header.len();
+        let (ipa, pa) = match self.snapshot.pages.get(at / SnapshotReader::RECORD) {
+            Some(&page) => page,
+            None => return Ok(0),
+        };
+        let within = at % SnapshotReader::RECORD;
+        let n = if within < 8 {
+            let ipa = ipa.as_u64().to_le_bytes();
+            let n = core::cmp::min(buf.len(), 8 - within);
+            buf[..n].copy_from_slice(&ipa[within..within + n]);
+            n
+        } else {
+            let within = within - 8;
+            if within == 0 {
+                // the guest may still have dirty lines for the page in its cache
+                aarch64::clean_invalidate_dcache(pa.as_u64(), PAGE_SIZE as u64);
+            }
+            let page = unsafe { core::slice::from_raw_parts(pa.as_usize() as *const u8, PAGE_SIZE) };
+            let n = core::cmp::min(buf.len(), PAGE_SIZE - within);
+            buf[..n].copy_from_slice(&page[within..within + n]);
+            n
+        };
+        self.offset += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,77 @@
+use core::cmp::min;
+use core::time::Duration;
+
+use crate::param::CAP_PERIOD;
+use crate::process::VmConfig;
+
+/// Pass a VM of weight 1 advances by for every microsecond it runs.
+const STRIDE1: u64 = 1 << 16;
+
+/// Stride scheduling state of a VM.
+///
+/// Every VM has a pass value that advances by the CPU time it used divided
+/// by its weight; the ready VM with the lowest pass runs next. Over time each
+/// VM gets a share of the CPU proportional to its weight. On top of that, a
+/// VM with a cap stops being picked once it has used up its share of the
+/// current `CAP_PERIOD`, even if nothing else wants to run.
+#[derive(Debug, Default, Clone)]
+pub struct Stride {
+    /// Weighted CPU time used so far.
+    pub pass: u64,
+    /// Total CPU time used so far.
+    pub runtime: Duration,
+    /// Start of the current cap period.
+    period_start: Duration,
+    /// CPU time used in the current cap period.
+    period_used: Duration,
+    /// When the VM was switched in, while it is running.
+    since: Option<Duration>,
+}
+
+impl Stride {
+    /// Starts a new cap period if the current one is over at time `now`.
+    fn roll(&mut self, now: Duration) {
+        if now >= self.period_start + CAP_PERIOD {
+            self.period_start = now;
+            self.period_used = Duration::from_secs(0);
+        }
+    }
+
+    /// Returns how much longer the VM may run in the current cap period at
+    /// time `now`, or `None` if it is uncapped.
+    pub fn budget(&mut self, config: &VmConfig, now: Duration) -> Option<Duration> {
+        let cap = config.cap?;
+        self.roll(now);
+        let allowed = CAP_PERIOD * cap / 100;
+        Some(allowed.checked_sub(self.period_used).unwrap_or_default())
+    }
+
+    /// Returns `true` if the VM has used up its cap at time `now`.
+    pub fn throttled(&mut self, config: &VmConfig, now: Duration) -> bool {
+        self.budget(config, now).map_or(false, |left| left == Duration::from_secs(0))
+    }
+
+    /// Records that the VM was switched in at time `now`, and returns how
+    /// long it may run before it is preempted.
+    pub fn start(&mut self, config: &VmConfig, now: Duration) -> Duration {
+        self.since = Some(now);
+        match self.budget(config, now) {
+            Some(left) => min(config.timeslice, left),
+            None => config.timeslice,
+        }
+    }
+
+    /// Records that the VM was switched out at time `now`, charging it for
+    /// the time it ran.
+    pub fn stop(&mut self, config: &VmConfig, now: Duration) {
+        let since = match self.since.take() {
+            Some(since) => since,
+            None => return,
+        };
+        let ran = now.checked_sub(since).unwrap_or_default();
+        self.runtime += ran;
+        self.roll(since);
+        self.period_used += ran;
+        self.pass += ran.as_micros() as u64 * STRIDE1 / config.weight as u64;
+    }
+}
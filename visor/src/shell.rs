@@ -2,6 +2,7 @@ use shim::io;
 use shim::ioerr;
 use shim::path::{Path, PathBuf, Component};
 use alloc::string::String;
+use alloc::vec::Vec;
 
 use core::time::Duration;
 
@@ -9,6 +10,7 @@ use stack_vec::StackVec;
 use core::fmt::Write as FmtWrite;
 use shim::io::Write as IoWrite;
 
+use aarch64::disasm;
 use pi::atags::Atags;
 use pi::timer;
 use pi::power;
@@ -16,10 +18,23 @@ use pi::power;
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry, Metadata, Timestamp};
 
+use xmodem::{Xmodem, Ymodem};
+
 use crate::fs;
+use crate::param::PAGE_SIZE;
 use crate::console::{kprint, kprintln, CONSOLE};
+use crate::crashlog;
+use crate::debug::{DebugState, WatchKind, Watchpoint};
+use crate::log::{Level, LOGGER};
+use crate::mutex::Mutex;
+use crate::process::{FaultPolicy, Process, RestartPolicy};
+use crate::traps::TrapFrame;
+use crate::traps::stats::{TraceEntry, TRACE};
+use crate::vm::{guest, GuestPageTable};
+use crate::vm::share;
 use crate::ALLOCATOR;
 use crate::FILESYSTEM;
+use crate::SCHEDULER;
 
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
@@ -63,7 +78,9 @@ impl<'a> Command<'a> {
 pub struct Shell<'a, FS: FileSystem + Copy> {
     prefix: &'a str,
     cur_path: PathBuf,
-    fs: FS
+    fs: FS,
+    guest: Option<&'a mut TrapFrame>,
+    exit: bool
 }
 
 impl<'a> Shell<'a, &fs::FileSystem> {
@@ -74,8 +91,104 @@ impl<'a> Shell<'a, &fs::FileSystem> {
         Shell{
             prefix,
             cur_path: path,
-            fs: &FILESYSTEM
+            fs: &FILESYSTEM,
+            guest: None,
+            exit: false
+        }
+    }
+
+    /// Returns a shell whose guest inspection commands (`xd`, `vtop`, `dis`,
+    /// `vmap`, `snap`) operate on the VM that trapped with the trap frame `tf`.
+    pub fn with_guest(prefix: &'a str, tf: &'a mut TrapFrame) -> Shell<'a, &'a fs::FileSystem> {
+        let mut shell = Shell::new(prefix);
+        shell.guest = Some(tf);
+        shell
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+/// A file received with `recv`, kept in memory.
+struct Upload {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Files received with `recv`.
+static UPLOADS: Mutex<Vec<Upload>> = Mutex::new(Vec::new());
+
+/// How long `recv` waits for each byte before repeating its handshake.
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Receives a YMODEM batch on the console. Waits for the sender until it
+/// starts or something else is typed.
+fn receive_batch() -> io::Result<Vec<Upload>> {
+    let mut console = CONSOLE.lock();
+    let (mut ymodem, mut next) = loop {
+        let mut ymodem = Ymodem::new(console.raw_with_timeout(RECV_TIMEOUT));
+        match ymodem.recv_header() {
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            next => break (ymodem, next),
+        }
+    };
+
+    let mut files = Vec::new();
+    while let Some(info) = next? {
+        let mut data = Vec::new();
+        ymodem.recv_data(&info, &mut data)?;
+        files.push(Upload { name: String::from(info.name()), data });
+        next = ymodem.recv_header();
+    }
+    Ok(files)
+}
+
+/// Resets the board once the console has drained. Unlike a panic, this
+/// leaves no crash log behind and doesn't wait for RETURN.
+fn reboot() -> ! {
+    kprintln!("oyasumi.");
+    let _ = CONSOLE.lock().flush();
+    unsafe { power::PowerManager::new().reset() }
+}
+
+fn parse_num(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Prints the disassembly of `count` instructions of the guest with context
+/// `tf` and stage 2 table `vmap`, starting at guest VA `start`, marking the
+/// one at its `ELR`.
+pub fn print_code(tf: &TrapFrame, vmap: &GuestPageTable, start: u64, count: u64) {
+    for i in 0..count {
+        let va = (start & !0b11).wrapping_add(i * 4);
+        let mut insn = [0u8; 4];
+        let marker = if va == tf.ELR { "=>" } else { "  " };
+        if guest::read_va(tf, vmap, va, &mut insn) == 4 {
+            let insn = u32::from_le_bytes(insn);
+            kprintln!("{} {:016x}: {:08x}  {}", marker, va, insn, disasm::disassemble(insn, va));
+        } else {
+            kprintln!("{} {:016x}: <unmapped>", marker, va);
+        }
+    }
+}
+
+/// Prints `data` as a canonical hex+ASCII dump labeled starting at `addr`.
+fn hexdump(addr: u64, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        kprint!("{:08x}: ", addr + (i * 16) as u64);
+        for j in 0..16 {
+            match line.get(j) {
+                Some(b) => kprint!("{:02x} ", b),
+                None => kprint!("   "),
+            }
         }
+        kprint!(" |");
+        for &b in line {
+            kprint!("{}", if b >= 0x20 && b < 0x7f { b as char } else { '.' });
+        }
+        kprintln!("|");
     }
 }
 
@@ -199,6 +312,693 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
         Ok(())
     }
 
+    /// Returns the trap frame of the guest this shell is inspecting, printing
+    /// a message if there is none.
+    fn guest_frame(&self) -> Option<&TrapFrame> {
+        if self.guest.is_none() {
+            kprintln!("no guest context");
+        }
+        self.guest.as_ref().map(|tf| &**tf)
+    }
+
+    /// `xd <ipa> [len]`: hexdumps `len` bytes of guest memory at `ipa`.
+    fn xd(&mut self, cmd: Command) -> io::Result<()> {
+        let tf = match self.guest_frame() { Some(tf) => tf, None => return Ok(()) };
+        let (ipa, len) = match (cmd.args.get(1).and_then(|a| parse_num(a)), cmd.args.get(2)) {
+            (Some(ipa), None) => (ipa, 256),
+            (Some(ipa), Some(len)) => match parse_num(len) {
+                Some(len) => (ipa, len as usize),
+                None => { kprintln!("usage: xd <ipa> [len]"); return Ok(()) }
+            },
+            _ => { kprintln!("usage: xd <ipa> [len]"); return Ok(()) }
+        };
+        let process = SCHEDULER.get_by_vmid(tf.vmid());
+        let mut buf = [0u8; 256];
+        let mut done = 0;
+        while done < len {
+            let want = core::cmp::min(buf.len(), len - done);
+            let n = guest::read_ipa(&process.vmap, ipa + done as u64, &mut buf[..want]);
+            hexdump(ipa + done as u64, &buf[..n]);
+            if n < want {
+                kprintln!("xd: IPA {:#x} is not mapped", ipa + (done + n) as u64);
+                break;
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// `vtop <va>`: translates a guest VA to an IPA and then a PA, showing
+    /// every stage 1 descriptor on the way.
+    fn vtop(&mut self, cmd: Command) -> io::Result<()> {
+        let tf = match self.guest_frame() { Some(tf) => tf, None => return Ok(()) };
+        let va = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(va) => va,
+            None => { kprintln!("usage: vtop <va>"); return Ok(()) }
+        };
+        let process = SCHEDULER.get_by_vmid(tf.vmid());
+        match guest::walk(tf, &process.vmap, va) {
+            Ok(walk) => {
+                for step in walk.steps.iter().filter_map(|s| s.as_ref()) {
+                    kprintln!("  L{} desc @ IPA {:#x} = {:#018x}", step.level, step.desc_ipa, step.desc);
+                }
+                match process.vmap.translate(walk.ipa.into()) {
+                    Some(pa) => kprintln!("VA {:#x} -> IPA {:#x} -> PA {:#x}", va, walk.ipa, pa.as_u64()),
+                    None => kprintln!("VA {:#x} -> IPA {:#x} -> (not mapped in stage 2)", va, walk.ipa),
+                }
+            },
+            Err(e) => kprintln!("VA {:#x}: stage 1 walk failed: {:?}", va, e),
+        }
+        Ok(())
+    }
+
+    /// `dis [va] [count]`: dumps guest instructions. Without arguments, shows
+    /// the instructions around the faulting `ELR`.
+    fn dis(&mut self, cmd: Command) -> io::Result<()> {
+        let tf = match self.guest_frame() { Some(tf) => tf, None => return Ok(()) };
+        let (start, count) = match cmd.args.get(1) {
+            None => (tf.ELR.wrapping_sub(4 * 4), 9),
+            Some(arg) => match (parse_num(arg), cmd.args.get(2).map(|c| parse_num(c))) {
+                (Some(va), None) => (va, 8),
+                (Some(va), Some(Some(count))) => (va, count),
+                _ => { kprintln!("usage: dis [va] [count]"); return Ok(()) }
+            }
+        };
+        let process = SCHEDULER.get_by_vmid(tf.vmid());
+        print_code(tf, &process.vmap, start, count);
+        Ok(())
+    }
+
+    /// `vmap`: lists the guest's stage 2 mappings, merging contiguous runs.
+    fn vmap(&mut self, _cmd: Command) -> io::Result<()> {
+        let tf = match self.guest_frame() { Some(tf) => tf, None => return Ok(()) };
+        let process = SCHEDULER.get_by_vmid(tf.vmid());
+        kprintln!("{:?}", process.vmap);
+        let mut run: Option<(u64, u64, u64)> = None; // (ipa, pa, len)
+        let mut pages = 0;
+        for (ipa, pa) in process.vmap.mappings() {
+            let (ipa, pa) = (ipa.as_u64(), pa.as_u64());
+            pages += 1;
+            run = match run {
+                Some((i, p, len)) if i + len == ipa && p + len == pa => Some((i, p, len + PAGE_SIZE as u64)),
+                Some((i, p, len)) => {
+                    kprintln!("  IPA {:#010x}-{:#010x} -> PA {:#010x}", i, i + len, p);
+                    Some((ipa, pa, PAGE_SIZE as u64))
+                },
+                None => Some((ipa, pa, PAGE_SIZE as u64)),
+            };
+        }
+        if let Some((i, p, len)) = run {
+            kprintln!("  IPA {:#010x}-{:#010x} -> PA {:#010x}", i, i + len, p);
+        }
+        kprintln!("{} pages ({} KiB) mapped", pages, pages * PAGE_SIZE / 1024);
+        Ok(())
+    }
+
+    /// `exits [vmid]`: shows exit counters of every VM, or just `vmid`.
+    /// `exits trace [count]`: shows the most recent exits.
+    /// `exits send`: transmits the exit trace as CSV over XMODEM.
+    /// `exits clear`: resets all counters and the trace.
+    fn exits(&mut self, cmd: Command) -> io::Result<()> {
+        match (cmd.args.get(1).map(|a| *a), cmd.args.get(2)) {
+            (Some("trace"), count) => {
+                let count = match count.map(|c| parse_num(c)) {
+                    None => 32,
+                    Some(Some(count)) => count as usize,
+                    Some(None) => { kprintln!("usage: exits trace [count]"); return Ok(()) }
+                };
+                let trace = TRACE.lock();
+                for entry in trace.iter().skip(trace.len().saturating_sub(count)) {
+                    kprintln!("{}", entry);
+                }
+            },
+            (Some("send"), None) => {
+                let mut csv = String::new();
+                csv.push_str(TraceEntry::CSV_HEADER);
+                csv.push('\n');
+                for entry in TRACE.lock().iter() {
+                    entry.write_csv(&mut csv).unwrap();
+                }
+                kprintln!("exits: sending {} bytes, start your XMODEM receiver", csv.len());
+                let result = Xmodem::transmit(csv.as_bytes(), CONSOLE.lock().raw());
+                match result {
+                    Ok(n) => kprintln!("exits: sent {} bytes", n),
+                    Err(e) => kprintln!("exits: transfer failed: {}", e),
+                }
+            },
+            (Some("clear"), None) => {
+                SCHEDULER.critical(|scheduler| {
+                    for process in scheduler.iter_mut() {
+                        process.exits = Default::default();
+                    }
+                });
+                TRACE.lock().clear();
+            },
+            (vmid, None) => {
+                let vmid = match vmid.map(parse_num) {
+                    None => None,
+                    Some(Some(vmid)) => Some(vmid as u8),
+                    Some(None) => { kprintln!("usage: exits [vmid|trace|send|clear]"); return Ok(()) }
+                };
+                SCHEDULER.critical(|scheduler| {
+                    for process in scheduler.iter_mut().filter(|p| vmid.map_or(true, |id| id == p.get_vmid())) {
+                        kprintln!("VM {}:", process.get_vmid());
+                        kprint!("{}", process.exits);
+                    }
+                });
+            },
+            _ => kprintln!("usage: exits [vmid|trace|send|clear]"),
+        }
+        Ok(())
+    }
+
+    /// `onfault <vmid> [inject|kill|debug]`: shows or sets what happens when
+    /// VM `vmid` takes an exception the hypervisor can't handle.
+    fn onfault(&mut self, cmd: Command) -> io::Result<()> {
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) if cmd.args.len() <= 3 => vmid as u8,
+            _ => { kprintln!("usage: onfault <vmid> [inject|kill|debug]"); return Ok(()) }
+        };
+        let policy = match cmd.args.get(2).map(|p| p.parse::<FaultPolicy>()) {
+            None => None,
+            Some(Ok(policy)) => Some(policy),
+            Some(Err(_)) => { kprintln!("usage: onfault <vmid> [inject|kill|debug]"); return Ok(()) }
+        };
+        SCHEDULER.critical(|scheduler| match scheduler.get_by_vmid(vmid) {
+            Some(process) => {
+                if let Some(policy) = policy {
+                    process.config.on_fault = policy;
+                }
+                kprintln!("VM {}: on fault {:?}", vmid, process.config.on_fault);
+            },
+            None => kprintln!("onfault: no VM {}", vmid),
+        });
+        Ok(())
+    }
+
+    /// `restart <vmid> [never|on-crash|always] [backoff-ms] [max-retries|-]`:
+    /// shows or sets VM `vmid`'s restart policy. `-` means no retry limit.
+    fn restart(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: restart <vmid> [never|on-crash|always] [backoff-ms] [max-retries|-]";
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) if cmd.args.len() <= 5 => vmid as u8,
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let policy = match cmd.args.get(2).map(|p| p.parse::<RestartPolicy>()) {
+            None => None,
+            Some(Ok(policy)) => Some(policy),
+            Some(Err(_)) => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let backoff = match cmd.args.get(3).map(|b| parse_num(b)) {
+            None => None,
+            Some(Some(ms)) => Some(Duration::from_millis(ms)),
+            Some(None) => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let retries = match cmd.args.get(4).map(|r| (*r, parse_num(r))) {
+            None => None,
+            Some(("-", _)) => Some(None),
+            Some((_, Some(max))) => Some(Some(max as u32)),
+            Some((_, None)) => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        SCHEDULER.critical(|scheduler| match scheduler.get_by_vmid(vmid) {
+            Some(process) => {
+                let config = &mut process.config;
+                config.restart = policy.unwrap_or(config.restart);
+                config.backoff = backoff.unwrap_or(config.backoff);
+                config.max_retries = retries.unwrap_or(config.max_retries);
+                kprintln!("VM {}: restart {:?}, backoff {}ms, max retries {:?}",
+                          vmid, config.restart, config.backoff.as_millis(), config.max_retries);
+            },
+            None => kprintln!("restart: no VM {}", vmid),
+        });
+        Ok(())
+    }
+
+    /// `sched [<vmid> [weight] [cap%|-] [slice-ms]]`: shows the scheduling
+    /// parameters and CPU usage of every VM, or shows or sets VM `vmid`'s
+    /// parameters. `-` removes the cap.
+    fn sched(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: sched [<vmid> [weight] [cap%|-] [slice-ms]]";
+        let show = |vmid: u8, process: &crate::process::Process| {
+            let config = &process.config;
+            kprintln!("VM {:<3} weight {:<5} cap {:<5} slice {:<6} cpu {}ms",
+                      vmid, config.weight,
+                      config.cap.map_or(String::from("-"), |cap| alloc::format!("{}%", cap)),
+                      alloc::format!("{}ms", config.timeslice.as_millis()),
+                      process.stride.runtime.as_millis());
+        };
+        if cmd.args.len() == 1 {
+            SCHEDULER.critical(|scheduler| {
+                for process in scheduler.iter_mut() {
+                    show(process.get_vmid(), process);
+                }
+            });
+            return Ok(());
+        }
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) if cmd.args.len() <= 5 => vmid as u8,
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let mut config = match SCHEDULER.critical(|scheduler| scheduler.get_by_vmid(vmid).map(|p| p.config.clone())) {
+            Some(config) => config,
+            None => { kprintln!("sched: no VM {}", vmid); return Ok(()) }
+        };
+        for (&key, &value) in ["weight", "cap", "slice"].iter().zip(cmd.args.iter().skip(2)) {
+            let value = value.trim_end_matches('%');
+            if config.set(key, value).is_err() {
+                kprintln!("sched: bad {} `{}`", key, value);
+                return Ok(());
+            }
+        }
+        SCHEDULER.critical(|scheduler| {
+            if let Some(process) = scheduler.get_by_vmid(vmid) {
+                process.config = config;
+                show(vmid, process);
+            }
+        });
+        Ok(())
+    }
+
+    /// `balloon [<vmid> <target-pages>]`: shows every VM's memory use and
+    /// balloon, or sets how many pages VM `vmid` is asked to give back.
+    fn balloon(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: balloon [<vmid> <target-pages>]";
+        let target = match (cmd.args.len(), cmd.args.get(1).and_then(|a| parse_num(a)),
+                            cmd.args.get(2).and_then(|a| parse_num(a))) {
+            (1, _, _) => None,
+            (3, Some(vmid), Some(pages)) => Some((vmid as u8, pages as usize)),
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        SCHEDULER.critical(|scheduler| {
+            if let Some((vmid, pages)) = target {
+                match scheduler.get_by_vmid(vmid) {
+                    Some(process) => process.balloon.target = pages,
+                    None => { kprintln!("balloon: no VM {}", vmid); return }
+                }
+            }
+            for process in scheduler.iter_mut() {
+                if target.map_or(false, |(vmid, _)| vmid != process.get_vmid()) {
+                    continue;
+                }
+                kprintln!("VM {:<3} mapped {:<6} ballooned {:<6} target {:<6} ({}KiB per page)",
+                          process.get_vmid(), process.vmap.mappings().count(),
+                          process.balloon.len(), process.balloon.target, PAGE_SIZE / 1024);
+            }
+        });
+        Ok(())
+    }
+
+    /// `merge [on|off]`: shows how much memory page merging saves, or turns
+    /// merging of new pages on or off.
+    fn merge(&mut self, cmd: Command) -> io::Result<()> {
+        match (cmd.args.len(), cmd.args.get(1)) {
+            (1, _) => {},
+            (2, Some(&"on")) => share::set_enabled(true),
+            (2, Some(&"off")) => share::set_enabled(false),
+            _ => { kprintln!("usage: merge [on|off]"); return Ok(()) }
+        }
+        let stats = share::stats();
+        kprintln!("merging {}, {} shared pages, {} pages ({}KiB) saved, {} passes",
+                  if stats.enabled { "on" } else { "off" }, stats.shared, stats.saved,
+                  stats.saved * PAGE_SIZE / 1024, stats.passes);
+        Ok(())
+    }
+
+    /// `dirty <vmid> [start|stop|clear|list]`: shows how many pages VM `vmid`
+    /// changed since dirty logging was started or cleared, starts or stops
+    /// logging, empties the log, or lists the changed pages.
+    fn dirty(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: dirty <vmid> [start|stop|clear|list]";
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) if cmd.args.len() <= 3 => vmid as u8,
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let action = cmd.args.get(2).map(|a| *a);
+        SCHEDULER.critical(|scheduler| {
+            let vmap = match scheduler.get_by_vmid(vmid) {
+                Some(process) => &mut process.vmap,
+                None => { kprintln!("dirty: no VM {}", vmid); return }
+            };
+            match action {
+                None | Some("list") => {},
+                Some("start") => vmap.start_dirty_log(),
+                Some("stop") => vmap.stop_dirty_log(),
+                Some("clear") => vmap.clear_dirty_log(),
+                Some(_) => { kprintln!("{}", USAGE); return }
+            }
+            let log = match vmap.dirty_log() {
+                Some(log) => log,
+                None => { kprintln!("VM {}: dirty logging off", vmid); return }
+            };
+            kprintln!("VM {}: {} dirty pages ({}KiB) of {} mapped", vmid, log.count(),
+                      log.count() * PAGE_SIZE / 1024, vmap.mappings().count());
+            if action == Some("list") {
+                for ipa in log.iter() {
+                    kprintln!("  {:#010x}{}", ipa.as_usize(),
+                              if vmap.translate(ipa).is_none() { " (unmapped)" } else { "" });
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// `gdb [vmid]`: attaches a debugger to VM `vmid`, by default the one the
+    /// monitor was entered from. The VM stops after its next instruction and
+    /// waits for `target remote` on the console.
+    fn gdb(&mut self, cmd: Command) -> io::Result<()> {
+        let guest_vmid = self.guest.as_ref().map(|tf| tf.vmid());
+        let vmid = match cmd.args.get(1).map(|a| parse_num(a)) {
+            None if cmd.args.len() == 1 && guest_vmid.is_some() => guest_vmid.unwrap(),
+            Some(Some(vmid)) if cmd.args.len() == 2 => vmid as u8,
+            _ => { kprintln!("usage: gdb [vmid]"); return Ok(()) }
+        };
+        let guest = &mut self.guest;
+        let attached = SCHEDULER.critical(|scheduler| {
+            let process = match scheduler.get_by_vmid(vmid) {
+                Some(process) => process,
+                None => return false,
+            };
+            match guest {
+                Some(tf) if tf.vmid() == vmid => {
+                    process.debug.attach(tf);
+                    process.debug.switch_in(tf);
+                },
+                _ => process.debug.attach(&mut process.context),
+            }
+            true
+        });
+        if !attached {
+            kprintln!("gdb: no VM {}", vmid);
+            return Ok(());
+        }
+        kprintln!("gdb: VM {} stops at its next instruction, connect with `target remote` to the console", vmid);
+        if guest_vmid == Some(vmid) {
+            self.exit = true;
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the debugging state of VM `vmid`, then programs it into the
+    /// hardware if the VM is the one the monitor was entered from. Returns
+    /// `None` if there is no such VM.
+    fn with_debug<T, F>(&mut self, vmid: u8, f: F) -> Option<T>
+        where F: FnOnce(&mut DebugState) -> T
+    {
+        let running = self.guest.as_mut().filter(|tf| tf.vmid() == vmid);
+        SCHEDULER.critical(|scheduler| {
+            let debug = &mut scheduler.get_by_vmid(vmid)?.debug;
+            let result = f(debug);
+            if let Some(tf) = running {
+                debug.switch_in(tf);
+            }
+            Some(result)
+        })
+    }
+
+    /// `break <vmid>`: lists the hardware breakpoints of VM `vmid`.
+    /// `break <vmid> <addr>`: sets one at guest VA `addr`.
+    /// `break <vmid> clear <addr>`: removes the one at guest VA `addr`.
+    fn breakpoint(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: break <vmid> [[clear] <addr>]";
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) => vmid as u8,
+            None => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let (clear, addr) = match (cmd.args.len(), cmd.args.get(2).map(|a| *a)) {
+            (2, _) => (false, None),
+            (3, Some(addr)) => (false, parse_num(addr)),
+            (4, Some("clear")) => (true, cmd.args.get(3).and_then(|a| parse_num(a))),
+            _ => (false, None),
+        };
+        if cmd.args.len() > 2 && addr.is_none() {
+            kprintln!("{}", USAGE);
+            return Ok(());
+        }
+        let result = self.with_debug(vmid, |debug| {
+            match addr {
+                Some(addr) if clear => {
+                    if !debug.remove_breakpoint(addr) {
+                        kprintln!("break: no breakpoint at {:#x}", addr);
+                    }
+                },
+                Some(addr) => if let Err(e) = debug.add_breakpoint(addr) {
+                    kprintln!("break: {:?}", e);
+                },
+                None => {},
+            }
+            for (n, b) in debug.hw_breakpoints.iter().enumerate() {
+                if let Some(addr) = b {
+                    kprintln!("{:<3} {:#x}", n, addr);
+                }
+            }
+        });
+        if result.is_none() {
+            kprintln!("break: no VM {}", vmid);
+        }
+        Ok(())
+    }
+
+    /// `watch <vmid>`: lists the hardware watchpoints of VM `vmid`.
+    /// `watch <vmid> <addr> [len] [r|w|rw]`: sets one on `len` bytes (default
+    /// 8) at guest VA `addr`, stopping on writes by default.
+    /// `watch <vmid> clear <addr>`: removes the ones at guest VA `addr`.
+    fn watch(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: watch <vmid> [<addr> [len] [r|w|rw] | clear <addr>]";
+        let vmid = match cmd.args.get(1).and_then(|a| parse_num(a)) {
+            Some(vmid) => vmid as u8,
+            None => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let mut clear = None;
+        let mut watch = None;
+        match cmd.args.get(2).map(|a| *a) {
+            None => {},
+            Some("clear") if cmd.args.len() == 4 => clear = cmd.args.get(3).and_then(|a| parse_num(a)),
+            Some(addr) if cmd.args.len() <= 5 => {
+                let len = cmd.args.get(3).map_or(Some(8), |a| parse_num(a));
+                let kind = match cmd.args.get(4).map(|a| *a) {
+                    None | Some("w") => Some(WatchKind::Write),
+                    Some("r") => Some(WatchKind::Read),
+                    Some("rw") => Some(WatchKind::Access),
+                    Some(_) => None,
+                };
+                if let (Some(addr), Some(len), Some(kind)) = (parse_num(addr), len, kind) {
+                    watch = Some(Watchpoint { addr, len, kind });
+                }
+            },
+            Some(_) => {},
+        }
+        if cmd.args.len() > 2 && clear.is_none() && watch.is_none() {
+            kprintln!("{}", USAGE);
+            return Ok(());
+        }
+        let result = self.with_debug(vmid, |debug| {
+            if let Some(addr) = clear {
+                if !debug.remove_watchpoint(addr) {
+                    kprintln!("watch: no watchpoint at {:#x}", addr);
+                }
+            }
+            if let Some(watch) = watch {
+                if let Err(e) = debug.add_watchpoint(watch) {
+                    kprintln!("watch: {:?}", e);
+                }
+            }
+            for (n, w) in debug.watchpoints.iter().enumerate() {
+                if let Some(w) = w {
+                    kprintln!("{:<3} {:#x} {} bytes {:?}", n, w.addr, w.len, w.kind);
+                }
+            }
+        });
+        if result.is_none() {
+            kprintln!("watch: no VM {}", vmid);
+        }
+        Ok(())
+    }
+
+    /// `snap [list]`: lists snapshots.
+    /// `snap take <vmid>`: takes a snapshot of VM `vmid`.
+    /// `snap restore <n>`: puts the VM of snapshot `n` back into its state.
+    /// `snap clone <n>`: starts a new VM from snapshot `n`.
+    /// `snap send <n>`: transmits snapshot `n` over XMODEM.
+    /// `snap drop <n>`: discards snapshot `n`.
+    fn snap(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: snap [list|take <vmid>|restore <n>|clone <n>|send <n>|drop <n>]";
+        let arg = match cmd.args.get(2).map(|a| parse_num(a)) {
+            None if cmd.args.len() <= 2 => None,
+            Some(Some(arg)) if cmd.args.len() == 3 => Some(arg as usize),
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let now = timer::current_time();
+        let guest = &mut self.guest;
+        SCHEDULER.critical(|scheduler| match (cmd.args.get(1).map(|a| *a), arg) {
+            (None, None) | (Some("list"), None) => {
+                for (i, snapshot) in scheduler.snapshots().iter().enumerate() {
+                    kprintln!("{:<3} VM {:<3} taken at {}ms, {} pages", i, snapshot.vmid,
+                              snapshot.taken.as_millis(), snapshot.pages());
+                }
+            },
+            (Some("take"), Some(vmid)) => {
+                match scheduler.snapshot(vmid as u8, guest.as_ref().map(|tf| &**tf), now) {
+                    Some(i) => kprintln!("snap: took snapshot {} of VM {}", i, vmid),
+                    None => kprintln!("snap: no VM {}", vmid),
+                }
+            },
+            (Some("restore"), Some(i)) => {
+                match scheduler.restore(i, guest.as_mut().map(|tf| &mut **tf), now) {
+                    Ok(vmid) => kprintln!("snap: restored VM {} from snapshot {}", vmid, i),
+                    Err(e) => kprintln!("snap: restore failed: {:?}", e),
+                }
+            },
+            (Some("clone"), Some(i)) => match scheduler.clone_snapshot(i, now) {
+                Ok(vmid) => kprintln!("snap: started VM {} from snapshot {}", vmid, i),
+                Err(e) => kprintln!("snap: clone failed: {:?}", e),
+            },
+            (Some("send"), Some(i)) => match scheduler.snapshots().get(i) {
+                Some(snapshot) => {
+                    let reader = snapshot.reader();
+                    kprintln!("snap: sending {} bytes, start your XMODEM receiver", reader.len());
+                    let result = Xmodem::transmit(reader, CONSOLE.lock().raw());
+                    match result {
+                        Ok(n) => kprintln!("snap: sent {} bytes", n),
+                        Err(e) => kprintln!("snap: transfer failed: {}", e),
+                    }
+                },
+                None => kprintln!("snap: no snapshot {}", i),
+            },
+            (Some("drop"), Some(i)) => {
+                if !scheduler.drop_snapshot(i) {
+                    kprintln!("snap: no snapshot {}", i);
+                }
+            },
+            _ => kprintln!("{}", USAGE),
+        });
+        Ok(())
+    }
+
+    /// `recv`: receives files over YMODEM and keeps them in memory.
+    /// `recv list`: lists the received files.
+    /// `recv run <n>`: starts a new VM from received file `n`.
+    /// `recv drop <n>`: discards received file `n`.
+    fn recv(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: recv [list|run <n>|drop <n>]";
+        let arg = match cmd.args.get(2).map(|a| parse_num(a)) {
+            None if cmd.args.len() <= 2 => None,
+            Some(Some(arg)) if cmd.args.len() == 3 => Some(arg as usize),
+            _ => { kprintln!("{}", USAGE); return Ok(()) }
+        };
+        let mut uploads = UPLOADS.lock();
+        match (cmd.args.get(1).map(|a| *a), arg) {
+            (None, None) => {
+                kprintln!("recv: start your YMODEM sender, or press a key to give up");
+                match receive_batch() {
+                    Ok(files) => {
+                        for file in files {
+                            kprintln!("recv: {} is file {}, {} bytes", file.name, uploads.len(), file.data.len());
+                            uploads.push(file);
+                        }
+                    },
+                    Err(e) => kprintln!("recv: transfer failed: {}", e),
+                }
+            },
+            (Some("list"), None) => {
+                for (i, file) in uploads.iter().enumerate() {
+                    kprintln!("{:<3} {:<32} {} bytes", i, file.name, file.data.len());
+                }
+            },
+            (Some("run"), Some(i)) => match uploads.get(i) {
+                Some(file) => match Process::load_image(&file.data) {
+                    Ok(process) => kprintln!("recv: started VM {} from {}", SCHEDULER.add(process), file.name),
+                    Err(e) => kprintln!("recv: load failed: {:?}", e),
+                },
+                None => kprintln!("recv: no file {}", i),
+            },
+            (Some("drop"), Some(i)) => {
+                if i < uploads.len() {
+                    uploads.remove(i);
+                } else {
+                    kprintln!("recv: no file {}", i);
+                }
+            },
+            _ => kprintln!("{}", USAGE),
+        }
+        Ok(())
+    }
+
+    /// `vms`: lists VMs and pending restarts.
+    fn vms(&mut self, _cmd: Command) -> io::Result<()> {
+        let now = timer::current_time();
+        SCHEDULER.critical(|scheduler| {
+            for process in scheduler.iter_mut() {
+                kprint!("VM {:<3} {:<14} {:<14} crashes {:<3} fault {:?}, restart {:?}",
+                        process.get_vmid(), alloc::format!("{:?}", process.state),
+                        process.image.as_ref().map_or(String::from("-"), |i| alloc::format!("{}", i.display())),
+                        process.crashes, process.config.on_fault, process.config.restart);
+                match process.devices.pm.remaining(now) {
+                    Some(left) => kprintln!(", watchdog {}ms left", left.as_millis()),
+                    None => kprintln!(),
+                }
+            }
+            for restart in scheduler.pending_restarts() {
+                kprintln!("VM {:<3} restarting in {}ms (crashes {})", restart.vmid,
+                          restart.at.checked_sub(now).unwrap_or_default().as_millis(), restart.crashes);
+            }
+        });
+        Ok(())
+    }
+
+    /// `crashlog [clear]`: shows the crash record of the last crashed boot,
+    /// or discards it.
+    fn crashlog(&mut self, cmd: Command) -> io::Result<()> {
+        match cmd.args.get(1).map(|a| *a) {
+            None => match crashlog::last() {
+                Some(text) => crashlog::print(text),
+                None => kprintln!("crashlog: no crash recorded"),
+            },
+            Some("clear") if cmd.args.len() == 2 => crashlog::clear(),
+            _ => kprintln!("usage: crashlog [clear]"),
+        }
+        Ok(())
+    }
+
+    /// `dmesg [clear]`: replays the log buffer, or clears it.
+    fn dmesg(&mut self, cmd: Command) -> io::Result<()> {
+        match cmd.args.get(1).map(|a| *a) {
+            None => {
+                let lines = LOGGER.lock().ring.lines();
+                kprint!("{}", String::from_utf8_lossy(&lines));
+            },
+            Some("clear") if cmd.args.len() == 2 => LOGGER.lock().ring.clear(),
+            _ => kprintln!("usage: dmesg [clear]"),
+        }
+        Ok(())
+    }
+
+    /// `log [<level> | <module> <level>|default]`: shows the log levels, or
+    /// sets the default level or the level of `module` and the modules
+    /// within it. `default` removes the module's filter.
+    fn log(&mut self, cmd: Command) -> io::Result<()> {
+        const USAGE: &str = "usage: log [<level> | <module> <level>|default], levels off|error|warn|info|debug|trace";
+        let mut logger = LOGGER.lock();
+        match (cmd.args.get(1).map(|a| *a), cmd.args.get(2).map(|a| *a)) {
+            (None, None) => {
+                kprintln!("default {}", logger.level);
+                for (module, level) in logger.filters.iter() {
+                    kprintln!("{} {}", module, level);
+                }
+            },
+            (Some(level), None) => match level.parse::<Level>() {
+                Ok(level) => logger.level = level,
+                Err(_) => kprintln!("{}", USAGE),
+            },
+            (Some(module), Some("default")) if cmd.args.len() == 3 => logger.set_filter(module, None),
+            (Some(module), Some(level)) if cmd.args.len() == 3 => match level.parse::<Level>() {
+                Ok(level) => logger.set_filter(module, Some(level)),
+                Err(_) => kprintln!("{}", USAGE),
+            },
+            _ => kprintln!("{}", USAGE),
+        }
+        Ok(())
+    }
+
     fn call_command(&mut self, cmd: Command) -> io::Result<()> {
         match cmd.path() {
             "echo" => {
@@ -209,8 +1009,8 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
                 Ok(())
             },
             "reboot" => {
-                kprint!("Time to die.");
-                panic!("Goodnight");
+                kprint!("Time to die. ");
+                reboot();
             },
             "help" => {
                 for _ in 0..3 {
@@ -234,6 +1034,30 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
             },
             "ls" => self.ls(cmd),
             "cat" => self.cat(cmd),
+            "xd" => self.xd(cmd),
+            "vtop" => self.vtop(cmd),
+            "dis" => self.dis(cmd),
+            "vmap" => self.vmap(cmd),
+            "exits" => self.exits(cmd),
+            "onfault" => self.onfault(cmd),
+            "restart" => self.restart(cmd),
+            "balloon" => self.balloon(cmd),
+            "merge" => self.merge(cmd),
+            "snap" => self.snap(cmd),
+            "dirty" => self.dirty(cmd),
+            "gdb" => self.gdb(cmd),
+            "break" => self.breakpoint(cmd),
+            "watch" => self.watch(cmd),
+            "sched" => self.sched(cmd),
+            "vms" => self.vms(cmd),
+            "recv" => self.recv(cmd),
+            "dmesg" => self.dmesg(cmd),
+            "crashlog" => self.crashlog(cmd),
+            "log" => self.log(cmd),
+            "exit" => {
+                self.exit = true;
+                Ok(())
+            },
             command => {
                 kprintln!("unknown command: {}", command);
                 Ok(())
@@ -255,6 +1079,17 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
     pub fn do_forever(&mut self) -> ! {
         loop {
             self.do_cmd();
+            if self.exit {
+                kprintln!("nothing to return to");
+                self.exit = false;
+            }
+        }
+    }
+
+    /// Runs commands until `exit` is entered.
+    pub fn do_until_exit(&mut self) {
+        while !self.exit {
+            self.do_cmd();
         }
     }
 
@@ -281,7 +1116,8 @@ impl<'a, FS: FileSystem + Copy> Shell<'a, FS> {
                     None => {}
                 };
             } else if b == 0x12 { // ^R reboot
-                panic!("Goodnight");
+                kprintln!();
+                reboot();
             } else if b < 0x20 || b > 0x7f {
                 CONSOLE.lock().write_byte(0x07); // ring terminal bell
             } else {
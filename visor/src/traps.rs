@@ -2,20 +2,36 @@ mod frame;
 mod syndrome;
 mod syscall;
 
+mod hvc;
+
+pub mod inject;
 pub mod irq;
+pub mod stats;
 use crate::IRQ;
 use crate::SCHEDULER;
+use crate::backtrace;
+use crate::crashlog;
+use crate::debug;
+use crate::log;
+use crate::process::{CrashReason, FaultPolicy, State, StopReason};
 pub use self::frame::TrapFrame;
 use self::syscall::{sys_sleep};
 use crate::param;
 use crate::vm::{PhysicalAddr, VirtualAddr};
 use crate::vm;
+use crate::vm::share::{self, CowError};
 use crate::util;
+use crate::vdev::Devices;
 use crate::mutex::ReentrantLock;
 
 use aarch64::*;
+use aarch64::disasm::{decode_load_store, Form, Index, LoadStore, Offset};
 use pi::interrupt::{Controller, Interrupt};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
+use pi::timer;
 
+use self::inject::InjectError;
+use self::stats::{Exit, TraceEntry, TRACE};
 use self::syndrome::*;
 use self::syscall::handle_syscall;
 
@@ -45,37 +61,98 @@ pub struct Info {
 }
 
 use crate::console::{kprintln};
-use crate::shell::Shell;
-
-fn handle_mmio(fault_addr: usize, iss: DataAbortSyndrome, tf: &mut TrapFrame) {
-    assert!(fault_addr >= param::IO_BASE && fault_addr < param::IO_BASE_END);
-    let sext = iss.get_value(DataAbortSyndrome::SSE) == 1;
-    let regno = iss.get_value(DataAbortSyndrome::SRT) as usize;
-    let write = iss.get_value(DataAbortSyndrome::WnR) == 1;
-    let reg64 = iss.get_value(DataAbortSyndrome::SF) == 1;
-    let access_size = iss.get_value(DataAbortSyndrome::SAS);
-    // kprintln!("Emulating {} {:x}({}), with reg {}{}, sext={}", if write { "write to" } else { "read from" }, fault_addr, 8 << access_size, if reg64 { "x" } else { "w" }, regno, sext);
+use crate::shell::{self, Shell};
+
+/// Returns `true` if guest accesses to `addr` are emulated by `handle_mmio`.
+fn is_mmio(addr: usize) -> bool {
+    (addr >= param::IO_BASE && addr < param::IO_BASE_END) || Devices::claims(addr)
+}
+
+/// A guest load or store of a single register to be emulated.
+struct MmioAccess {
+    write: bool,
+    /// The access size in bytes, as a power of two.
+    size: u64,
+    reg: usize,
+    reg64: bool,
+    sext: bool,
+}
+
+impl MmioAccess {
+    /// Describes the access by its syndrome, which does so for single
+    /// register accesses without writeback.
+    fn from_iss(iss: DataAbortSyndrome) -> MmioAccess {
+        MmioAccess {
+            write: iss.get_value(DataAbortSyndrome::WnR) == 1,
+            size: iss.get_value(DataAbortSyndrome::SAS),
+            reg: iss.get_value(DataAbortSyndrome::SRT) as usize,
+            reg64: iss.get_value(DataAbortSyndrome::SF) == 1,
+            sext: iss.get_value(DataAbortSyndrome::SSE) == 1,
+        }
+    }
+
+    /// Describes the access by the instruction making it.
+    fn from_insn(insn: &LoadStore) -> MmioAccess {
+        MmioAccess {
+            write: !insn.load,
+            size: insn.size.trailing_zeros() as u64,
+            reg: insn.rt as usize,
+            reg64: insn.wide,
+            sext: insn.signed,
+        }
+    }
+}
+
+/// Decodes the load or store at the running guest's `ELR`, if it is one that
+/// `handle_mmio` can emulate.
+fn decode_mmio(tf: &TrapFrame) -> Option<LoadStore> {
+    let mut insn = [0u8; 4];
+    let process = SCHEDULER.get_by_vmid(tf.vmid());
+    if vm::guest::read_va(tf, &process.vmap, tf.ELR, &mut insn) != 4 {
+        return None;
+    }
+    match decode_load_store(u32::from_le_bytes(insn)) {
+        Some(insn) if !insn.simd && insn.rt2.is_none()
+            && (insn.form == Form::Single || insn.form == Form::Unscaled) => Some(insn),
+        _ => None,
+    }
+}
+
+fn handle_mmio(fault_addr: usize, access: MmioAccess, tf: &mut TrapFrame) {
+    assert!(is_mmio(fault_addr));
+    let MmioAccess { write, size: access_size, reg: regno, reg64, sext } = access;
+    let now = timer::current_time();
+    log::trace!("emulating {} {:x}({}), with reg {}{}, sext={}", if write { "write to" } else { "read from" }, fault_addr, 8 << access_size, if reg64 { "x" } else { "w" }, regno, sext);
     if write {
         let mut data: u64 = tf.xn[regno];
         if !reg64 { // 32-bit register
             data &= 0xFFFFFFFF;
         }
-        unsafe { match access_size {
-            // sext dont apply for stores
-            0 => *(fault_addr as *mut u8)  = data as u8,
-            1 => *(fault_addr as *mut u16) = data as u16,
-            2 => *(fault_addr as *mut u32) = data as u32,
-            3 => *(fault_addr as *mut u64) = data as u64,
-            _ => unreachable!()
-        }};
+        let emulated = SCHEDULER.get_by_vmid(tf.vmid()).devices.write(fault_addr, access_size, data, now);
+        if !emulated {
+            unsafe { match access_size {
+                // sext dont apply for stores
+                0 => *(fault_addr as *mut u8)  = data as u8,
+                1 => *(fault_addr as *mut u16) = data as u16,
+                2 => *(fault_addr as *mut u32) = data as u32,
+                3 => *(fault_addr as *mut u64) = data as u64,
+                _ => unreachable!()
+            }};
+        }
     } else {
-        let data: u64 = unsafe { match access_size {
-            0 => (if sext { *(fault_addr as *mut i8)  as u64 } else { *(fault_addr as *mut u8)  as u64 }),
-            1 => (if sext { *(fault_addr as *mut i16) as u64 } else { *(fault_addr as *mut u16) as u64 }),
-            2 => (if sext { *(fault_addr as *mut i32) as u64 } else { *(fault_addr as *mut u32) as u64 }),
-            3 => (if sext { *(fault_addr as *mut i64) as u64 } else { *(fault_addr as *mut u64) as u64 }),
-            _ => unreachable!()
-        }};
+        let emulated = SCHEDULER.get_by_vmid(tf.vmid()).devices.read(fault_addr, access_size, now);
+        let raw: u64 = match emulated {
+            Some(data) => data,
+            None => unsafe { match access_size {
+                0 => *(fault_addr as *mut u8)  as u64,
+                1 => *(fault_addr as *mut u16) as u64,
+                2 => *(fault_addr as *mut u32) as u64,
+                3 => *(fault_addr as *mut u64) as u64,
+                _ => unreachable!()
+            }}
+        };
+        let shift = 64 - (8 << access_size);
+        let data = if sext { (((raw << shift) as i64) >> shift) as u64 } else { raw };
         tf.xn[regno] = if reg64 {
             data
         } else {
@@ -85,8 +162,26 @@ fn handle_mmio(fault_addr: usize, iss: DataAbortSyndrome, tf: &mut TrapFrame) {
     tf.ELR += 4; // skip over emulated instruction
 }
 
+/// Handles the running VM not getting a page: has the other VMs shrink, and
+/// switches away so that the access is retried once they had a chance to.
+/// If no other VM can give pages back, the VM is stopped as crashed instead.
+fn out_of_memory(tf: &mut TrapFrame) {
+    let vmid = tf.vmid();
+    let owed = SCHEDULER.critical(|scheduler| {
+        scheduler.reclaim(vmid, param::BALLOON_RECLAIM_PAGES)
+    });
+    if owed == 0 {
+        log::warn!("VM {}: out of memory, and no other VM can give pages back", vmid);
+        SCHEDULER.stop(tf, StopReason::Crash(CrashReason::OutOfMemory));
+        return;
+    }
+    log::warn!("VM {}: out of memory, other VMs owe {} pages", vmid, owed);
+    SCHEDULER.switch(State::Ready, tf);
+}
+
 // // kern_base..max_vm
-fn handle_lower_el_synchronous(info: Info, syndrome: Syndrome, far: u64, hpfar: u64, tf: &mut TrapFrame) {
+fn handle_lower_el_synchronous(info: Info, esr: u32, far: u64, hpfar: u64, tf: &mut TrapFrame) -> Exit {
+    let syndrome = Syndrome::from(esr);
     if let Some((kind, info)) = syndrome.get_abort_info() {
         if kind == Fault::AccessFlag || kind == Fault::Translation {
             let translation_fault_addr = ((hpfar >> 4) << 12) as usize;
@@ -94,79 +189,202 @@ fn handle_lower_el_synchronous(info: Info, syndrome: Syndrome, far: u64, hpfar:
             let fault_page = VirtualAddr::from(util::align_down(translation_fault_addr, param::PAGE_SIZE));
             if translation_fault_addr < param::GUEST_MAX_VM_SIZE {
                 // lazy paging
-                let vmid = VTTBR_EL2::get_masked(tf.VTTBR, VTTBR_EL2::VMID);
-                let mut process = SCHEDULER.get_by_vmid(vmid as u8);
-                let vmap = &mut process.vmap;
-                if !vmap.get_entry(fault_page).is_valid() {
-                    vmap.alloc(fault_page, vm::PagePerm::RWX);
-                    return;
+                let vmid = tf.vmid();
+                let mut process = SCHEDULER.get_by_vmid(vmid);
+                if !process.vmap.get_entry(fault_page).is_valid() {
+                    if process.vmap.try_alloc(fault_page, vm::PagePerm::RWX).is_some() {
+                        // touching a ballooned page takes it back
+                        process.balloon.deflate(fault_page.as_usize());
+                        return Exit::LazyPage;
+                    }
+                    core::mem::drop(process);
+                    out_of_memory(tf);
+                    return Exit::LazyPage;
+                }
+            }
+        } else if kind == Fault::Permission {
+            let fault_addr = ((hpfar >> 4) << 12) as usize;
+            let fault_page = VirtualAddr::from(util::align_down(fault_addr, param::PAGE_SIZE));
+            let write = match syndrome {
+                Syndrome::DataAbort { iss, .. } => iss.get_value(DataAbortSyndrome::WnR) == 1,
+                _ => false,
+            };
+            if write && fault_addr < param::GUEST_MAX_VM_SIZE {
+                let mut process = SCHEDULER.get_by_vmid(tf.vmid());
+                // the first write to a page since dirty logging was (re)started
+                if process.vmap.log_write(fault_page) {
+                    return Exit::DirtyLog;
+                }
+                // a write to a page shared with other VMs
+                let result = share::break_cow(&mut process.vmap, fault_page);
+                core::mem::drop(process);
+                match result {
+                    Ok(()) => return Exit::CopyOnWrite,
+                    Err(CowError::OutOfMemory) => {
+                        out_of_memory(tf);
+                        return Exit::CopyOnWrite;
+                    },
+                    Err(CowError::NotShared) => {},
                 }
             }
         }
     }
 
     match syndrome {
+        Syndrome::Hvc(0) => {
+            hvc::handle_hvc(tf);
+            return Exit::Sync(syndrome);
+        },
+        Syndrome::SimdFp => {
+            // first FP/SIMD access since the VM was switched in
+            let vmid = tf.vmid();
+            SCHEDULER.critical(|scheduler| scheduler.load_fp(vmid));
+            return Exit::Sync(syndrome);
+        },
+        Syndrome::Brk(_) | Syndrome::Step | Syndrome::Breakpoint | Syndrome::Watchpoint => {
+            // while the VM is being debugged, MDCR_EL2.TDE routes these to us
+            let event = match syndrome {
+                Syndrome::Brk(_) => debug::Event::Brk,
+                Syndrome::Step => debug::Event::Step,
+                Syndrome::Breakpoint => debug::Event::Breakpoint,
+                _ => debug::Event::Watchpoint(far),
+            };
+            if debug::handle(tf, event) {
+                return Exit::Sync(syndrome);
+            }
+            // the guest's own, which it would have taken without TDE
+            if inject::inject_sync(tf, esr, far).is_ok() {
+                return Exit::Sync(syndrome);
+            }
+        },
+        Syndrome::MsrMrsSystem(iss) => {
+            // while the VM is being debugged, MDCR_EL2.TDE traps its debug
+            // register accesses too
+            let emulated = SCHEDULER.get_by_vmid(tf.vmid()).debug.emulate_sysreg(tf, iss);
+            if emulated {
+                return Exit::Sync(syndrome);
+            }
+        },
         Syndrome::DataAbort{kind, level, iss} => {
-            if kind == Fault::Translation {
-                if iss.get_value(DataAbortSyndrome::ISV) == 0 {
-                    panic!("DataAbort ISS not vaid?");
-                }
-                if iss.get_value(DataAbortSyndrome::CM) == 0 {
-                    let fault_addr = if iss.get_value(DataAbortSyndrome::FnV) != 0 {
-                        ((hpfar >> 4) << 12) // FAR not valid
-                    } else {
-                        far
-                    } as usize;
-                    if fault_addr >= param::IO_BASE && fault_addr < param::IO_BASE_END {
-                        handle_mmio(fault_addr, iss, tf);
-                        return;
+            let fault_addr = if iss.get_value(DataAbortSyndrome::FnV) != 0 {
+                ((hpfar >> 4) << 12) // FAR not valid
+            } else {
+                far
+            } as usize;
+            if kind == Fault::Translation && is_mmio(fault_addr) {
+                if iss.get_value(DataAbortSyndrome::ISV) == 1 {
+                    if iss.get_value(DataAbortSyndrome::CM) == 0 {
+                        handle_mmio(fault_addr, MmioAccess::from_iss(iss), tf);
+                        return Exit::Mmio(fault_addr);
+                    }
+                    log::warn!("VM {}: cache maintenance abort on MMIO at {:#x}", tf.vmid(), fault_addr);
+                } else if let Some(insn) = decode_mmio(tf) {
+                    // without a valid ISS, e.g. for pre- and post-indexed
+                    // accesses, the instruction tells what to emulate
+                    handle_mmio(fault_addr, MmioAccess::from_insn(&insn), tf);
+                    if let (Offset::Imm(imm), Some(rn)) = (insn.offset, insn.rn) {
+                        if insn.index != Index::Offset {
+                            let base = if rn == 31 { tf.sp_mut() } else { &mut tf.xn[rn as usize] };
+                            *base = base.wrapping_add(imm as u64);
+                        }
                     }
-                } else {
-                    kprintln!("Cache management abort?");
+                    return Exit::Mmio(fault_addr);
                 }
             }
         },
         _ => {},
     }
-    kprintln!("Received system exception at {:x}", tf.ELR);
-    kprintln!("Exception info: {:?}", info);
-    kprintln!("Context: {:?}", tf);
-    kprintln!("VMID: {:?}", VTTBR_EL2::get_masked(tf.VTTBR, VTTBR_EL2::VMID));
-    kprintln!("Syndrome: {:?}", syndrome);
-    kprintln!("Fault address EL2: {:x}", far);
-    kprintln!("Translation fault address: {:x}", (hpfar >> 4) << 12);
-    Shell::new("! ").do_forever();
+
+    let vmid = tf.vmid();
+    log::error!("VM {}: unhandled {:?} at {:#x}, FAR {:#x}", vmid, syndrome, tf.ELR, far);
+    shell::print_code(tf, &SCHEDULER.get_by_vmid(vmid).vmap, tf.ELR.wrapping_sub(4 * 4), 9);
+    backtrace::print_guest(tf, &SCHEDULER.get_by_vmid(vmid).vmap);
+    let policy = SCHEDULER.get_by_vmid(vmid).config.on_fault;
+    let reason = match policy {
+        FaultPolicy::Inject => match inject::inject_sync(tf, esr, far) {
+            Ok(()) => return Exit::Sync(syndrome),
+            Err(InjectError::NoVectors) => CrashReason::NoVectors,
+            Err(InjectError::Nested) => CrashReason::NestedFault,
+        },
+        FaultPolicy::Kill => CrashReason::Killed,
+        FaultPolicy::Debug => {
+            kprintln!("Received system exception at {:x}", tf.ELR);
+            kprintln!("Exception info: {:?}", info);
+            kprintln!("Context: {:?}", tf);
+            kprintln!("VMID: {:?}", vmid);
+            kprintln!("Syndrome: {:?}", syndrome);
+            kprintln!("Fault address EL2: {:x}", far);
+            kprintln!("Translation fault address: {:x}", (hpfar >> 4) << 12);
+            Shell::with_guest("! ", tf).do_forever();
+        }
+    };
+    SCHEDULER.stop(tf, StopReason::Crash(reason));
+    Exit::Sync(syndrome)
 }
 
 static DOUBLE_FAULT_LOCK: ReentrantLock = ReentrantLock::new();
 
 /// This function is called when an exception occurs. The `info` parameter
 /// specifies the source and kind of exception that has occurred. The `esr` is
-/// the value of the exception syndrome register. Finally, `tf` is a pointer to
-/// the trap frame for the exception.
+/// the value of the exception syndrome register. `tf` is a pointer to the
+/// trap frame for the exception. Finally, `entry` is the cycle counter value
+/// sampled as the exception vector was entered.
 #[no_mangle]
-pub extern "C" fn handle_exception(info: Info, esr: u32, far: u64, hpfar: u64, tf: &mut TrapFrame) {
+pub extern "C" fn handle_exception(info: Info, esr: u32, far: u64, hpfar: u64, tf: &mut TrapFrame, entry: u64) {
     let x = DOUBLE_FAULT_LOCK.enter();
     if info.source == Source::LowerAArch64 {
-        if Kind::Synchronous == info.kind {
-            let syndrome = Syndrome::from(esr);
-            handle_lower_el_synchronous(info, syndrome, far, hpfar, tf);
-            return
+        let (vmid, elr) = (tf.vmid(), tf.ELR);
+        let exit = if Kind::Synchronous == info.kind {
+            Some(handle_lower_el_synchronous(info, esr, far, hpfar, tf))
         } else if info.kind == Kind::Irq {
-            let controller = Controller::new();
-            for &interrupt in Interrupt::iter().filter(|&&i| controller.is_pending(i)) {
-                // kprintln!("Interrupt {} is pending", interrupt as usize);
-                IRQ.invoke(interrupt, tf);
+            let local = LocalController::new(0);
+            if local.is_pending(LocalInterrupt::CntV) {
+                // the running guest's virtual timer fired
+                SCHEDULER.get_by_vmid(vmid).devices.vtimer.fire();
+            }
+            let mut first = None;
+            if local.is_pending(LocalInterrupt::Gpu) {
+                let controller = Controller::new();
+                for &interrupt in Interrupt::iter().filter(|&&i| controller.is_pending(i)) {
+                    log::trace!("interrupt {} is pending", interrupt as usize);
+                    first = first.or(Some(interrupt));
+                    IRQ.invoke(interrupt, tf);
+                }
             }
+            Some(Exit::Irq(first))
+        } else {
+            None
+        };
+        if let Some(exit) = exit {
+            let cycles = stats::cycles().wrapping_sub(entry);
+            let resumed = tf.vmid();
+            SCHEDULER.critical(|scheduler| {
+                // the VM may have been killed while handling the exit
+                if let Some(process) = scheduler.get_by_vmid(vmid) {
+                    process.exits.record(&exit, cycles);
+                }
+                if let Some(process) = scheduler.get_by_vmid(resumed) {
+                    process.devices.update_irq();
+                }
+            });
+            TRACE.lock().push(TraceEntry { at: entry, cycles, vmid, exit, elr, esr, far, hpfar });
             return
         }
     } else {
         kprintln!("We messed up big time");
     }
+    crashlog::begin(format_args!("unhandled exception {:?} at {:#x}", info, tf.ELR));
     kprintln!("Received system exception at {:x}", tf.ELR);
     kprintln!("Exception info: {:?}", info);
     kprintln!("Source: {:x}", esr);
     kprintln!("Context: {:?}", tf);
-    kprintln!("VMID: {:?}", VTTBR_EL2::get_masked(tf.VTTBR, VTTBR_EL2::VMID));
+    kprintln!("VMID: {:?}", tf.vmid());
+    if info.source == Source::LowerAArch64 {
+        backtrace::print_guest(tf, &SCHEDULER.get_by_vmid(tf.vmid()).vmap);
+        crashlog::end();
+        Shell::with_guest("! ", tf).do_forever();
+    }
+    backtrace::print_trap(tf);
+    crashlog::end();
     Shell::new("! ").do_forever();
 }
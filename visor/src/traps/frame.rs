@@ -15,7 +15,31 @@ pub struct TrapFrame {
     pub TPIDR_EL0: u64,
     pub TPIDR_EL1: u64,
     pub SPSR_EL1: u64,
-    _pad1: u64,
-    pub qn: [u128; 32],
+    pub TCR_EL1: u64,
+    pub ELR_EL1: u64,
+    pub ESR_EL1: u64,
+    pub FAR_EL1: u64,
+    pub SPSR: u64,
+    /// The value `MDSCR_EL1` is loaded with, the debugger's while the VM is
+    /// being debugged (see `DebugState`).
+    pub MDSCR_EL1: u64,
+    _reserved: u64,
     pub xn: [u64; 32] // lr = x30, xzr = x31
 }
+
+impl TrapFrame {
+    /// Returns the VMID of the guest this trap frame belongs to.
+    pub fn vmid(&self) -> u8 {
+        aarch64::VTTBR_EL2::get_value(self.VTTBR, aarch64::VTTBR_EL2::VMID) as u8
+    }
+
+    /// Returns the stack pointer the guest is using: `SP_EL1` in EL1h,
+    /// `SP_EL0` otherwise.
+    pub fn sp_mut(&mut self) -> &mut u64 {
+        if self.SPSR & aarch64::SPSR_EL2::M == 0b0101 { // EL1h
+            &mut self.SP_EL1
+        } else {
+            &mut self.SP_EL0
+        }
+    }
+}
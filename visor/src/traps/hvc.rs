@@ -0,0 +1,79 @@
+use crate::param::{GUEST_MAX_VM_SIZE, PAGE_SIZE};
+use crate::process::{Process, StopReason};
+use crate::traps::TrapFrame;
+use crate::vm::VirtualAddr;
+use crate::SCHEDULER;
+
+// Function IDs follow the SMC Calling Convention: the ID goes in x0, the
+// arguments in x1.., and the result comes back in x0.
+
+/// PSCI `SYSTEM_OFF`: stop this VM for good.
+pub const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+/// PSCI `SYSTEM_RESET`: restart this VM from its image.
+pub const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+/// Hands the `x2` pages starting at IPA `x1` back to the hypervisor. Returns
+/// the number of pages that were freed.
+pub const HVC_BALLOON_INFLATE: u32 = 0xC600_0003;
+/// Returns how many more pages the VM should hand back, or a negative count
+/// it may take back, and the page size in `x1`.
+pub const HVC_BALLOON_TARGET: u32 = 0xC600_0004;
+/// Takes the `x2` pages starting at IPA `x1` back. They are mapped again when
+/// first touched, which also takes them back implicitly.
+pub const HVC_BALLOON_DEFLATE: u32 = 0xC600_0005;
+
+const SUCCESS: i64 = 0;
+const NOT_SUPPORTED: i64 = -1;
+const INVALID_PARAMETERS: i64 = -2;
+
+/// Returns the page-aligned IPAs of the `count` pages from `ipa`, if they are
+/// all guest RAM.
+fn balloon_pages(ipa: u64, count: u64) -> Option<impl Iterator<Item = usize>> {
+    let (ipa, count) = (ipa as usize, count as usize);
+    let end = count.checked_mul(PAGE_SIZE).and_then(|len| ipa.checked_add(len))?;
+    if ipa % PAGE_SIZE != 0 || end > GUEST_MAX_VM_SIZE {
+        return None;
+    }
+    Some((ipa..end).step_by(PAGE_SIZE))
+}
+
+/// Unmaps and frees the given pages of `process`, returning how many were
+/// mapped.
+fn inflate(process: &mut Process, pages: impl Iterator<Item = usize>) -> i64 {
+    let mut freed = 0;
+    for page in pages {
+        if process.vmap.unmap(VirtualAddr::from(page)) {
+            process.balloon.inflate(page);
+            freed += 1;
+        }
+    }
+    freed
+}
+
+/// Handles an `hvc #0` made by the guest with context `tf`.
+pub fn handle_hvc(tf: &mut TrapFrame) {
+    let function = tf.xn[0] as u32;
+    let result = match function {
+        PSCI_SYSTEM_OFF => return SCHEDULER.stop(tf, StopReason::Shutdown),
+        PSCI_SYSTEM_RESET => return SCHEDULER.stop(tf, StopReason::Reset),
+        HVC_BALLOON_INFLATE => match balloon_pages(tf.xn[1], tf.xn[2]) {
+            Some(pages) => inflate(&mut *SCHEDULER.get_by_vmid(tf.vmid()), pages),
+            None => INVALID_PARAMETERS,
+        },
+        HVC_BALLOON_TARGET => {
+            tf.xn[1] = PAGE_SIZE as u64;
+            let mut process = SCHEDULER.get_by_vmid(tf.vmid());
+            process.balloon.active = true;
+            process.balloon.wanted()
+        },
+        HVC_BALLOON_DEFLATE => match balloon_pages(tf.xn[1], tf.xn[2]) {
+            Some(pages) => {
+                let mut process = SCHEDULER.get_by_vmid(tf.vmid());
+                pages.for_each(|page| { process.balloon.deflate(page); });
+                SUCCESS
+            },
+            None => INVALID_PARAMETERS,
+        },
+        _ => NOT_SUPPORTED,
+    };
+    tf.xn[0] = result as u64;
+}
@@ -0,0 +1,100 @@
+use aarch64::{ESR_EL1, SPSR_EL2};
+
+use crate::param::GUEST_VBAR_SENTINEL;
+use crate::traps::syndrome::Syndrome;
+use crate::traps::TrapFrame;
+
+/// Offsets of the synchronous exception vectors from `VBAR_EL1` (ref: D1.10.2).
+const CURRENT_EL_SP0: u64 = 0x000;
+const CURRENT_EL_SPX: u64 = 0x200;
+const LOWER_EL_AARCH64: u64 = 0x400;
+const LOWER_EL_AARCH32: u64 = 0x600;
+
+/// Size of the guest's whole vector table.
+const VECTOR_TABLE_SIZE: u64 = 0x800;
+
+/// Exception classes that depend on the level the exception came from
+/// (ref: D13.2.37). ESR_EL2 always reports the lower EL flavor.
+const EC_IABT_LOWER: u32 = 0b100000;
+const EC_IABT_CURRENT: u32 = 0b100001;
+const EC_DABT_LOWER: u32 = 0b100100;
+const EC_DABT_CURRENT: u32 = 0b100101;
+
+/// Fault status code for a synchronous external abort.
+const FSC_EXTERNAL_ABORT: u32 = 0b010000;
+
+/// ISS bits of a data abort that carry over into the injected abort.
+const DABT_ISS_KEPT: u32 = (1 << 10) | (1 << 6); // FnV, WnR
+
+/// Reasons an exception can't be reflected to the guest.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InjectError {
+    /// The guest hasn't installed its exception vectors yet.
+    NoVectors,
+    /// The exception was taken from within the guest's own vectors, so
+    /// injecting it again would loop.
+    Nested,
+}
+
+/// Builds the ESR_EL1 a guest should see for an exception that reached the
+/// hypervisor with ESR_EL2 `esr`, when taken from EL1 if `from_el1`.
+///
+/// Aborts become synchronous external aborts, as the guest has no way to fix
+/// up a stage 2 fault. Exceptions whose syndrome is meaningful at EL1 are
+/// passed through, and anything else is reported as an unknown exception.
+fn guest_esr(syndrome: Syndrome, esr: u32, from_el1: bool) -> u32 {
+    let il = ESR_EL1::IL as u32;
+    match syndrome {
+        Syndrome::DataAbort { .. } => {
+            let ec = if from_el1 { EC_DABT_CURRENT } else { EC_DABT_LOWER };
+            (ec << 26) | il | (esr & DABT_ISS_KEPT) | FSC_EXTERNAL_ABORT
+        },
+        Syndrome::InstructionAbort { .. } => {
+            let ec = if from_el1 { EC_IABT_CURRENT } else { EC_IABT_LOWER };
+            (ec << 26) | il | FSC_EXTERNAL_ABORT
+        },
+        Syndrome::PCAlignmentFault | Syndrome::SpAlignmentFault | Syndrome::Brk(_) => esr,
+        _ => il,
+    }
+}
+
+/// Checks whether the exception in `tf` can be reflected to the guest.
+pub fn can_inject(tf: &TrapFrame) -> Result<(), InjectError> {
+    if tf.VBAR_EL1 == GUEST_VBAR_SENTINEL {
+        return Err(InjectError::NoVectors);
+    }
+    if tf.ELR >= tf.VBAR_EL1 && tf.ELR < tf.VBAR_EL1 + VECTOR_TABLE_SIZE {
+        return Err(InjectError::Nested);
+    }
+    Ok(())
+}
+
+/// Reflects a synchronous exception with syndrome `esr` and fault address
+/// `far` to the guest whose context is `tf`, as if it had been taken to EL1.
+///
+/// The guest's return state goes into `ELR_EL1`/`SPSR_EL1`, and `tf` is set up
+/// to resume at the appropriate entry of `VBAR_EL1` in EL1h with all
+/// exceptions masked (ref: D1.10).
+pub fn inject_sync(tf: &mut TrapFrame, esr: u32, far: u64) -> Result<(), InjectError> {
+    can_inject(tf)?;
+
+    let syndrome = Syndrome::from(esr);
+    let from = tf.SPSR & SPSR_EL2::M;
+    let offset = match (tf.SPSR & SPSR_EL2::M4 != 0, from) {
+        (true, _) => LOWER_EL_AARCH32,
+        (false, 0b0100) => CURRENT_EL_SP0,
+        (false, 0b0101) => CURRENT_EL_SPX,
+        (false, _) => LOWER_EL_AARCH64,
+    };
+
+    tf.ESR_EL1 = guest_esr(syndrome, esr, from & 0b1100 == 0b0100) as u64;
+    if syndrome.get_abort_info().is_some() || syndrome == Syndrome::PCAlignmentFault {
+        tf.FAR_EL1 = far;
+    }
+    tf.ELR_EL1 = tf.ELR;
+    tf.SPSR_EL1 = tf.SPSR;
+    tf.SPSR = (SPSR_EL2::M & 0b0101) // EL1h
+        | SPSR_EL2::D | SPSR_EL2::A | SPSR_EL2::I | SPSR_EL2::F;
+    tf.ELR = tf.VBAR_EL1 + offset;
+    Ok(())
+}
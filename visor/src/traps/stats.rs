@@ -0,0 +1,231 @@
+use core::fmt;
+
+use aarch64::PMCCNTR_EL0;
+use pi::interrupt::Interrupt;
+
+use crate::mutex::Mutex;
+use crate::param::{EXIT_TRACE_LEN, IO_BASE};
+use crate::traps::syndrome::Syndrome;
+
+/// Device register ranges that MMIO exits are bucketed by. Anything else in
+/// the peripheral window is counted as "other".
+const MMIO_RANGES: [(&str, usize, usize); MMIO_BUCKETS - 1] = [
+    ("systimer", IO_BASE + 0x3000, IO_BASE + 0x4000),
+    ("irq", IO_BASE + 0xB200, IO_BASE + 0xB400),
+    ("armtimer", IO_BASE + 0xB400, IO_BASE + 0xB800),
+    ("mailbox", IO_BASE + 0xB880, IO_BASE + 0xB8C0),
+    ("pm", IO_BASE + 0x100000, IO_BASE + 0x101000),
+    ("gpio", IO_BASE + 0x200000, IO_BASE + 0x201000),
+    ("uart0", IO_BASE + 0x201000, IO_BASE + 0x202000),
+    ("aux", IO_BASE + 0x215000, IO_BASE + 0x216000),
+    ("emmc", IO_BASE + 0x300000, IO_BASE + 0x301000),
+];
+
+const MMIO_BUCKETS: usize = 10;
+const IRQ_BUCKETS: usize = Interrupt::MAX + 1;
+
+/// Returns the current value of the cycle counter.
+#[inline(always)]
+pub fn cycles() -> u64 {
+    unsafe { PMCCNTR_EL0.get() }
+}
+
+fn mmio_bucket(addr: usize) -> usize {
+    MMIO_RANGES.iter()
+        .position(|&(_, start, end)| addr >= start && addr < end)
+        .unwrap_or(MMIO_RANGES.len())
+}
+
+fn mmio_name(bucket: usize) -> &'static str {
+    MMIO_RANGES.get(bucket).map(|r| r.0).unwrap_or("other")
+}
+
+fn irq_name(bucket: usize) -> &'static str {
+    ["Timer1", "Timer3", "Usb", "Gpio0", "Gpio1", "Gpio2", "Gpio3", "Uart", "spurious"][bucket]
+}
+
+/// How a guest exit was handled, for accounting purposes.
+#[derive(Debug, Copy, Clone)]
+pub enum Exit {
+    /// A synchronous exception handled on its own merits.
+    Sync(Syndrome),
+    /// A data abort emulated as a device access to this address.
+    Mmio(usize),
+    /// A stage 2 fault resolved by lazily allocating a page.
+    LazyPage,
+    /// A write to a shared page, resolved by giving the VM its own copy.
+    CopyOnWrite,
+    /// A write to a page write-protected for dirty logging.
+    DirtyLog,
+    /// An interrupt. `None` if nothing was pending by the time we looked.
+    Irq(Option<Interrupt>),
+}
+
+impl Exit {
+    /// Returns a short human readable name for this exit.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Exit::Sync(ref syndrome) => Syndrome::name(syndrome.index()),
+            Exit::Mmio(addr) => mmio_name(mmio_bucket(addr)),
+            Exit::LazyPage => "lazy-page",
+            Exit::CopyOnWrite => "cow",
+            Exit::DirtyLog => "dirty-log",
+            Exit::Irq(irq) => irq_name(irq.map(Interrupt::to_index).unwrap_or(Interrupt::MAX)),
+        }
+    }
+}
+
+/// A count of events and the cycles spent handling them.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Counter {
+    pub count: u64,
+    pub cycles: u64,
+}
+
+impl Counter {
+    fn add(&mut self, cycles: u64) {
+        self.count += 1;
+        self.cycles += cycles;
+    }
+}
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let avg = if self.count == 0 { 0 } else { self.cycles / self.count };
+        write!(f, "{:>10} {:>14} {:>10}", self.count, self.cycles, avg)
+    }
+}
+
+/// Exit counters of a single VM.
+#[derive(Debug, Default, Clone)]
+pub struct ExitStats {
+    /// Every exit, whatever the reason.
+    pub total: Counter,
+    /// Synchronous exits not covered by `mmio`, `lazy_paging`, `cow` or
+    /// `dirty_log`, by `Syndrome::index()`.
+    pub syndromes: [Counter; Syndrome::KINDS],
+    /// Emulated device accesses, by `MMIO_RANGES` entry.
+    pub mmio: [Counter; MMIO_BUCKETS],
+    /// Interrupts, by `Interrupt::to_index()`, with spurious ones last.
+    pub irqs: [Counter; IRQ_BUCKETS],
+    /// Stage 2 faults resolved by allocating a page.
+    pub lazy_paging: Counter,
+    /// Writes to shared pages resolved by copying them.
+    pub cow: Counter,
+    /// First writes to pages since dirty logging was (re)started.
+    pub dirty_log: Counter,
+}
+
+impl ExitStats {
+    /// Accounts for one `exit` that took `cycles` cycles to handle.
+    pub fn record(&mut self, exit: &Exit, cycles: u64) {
+        self.total.add(cycles);
+        match *exit {
+            Exit::Sync(ref syndrome) => self.syndromes[syndrome.index()].add(cycles),
+            Exit::Mmio(addr) => self.mmio[mmio_bucket(addr)].add(cycles),
+            Exit::LazyPage => self.lazy_paging.add(cycles),
+            Exit::CopyOnWrite => self.cow.add(cycles),
+            Exit::DirtyLog => self.dirty_log.add(cycles),
+            Exit::Irq(irq) => {
+                self.irqs[irq.map(Interrupt::to_index).unwrap_or(Interrupt::MAX)].add(cycles)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExitStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  {:<24} {:>10} {:>14} {:>10}", "reason", "count", "cycles", "avg")?;
+        writeln!(f, "  {:<24} {}", "total", self.total)?;
+        for (i, c) in self.syndromes.iter().enumerate().filter(|(_, c)| c.count > 0) {
+            writeln!(f, "  {:<24} {}", Syndrome::name(i), c)?;
+        }
+        for (i, c) in self.mmio.iter().enumerate().filter(|(_, c)| c.count > 0) {
+            writeln!(f, "  mmio {:<19} {}", mmio_name(i), c)?;
+        }
+        for (i, c) in self.irqs.iter().enumerate().filter(|(_, c)| c.count > 0) {
+            writeln!(f, "  irq {:<20} {}", irq_name(i), c)?;
+        }
+        if self.lazy_paging.count > 0 {
+            writeln!(f, "  {:<24} {}", "lazy paging", self.lazy_paging)?;
+        }
+        if self.cow.count > 0 {
+            writeln!(f, "  {:<24} {}", "copy on write", self.cow)?;
+        }
+        if self.dirty_log.count > 0 {
+            writeln!(f, "  {:<24} {}", "dirty logging", self.dirty_log)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single exit as recorded in the trace ring.
+#[derive(Debug, Copy, Clone)]
+pub struct TraceEntry {
+    /// Cycle counter value when the exit was taken.
+    pub at: u64,
+    /// Cycles spent in the hypervisor handling the exit.
+    pub cycles: u64,
+    pub vmid: u8,
+    pub exit: Exit,
+    pub elr: u64,
+    pub esr: u32,
+    pub far: u64,
+    pub hpfar: u64,
+}
+
+impl TraceEntry {
+    /// Header line matching the output of `TraceEntry::write_csv()`.
+    pub const CSV_HEADER: &'static str = "at,cycles,vmid,exit,elr,esr,far,hpfar";
+
+    /// Writes this entry as one comma separated line.
+    pub fn write_csv<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        writeln!(w, "{},{},{},{},{:#x},{:#x},{:#x},{:#x}",
+                 self.at, self.cycles, self.vmid, self.exit.name(),
+                 self.elr, self.esr, self.far, self.hpfar)
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>16} vm{:<3} {:<18} ELR {:#010x} ESR {:#010x} FAR {:#010x} HPFAR {:#010x} ({} cycles)",
+               self.at, self.vmid, self.exit.name(), self.elr, self.esr, self.far, self.hpfar, self.cycles)
+    }
+}
+
+/// Fixed-size ring of the most recent exits across all VMs.
+pub struct TraceRing {
+    entries: [Option<TraceEntry>; EXIT_TRACE_LEN],
+    next: usize,
+}
+
+impl TraceRing {
+    const fn new() -> TraceRing {
+        TraceRing { entries: [None; EXIT_TRACE_LEN], next: 0 }
+    }
+
+    /// Appends `entry`, overwriting the oldest one if the ring is full.
+    pub fn push(&mut self, entry: TraceEntry) {
+        self.entries[self.next] = Some(entry);
+        self.next = (self.next + 1) % EXIT_TRACE_LEN;
+    }
+
+    /// Forgets every recorded exit.
+    pub fn clear(&mut self) {
+        *self = TraceRing::new();
+    }
+
+    /// Returns the number of recorded exits.
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    /// Iterates over the recorded exits from oldest to newest.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a TraceEntry> + 'a {
+        (0..EXIT_TRACE_LEN)
+            .filter_map(move |i| self.entries[(self.next + i) % EXIT_TRACE_LEN].as_ref())
+    }
+}
+
+/// Global trace of recent guest exits.
+pub static TRACE: Mutex<TraceRing> = Mutex::new(TraceRing::new());
@@ -52,7 +52,8 @@ pub enum Syndrome {
     Svc(u16),
     Hvc(u16),
     Smc(u16),
-    MsrMrsSystem,
+    /// A trapped system register access, with its ISS.
+    MsrMrsSystem(u32),
     InstructionAbort { kind: Fault, level: u8 },
     PCAlignmentFault,
     DataAbort { kind: Fault, level: u8, iss: DataAbortSyndrome },
@@ -67,6 +68,46 @@ pub enum Syndrome {
 }
 
 impl Syndrome {
+    /// The number of distinct `Syndrome` variants.
+    pub const KINDS: usize = 19;
+
+    /// Returns a dense index in `0..Syndrome::KINDS` identifying the variant,
+    /// ignoring any payload.
+    pub fn index(&self) -> usize {
+        use self::Syndrome::*;
+        match self {
+            Unknown(_) => 0,
+            WfiWfe => 1,
+            SimdFp => 2,
+            IllegalExecutionState => 3,
+            Svc(_) => 4,
+            Hvc(_) => 5,
+            Smc(_) => 6,
+            MsrMrsSystem(_) => 7,
+            InstructionAbort { .. } => 8,
+            PCAlignmentFault => 9,
+            DataAbort { .. } => 10,
+            SpAlignmentFault => 11,
+            TrappedFpu => 12,
+            SError => 13,
+            Breakpoint => 14,
+            Step => 15,
+            Watchpoint => 16,
+            Brk(_) => 17,
+            Other(_) => 18,
+        }
+    }
+
+    /// Returns the name of the variant with index `index`.
+    pub fn name(index: usize) -> &'static str {
+        [
+            "Unknown", "WfiWfe", "SimdFp", "IllegalExecutionState", "Svc", "Hvc",
+            "Smc", "MsrMrsSystem", "InstructionAbort", "PCAlignmentFault",
+            "DataAbort", "SpAlignmentFault", "TrappedFpu", "SError", "Breakpoint",
+            "Step", "Watchpoint", "Brk", "Other",
+        ][index]
+    }
+
     pub fn get_abort_info(&self) -> Option<(Fault, u8)> {
         match self {
             Syndrome::InstructionAbort{kind, level} => Some((*kind, *level)),
@@ -89,7 +130,7 @@ impl From<u32> for Syndrome {
             0b010001 | 0b010101 => Svc(esr as u16),
             0b010010 | 0b010110 => Hvc(esr as u16),
             0b010011 | 0b010111 => Smc(esr as u16),
-            0b011000 => MsrMrsSystem,
+            0b011000 => MsrMrsSystem(esr & 0x1FFFFFF),
             0b100000 | 0b100001 => InstructionAbort{kind: Fault::from(esr & 0b111111), level: (esr & 0b11) as u8},
             0b100010 => PCAlignmentFault,
             0b100100 | 0b100101 => DataAbort{kind: Fault::from(esr & 0b111111), level: (esr & 0b11) as u8, iss: DataAbortSyndrome::new(esr as u64 & 0x1FFFFFF)},
@@ -0,0 +1,100 @@
+pub mod local;
+pub mod pm;
+pub mod vtimer;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use aarch64::HCR_EL2;
+
+use self::local::LocalIntc;
+use self::pm::PowerManager;
+use self::vtimer::VirtualTimer;
+
+/// Devices emulated separately for every VM instead of passing the guest's
+/// accesses through to the hardware.
+#[derive(Debug, Default, Clone)]
+pub struct Devices {
+    pub pm: PowerManager,
+    pub local: LocalIntc,
+    pub vtimer: VirtualTimer,
+}
+
+impl Devices {
+    /// Returns `true` if `addr` belongs to an emulated device outside the
+    /// peripheral range between `IO_BASE` and `IO_BASE_END`.
+    pub fn claims(addr: usize) -> bool {
+        LocalIntc::contains(addr)
+    }
+
+    /// Emulates a guest read of `1 << size` bytes at physical address `addr`
+    /// at time `now`. Returns `None` if no emulated device claims `addr`.
+    /// The emulated registers are all 32 bits wide, so `size` is ignored.
+    pub fn read(&mut self, addr: usize, _size: u64, now: Duration) -> Option<u64> {
+        if PowerManager::contains(addr) {
+            return Some(self.pm.read(addr - PowerManager::BASE, now) as u64);
+        }
+        if LocalIntc::contains(addr) {
+            let offset = addr - LocalIntc::BASE;
+            let timer = self.vtimer.pending();
+            if LocalIntc::is_irq_source(offset) {
+                self.vtimer.acknowledge();
+            }
+            return Some(self.local.read(offset, timer) as u64);
+        }
+        None
+    }
+
+    /// Emulates a guest write of `value`, `1 << size` bytes wide, to physical
+    /// address `addr` at time `now`. Returns `false` if no emulated device
+    /// claims `addr`.
+    pub fn write(&mut self, addr: usize, _size: u64, value: u64, now: Duration) -> bool {
+        if PowerManager::contains(addr) {
+            self.pm.write(addr - PowerManager::BASE, value as u32, now);
+            return true;
+        }
+        if LocalIntc::contains(addr) {
+            self.local.write(addr - LocalIntc::BASE, value as u32);
+            return true;
+        }
+        false
+    }
+
+    /// Raises or lowers the virtual IRQ line of the running VM according to
+    /// its devices. Called on every exit, as the VM is about to be resumed.
+    pub fn update_irq(&mut self) {
+        let pending = self.vtimer.raise() && self.local.timer_enabled();
+        unsafe {
+            let hcr = HCR_EL2.get();
+            HCR_EL2.set(if pending { hcr | HCR_EL2::VI } else { hcr & !HCR_EL2::VI });
+        }
+    }
+
+    /// Loads per-VM device state into the hardware as the VM is switched in.
+    pub fn switch_in(&mut self) {
+        self.vtimer.restore();
+        self.update_irq();
+    }
+
+    /// Saves per-VM device state from the hardware as the VM is switched out.
+    pub fn switch_out(&mut self) {
+        self.vtimer.save();
+    }
+
+    /// Returns a copy of the devices of the VM, taken while it is running if
+    /// `running`.
+    pub fn snapshot(&self, running: bool) -> Devices {
+        let mut devices = self.clone();
+        if running {
+            devices.vtimer.sync();
+        }
+        devices
+    }
+
+    /// Appends the device state to `out`, for a serialized snapshot.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        self.pm.encode(out);
+        self.local.encode(out);
+        self.vtimer.encode(out);
+    }
+}
@@ -0,0 +1,57 @@
+use alloc::vec::Vec;
+
+use pi::local_interrupt::{LocalInterrupt, LOCAL_BASE, LOCAL_BASE_END};
+
+// Register offsets from `LocalIntc::BASE`.
+const CORE0_TIMER_INT_CONTROL: usize = 0x40;
+const CORE0_IRQ_SOURCE: usize = 0x60;
+
+/// The guest's view of the ARM local interrupt controller. A VM has a single
+/// core, and the only interrupt it can receive through it is the one of its
+/// virtual timer; other registers read as zero and ignore writes.
+#[derive(Debug, Default, Clone)]
+pub struct LocalIntc {
+    timer_ctl: u32,
+}
+
+impl LocalIntc {
+    pub const BASE: usize = LOCAL_BASE;
+
+    /// Returns `true` if `addr` falls in the emulated register block.
+    pub fn contains(addr: usize) -> bool {
+        addr >= LOCAL_BASE && addr < LOCAL_BASE_END
+    }
+
+    /// Returns `true` if the register at `offset` is the IRQ source register,
+    /// which the guest reads to find out why it was interrupted.
+    pub fn is_irq_source(offset: usize) -> bool {
+        offset == CORE0_IRQ_SOURCE
+    }
+
+    /// Returns `true` if the guest routed its virtual timer interrupt to IRQ.
+    pub fn timer_enabled(&self) -> bool {
+        self.timer_ctl & (1 << LocalInterrupt::CntV as u32) != 0
+    }
+
+    /// Reads the register at `offset`, with the guest's virtual timer
+    /// interrupt pending if `timer`.
+    pub fn read(&self, offset: usize, timer: bool) -> u32 {
+        match offset {
+            CORE0_TIMER_INT_CONTROL => self.timer_ctl,
+            CORE0_IRQ_SOURCE if timer && self.timer_enabled() => 1 << LocalInterrupt::CntV as u32,
+            _ => 0,
+        }
+    }
+
+    /// Appends the register state to `out`: `timer_ctl` as a `u32`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.timer_ctl.to_le_bytes());
+    }
+
+    /// Writes `value` to the register at `offset`.
+    pub fn write(&mut self, offset: usize, value: u32) {
+        if offset == CORE0_TIMER_INT_CONTROL {
+            self.timer_ctl = value & 0xff;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::param::IO_BASE;
+use crate::process::{CrashReason, StopReason};
+
+// Register offsets from `PowerManager::BASE`.
+const PM_RSTC: usize = 0x0;
+const PM_RSTS: usize = 0x4;
+const PM_WDOG: usize = 0x8;
+
+/// Writes are ignored unless the top byte holds this password.
+const PM_PASSWORD: u32 = 0x5a00_0000;
+const PM_PASSWORD_MASK: u32 = 0xff00_0000;
+
+/// What happens when the watchdog expires: `FULL_RESET` resets the board.
+const PM_RSTC_WRCFG_MASK: u32 = 0x30;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x20;
+
+/// Watchdog timeout, in ticks of 1/65536 of a second.
+const PM_WDOG_TIME_MASK: u32 = 0x000f_ffff;
+const PM_WDOG_TICKS_PER_SEC: u64 = 65536;
+
+/// Partition that the firmware treats as "halt" rather than a reboot.
+const PM_PARTITION_HALT: u32 = 63;
+
+/// Timeouts up to this long are taken as a deliberate reset, the way
+/// `pi::power::PowerManager::reset` and Linux reboot the board. A longer
+/// timeout running out means the guest stopped petting its watchdog.
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Emulation of the reset controller and watchdog of the power management
+/// block. Each VM gets its own, so a guest resetting "the board" only
+/// restarts itself.
+#[derive(Debug, Default, Clone)]
+pub struct PowerManager {
+    rstc: u32,
+    rsts: u32,
+    /// Timeout last loaded into `PM_WDOG`.
+    timeout: Duration,
+    /// When the watchdog counter reaches zero, if it is counting.
+    deadline: Option<Duration>,
+}
+
+impl PowerManager {
+    /// Physical address of `PM_RSTC`, the first emulated register.
+    pub const BASE: usize = IO_BASE + 0x0010001c;
+    /// Size of the emulated register block.
+    pub const SIZE: usize = 0xc;
+
+    /// Returns `true` if `addr` falls in the emulated register block.
+    pub fn contains(addr: usize) -> bool {
+        addr >= PowerManager::BASE && addr < PowerManager::BASE + PowerManager::SIZE
+    }
+
+    /// Returns the boot partition selected in `PM_RSTS`, whose bits are
+    /// spread over the even bit positions 0 to 10.
+    fn partition(&self) -> u32 {
+        (0..6).fold(0, |p, i| p | (((self.rsts >> (i * 2)) & 1) << i))
+    }
+
+    fn full_reset(&self) -> bool {
+        self.rstc & PM_RSTC_WRCFG_MASK == PM_RSTC_WRCFG_FULL_RESET
+    }
+
+    /// Reads the register at `offset` at time `now`. `PM_WDOG` reads back the
+    /// ticks left before the watchdog fires.
+    pub fn read(&mut self, offset: usize, now: Duration) -> u32 {
+        match offset {
+            PM_RSTC => self.rstc,
+            PM_RSTS => self.rsts,
+            PM_WDOG => {
+                let left = self.deadline
+                    .and_then(|deadline| deadline.checked_sub(now))
+                    .unwrap_or_default();
+                let ticks = left.as_micros() as u64 * PM_WDOG_TICKS_PER_SEC / 1_000_000;
+                ticks as u32 & PM_WDOG_TIME_MASK
+            },
+            _ => 0,
+        }
+    }
+
+    /// Writes `value` to the register at `offset` at time `now`. Writes
+    /// without the password are dropped, as on hardware.
+    pub fn write(&mut self, offset: usize, value: u32, now: Duration) {
+        if value & PM_PASSWORD_MASK != PM_PASSWORD {
+            return;
+        }
+        let value = value & !PM_PASSWORD_MASK;
+        match offset {
+            PM_RSTC => {
+                self.rstc = value;
+                if !self.full_reset() {
+                    self.deadline = None;
+                }
+            },
+            PM_RSTS => self.rsts = value,
+            PM_WDOG => {
+                let ticks = (value & PM_WDOG_TIME_MASK) as u64;
+                self.timeout = Duration::from_micros(ticks * 1_000_000 / PM_WDOG_TICKS_PER_SEC);
+                self.deadline = Some(now + self.timeout);
+            },
+            _ => {},
+        }
+    }
+
+    /// Restarts a counting watchdog from its full timeout at time `now`, as
+    /// when the VM is brought back from a snapshot.
+    pub fn rearm(&mut self, now: Duration) {
+        if self.deadline.is_some() {
+            self.deadline = Some(now + self.timeout);
+        }
+    }
+
+    /// Returns the time left at `now` before the watchdog fires, if it is
+    /// counting with a full reset configured.
+    pub fn remaining(&self, now: Duration) -> Option<Duration> {
+        match self.deadline {
+            Some(deadline) if self.full_reset() => {
+                Some(deadline.checked_sub(now).unwrap_or_default())
+            },
+            _ => None,
+        }
+    }
+
+    /// Appends the register state to `out`: `PM_RSTC` and `PM_RSTS` as `u32`s,
+    /// then the watchdog timeout and the deadline in microseconds as `u64`s,
+    /// with `u64::max_value()` for no deadline.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let deadline = self.deadline.map_or(u64::max_value(), |d| d.as_micros() as u64);
+        out.extend_from_slice(&self.rstc.to_le_bytes());
+        out.extend_from_slice(&self.rsts.to_le_bytes());
+        out.extend_from_slice(&(self.timeout.as_micros() as u64).to_le_bytes());
+        out.extend_from_slice(&deadline.to_le_bytes());
+    }
+
+    /// Checks the watchdog at time `now`. If it has fired with a full reset
+    /// configured, returns why the VM should stop: a halt, a reset, or a hang.
+    pub fn expired(&self, now: Duration) -> Option<StopReason> {
+        match self.deadline {
+            Some(deadline) if now >= deadline && self.full_reset() => {
+                Some(if self.partition() == PM_PARTITION_HALT {
+                    StopReason::Shutdown
+                } else if self.timeout <= RESET_TIMEOUT {
+                    StopReason::Reset
+                } else {
+                    StopReason::Crash(CrashReason::Watchdog)
+                })
+            },
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use alloc::vec::Vec;
+
+use aarch64::{CNTPCT_EL0, CNTVOFF_EL2, CNTV_CTL_EL0, CNTV_CVAL_EL0};
+use pi::local_interrupt::{LocalController, LocalInterrupt};
+
+/// A VM's virtual timer (CNTV) of the generic timer.
+///
+/// The hardware timer holds the registers of the running VM; they are saved
+/// and the timer stopped when the VM is switched out. The timer interrupt is
+/// taken by the hypervisor and passed on to the guest as a virtual IRQ. Its
+/// line stays high until the guest reprograms the timer, so routing of the
+/// physical interrupt is turned off while the guest has one pending.
+///
+/// Reprogramming the timer doesn't trap, so the guest's read of its IRQ
+/// source stands in for the acknowledgement: the virtual IRQ is lowered then,
+/// and only raised again if the line is still high at a later exit.
+#[derive(Debug, Clone)]
+pub struct VirtualTimer {
+    /// Physical count at which the VM's virtual count was zero.
+    pub offset: u64,
+    ctl: u64,
+    cval: u64,
+    /// Whether the timer interrupt is pending for the guest.
+    asserted: bool,
+    /// Whether the guest read its IRQ source since the interrupt fired.
+    acked: bool,
+}
+
+/// Routes the physical virtual timer interrupt to the hypervisor, or not.
+fn route(enable: bool) {
+    let mut local = LocalController::new(0);
+    if enable {
+        local.enable_timer(LocalInterrupt::CntV);
+    } else {
+        local.disable_timer(LocalInterrupt::CntV);
+    }
+}
+
+impl VirtualTimer {
+    /// Returns a stopped timer whose virtual count starts at zero now.
+    pub fn new() -> VirtualTimer {
+        VirtualTimer {
+            offset: unsafe { CNTPCT_EL0.get() },
+            ctl: 0,
+            cval: 0,
+            asserted: false,
+            acked: false,
+        }
+    }
+
+    /// Returns `true` if the interrupt line of the hardware timer is high.
+    fn line() -> bool {
+        let on = CNTV_CTL_EL0::ENABLE | CNTV_CTL_EL0::ISTATUS;
+        unsafe { CNTV_CTL_EL0.get() & (on | CNTV_CTL_EL0::IMASK) == on }
+    }
+
+    /// Loads the timer into the hardware as its VM is switched in.
+    pub fn restore(&self) {
+        unsafe {
+            CNTVOFF_EL2.set(self.offset);
+            CNTV_CVAL_EL0.set(self.cval);
+            CNTV_CTL_EL0.set(self.ctl);
+        }
+        route(!self.asserted);
+    }
+
+    /// Reads the timer back from the hardware while its VM is running.
+    pub fn sync(&mut self) {
+        unsafe {
+            self.cval = CNTV_CVAL_EL0.get();
+            self.ctl = CNTV_CTL_EL0.get() & (CNTV_CTL_EL0::ENABLE | CNTV_CTL_EL0::IMASK);
+        }
+    }
+
+    /// Saves the timer from the hardware and stops it as its VM is switched
+    /// out, so it can't fire while another VM runs.
+    pub fn save(&mut self) {
+        self.sync();
+        unsafe { CNTV_CTL_EL0.set(0) };
+    }
+
+    /// Appends the timer state to `out`: the offset, `CNTV_CTL_EL0` and
+    /// `CNTV_CVAL_EL0` as `u64`s, then whether the interrupt is pending as a
+    /// `u32`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.ctl.to_le_bytes());
+        out.extend_from_slice(&self.cval.to_le_bytes());
+        out.extend_from_slice(&(self.asserted as u32).to_le_bytes());
+    }
+
+    /// Handles the timer interrupt, taken while this timer's VM was running.
+    pub fn fire(&mut self) {
+        self.asserted = true;
+        self.acked = false;
+        route(false);
+    }
+
+    /// Returns `true` if the interrupt of the running VM's timer is pending.
+    /// It stops being pending once the guest has reprogrammed, masked or
+    /// disabled the timer.
+    pub fn pending(&mut self) -> bool {
+        if self.asserted && !VirtualTimer::line() {
+            self.asserted = false;
+            self.acked = false;
+            route(true);
+        }
+        self.asserted
+    }
+
+    /// Notes that the guest read its IRQ source, which is how its handler
+    /// acknowledges the interrupt.
+    pub fn acknowledge(&mut self) {
+        if self.asserted {
+            self.acked = true;
+        }
+    }
+
+    /// Returns `true` if the running VM's virtual IRQ should be raised as it
+    /// is resumed. Right after an acknowledgement it stays low, so the
+    /// guest's handler can reprogram the timer without taking the interrupt
+    /// again.
+    pub fn raise(&mut self) -> bool {
+        let pending = self.pending() && !self.acked;
+        self.acked = false;
+        pending
+    }
+}
+
+impl Default for VirtualTimer {
+    fn default() -> VirtualTimer {
+        VirtualTimer::new()
+    }
+}
@@ -6,9 +6,13 @@ use crate::util::align_up;
 use aarch64::*;
 
 mod address;
+mod dirty;
 mod pagetable;
+pub mod guest;
+pub mod share;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::dirty::DirtyLog;
 pub use self::pagetable::*;
 use crate::param::{VISOR_MASK_BITS, GUEST_MASK_BITS};
 
@@ -0,0 +1,82 @@
+use crate::param::{GUEST_IPA_SIZE, PAGE_SIZE};
+use crate::vm::VirtualAddr;
+
+/// Number of pages in the guest IPA space.
+const PAGES: usize = GUEST_IPA_SIZE / PAGE_SIZE;
+
+/// A bitmap of the guest pages that changed since dirty logging was started
+/// or last cleared, one bit per page of the IPA space.
+pub struct DirtyLog {
+    bits: [u64; PAGES / 64],
+    /// The pages write-protected only for the log, which are made writable
+    /// again on their first write or when logging stops.
+    protected: [u64; PAGES / 64],
+}
+
+impl DirtyLog {
+    /// Returns an empty log.
+    pub fn new() -> DirtyLog {
+        DirtyLog { bits: [0; PAGES / 64], protected: [0; PAGES / 64] }
+    }
+
+    /// Records that the log write-protected the page at `ipa`.
+    pub fn protect(&mut self, ipa: VirtualAddr) {
+        let page = ipa.as_usize() / PAGE_SIZE;
+        self.protected[page / 64] |= 1 << (page % 64);
+    }
+
+    /// Forgets that the log write-protected the page at `ipa`. Returns
+    /// `true` if it did.
+    pub fn unprotect(&mut self, ipa: VirtualAddr) -> bool {
+        let page = ipa.as_usize() / PAGE_SIZE;
+        if page >= PAGES || self.protected[page / 64] & (1 << (page % 64)) == 0 {
+            return false;
+        }
+        self.protected[page / 64] &= !(1 << (page % 64));
+        true
+    }
+
+    /// Records that the page at `ipa` changed.
+    pub fn mark(&mut self, ipa: VirtualAddr) {
+        let page = ipa.as_usize() / PAGE_SIZE;
+        self.bits[page / 64] |= 1 << (page % 64);
+    }
+
+    /// Returns `true` if the page at `ipa` changed.
+    pub fn is_dirty(&self, ipa: VirtualAddr) -> bool {
+        let page = ipa.as_usize() / PAGE_SIZE;
+        page < PAGES && self.bits[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    /// Forgets every change. Pages stay write-protected.
+    pub fn clear(&mut self) {
+        self.bits = [0; PAGES / 64];
+    }
+
+    /// Returns the number of pages that changed.
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Returns an iterator over the IPAs of the pages that changed, in
+    /// ascending order.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = VirtualAddr> + 'a {
+        (0..PAGES)
+            .filter(move |page| self.bits[page / 64] & (1 << (page % 64)) != 0)
+            .map(|page| VirtualAddr::from(page * PAGE_SIZE))
+    }
+
+    /// Returns an iterator over the IPAs of the pages the log write-protected,
+    /// in ascending order.
+    pub fn protected<'a>(&'a self) -> impl Iterator<Item = VirtualAddr> + 'a {
+        (0..PAGES)
+            .filter(move |page| self.protected[page / 64] & (1 << (page % 64)) != 0)
+            .map(|page| VirtualAddr::from(page * PAGE_SIZE))
+    }
+}
+
+impl core::fmt::Debug for DirtyLog {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "DirtyLog {{ {} pages }}", self.count())
+    }
+}
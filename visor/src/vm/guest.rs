@@ -0,0 +1,220 @@
+use core::fmt;
+
+use aarch64::SCTLR_EL1;
+
+use crate::param::PAGE_SIZE;
+use crate::traps::TrapFrame;
+use crate::vm::share;
+use crate::vm::{GuestPageTable, PhysicalAddr, VirtualAddr};
+
+/// Reasons a guest virtual address could not be translated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WalkError {
+    /// The address is not covered by either of the guest's translation tables.
+    OutOfRange,
+    /// Translation table walks for this half of the address space are disabled (`EPDn`).
+    WalkDisabled,
+    /// `TCR_EL1` describes a granule or address size we don't know how to walk.
+    BadGranule,
+    /// The descriptor at `level` was invalid or reserved.
+    Translation { level: u8 },
+    /// The stage 1 walk touched an IPA that isn't backed by stage 2.
+    Stage2 { ipa: u64 },
+}
+
+/// One step of a stage 1 walk: the IPA of the descriptor that was read and its value.
+#[derive(Debug, Copy, Clone)]
+pub struct WalkStep {
+    pub level: u8,
+    pub desc_ipa: u64,
+    pub desc: u64,
+}
+
+/// The result of walking a guest's stage 1 translation tables.
+pub struct Walk {
+    pub va: u64,
+    pub ipa: u64,
+    /// Descriptors visited, indexed by lookup level. Levels not visited are `None`.
+    pub steps: [Option<WalkStep>; 4],
+}
+
+impl fmt::Debug for Walk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VA {:#x} -> IPA {:#x}", self.va, self.ipa)
+    }
+}
+
+/// Copies guest memory starting at `ipa` into `buf`, crossing page boundaries
+/// as needed. Stops at the first unmapped page and returns the number of bytes
+/// copied.
+pub fn read_ipa(vmap: &GuestPageTable, ipa: u64, buf: &mut [u8]) -> usize {
+    let mut done = 0;
+    while done < buf.len() {
+        let addr = ipa as usize + done;
+        let pa = match vmap.translate(VirtualAddr::from(addr)) {
+            Some(pa) => pa,
+            None => break,
+        };
+        let chunk = core::cmp::min(buf.len() - done, PAGE_SIZE - (addr % PAGE_SIZE));
+        unsafe {
+            core::ptr::copy_nonoverlapping(pa.as_ptr(), buf[done..].as_mut_ptr(), chunk);
+        }
+        done += chunk;
+    }
+    done
+}
+
+/// Reads the 64-bit little endian word at `ipa`, which must be 8-byte aligned.
+fn read_ipa_u64(vmap: &GuestPageTable, ipa: u64) -> Option<u64> {
+    vmap.translate(VirtualAddr::from(ipa))
+        .map(|pa| unsafe { core::ptr::read_volatile(pa.as_usize() as *const u64) })
+}
+
+/// Translates a guest virtual address into an IPA by walking the guest's stage
+/// 1 tables described by the `TTBR0_EL1`, `TTBR1_EL1`, `TCR_EL1` and
+/// `SCTLR_EL1` values saved in `tf`. Descriptors are read through `vmap`.
+///
+/// If the guest's MMU is off, the VA is returned unchanged as the IPA.
+pub fn walk(tf: &TrapFrame, vmap: &GuestPageTable, va: u64) -> Result<Walk, WalkError> {
+    let mut walk = Walk { va, ipa: va, steps: [None; 4] };
+    if tf.SCTLR_EL1 & SCTLR_EL1::M == 0 {
+        return Ok(walk);
+    }
+
+    let tcr = tf.TCR_EL1;
+    // select the half of the address space; bit 55 decides when top-byte-ignore is on
+    let upper = (va >> 55) & 1 == 1;
+    let (txsz, tg, epd, tbi, ttbr) = if upper {
+        ((tcr >> 16) & 0x3f, match (tcr >> 30) & 0b11 {
+            0b01 => 14, 0b10 => 12, 0b11 => 16, _ => 0,
+        }, (tcr >> 23) & 1, (tcr >> 38) & 1, tf.TTBR1_EL1)
+    } else {
+        (tcr & 0x3f, match (tcr >> 14) & 0b11 {
+            0b00 => 12, 0b01 => 16, 0b10 => 14, _ => 0,
+        }, (tcr >> 7) & 1, (tcr >> 37) & 1, tf.TTBR0_EL1)
+    };
+    if tg == 0 {
+        return Err(WalkError::BadGranule);
+    }
+    if epd == 1 {
+        return Err(WalkError::WalkDisabled);
+    }
+
+    let ia_bits = 64 - txsz;
+    if ia_bits <= tg || ia_bits > 48 {
+        return Err(WalkError::BadGranule);
+    }
+
+    // every bit above the input address size must match the selected half
+    let top_bits = if tbi == 1 { 56 } else { 64 };
+    if ia_bits < top_bits {
+        let mask = ((1u64 << (top_bits - ia_bits)) - 1) << ia_bits;
+        if va & mask != if upper { mask } else { 0 } {
+            return Err(WalkError::OutOfRange);
+        }
+    }
+
+    let stride = tg - 3;
+    let levels = (ia_bits - tg + stride - 1) / stride;
+    let mut level = 4 - levels;
+    let mut table = ttbr & 0x0000_FFFF_FFFF_FFFE;
+    loop {
+        let shift = tg + stride * (3 - level);
+        let width = core::cmp::min(stride, ia_bits - shift);
+        let index = (va >> shift) & ((1 << width) - 1);
+        let desc_ipa = table + index * 8;
+        let desc = read_ipa_u64(vmap, desc_ipa).ok_or(WalkError::Stage2 { ipa: desc_ipa })?;
+        walk.steps[level as usize] = Some(WalkStep { level: level as u8, desc_ipa, desc });
+
+        if desc & 1 == 0 {
+            return Err(WalkError::Translation { level: level as u8 });
+        }
+        let addr = desc & 0x0000_FFFF_FFFF_FFFF;
+        let is_table = desc & 0b10 != 0;
+        if level == 3 {
+            if !is_table {
+                return Err(WalkError::Translation { level: 3 });
+            }
+            walk.ipa = (addr & !((1 << tg) - 1)) | (va & ((1 << tg) - 1));
+            return Ok(walk);
+        }
+        if !is_table {
+            // block descriptor
+            walk.ipa = (addr & !((1 << shift) - 1)) | (va & ((1 << shift) - 1));
+            return Ok(walk);
+        }
+        table = addr & !((1 << tg) - 1);
+        level += 1;
+    }
+}
+
+/// Translates a guest virtual address all the way to the physical address
+/// backing it. Returns the stage 1 walk along with the final `PhysicalAddr`.
+pub fn translate(tf: &TrapFrame, vmap: &GuestPageTable, va: u64) -> Result<(Walk, PhysicalAddr), WalkError> {
+    let walk = walk(tf, vmap, va)?;
+    match vmap.translate(VirtualAddr::from(walk.ipa)) {
+        Some(pa) => Ok((walk, pa)),
+        None => Err(WalkError::Stage2 { ipa: walk.ipa }),
+    }
+}
+
+/// Copies guest memory starting at the guest virtual address `va` into `buf`.
+/// Returns the number of bytes copied before the first untranslatable address.
+pub fn read_va(tf: &TrapFrame, vmap: &GuestPageTable, va: u64, buf: &mut [u8]) -> usize {
+    let mut done = 0;
+    while done < buf.len() {
+        let addr = va + done as u64;
+        // translations are at least 4KiB granular, so don't cross one in a single copy
+        let chunk = core::cmp::min(buf.len() - done, 0x1000 - (addr as usize & 0xfff));
+        let (_, pa) = match translate(tf, vmap, addr) {
+            Ok(t) => t,
+            Err(_) => break,
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(pa.as_ptr(), buf[done..].as_mut_ptr(), chunk);
+        }
+        done += chunk;
+    }
+    done
+}
+
+/// Makes the page at `ipa` privately writable by the guest, the way a guest
+/// write to it would. Returns `false` if that failed.
+fn make_writable(vmap: &mut GuestPageTable, ipa: u64) -> bool {
+    let page = VirtualAddr::from(ipa as usize & !(PAGE_SIZE - 1));
+    vmap.log_write(page);
+    !vmap.is_shared(page) || share::break_cow(vmap, page).is_ok()
+}
+
+/// Copies `data` into guest memory starting at the guest virtual address
+/// `va`, on behalf of the guest: pages it shares with others are copied first.
+/// The instruction cache is invalidated, so `data` may be code. Returns the
+/// number of bytes copied before the first untranslatable address.
+pub fn write_va(tf: &TrapFrame, vmap: &mut GuestPageTable, va: u64, data: &[u8]) -> usize {
+    let mut done = 0;
+    while done < data.len() {
+        let addr = va + done as u64;
+        let chunk = core::cmp::min(data.len() - done, 0x1000 - (addr as usize & 0xfff));
+        let ipa = match walk(tf, vmap, addr) {
+            Ok(walk) => walk.ipa,
+            Err(_) => break,
+        };
+        if !make_writable(vmap, ipa) {
+            break;
+        }
+        let pa = match vmap.translate(VirtualAddr::from(ipa)) {
+            Some(pa) => pa,
+            None => break,
+        };
+        // the guest's own dirty lines must not overwrite the new data later
+        aarch64::clean_invalidate_dcache(pa.as_u64(), chunk as u64);
+        unsafe {
+            core::ptr::copy_nonoverlapping(data[done..].as_ptr(), pa.as_usize() as *mut u8, chunk);
+        }
+        done += chunk;
+    }
+    if done > 0 {
+        aarch64::clear_icache();
+    }
+    done
+}
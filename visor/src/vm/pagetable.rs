@@ -9,8 +9,10 @@ use core::alloc::{GlobalAlloc, Layout};
 
 use crate::allocator;
 use crate::param::*;
+use pi::local_interrupt::LOCAL_BASE;
 use crate::util::align_up;
-use crate::vm::{PhysicalAddr, VirtualAddr};
+use crate::vm::share;
+use crate::vm::{DirtyLog, PhysicalAddr, VirtualAddr};
 use crate::ALLOCATOR;
 use crate::VMM;
 
@@ -25,7 +27,8 @@ impl Page {
     pub const SIZE: usize = PAGE_SIZE;
     pub const ALIGN: usize = PAGE_SIZE;
 
-    fn layout() -> Layout {
+    /// The layout pages are allocated and freed with.
+    pub fn layout() -> Layout {
         unsafe { Layout::from_size_align_unchecked(Self::SIZE, Self::ALIGN) }
     }
 }
@@ -67,9 +70,9 @@ impl L3Entry {
 
     /// Extracts `ADDR` field of the L3Entry and returns as a `PhysicalAddr`
     /// if valid. Otherwise, return `None`.
-    fn get_page_addr(&self) -> Option<PhysicalAddr> {
+    pub fn get_page_addr(&self) -> Option<PhysicalAddr> {
         if self.is_valid() {
-            Some(PhysicalAddr::from(self.0.get_value(RawEntry::ADDR)))
+            Some(PhysicalAddr::from(self.0.get_masked(RawEntry::ADDR)))
         } else {
             None
         }
@@ -235,22 +238,36 @@ impl VisorPageTable {
     /// Returns a new `VisorPageTable`. `VisorPageTable` should have a `Pagetable`
     /// created with `KERN_RW` permission.
     ///
-    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM and
-    /// physical address range from `IO_BASE` to `IO_BASE_END` for peripherals.
+    /// Set L3entry of ARM physical address starting at 0x00000000 for RAM
+    /// (with the crash log region at its end non-cacheable) and physical
+    /// address range from `IO_BASE` to `IO_BASE_END` for peripherals,
+    /// plus an L2 block for the ARM local peripherals at `LOCAL_BASE`.
     /// Each L3 entry should have correct value for lower attributes[10:0] as well
     /// as address[47:16]. Refer to the definition of `RawEntry` in `vmsa.rs` for
     /// more details.
     pub fn new() -> VisorPageTable {
         let mut pt = PageTable::new(EntryPerm::KERN_RW); // kernel R/W
         // fill in address space
-        let (_ , mut end) = allocator::memory_map().expect("memory_map");
-        end = align_up(end, PAGE_SIZE);
+        let (_ , mem_end) = allocator::memory_map().expect("memory_map");
+        let end = align_up(mem_end, PAGE_SIZE);
         for addr in (0..end).step_by(PAGE_SIZE) {
             pt.set_entry(VirtualAddr::from(addr), Self::new_l3pte(PhysicalAddr::from(addr), false));
         }
+        // the crash log past the allocator's memory is non-cacheable, so a
+        // crash record is in RAM when the board resets
+        for addr in (end..align_up(mem_end + CRASH_LOG_SIZE, PAGE_SIZE)).step_by(PAGE_SIZE) {
+            let mut pte = Self::new_l3pte(PhysicalAddr::from(addr), false);
+            pte.set_value(0b010, RawEntry::ATTR);
+            pt.set_entry(VirtualAddr::from(addr), pte);
+        }
         for addr in (IO_BASE..IO_BASE_END).step_by(PAGE_SIZE) {
             pt.set_entry(VirtualAddr::from(addr), Self::new_l3pte(PhysicalAddr::from(addr), true));
         }
+        // the ARM local peripherals sit right past the range covered by the L3
+        // tables, so map them with a single 512MiB L2 block instead.
+        let mut local = Self::new_l3pte(PhysicalAddr::from(LOCAL_BASE), true);
+        local.set_value(0, RawEntry::TYPE); // block
+        pt.l2.entries[LOCAL_BASE >> 29] = local;
         VisorPageTable(pt)
     }
 
@@ -265,7 +282,14 @@ pub enum PagePerm {
     RWX,
 }
 
-pub struct GuestPageTable(Box<PageTable>);
+pub struct GuestPageTable {
+    table: Box<PageTable>,
+    /// VMID of the VM this table belongs to, which tags its TLB entries.
+    vmid: u8,
+    /// Pages changed since dirty logging was started or last cleared, while
+    /// it is on.
+    dirty: Option<Box<DirtyLog>>,
+}
 
 impl GuestPageTable {
     /// Returns a new `GuestPageTable` containing a `PageTable` created with
@@ -290,7 +314,7 @@ impl GuestPageTable {
         // do NOT cache pagetables in hypervisor memory, or else we will need to flush every time we edit them, as it may cause incoherency with the TLB
         VMM.mark_noncacheable(&pt);
         
-        GuestPageTable(pt)
+        GuestPageTable { table: pt, vmid: 0, dirty: None }
     }
 
     /// Allocates a page and set an L3 entry translates given virtual address to the
@@ -301,32 +325,256 @@ impl GuestPageTable {
     /// Panics if the virtual address has already been allocated.
     /// Panics if allocator fails to allocate a page.
     ///
-    /// TODO. use Result<T> and make it failurable
     /// TODO. use perm properly
-    pub fn alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> &mut [u8] {
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> &mut [u8] {
+        self.try_alloc(va, perm).expect("failed to allocate user page")
+    }
+
+    /// Like `alloc`, but returns `None` if no page could be allocated. The
+    /// page is zeroed, as it may have belonged to another VM before.
+    ///
+    /// # Panics
+    /// Panics if the virtual address has already been allocated.
+    pub fn try_alloc(&mut self, va: VirtualAddr, _perm: PagePerm) -> Option<&mut [u8]> {
+        if self.get_entry(va).is_valid() {
+            panic!("page is already allocated")
+        }
+        let buf = GuestPageTable::new_page()?;
+        self.set_entry(va, GuestPageTable::new_l3pte(buf, false));
+        self.mark_dirty(va);
+        Some(unsafe { core::slice::from_raw_parts_mut(buf.as_usize() as *mut u8, PAGE_SIZE) })
+    }
+
+    /// Allocates a zeroed page to back guest memory.
+    pub fn new_page() -> Option<PhysicalAddr> {
         use core::alloc::GlobalAlloc;
         // todo: mark allocate pages are NC for visor
         let buf = unsafe { ALLOCATOR.alloc(Page::layout()) };
         if buf as usize == 0 {
-            panic!("failed to allocate user page");
-        }
-        if self.get_entry(va).is_valid() {
-            panic!("page is already allocated")
+            return None;
         }
+        unsafe { core::ptr::write_bytes(buf, 0, PAGE_SIZE) };
+        // the guest maps its memory cacheable, so the zeroes must not be
+        // sitting in the visor's dcache once the page is non-cacheable here
+        aarch64::clean_invalidate_dcache(buf as u64, PAGE_SIZE as u64);
+        VMM.mark_noncacheable(buf as *const Page);
+        Some(PhysicalAddr::from(buf))
+    }
 
+    /// Returns a stage 2 entry mapping the page at `pa`. Shared pages are
+    /// mapped read-only, so that writes to them fault.
+    fn new_l3pte(pa: PhysicalAddr, shared: bool) -> RawEntry {
         let mut pte = RawStage2Entry::new(0);
-        pte.set_value(buf as u64 >> PAGE_ALIGN, RawStage2Entry::ADDR);
+        pte.set_value(pa.as_u64() >> PAGE_ALIGN, RawStage2Entry::ADDR);
         pte.set_value(1, RawStage2Entry::VALID); // valid
         pte.set_value(1, RawStage2Entry::TYPE); // valid
         pte.set_value(0b11, RawStage2Entry::CACHE); // normal memory, outer write-back cacheable
         pte.set_value(0b11, RawStage2Entry::ATTR); // inner write-back cacheable
-        pte.set_value(Stage2EntryPerm::READWRITE, RawStage2Entry::S2AP); // R/W
+        if shared {
+            pte.set_value(Stage2EntryPerm::READONLY, RawStage2Entry::S2AP); // R
+            pte.set_value(1, RawStage2Entry::SHARED);
+        } else {
+            pte.set_value(Stage2EntryPerm::READWRITE, RawStage2Entry::S2AP); // R/W
+        }
         pte.set_value(0b11, RawStage2Entry::SH); // inner shareable
         pte.set_value(1, RawStage2Entry::AF); // we don't need AF yet
-        self.set_entry(va, RawEntry::new(pte.get()));
+        RawEntry::new(pte.get())
+    }
 
-        VMM.mark_noncacheable(buf as *const Page);
-        unsafe { core::slice::from_raw_parts_mut(buf, PAGE_SIZE) }
+    /// Frees the page at `pa` that was mapped by this table, or drops this
+    /// table's reference to it if it is `shared`.
+    fn free_page(pa: PhysicalAddr, shared: bool) {
+        use core::alloc::GlobalAlloc;
+        if shared {
+            share::release(pa);
+        } else {
+            unsafe { ALLOCATOR.dealloc(pa.as_usize() as *mut u8, Page::layout()) };
+        }
+    }
+
+    /// Unmaps the page at `va` and frees the page backing it. Returns `false`
+    /// if nothing was mapped there.
+    pub fn unmap(&mut self, va: VirtualAddr) -> bool {
+        let page = match self.get_entry(va).get_page_addr() {
+            Some(page) => page,
+            None => return false,
+        };
+        let shared = self.is_shared(va);
+        self.set_entry(va, RawEntry::new(0));
+        self.invalidate(va, PAGE_SIZE);
+        self.mark_dirty(va);
+        GuestPageTable::free_page(page, shared);
+        true
+    }
+
+    /// Maps the page at `va`, which must not be mapped yet, to `pa`,
+    /// read-only if `shared` or while dirty logging is on.
+    pub fn map(&mut self, va: VirtualAddr, pa: PhysicalAddr, shared: bool) {
+        assert!(!self.get_entry(va).is_valid(), "page is already mapped");
+        self.set_entry(va, GuestPageTable::new_l3pte(pa, shared));
+        self.log_mapping(va);
+    }
+
+    /// Maps the page at `va`, which must be mapped already, to `pa` instead,
+    /// read-only if `shared` or while dirty logging is on. The page
+    /// previously mapped is not freed.
+    pub fn remap(&mut self, va: VirtualAddr, pa: PhysicalAddr, shared: bool) {
+        assert!(self.get_entry(va).is_valid(), "remapping an unmapped page");
+        self.set_entry(va, GuestPageTable::new_l3pte(pa, shared));
+        self.log_mapping(va);
+        self.invalidate(va, PAGE_SIZE);
+    }
+
+    /// Records the page just mapped at `va` in the dirty log, if it is on,
+    /// and write-protects it for the log unless it is shared anyway.
+    fn log_mapping(&mut self, va: VirtualAddr) {
+        self.mark_dirty(va);
+        if self.dirty.is_some() && !self.is_shared(va) {
+            self.get_entry(va).0.set_value(Stage2EntryPerm::READONLY, RawStage2Entry::S2AP);
+            self.dirty.as_mut().unwrap().protect(va);
+        }
+    }
+
+    /// Returns `true` if the page at `va` is mapped shared, copy-on-write.
+    pub fn is_shared(&mut self, va: VirtualAddr) -> bool {
+        let entry = self.get_entry(va);
+        entry.is_valid() && entry.0.get_value(RawStage2Entry::SHARED) == 1
+    }
+
+    /// Starts dirty logging with an empty log, write-protecting every page
+    /// so that the first write to each is seen by `log_write`.
+    pub fn start_dirty_log(&mut self) {
+        self.dirty = Some(Box::new(DirtyLog::new()));
+        self.write_protect();
+    }
+
+    /// Stops dirty logging and makes the pages it write-protected writable
+    /// again.
+    pub fn stop_dirty_log(&mut self) {
+        let dirty = match self.dirty.take() {
+            Some(dirty) => dirty,
+            None => return,
+        };
+        for va in dirty.protected() {
+            // pages unmapped since keep their bit
+            let entry = self.get_entry(va);
+            if entry.is_valid() {
+                entry.0.set_value(Stage2EntryPerm::READWRITE, RawStage2Entry::S2AP);
+            }
+        }
+        aarch64::invalidate_vmid(self.vttbr());
+    }
+
+    /// Empties the dirty log, if it is on, and write-protects every page
+    /// again.
+    pub fn clear_dirty_log(&mut self) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.clear();
+            self.write_protect();
+        }
+    }
+
+    /// Returns the dirty log, if it is on. Besides pages written to, it has
+    /// the pages that were mapped or unmapped since it was last cleared.
+    pub fn dirty_log(&self) -> Option<&DirtyLog> {
+        self.dirty.as_ref().map(|dirty| &**dirty)
+    }
+
+    fn mark_dirty(&mut self, va: VirtualAddr) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.mark(va);
+        }
+    }
+
+    /// Write-protects every writable page for the dirty log, which must be
+    /// on, recording which pages it protected.
+    fn write_protect(&mut self) {
+        let dirty = self.dirty.as_mut().expect("dirty logging is off");
+        let entries = self.table.l3.iter_mut().flat_map(|l3| l3.entries.iter_mut());
+        for (i, entry) in entries.enumerate() {
+            let writable = entry.0.get_value(RawStage2Entry::S2AP) == Stage2EntryPerm::READWRITE;
+            if entry.is_valid() && writable {
+                entry.0.set_value(Stage2EntryPerm::READONLY, RawStage2Entry::S2AP);
+                dirty.protect(VirtualAddr::from(i * PAGE_SIZE));
+            }
+        }
+        aarch64::invalidate_vmid(self.vttbr());
+    }
+
+    /// Handles a write to the page at `va` that took a permission fault,
+    /// recording it in the dirty log if it is on. Returns `true` if the page
+    /// was only write-protected for the log, in which case it is writable
+    /// again. Shared pages are recorded, but have to be broken up by the
+    /// caller.
+    pub fn log_write(&mut self, va: VirtualAddr) -> bool {
+        if self.dirty.is_none() || !self.get_entry(va).is_valid() {
+            return false;
+        }
+        self.mark_dirty(va);
+        if !self.dirty.as_mut().unwrap().unprotect(va) {
+            return false;
+        }
+        self.get_entry(va).0.set_value(Stage2EntryPerm::READWRITE, RawStage2Entry::S2AP);
+        self.invalidate(va, PAGE_SIZE);
+        true
+    }
+
+    /// Returns the VMID this table is tagged with.
+    pub fn vmid(&self) -> u8 {
+        self.vmid
+    }
+
+    /// Returns the value of `VTTBR_EL2` that selects this table.
+    pub fn vttbr(&self) -> u64 {
+        self.get_baddr().as_u64() | ((self.vmid as u64) << 48)
+    }
+
+    /// Tags this table with VMID `vmid`. Entries some earlier VM with the
+    /// same VMID left in the TLB are flushed.
+    pub fn set_vmid(&mut self, vmid: u8) {
+        self.vmid = vmid;
+        aarch64::invalidate_vmid(self.vttbr());
+    }
+
+    /// Flushes the TLB entries for the IPAs in `[ipa, ipa + length)`. Must be
+    /// called after changing or removing any valid mapping in that range.
+    pub fn invalidate(&self, ipa: VirtualAddr, length: usize) {
+        aarch64::invalidate_ipa_range(self.vttbr(), ipa.as_u64(), length as u64, PAGE_SIZE as u64);
+    }
+
+    /// Translates the intermediate physical address `ipa` into the physical
+    /// address backing it. Returns `None` if the page containing `ipa` is not
+    /// mapped or `ipa` lies outside of the guest address space.
+    pub fn translate(&self, ipa: VirtualAddr) -> Option<PhysicalAddr> {
+        let addr = ipa.as_usize();
+        if addr >= GUEST_IPA_SIZE {
+            return None;
+        }
+        let (l2index, l3index) = PageTable::locate(VirtualAddr::from(addr & PAGE_MASK));
+        self.l3[l2index].entries[l3index]
+            .get_page_addr()
+            .map(|page| page + PhysicalAddr::from(addr & !PAGE_MASK))
+    }
+
+    /// Returns the first page mapped at or above `from`, as an `(ipa, pa)`
+    /// pair.
+    pub fn next_mapping(&self, from: VirtualAddr) -> Option<(VirtualAddr, PhysicalAddr)> {
+        let first = align_up(from.as_usize(), PAGE_SIZE) / PAGE_SIZE;
+        // start at `first` rather than skipping every entry below it
+        let count = self.l3[0].entries.len();
+        let low = self.l3[0].entries.get(first..).unwrap_or(&[]);
+        let high = self.l3[1].entries.get(first.saturating_sub(count)..).unwrap_or(&[]);
+        low.iter().chain(high).enumerate().filter_map(|(i, pte)| {
+            pte.get_page_addr().map(|pa| (VirtualAddr::from((first + i) * PAGE_SIZE), pa))
+        }).next()
+    }
+
+    /// Returns an iterator over the `(ipa, pa)` pairs of every page mapped in
+    /// this table, in ascending IPA order.
+    pub fn mappings<'a>(&'a self) -> impl Iterator<Item = (VirtualAddr, PhysicalAddr)> + 'a {
+        self.into_iter().enumerate().filter_map(|(i, pte)| {
+            pte.get_page_addr().map(|pa| (VirtualAddr::from(i * PAGE_SIZE), pa))
+        })
     }
 }
 
@@ -342,7 +590,7 @@ impl Deref for GuestPageTable {
     type Target = PageTable;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.table
     }
 }
 
@@ -354,17 +602,18 @@ impl DerefMut for VisorPageTable {
 
 impl DerefMut for GuestPageTable {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.table
     }
 }
 
 impl Drop for GuestPageTable {
     fn drop(&mut self) {
-        use core::alloc::GlobalAlloc;
+        // nothing may translate to the pages once they are freed
+        aarch64::invalidate_vmid(self.vttbr());
         for pte in self.into_iter() {
             if pte.0.get() != 0 {
-                let page = (pte.0.get_value(RawStage2Entry::ADDR) << PAGE_ALIGN) as *mut u8;
-                unsafe { ALLOCATOR.dealloc(page, Page::layout()) };
+                let page = PhysicalAddr::from(pte.0.get_value(RawStage2Entry::ADDR) << PAGE_ALIGN);
+                GuestPageTable::free_page(page, pte.0.get_value(RawStage2Entry::SHARED) == 1);
             }
         }
     }
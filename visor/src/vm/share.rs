@@ -0,0 +1,275 @@
+use alloc::collections::BTreeMap;
+
+use crate::mutex::Mutex;
+use crate::param::PAGE_SIZE;
+use crate::vm::{GuestPageTable, Page, PhysicalAddr, VirtualAddr};
+
+/// Returns the FNV-1a hash of the page at `pa`.
+fn hash_page(pa: PhysicalAddr) -> u64 {
+    // the guest may still have dirty lines for the page in its cache
+    aarch64::clean_invalidate_dcache(pa.as_u64(), PAGE_SIZE as u64);
+    let words = unsafe { core::slice::from_raw_parts(pa.as_usize() as *const u64, PAGE_SIZE / 8) };
+    words.iter().fold(0xcbf29ce484222325, |hash, &word| {
+        (hash ^ word).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Returns `true` if the pages at `a` and `b` have the same contents.
+fn same(a: PhysicalAddr, b: PhysicalAddr) -> bool {
+    aarch64::clean_invalidate_dcache(a.as_u64(), PAGE_SIZE as u64);
+    aarch64::clean_invalidate_dcache(b.as_u64(), PAGE_SIZE as u64);
+    let a = unsafe { core::slice::from_raw_parts(a.as_usize() as *const u64, PAGE_SIZE / 8) };
+    let b = unsafe { core::slice::from_raw_parts(b.as_usize() as *const u64, PAGE_SIZE / 8) };
+    a == b
+}
+
+fn free(pa: PhysicalAddr) {
+    use core::alloc::GlobalAlloc;
+    unsafe { crate::ALLOCATOR.dealloc(pa.as_usize() as *mut u8, Page::layout()) };
+}
+
+/// A page mapped read-only into one or more guests.
+#[derive(Debug)]
+struct Shared {
+    /// Hash the page is found by in `Merger::stable`, if it was merged.
+    hash: Option<u64>,
+    /// Number of stage 2 entries mapping the page, plus snapshots holding it.
+    refs: usize,
+}
+
+/// Finds guest pages with identical contents and maps them to a single,
+/// read-only copy. A write to such a page faults, and the writer gets a
+/// private copy back (see `break_cow`).
+///
+/// Pages are visited a few at a time, in order of VMID and IPA. A page whose
+/// hash matches a shared page is merged into it right away. Otherwise it is
+/// remembered as a candidate until the end of the pass, so that the next page
+/// with the same contents, in any VM, can be merged with it.
+#[derive(Debug)]
+struct Merger {
+    enabled: bool,
+    /// Shared pages, by physical address.
+    shared: BTreeMap<usize, Shared>,
+    /// Shared pages, by hash.
+    stable: BTreeMap<u64, PhysicalAddr>,
+    /// Pages seen during this pass, by hash, as `(vmid, ipa)`. They are
+    /// still writable, so the hash may be out of date.
+    unstable: BTreeMap<u64, (u8, VirtualAddr)>,
+    /// Next `(vmid, ipa)` to look at.
+    cursor: (u8, usize),
+    /// Number of complete passes over guest memory.
+    passes: u64,
+}
+
+/// Statistics of the page merger.
+#[derive(Debug, Copy, Clone)]
+pub struct MergeStats {
+    pub enabled: bool,
+    /// Number of shared pages.
+    pub shared: usize,
+    /// Number of pages saved by sharing.
+    pub saved: usize,
+    /// Number of complete passes over guest memory.
+    pub passes: u64,
+}
+
+/// Reasons a shared page couldn't be made writable.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CowError {
+    /// The page isn't shared, so the fault is not ours to handle.
+    NotShared,
+    /// There was no memory left to copy the page to.
+    OutOfMemory,
+}
+
+static MERGER: Mutex<Option<Merger>> = Mutex::new(None);
+
+impl Merger {
+    fn new() -> Merger {
+        Merger {
+            enabled: true,
+            shared: BTreeMap::new(),
+            stable: BTreeMap::new(),
+            unstable: BTreeMap::new(),
+            cursor: (0, 0),
+            passes: 0,
+        }
+    }
+
+    /// Looks at the page at `va` in `tables[index]`, backed by `pa`, and
+    /// merges it with an identical page if there is one.
+    fn merge(&mut self, tables: &mut [&mut GuestPageTable], index: usize, va: VirtualAddr, pa: PhysicalAddr) {
+        let hash = hash_page(pa);
+        if let Some(&shared) = self.stable.get(&hash) {
+            // on a hash collision the page just stays private
+            if same(shared, pa) {
+                tables[index].remap(va, shared, true);
+                free(pa);
+                self.shared.get_mut(&shared.as_usize()).expect("stable page not shared").refs += 1;
+            }
+            return;
+        }
+
+        let vmid = tables[index].vmid();
+        if let Some(&(other_vmid, other_va)) = self.unstable.get(&hash) {
+            let other = tables.iter().position(|t| t.vmid() == other_vmid);
+            if let Some(other) = other {
+                let candidate = tables[other].translate(other_va);
+                match candidate {
+                    Some(candidate) if candidate != pa
+                        && !tables[other].is_shared(other_va)
+                        && same(candidate, pa) => {
+                        tables[other].remap(other_va, candidate, true);
+                        tables[index].remap(va, candidate, true);
+                        free(pa);
+                        self.shared.insert(candidate.as_usize(), Shared { hash: Some(hash), refs: 2 });
+                        self.stable.insert(hash, candidate);
+                        self.unstable.remove(&hash);
+                        return;
+                    },
+                    _ => {},
+                }
+            }
+        }
+        self.unstable.insert(hash, (vmid, va));
+    }
+
+    /// Takes another reference to the page at `pa`, which is shared from now
+    /// on if it wasn't yet.
+    fn get(&mut self, pa: PhysicalAddr) {
+        self.shared.entry(pa.as_usize())
+            .or_insert(Shared { hash: None, refs: 0 })
+            .refs += 1;
+    }
+
+    /// Starts the next pass over guest memory.
+    fn wrap(&mut self) {
+        self.cursor = (0, 0);
+        self.unstable.clear();
+        self.passes += 1;
+    }
+
+    /// Drops one reference to the shared page at `pa`, returning `true` if
+    /// it was the last one. The page is then no longer tracked.
+    fn put(&mut self, pa: PhysicalAddr) -> bool {
+        let last = match self.shared.get_mut(&pa.as_usize()) {
+            Some(shared) => {
+                shared.refs -= 1;
+                shared.refs == 0
+            },
+            None => true,
+        };
+        if last {
+            if let Some(hash) = self.shared.remove(&pa.as_usize()).and_then(|shared| shared.hash) {
+                self.stable.remove(&hash);
+            }
+        }
+        last
+    }
+}
+
+/// Runs the merger over at most `budget` pages of the guest memory mapped by
+/// `tables`, continuing where the last call stopped.
+pub fn scan(tables: &mut [&mut GuestPageTable], budget: usize) {
+    let mut merger = MERGER.lock();
+    let merger = merger.get_or_insert_with(Merger::new);
+    if !merger.enabled || tables.is_empty() {
+        return;
+    }
+    tables.sort_by_key(|t| t.vmid());
+    for _ in 0..budget {
+        let (vmid, ipa) = merger.cursor;
+        let next = tables.iter().enumerate()
+            .filter(|(_, t)| t.vmid() >= vmid)
+            .filter_map(|(i, t)| {
+                let from = if t.vmid() == vmid { ipa } else { 0 };
+                t.next_mapping(VirtualAddr::from(from)).map(|(va, pa)| (i, va, pa))
+            })
+            .next();
+        let (index, va, pa) = match next {
+            Some(next) => next,
+            None => {
+                merger.wrap();
+                continue;
+            },
+        };
+        merger.cursor = (tables[index].vmid(), va.as_usize() + PAGE_SIZE);
+        if !tables[index].is_shared(va) {
+            merger.merge(tables, index, va, pa);
+        }
+    }
+}
+
+/// Makes the page mapped at `ipa` in `table` shared, mapping it read-only if
+/// it wasn't already, and takes another reference to it for the caller.
+/// Returns the page, or `None` if nothing is mapped at `ipa`.
+pub fn share(table: &mut GuestPageTable, ipa: VirtualAddr) -> Option<PhysicalAddr> {
+    let pa = table.translate(ipa)?;
+    let mut merger = MERGER.lock();
+    let merger = merger.get_or_insert_with(Merger::new);
+    if !table.is_shared(ipa) {
+        merger.get(pa);
+        table.remap(ipa, pa, true);
+    }
+    merger.get(pa);
+    Some(pa)
+}
+
+/// Maps the shared page at `pa`, which the caller holds a reference to, at
+/// `ipa` in `table`. The new mapping takes a reference of its own.
+pub fn map(table: &mut GuestPageTable, ipa: VirtualAddr, pa: PhysicalAddr) {
+    MERGER.lock().get_or_insert_with(Merger::new).get(pa);
+    table.map(ipa, pa, true);
+}
+
+/// Drops a reference to the shared page at `pa`, freeing the page once
+/// nothing maps or holds it anymore.
+pub fn release(pa: PhysicalAddr) {
+    let last = MERGER.lock().as_mut().map_or(true, |merger| merger.put(pa));
+    if last {
+        free(pa);
+    }
+}
+
+/// Gives the guest of `table` a private, writable copy of the shared page at
+/// `ipa` after it wrote to it. The last guest to write to a shared page gets
+/// to keep it.
+pub fn break_cow(table: &mut GuestPageTable, ipa: VirtualAddr) -> Result<(), CowError> {
+    if !table.is_shared(ipa) {
+        return Err(CowError::NotShared);
+    }
+    let pa = table.translate(ipa).ok_or(CowError::NotShared)?;
+    let mut merger = MERGER.lock();
+    let merger = merger.get_or_insert_with(Merger::new);
+    let refs = merger.shared.get(&pa.as_usize()).map_or(1, |shared| shared.refs);
+    if refs == 1 {
+        merger.put(pa);
+        table.remap(ipa, pa, false);
+        return Ok(());
+    }
+    let copy = GuestPageTable::new_page().ok_or(CowError::OutOfMemory)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(pa.as_usize() as *const u8, copy.as_usize() as *mut u8, PAGE_SIZE);
+    }
+    table.remap(ipa, copy, false);
+    merger.put(pa);
+    Ok(())
+}
+
+/// Turns merging of new pages on or off. Pages that are already shared stay
+/// shared until written to.
+pub fn set_enabled(enabled: bool) {
+    MERGER.lock().get_or_insert_with(Merger::new).enabled = enabled;
+}
+
+/// Returns the merger's statistics.
+pub fn stats() -> MergeStats {
+    let mut merger = MERGER.lock();
+    let merger = merger.get_or_insert_with(Merger::new);
+    MergeStats {
+        enabled: merger.enabled,
+        shared: merger.shared.len(),
+        saved: merger.shared.values().map(|shared| shared.refs - 1).sum(),
+        passes: merger.passes,
+    }
+}